@@ -0,0 +1,90 @@
+// aish example plugin: data-science
+// Installed by `aish example install data-science`. Loaded automatically from
+// ~/.aish/plugins after your ~/.aish.ts; edit freely.
+//
+// Provides:
+//   agent tools  - csv_summary, python_eval
+//   commands     - `nb` (start Jupyter Lab), `venv` (show the active environment)
+//   templates    - eda, explain_model
+
+globalThis.commands ??= {};
+globalThis.promptTemplates ??= {};
+
+// op_execute_command is async: await its output
+const run = async (command: string): Promise<string> => await Deno.core.ops.op_execute_command(command);
+
+// Quote a value for safe use as a single shell word
+const shellQuote = (value: string): string => `'${String(value).replace(/'/g, `'\\''`)}'`;
+
+// ---- Agent tools -----------------------------------------------------------
+
+registerAgentTool({
+  name: "csv_summary",
+  description: "Summarize a CSV file: row count, header, and the first rows",
+  parameters: {
+    type: "object",
+    properties: {
+      path: { type: "string", description: "Path to the CSV file" },
+      rows: { type: "number", description: "Number of sample rows to show (default 5)" }
+    },
+    required: ["path"]
+  },
+  handler: async (params: { path: string, rows?: number }) => {
+    try {
+      const path = shellQuote(params.path);
+      // A whole number, whatever the model sent
+      const rows = Math.max(1, Math.trunc(Number(params.rows ?? 5)) || 5);
+      return {
+        success: true,
+        line_count: (await run(`wc -l < ${path}`)).trim(),
+        header: (await run(`head -n 1 ${path}`)).trim(),
+        sample: await run(`head -n ${rows + 1} ${path} | tail -n ${rows}`)
+      };
+    } catch (error) {
+      return { success: false, error: error.message };
+    }
+  }
+});
+
+registerAgentTool({
+  name: "python_eval",
+  description: "Run a short Python 3 snippet and return its stdout (use print for results)",
+  parameters: {
+    type: "object",
+    properties: {
+      code: { type: "string", description: "Python source to run" }
+    },
+    required: ["code"]
+  },
+  handler: async (params: { code: string }) => {
+    try {
+      return { success: true, output: await run(`python3 -c ${shellQuote(params.code)} 2>&1`) };
+    } catch (error) {
+      return { success: false, error: error.message };
+    }
+  }
+});
+
+// ---- Commands --------------------------------------------------------------
+
+globalThis.commands.nb = async (args: string[]) => {
+  try {
+    return await run(`jupyter lab --no-browser ${args.map(shellQuote).join(" ")} > /tmp/aish-jupyter.log 2>&1 & echo "Jupyter Lab starting, log: /tmp/aish-jupyter.log"`);
+  } catch (error) {
+    return `nb: ${error.message}`;
+  }
+};
+
+globalThis.commands.venv = () => {
+  const venv = Deno.core.ops.op_get_env("VIRTUAL_ENV") ?? Deno.core.ops.op_get_env("CONDA_DEFAULT_ENV");
+  return venv ? `Active environment: ${venv}` : "No Python environment active";
+};
+
+// ---- Prompt templates ------------------------------------------------------
+
+globalThis.promptTemplates.eda =
+  "Do a quick exploratory analysis of the dataset named below: shape, column types, " +
+  "missing values, and anything surprising. Use tools to look at the data first.";
+globalThis.promptTemplates.explain_model =
+  "Explain what the model code below does, its assumptions, and how its performance " +
+  "should be evaluated, in terms a stakeholder can follow.";
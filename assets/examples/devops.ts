@@ -0,0 +1,99 @@
+// aish example plugin: devops
+// Installed by `aish example install devops`. Loaded automatically from
+// ~/.aish/plugins after your ~/.aish.ts; edit freely.
+//
+// Provides:
+//   agent tools  - docker_ps, kube_pods, disk_usage
+//   commands     - `ports` (listening ports), `k <args>` (kubectl shorthand)
+//   templates    - incident, postmortem
+
+globalThis.commands ??= {};
+globalThis.promptTemplates ??= {};
+
+// op_execute_command is async: await its output
+const run = async (command: string): Promise<string> => await Deno.core.ops.op_execute_command(command);
+
+// Quote a value for safe use as a single shell word
+const shellQuote = (value: string): string => `'${String(value).replace(/'/g, `'\\''`)}'`;
+
+// ---- Agent tools -----------------------------------------------------------
+
+registerAgentTool({
+  name: "docker_ps",
+  description: "List Docker containers with status, image and ports",
+  parameters: {
+    type: "object",
+    properties: {
+      all: { type: "boolean", description: "Include stopped containers" }
+    },
+    required: []
+  },
+  handler: async (params: { all?: boolean }) => {
+    try {
+      const flags = params.all ? "-a " : "";
+      return { success: true, containers: await run(`docker ps ${flags}--format '{{.Names}}\t{{.Status}}\t{{.Image}}\t{{.Ports}}'`) };
+    } catch (error) {
+      return { success: false, error: error.message };
+    }
+  }
+});
+
+registerAgentTool({
+  name: "kube_pods",
+  description: "List Kubernetes pods in a namespace, including restarts and age",
+  parameters: {
+    type: "object",
+    properties: {
+      namespace: { type: "string", description: "Namespace (defaults to the current context's namespace)" }
+    },
+    required: []
+  },
+  handler: async (params: { namespace?: string }) => {
+    try {
+      const ns = params.namespace ? `-n ${shellQuote(params.namespace)}` : "";
+      return { success: true, pods: await run(`kubectl get pods ${ns} -o wide`) };
+    } catch (error) {
+      return { success: false, error: error.message };
+    }
+  }
+});
+
+registerAgentTool({
+  name: "disk_usage",
+  description: "Show filesystem usage for all mounted filesystems",
+  parameters: { type: "object", properties: {}, required: [] },
+  handler: async () => {
+    try {
+      return { success: true, usage: await run("df -h") };
+    } catch (error) {
+      return { success: false, error: error.message };
+    }
+  }
+});
+
+// ---- Commands --------------------------------------------------------------
+
+globalThis.commands.ports = async () => {
+  try {
+    return await run("ss -tlnp 2>/dev/null || netstat -tln");
+  } catch (error) {
+    return `ports: ${error.message}`;
+  }
+};
+
+globalThis.commands.k = async (args: string[]) => {
+  try {
+    return await run(`kubectl ${args.map(shellQuote).join(" ")}`);
+  } catch (error) {
+    return `k: ${error.message}`;
+  }
+};
+
+// ---- Prompt templates ------------------------------------------------------
+
+globalThis.promptTemplates.incident =
+  "You are on call. Triage the issue described below: gather evidence with tools, " +
+  "state the most likely cause, and propose the least risky mitigation first.";
+globalThis.promptTemplates.postmortem =
+  "Draft a blameless postmortem with sections: Summary, Impact, Timeline, Root Cause, " +
+  "Action Items. Ask for any facts you cannot determine from the shell.";
@@ -0,0 +1,77 @@
+// aish example plugin: rust-dev
+// Installed by `aish example install rust-dev`. Loaded automatically from
+// ~/.aish/plugins after your ~/.aish.ts; edit freely.
+//
+// Provides:
+//   agent tools  - cargo_check, cargo_test, crate_info
+//   commands     - `ck` (cargo clippy with warnings denied), `deps` (dependency tree)
+//   templates    - review, explain_error
+
+globalThis.commands ??= {};
+globalThis.promptTemplates ??= {};
+
+// op_execute_command is async: await its output
+const run = async (command: string): Promise<string> => await Deno.core.ops.op_execute_command(command);
+
+// Quote a value for safe use as a single shell word
+const shellQuote = (value: string): string => `'${String(value).replace(/'/g, `'\\''`)}'`;
+
+// Cargo writes diagnostics to stderr and exits non-zero on errors, so capture both
+const runCargo = async (args: string): Promise<{ success: boolean, output: string }> => {
+  try {
+    return { success: true, output: await run(`cargo ${args} 2>&1`) };
+  } catch (error) {
+    return { success: false, output: error.message };
+  }
+};
+
+// ---- Agent tools -----------------------------------------------------------
+
+registerAgentTool({
+  name: "cargo_check",
+  description: "Run `cargo check` (or clippy) on the current workspace and return diagnostics",
+  parameters: {
+    type: "object",
+    properties: {
+      clippy: { type: "boolean", description: "Run clippy instead of check" }
+    },
+    required: []
+  },
+  handler: (params: { clippy?: boolean }) =>
+    runCargo(params.clippy ? "clippy --workspace --all-targets --message-format short" : "check --workspace --message-format short")
+});
+
+registerAgentTool({
+  name: "cargo_test",
+  description: "Run cargo tests, optionally filtered by test name",
+  parameters: {
+    type: "object",
+    properties: {
+      filter: { type: "string", description: "Only run tests whose name contains this string" }
+    },
+    required: []
+  },
+  handler: (params: { filter?: string }) =>
+    runCargo(`test --workspace ${params.filter ? shellQuote(params.filter) : ""}`)
+});
+
+registerAgentTool({
+  name: "crate_info",
+  description: "Show the package name, version and direct dependencies of the current crate",
+  parameters: { type: "object", properties: {}, required: [] },
+  handler: () => runCargo("tree --depth 1")
+});
+
+// ---- Commands --------------------------------------------------------------
+
+globalThis.commands.ck = async () => (await runCargo("clippy --workspace --all-targets -- -D warnings")).output;
+globalThis.commands.deps = async (args: string[]) => (await runCargo(`tree ${args.map(shellQuote).join(" ")}`)).output;
+
+// ---- Prompt templates ------------------------------------------------------
+
+globalThis.promptTemplates.review =
+  "Review the Rust changes in `git diff` for correctness, error handling, unnecessary " +
+  "clones and allocations, and unidiomatic code. Be specific and cite lines.";
+globalThis.promptTemplates.explain_error =
+  "Explain the compiler error below in plain terms, point to the offending code, and " +
+  "suggest the smallest fix that satisfies the borrow checker.";
@@ -0,0 +1,1734 @@
+use anyhow::Result;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::context_ledger::{self, ContextLedger};
+use crate::render::{AgentEvent, PlanStep, Renderer};
+use crate::spinner::Spinner;
+use crate::ts_runtime::guard::Denial;
+use crate::ts_runtime::ops::session_var_env;
+use crate::ui::{self, Role};
+use crate::{approval, context, events, evidence, file_change, humanize, logging, patch, pathname, platform, project_index, provider_health, response_cache, search, session_store, state, status_cache, timeout, tool_rate, transcript, trash, ts_runtime, usage, wasm_plugins, Config};
+
+// Upper bound for a single retry delay, including server-requested ones
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+// Rough size of a token, for estimating how full the context window is
+const CHARS_PER_TOKEN: usize = 4;
+// Each message's share of a summary request, in characters
+const MAX_SUMMARIZED_MESSAGE_CHARS: usize = 4000;
+
+const SUMMARY_PROMPT: &str = "You compress the earlier part of a conversation between a user and a \
+shell assistant that runs tools. Summarize it so the assistant can carry on: what was asked, what \
+was tried, what was found (commands, paths, errors, values), what changed and what is left to do. \
+Be concise and factual; omit pleasantries. Reply with the summary only.";
+
+lazy_static::lazy_static! {
+    // The latest final answer, for `copy`
+    static ref LAST_ANSWER: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// The agent's latest final answer this session
+pub fn last_answer() -> Option<String> {
+    LAST_ANSWER.lock().ok().and_then(|answer| answer.clone())
+}
+
+// Set when an approved `propose_config_change` wrote the configuration
+static CONFIG_CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the agent wrote a configuration change since the last call, so
+/// the shell reloads it once the prompt is done
+pub fn take_config_change() -> bool {
+    CONFIG_CHANGED.swap(false, Ordering::Relaxed)
+}
+
+fn remember_answer(answer: &str) {
+    if let Ok(mut last) = LAST_ANSWER.lock() {
+        *last = Some(answer.to_string());
+    }
+}
+
+/// List the evidence `answer` cites under it, and keep it for `evidence`
+fn render_citations(answer: &str, evidence: &[evidence::Evidence], renderer: &dyn Renderer) {
+    let cited = evidence::cited(answer, evidence);
+    for evidence in &cited {
+        renderer.render(&AgentEvent::Citation { number: evidence.number, source: &evidence.source });
+    }
+    evidence::set_last(cited);
+}
+
+/// Cut `output` to at most `max_bytes`, on a character boundary, noting what was dropped
+fn truncate_output(mut output: String, max_bytes: usize) -> String {
+    let total = output.len();
+    if total <= max_bytes {
+        return output;
+    }
+    let mut end = max_bytes;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    output.truncate(end);
+    output.push_str(&truncation_marker(end, total));
+    output
+}
+
+fn truncation_marker(shown: usize, total: usize) -> String {
+    format!(
+        "\n[output truncated: first {} of {} shown; narrow the request (e.g. filter or limit the output) to see more]",
+        humanize::bytes(shown as u64),
+        humanize::bytes(total as u64),
+    )
+}
+
+/// Read `reader` to the end, keeping its first `max_bytes`; returns them and the total size
+fn read_capped(mut reader: impl Read, max_bytes: usize) -> (Vec<u8>, usize) {
+    let mut kept = Vec::new();
+    let mut total = 0;
+    let mut buffer = [0u8; 8192];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => {
+                total += read;
+                let room = max_bytes.saturating_sub(kept.len());
+                kept.extend_from_slice(&buffer[..read.min(room)]);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+    (kept, total)
+}
+
+/// Tool result telling the model its arguments didn't match the schema
+fn invalid_arguments(function_name: &str, errors: Vec<Value>) -> String {
+    json!({
+        "status": "invalid_arguments",
+        "tool": function_name,
+        "errors": errors,
+        "guidance": "The tool was not run. Fix the arguments to match its parameters schema and call it again.",
+    })
+    .to_string()
+}
+
+/// The `write_file` tool: create or replace a file, reporting what happened as JSON
+fn write_file(args: &Value) -> String {
+    let (Some(path), Some(content)) = (args["path"].as_str(), args["content"].as_str()) else {
+        return json!({ "error": "invalid_arguments", "message": "path and content are required" }).to_string();
+    };
+    let resolved = patch::resolve(path, &state::current_dir());
+    let created = !resolved.exists();
+    let result = resolved.parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&resolved, content));
+    match result {
+        Ok(()) => {
+            events::emit(events::FILE_CHANGED, json!({ "path": resolved.display().to_string(), "tool": "write_file" }));
+            json!({
+                "status": "written",
+                "path": resolved.display().to_string(),
+                "created": created,
+                "bytes": content.len(),
+                "lines": content.lines().count(),
+            })
+        }
+        Err(e) => json!({ "error": "write_failed", "path": resolved.display().to_string(), "message": e.to_string() }),
+    }
+    .to_string()
+}
+
+/// The `apply_patch` tool: apply a unified diff to every file the user
+/// `approved` (one answer per file) or to none, reporting each file changed
+/// and each one skipped as JSON
+fn apply_patch(args: &Value, approved: &[bool]) -> String {
+    let Some(diff) = args["patch"].as_str() else {
+        return json!({ "error": "invalid_arguments", "message": "patch is required" }).to_string();
+    };
+    let mut skipped = Vec::new();
+    let applied = patch::parse(diff).and_then(|files| {
+        let files: Vec<_> = files.into_iter().zip(approved)
+            .filter_map(|(file, approved)| {
+                if !approved {
+                    skipped.push(file.path);
+                    return None;
+                }
+                Some(file)
+            })
+            .collect();
+        let applied = patch::apply(&files, &state::current_dir())?;
+        patch::write(&applied)?;
+        for file in &applied {
+            events::emit(events::FILE_CHANGED, json!({ "path": file.path.display().to_string(), "tool": "apply_patch" }));
+        }
+        Ok(files.into_iter().zip(applied).map(|(file, applied)| json!({
+            "path": applied.path.display().to_string(),
+            "hunks": applied.hunks,
+            "created": file.creates,
+            "deleted": file.deletes,
+        })).collect::<Vec<_>>())
+    });
+    match applied {
+        Ok(files) if skipped.is_empty() => json!({ "status": "applied", "files": files }),
+        Ok(files) => json!({ "status": "applied", "files": files, "rejected_by_user": skipped }),
+        Err(e) => json!({
+            "error": "patch_failed",
+            "message": e.to_string(),
+            "guidance": "No file was changed. Re-read the file and send a patch whose context lines match it exactly.",
+        }),
+    }
+    .to_string()
+}
+
+/// The messages to send after `onAiRequest(messages)`, which may return a
+/// replacement array (e.g. with context injected or secrets redacted); the
+/// conversation itself is left as it was. Middleware errors (logged) send
+/// `messages` as is.
+async fn request_middleware(
+    messages: &[ChatMessage],
+    middleware: &HashSet<String>,
+    ts_config_loader: &ts_runtime::TypeScriptConfigLoader,
+) -> Option<Vec<ChatMessage>> {
+    if !middleware.contains("onAiRequest") {
+        return None;
+    }
+    let result = ts_config_loader.call_ai_middleware("onAiRequest", &json!(messages)).await
+        .and_then(|value| match value {
+            Value::Null => Ok(None),
+            value => serde_json::from_value::<Vec<ChatMessage>>(value)
+                .map(Some)
+                .map_err(|e| anyhow::anyhow!("onAiRequest must return an array of messages or nothing: {}", e)),
+        });
+    result.unwrap_or_else(|e| {
+        tracing::error!(error = %e, "onAiRequest middleware failed; sending the request unchanged");
+        None
+    })
+}
+
+/// The model's reply after `onAiResponse(message)`, which may return a
+/// replacement message; middleware errors (logged) keep the reply as it was
+async fn response_middleware(
+    message: ChatMessage,
+    middleware: &HashSet<String>,
+    ts_config_loader: &ts_runtime::TypeScriptConfigLoader,
+) -> ChatMessage {
+    if !middleware.contains("onAiResponse") {
+        return message;
+    }
+    let result = ts_config_loader.call_ai_middleware("onAiResponse", &json!(message)).await
+        .and_then(|value| match value {
+            Value::Null => Ok(None),
+            value => serde_json::from_value::<ChatMessage>(value)
+                .map(Some)
+                .map_err(|e| anyhow::anyhow!("onAiResponse must return a message or nothing: {}", e)),
+        });
+    match result {
+        Ok(Some(replacement)) => replacement,
+        Ok(None) => message,
+        Err(e) => {
+            tracing::error!(error = %e, "onAiResponse middleware failed; keeping the reply unchanged");
+            message
+        }
+    }
+}
+
+/// Approximate tokens `messages` take up in a request
+fn estimate_tokens(messages: &[ChatMessage]) -> usize {
+    let chars: usize = messages.iter()
+        .map(|message| {
+            message.content.as_ref().map_or(0, String::len)
+                + message.tool_calls.iter().flatten()
+                    .map(|call| call.function.name.len() + call.function.arguments.len())
+                    .sum::<usize>()
+        })
+        .sum();
+    // A few tokens of framing per message
+    chars / CHARS_PER_TOKEN + messages.len() * 4
+}
+
+/// The messages that can be replaced by a summary: everything after the
+/// opening system messages and prompt, except the `keep_recent` newest. The
+/// range ends before an assistant or user message, so tool results are never
+/// separated from the call that asked for them.
+fn summarizable(messages: &[ChatMessage], keep_recent: usize) -> Option<std::ops::Range<usize>> {
+    let start = messages.iter().position(|message| message.role == "user")? + 1;
+    let mut end = messages.len().saturating_sub(keep_recent.max(1));
+    while end > start && messages[end].role == "tool" {
+        end -= 1;
+    }
+    // Replacing a single message with a summary saves nothing
+    (end >= start + 2).then_some(start..end)
+}
+
+/// Whether the API refused a request for being longer than the model's context window
+fn is_context_overflow(error: &anyhow::Error) -> bool {
+    let message = error.to_string();
+    message.contains("context_length_exceeded") || message.contains("maximum context length")
+}
+
+/// What one prompt has used of its `ai.max_iterations`, `ai.max_prompt_tokens`
+/// and `ai.max_prompt_secs` limits
+struct Budget {
+    iterations: u32,
+    tokens: u64,
+    started: Instant,
+}
+
+impl Budget {
+    fn new() -> Self {
+        Self { iterations: 0, tokens: 0, started: Instant::now() }
+    }
+
+    /// The limit that has been reached, described for the user
+    fn exceeded(&self, config: &Config) -> Option<String> {
+        if let Some(max) = config.max_iterations().filter(|max| self.iterations >= *max) {
+            return Some(format!("{} rounds of tool calls (ai.max_iterations)", max));
+        }
+        if let Some(max) = config.max_prompt_tokens().filter(|max| self.tokens >= *max) {
+            return Some(format!("{} tokens (ai.max_prompt_tokens)", max));
+        }
+        if let Some(max) = config.max_prompt_duration().filter(|max| self.started.elapsed() >= *max) {
+            return Some(format!("{} (ai.max_prompt_secs)", humanize::duration(max)));
+        }
+        None
+    }
+}
+
+/// Ask whether the agent may keep going past `limit`; with the same budget
+/// again if so. Without a terminal the answer is no.
+fn continue_past(limit: &str) -> bool {
+    approval::ask(&approval::ApprovalRequest {
+        question: "Budget exceeded. Keep going?",
+        subject: &format!("used {}", limit),
+        details: None,
+        editable: false,
+        always_key: None,
+        default_approve: false,
+        risky: false,
+    })
+    .approved()
+}
+
+/// Keep a message of the conversation in the session store
+fn store_message(message: &ChatMessage) {
+    if let Ok(value) = serde_json::to_value(message) {
+        session_store::record_message(&value);
+    }
+}
+
+/// The resumed session's stored messages, without assistant tool calls
+/// whose results were never stored (the prompt failed or was interrupted
+/// mid-step), which the API would reject
+fn resumed_conversation() -> Vec<ChatMessage> {
+    let stored: Vec<ChatMessage> = session_store::conversation()
+        .into_iter()
+        .filter_map(|value| serde_json::from_value(value).ok())
+        .collect();
+    let mut conversation: Vec<ChatMessage> = Vec::with_capacity(stored.len());
+    // Whether tool results belong to a tool-call message that was kept
+    let mut answering = false;
+    for (index, message) in stored.iter().enumerate() {
+        if message.role == "tool" {
+            if answering {
+                conversation.push(message.clone());
+            }
+            continue;
+        }
+        answering = false;
+        if let Some(tool_calls) = &message.tool_calls {
+            let answered: HashSet<&String> = stored[index + 1..]
+                .iter()
+                .take_while(|next| next.role == "tool")
+                .filter_map(|next| next.tool_call_id.as_ref())
+                .collect();
+            if !tool_calls.iter().all(|call| answered.contains(&call.id)) {
+                continue;
+            }
+            answering = true;
+        }
+        conversation.push(message.clone());
+    }
+    conversation
+}
+
+/// The messages as plain text for the summary request
+fn summary_transcript(messages: &[ChatMessage]) -> String {
+    let mut transcript = String::new();
+    for message in messages {
+        if let Some(content) = &message.content {
+            let mut content: String = content.chars().take(MAX_SUMMARIZED_MESSAGE_CHARS).collect();
+            if content.len() < message.content.as_ref().map_or(0, String::len) {
+                content.push_str(" […]");
+            }
+            transcript.push_str(&format!("[{}]\n{}\n\n", message.role, content));
+        }
+        for call in message.tool_calls.iter().flatten() {
+            transcript.push_str(&format!("[assistant called {}]\n{}\n\n", call.function.name, call.function.arguments));
+        }
+    }
+    transcript
+}
+
+/// Exponential backoff (1s, 2s, 4s, ...) with up to 50% random jitter
+fn backoff_delay(attempt: u32) -> Duration {
+    let base = Duration::from_secs(1u64 << attempt.saturating_sub(1).min(6));
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+    let jitter = base.mul_f64((nanos % 1000) as f64 / 2000.0);
+    (base + jitter).min(MAX_RETRY_DELAY)
+}
+
+/// Delay requested by the server through a Retry-After header (seconds form)
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response.headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .map(|secs| Duration::from_secs_f64(secs.max(0.0)).min(MAX_RETRY_DELAY))
+}
+
+// Consecutive failures (errors or timeouts) after which a TypeScript tool is
+// left out of the schemas until the configuration is reloaded
+const DEGRADED_AFTER_FAILURES: u32 = 3;
+
+// How long a successful API key check is trusted
+const KEY_CHECK_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn key_check_cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("aish").join("key_check"))
+}
+
+fn key_check_cached(key_hash: &str) -> bool {
+    let Some(content) = key_check_cache_path().and_then(|p| std::fs::read_to_string(p).ok()) else {
+        return false;
+    };
+    let Some((cached_hash, checked_at)) = content.trim().split_once(' ') else {
+        return false;
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    cached_hash == key_hash
+        && checked_at.parse::<u64>().map_or(false, |t| now.saturating_sub(t) < KEY_CHECK_TTL.as_secs())
+}
+
+fn record_key_check(key_hash: &str) {
+    let Some(path) = key_check_cache_path() else {
+        return;
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    let _ = std::fs::write(path, format!("{} {}\n", key_hash, now));
+}
+
+// Used when .aish.ts does not set ai.systemPrompt
+const DEFAULT_SYSTEM_PROMPT: &str = "You are an AI assistant integrated into a Unix shell called 'aish'. \
+    Your role is to help users accomplish tasks by analyzing their requests and \
+    executing appropriate commands when needed.\n\n\
+    You have access to a 'run_command' tool that can execute shell commands. \
+    Use this tool when the user's request requires running commands. \
+    To create or change files, use write_file or apply_patch rather than \
+    shell redirection or sed.\n\n\
+    For tasks that take several steps, first call the 'plan' tool with a short \
+    list of steps, then call it again with updated statuses as each step \
+    finishes or fails, so the user can follow along.\n\n\
+    When you use run_command, always prefix your explanation with:\n\
+    '**** Running command'\n\
+    Then show the command being executed with a '$ ' prefix.\n\n\
+    If a tool result has status 'denied', a policy or the user refused the call. \
+    Do not retry it; use the policy and rule to find a compliant alternative, \
+    or explain to the user what you need.\n\n\
+    Tool results start with '[evidence N]'. When a claim in your answer rests \
+    on a tool result, cite it inline as [N]. A result may instead say it is \
+    unchanged from, or give a diff against, an earlier [evidence M].\n\n\
+    After executing commands and getting the results, provide a helpful \
+    response to the user. If the command output answers their question, \
+    you can simply acknowledge the result. If additional explanation is needed, \
+    provide it.\n\n\
+    Always be concise and helpful.";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ToolCall {
+    id: String,
+    r#type: String,
+    function: FunctionCall,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FunctionCall {
+    name: String,
+    arguments: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChatMessage {
+    role: String,
+    content: Option<String>,
+    tool_calls: Option<Vec<ToolCall>>,
+    tool_call_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OpenAIResponse {
+    choices: Vec<Choice>,
+    usage: Option<usage::TokenUsage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Choice {
+    message: ChatMessage,
+    finish_reason: Option<String>,
+}
+
+/// Resolved AI settings for a single request
+struct RequestSettings {
+    api_key: String,
+    model: String,
+    base_url: String,
+    temperature: f32,
+    max_tokens: u32,
+    top_p: Option<f32>,
+    seed: Option<u64>,
+}
+
+/// OpenAI chat-completions agent loop with built-in and TypeScript-defined tools
+pub struct AiAgent {
+    // Or why the `ai` gateway settings couldn't make one; AI requests are
+    // refused then rather than sent around the gateway or proxy
+    client: Result<Client, String>,
+    config: Config,
+    // Consecutive failures per TypeScript tool; reset by a success or a new agent
+    tool_failures: Mutex<HashMap<String, u32>>,
+    // What the model of a conversation (by id) has been sent, carried from prompt to prompt
+    ledger: Mutex<(String, ContextLedger)>,
+}
+
+impl AiAgent {
+    pub fn new(config: Config) -> Self {
+        let client = config.http_client().map_err(|e| e.to_string());
+        if let Err(e) = &client {
+            tracing::error!(error = %e, "invalid AI HTTP settings");
+        }
+        Self {
+            client,
+            config,
+            tool_failures: Mutex::new(HashMap::new()),
+            ledger: Mutex::new((String::new(), ContextLedger::default())),
+        }
+    }
+
+    /// Check the configured API key in the background with a cheap `GET /models`.
+    /// Successful checks are cached for a day so most startups make no request.
+    /// Returns a warning to show the user, if any.
+    pub fn spawn_key_check(&self) -> tokio::task::JoinHandle<Option<String>> {
+        let client = self.client.clone();
+        let api_key = self.config.ai.as_ref().and_then(|ai| ai.api_key.clone());
+        let base_url = self.config.ai.as_ref()
+            .and_then(|ai| ai.base_url.as_ref())
+            .cloned()
+            .unwrap_or_else(|| "https://api.openai.com/v1".to_string());
+
+        tokio::spawn(async move {
+            let client = match client {
+                Ok(client) => client,
+                Err(e) => return Some(format!("{}; AI requests are disabled until the configuration is fixed", e)),
+            };
+            let Some(api_key) = api_key else {
+                return Some("No AI API key configured; set ai.api_key in ~/.aish.ts to use agent mode".to_string());
+            };
+
+            // Stable across builds, unlike DefaultHasher, and the key can't be read back
+            let key_hash: String = Sha256::new()
+                .chain_update(&api_key)
+                .chain_update([0u8])
+                .chain_update(&base_url)
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            if key_check_cached(&key_hash) {
+                return None;
+            }
+
+            let response = client
+                .get(format!("{}/models", base_url))
+                .header("Authorization", format!("Bearer {}", api_key))
+                .timeout(Duration::from_secs(10))
+                .send()
+                .await;
+
+            match response {
+                Ok(response) if response.status().is_success() => {
+                    record_key_check(&key_hash);
+                    None
+                }
+                Ok(response) if matches!(response.status().as_u16(), 401 | 403) => {
+                    Some(format!("AI API key was rejected by {} (HTTP {}); agent prompts will fail until it is fixed", base_url, response.status()))
+                }
+                // Other statuses (e.g. gateways without /models) say nothing about the key
+                Ok(_) => None,
+                Err(e) => Some(format!("Could not reach AI provider {}: {}", base_url, e)),
+            }
+        })
+    }
+
+    /// Settings for a request, with `sampling` overriding the profile and configuration
+    fn request_settings(&self, sampling: &ts_runtime::SamplingPreset) -> Result<RequestSettings> {
+        let api_key = self.config.ai.as_ref()
+            .and_then(|ai| ai.api_key.clone())
+            .ok_or_else(|| anyhow::anyhow!(
+                "OpenAI API key not found. Please set it in ~/.aish.ts:\n\n\
+                ai: {{ api_key: \"your-api-key-here\" }}"
+            ))?;
+
+        // A profile for the current directory overrides model and temperature
+        let profile = self.active_profile().map(|(_, profile)| profile);
+
+        let model = profile.and_then(|p| p.model.clone())
+            .or_else(|| self.config.ai.as_ref().and_then(|ai| ai.model.clone()))
+            .unwrap_or_else(|| "gpt-4".to_string());
+
+        let base_url = self.config.ai.as_ref()
+            .and_then(|ai| ai.base_url.as_ref())
+            .cloned()
+            .unwrap_or_else(|| "https://api.openai.com/v1".to_string());
+
+        let temperature = sampling.temperature
+            .or_else(|| profile.and_then(|p| p.temperature))
+            .or_else(|| self.config.ai.as_ref().and_then(|ai| ai.temperature))
+            .unwrap_or(0.7);
+        let top_p = sampling.top_p.or_else(|| self.config.ai.as_ref().and_then(|ai| ai.top_p));
+        let seed = sampling.seed.or_else(|| self.config.ai.as_ref().and_then(|ai| ai.seed));
+
+        let max_tokens = self.config.ai.as_ref()
+            .and_then(|ai| ai.max_tokens)
+            .unwrap_or(1000);
+
+        Ok(RequestSettings { api_key, model, base_url, temperature, max_tokens, top_p, seed })
+    }
+
+    /// The profile selected by the current directory, if any
+    pub fn active_profile(&self) -> Option<(&str, &ts_runtime::TypeScriptProfile)> {
+        self.config.profile_for(&state::current_dir())
+    }
+
+    /// Whether a TypeScript tool has failed so often that it is no longer offered
+    fn tool_degraded(&self, tool_name: &str) -> bool {
+        self.tool_failures.lock()
+            .is_ok_and(|failures| failures.get(tool_name).is_some_and(|&count| count >= DEGRADED_AFTER_FAILURES))
+    }
+
+    fn record_tool_outcome(&self, tool_name: &str, ok: bool) {
+        if let Ok(mut failures) = self.tool_failures.lock() {
+            if ok {
+                failures.remove(tool_name);
+            } else {
+                let count = failures.entry(tool_name.to_string()).or_insert(0);
+                *count += 1;
+                if *count == DEGRADED_AFTER_FAILURES {
+                    tracing::warn!(tool = tool_name, "tool degraded after repeated failures");
+                }
+            }
+        }
+    }
+
+    /// Call a TypeScript or WASM plugin tool within its (timeout, max output
+    /// bytes) limits, turning errors and timeouts into structured results the
+    /// model can act on
+    async fn call_ts_tool(
+        &self,
+        function_name: &str,
+        args: &Value,
+        ts_config_loader: &ts_runtime::TypeScriptConfigLoader,
+        (timeout, max_output): (Duration, usize),
+    ) -> Result<String> {
+        if self.tool_degraded(function_name) {
+            return Ok(json!({
+                "error": "degraded",
+                "tool": function_name,
+                "message": "This tool failed repeatedly and is disabled until the configuration is reloaded. Do not call it again; use another approach.",
+            }).to_string());
+        }
+
+        let result = if wasm_plugins::provides_tool(function_name) {
+            wasm_plugins::call_tool(function_name, args, timeout)
+        } else {
+            ts_config_loader.call_agent_tool(function_name, args, timeout).await
+        };
+        self.record_tool_outcome(function_name, result.is_ok());
+        let degraded = self.tool_degraded(function_name);
+        Ok(match result {
+            Ok(result) => truncate_output(serde_json::to_string_pretty(&result)?, max_output),
+            Err(e) if e.downcast_ref::<ts_runtime::TimedOut>().is_some() => json!({
+                "error": "timeout",
+                "tool": function_name,
+                "timeout_secs": timeout.as_secs(),
+                "message": format!("The tool did not finish within {}s and was stopped.", timeout.as_secs()),
+                "degraded": degraded,
+            }).to_string(),
+            Err(e) => json!({
+                "error": "tool_failed",
+                "tool": function_name,
+                "message": e.to_string(),
+                "degraded": degraded,
+            }).to_string(),
+        })
+    }
+
+    /// Whether the active profile lets the agent use `tool_name`
+    fn tool_allowed(&self, tool_name: &str) -> bool {
+        match self.active_profile().and_then(|(_, profile)| profile.tools.as_ref()) {
+            Some(tools) => tools.iter().any(|tool| tool == tool_name),
+            None => true,
+        }
+    }
+
+    /// The agent's system prompt: the active profile's, else `ai.systemPrompt`
+    /// from the configuration (a string or a function of the shell context),
+    /// else the built-in text
+    async fn system_prompt(&self, ts_config_loader: &ts_runtime::TypeScriptConfigLoader) -> String {
+        if let Some(prompt) = self.active_profile().and_then(|(_, profile)| profile.system_prompt.clone()) {
+            return prompt;
+        }
+
+        let cwd = state::current_dir();
+        let status = status_cache::snapshot(&cwd);
+        let context = json!({
+            "current_dir": cwd.display().to_string(),
+            "git_branch": status.git_branch,
+            "git_dirty": status.git_dirty,
+            "kube_context": status.kube_context,
+            "model": self.config.ai.as_ref().and_then(|ai| ai.model.clone()),
+        });
+        match ts_config_loader.call_system_prompt(&context).await {
+            Ok(Some(prompt)) => return prompt,
+            Ok(None) => {}
+            Err(e) => {
+                tracing::error!(error = %e, "systemPrompt function failed; using the default");
+            }
+        }
+
+        self.config.ai.as_ref()
+            .and_then(|ai| ai.system_prompt.clone())
+            .unwrap_or_else(|| DEFAULT_SYSTEM_PROMPT.to_string())
+    }
+
+    /// Single-turn completion without tools, for builtins that just need text back
+    pub async fn complete(&self, system_prompt: &str, prompt: &str) -> Result<String> {
+        let settings = self.request_settings(&Default::default())?;
+
+        let messages = vec![
+            ChatMessage {
+                role: "system".to_string(),
+                content: Some(system_prompt.to_string()),
+                tool_calls: None,
+                tool_call_id: None,
+            },
+            ChatMessage {
+                role: "user".to_string(),
+                content: Some(prompt.to_string()),
+                tool_calls: None,
+                tool_call_id: None,
+            },
+        ];
+
+        let response = self.make_openai_request(&messages, &settings, None).await?;
+        response.choices.into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .ok_or_else(|| anyhow::anyhow!("No response from OpenAI"))
+    }
+
+    /// Run the agent loop for `prompt`; `sampling` overrides the configured
+    /// temperature, top_p and seed where set. Returns whether the model gave
+    /// an answer that passed `validateResponse`: false when the budget
+    /// stopped it or its last answer still failed validation.
+    pub async fn process_prompt(
+        &self,
+        prompt: &str,
+        ts_config_loader: &ts_runtime::TypeScriptConfigLoader,
+        renderer: &dyn Renderer,
+        sampling: &ts_runtime::SamplingPreset,
+    ) -> Result<bool> {
+        let settings = self.request_settings(sampling)?;
+
+        // Load available tools from TypeScript configuration; a broken config
+        // (already reported at startup) just means no custom tools
+        let tool_registry = ts_config_loader.load_agent_tools().await
+            .unwrap_or_else(|_| ts_runtime::ToolRegistry { tools: HashMap::new() });
+        let middleware = ts_config_loader.defined_functions(&ts_runtime::AI_MIDDLEWARE).await
+            .unwrap_or_default();
+
+        let mut messages = vec![
+            ChatMessage {
+                role: "system".to_string(),
+                content: Some(self.system_prompt(ts_config_loader).await),
+                tool_calls: None,
+                tool_call_id: None,
+            },
+        ];
+
+        // A resumed session's conversation so far
+        messages.extend(resumed_conversation());
+
+        // What the model has seen, so a repeated tool call or context
+        // snapshot sends only what changed; after a restart it comes from the
+        // session store
+        let conversation = session_store::conversation_id();
+        let mut ledger = match self.ledger.lock() {
+            Ok(held) if held.0 == conversation => held.1.clone(),
+            _ => session_store::ledger()
+                .and_then(|ledger| serde_json::from_value(ledger).ok())
+                .unwrap_or_default(),
+        };
+        ledger.resume(messages.iter().filter_map(|message| message.content.as_deref()));
+        // Every tool result so far, numbered for citation
+        let mut evidence: Vec<evidence::Evidence> = Vec::new();
+
+        // Where the user is and what they just did by hand, so "why did that
+        // fail?" has an answer
+        let cwd = state::current_dir();
+        let status = status_cache::snapshot(&cwd);
+        let mut snapshot = format!("Working directory: {}\n", cwd.display());
+        if let Some(branch) = &status.git_branch {
+            snapshot.push_str(&format!("Git branch: {}{}\n", branch, if status.git_dirty { " (uncommitted changes)" } else { "" }));
+        }
+        if let Some(kube_context) = &status.kube_context {
+            snapshot.push_str(&format!("Kubernetes context: {}\n", kube_context));
+        }
+        let window = self.config.context_commands();
+        if let Some(recent) = context::render(window, self.config.context_output_chars()) {
+            snapshot.push('\n');
+            snapshot.push_str(&recent);
+        }
+        let number = ledger.next_number();
+        messages.push(ChatMessage {
+            role: "system".to_string(),
+            content: Some(if self.config.differential_context() {
+                ledger.encode("context snapshot", number, &snapshot)
+            } else {
+                evidence::label(number, &snapshot)
+            }),
+            tool_calls: None,
+            tool_call_id: None,
+        });
+        store_message(&messages[messages.len() - 1]);
+        self.keep_ledger(&conversation, &ledger);
+        evidence.push(evidence::Evidence { number, source: "context snapshot".to_string(), output: snapshot });
+
+        messages.push(ChatMessage {
+            role: "user".to_string(),
+            content: Some(prompt.to_string()),
+            tool_calls: None,
+            tool_call_id: None,
+        });
+        store_message(&messages[messages.len() - 1]);
+
+        // Shown whenever we're waiting, and hidden before anything is rendered
+        let spinner = Spinner::new();
+        let mut validation_retries = 0;
+        let mut budget = Budget::new();
+        let mut answered = false;
+        loop {
+            if let Some(limit) = budget.exceeded(&self.config) {
+                tracing::info!(%limit, iterations = budget.iterations, tokens = budget.tokens, "agent budget exceeded");
+                if !continue_past(&limit) {
+                    renderer.render(&AgentEvent::Notice {
+                        message: &format!("Stopped: this prompt used {}", limit),
+                    });
+                    break;
+                }
+                budget = Budget::new();
+            }
+            if estimate_tokens(&messages) > self.config.summarize_threshold_tokens()
+                && self.summarize_older(&mut messages, renderer, &spinner).await
+            {
+                ledger.retain_sent(messages.iter().filter_map(|message| message.content.as_deref()));
+            }
+            let rewritten = request_middleware(&messages, &middleware, ts_config_loader).await;
+            spinner.show("thinking…");
+            let response = self.make_openai_request(rewritten.as_deref().unwrap_or(&messages), &settings, Some(&tool_registry)).await;
+            spinner.hide();
+            let response = match response {
+                Err(e) if is_context_overflow(&e) => {
+                    if !self.summarize_older(&mut messages, renderer, &spinner).await {
+                        return Err(e);
+                    }
+                    ledger.retain_sent(messages.iter().filter_map(|message| message.content.as_deref()));
+                    continue;
+                }
+                response => response?,
+            };
+            budget.tokens += response.usage.as_ref().map_or(0, |usage| usage.total_tokens);
+            
+            if let Some(choice) = response.choices.into_iter().next() {
+                let message = &response_middleware(choice.message, &middleware, ts_config_loader).await;
+                messages.push(message.clone());
+                store_message(message);
+
+                // Check if the assistant wants to use tools
+                if let Some(tool_calls) = &message.tool_calls {
+                    budget.iterations += 1;
+                    for tool_call in tool_calls {
+                        let function_name = &tool_call.function.name;
+                        tracing::info!(tool = %function_name, argument_bytes = tool_call.function.arguments.len(), "tool call");
+                        // Some models send an empty string for tools without parameters
+                        let arguments = match tool_call.function.arguments.trim() {
+                            "" => "{}",
+                            arguments => arguments,
+                        };
+                        
+                        let (output, source, call) = match serde_json::from_str::<Value>(arguments) {
+                            Ok(args) => (
+                                self.call_tool(function_name, &args, &tool_registry, ts_config_loader, renderer, &spinner).await?,
+                                evidence::source(function_name, &args),
+                                context_ledger::key(function_name, &args),
+                            ),
+                            Err(e) => (
+                                invalid_arguments(function_name, vec![json!({
+                                    "path": "",
+                                    "message": format!("arguments are not valid JSON: {}", e),
+                                })]),
+                                function_name.clone(),
+                                format!("{} {}", function_name, arguments),
+                            ),
+                        };
+                        
+                        tracing::debug!(tool = %function_name, output_chars = output.len(), "tool result");
+                        transcript::record(transcript::Entry::ToolCall {
+                            name: function_name.clone(),
+                            arguments: tool_call.function.arguments.clone(),
+                            output: output.clone(),
+                        });
+
+                        // Add tool response to conversation
+                        let number = ledger.next_number();
+                        messages.push(ChatMessage {
+                            role: "tool".to_string(),
+                            content: Some(if self.config.differential_context() {
+                                ledger.encode(&call, number, &output)
+                            } else {
+                                evidence::label(number, &output)
+                            }),
+                            tool_calls: None,
+                            tool_call_id: Some(tool_call.id.clone()),
+                        });
+                        store_message(&messages[messages.len() - 1]);
+                        self.keep_ledger(&conversation, &ledger);
+                        evidence.push(evidence::Evidence { number, source, output });
+                    }
+                } else {
+                    // No tools used, this is the final response
+                    let Some(content) = &message.content else {
+                        break;
+                    };
+                    let feedback = match ts_config_loader.call_validate_response(content, &json!({ "prompt": prompt })).await {
+                        Ok(ts_runtime::ResponseValidation::Ok) => None,
+                        Ok(ts_runtime::ResponseValidation::Retry(feedback)) => Some(feedback),
+                        Err(e) => {
+                            tracing::warn!(error = %e, "validateResponse failed; accepting the response");
+                            renderer.render(&AgentEvent::Notice {
+                                message: &format!("{}; accepting the response unvalidated", e),
+                            });
+                            None
+                        }
+                    };
+                    match feedback {
+                        Some(feedback) if validation_retries < self.config.max_validation_retries() => {
+                            validation_retries += 1;
+                            tracing::info!(attempt = validation_retries, %feedback, "response failed validation; re-prompting");
+                            renderer.render(&AgentEvent::Notice {
+                                message: &format!("Response failed validation ({}); retrying ({}/{})",
+                                    feedback, validation_retries, self.config.max_validation_retries()),
+                            });
+                            messages.push(ChatMessage {
+                                role: "user".to_string(),
+                                content: Some(format!("Your response failed validation: {}\nReply again with a corrected response.", feedback)),
+                                tool_calls: None,
+                                tool_call_id: None,
+                            });
+                        }
+                        Some(feedback) => {
+                            renderer.render(&AgentEvent::Response { content });
+                            remember_answer(content);
+                            render_citations(content, &evidence, renderer);
+                            renderer.render(&AgentEvent::Notice {
+                                message: &format!("The response still fails validation after {} retries: {}", validation_retries, feedback),
+                            });
+                            break;
+                        }
+                        None => {
+                            renderer.render(&AgentEvent::Response { content });
+                            remember_answer(content);
+                            render_citations(content, &evidence, renderer);
+                            answered = true;
+                            break;
+                        }
+                    }
+                }
+            } else {
+                return Err(anyhow::anyhow!("No response from OpenAI"));
+            }
+        }
+
+        Ok(answered)
+    }
+
+    /// Hold on to `ledger` for the next prompt of `conversation`, and store
+    /// it for when the session is resumed after a restart
+    fn keep_ledger(&self, conversation: &str, ledger: &ContextLedger) {
+        if let Ok(mut held) = self.ledger.lock() {
+            *held = (conversation.to_string(), ledger.clone());
+        }
+        if let Ok(value) = serde_json::to_value(ledger) {
+            session_store::record_ledger(&value);
+        }
+    }
+
+    /// Replace older steps of the conversation with a summary written by
+    /// `ai.summary_model`, so it fits the context window again. Returns
+    /// whether anything was summarized; failures are logged and leave
+    /// `messages` as it was.
+    async fn summarize_older(&self, messages: &mut Vec<ChatMessage>, renderer: &dyn Renderer, spinner: &Spinner) -> bool {
+        let Some(range) = summarizable(messages, self.config.keep_recent_messages()) else {
+            return false;
+        };
+        spinner.show("summarizing earlier steps…");
+        let summarizer = AiAgent::new(self.config.summary_config());
+        let summary = summarizer.complete(SUMMARY_PROMPT, &summary_transcript(&messages[range.clone()])).await;
+        spinner.hide();
+        let summary = match summary {
+            Ok(summary) => summary,
+            Err(e) => {
+                tracing::warn!(error = %e, "summarizing the conversation failed");
+                return false;
+            }
+        };
+        
+        let summarized = range.len();
+        let before = estimate_tokens(messages);
+        let start = range.start;
+        messages.drain(range);
+        messages.insert(start, ChatMessage {
+            role: "system".to_string(),
+            content: Some(format!("Summary of earlier steps in this conversation:\n{}", summary.trim())),
+            tool_calls: None,
+            tool_call_id: None,
+        });
+        tracing::info!(summarized, before, after = estimate_tokens(messages), "summarized older messages");
+        renderer.render(&AgentEvent::Notice {
+            message: &format!("Summarized {} earlier messages to stay within the context window", summarized),
+        });
+        true
+    }
+
+    /// Run one tool call with the same policy checks as the agent loop:
+    /// profile tool lists, nesting limits, the command guard and approvals.
+    /// Refusals and tool failures come back as results the model can act on.
+    pub async fn call_tool(
+        &self,
+        function_name: &str,
+        args: &Value,
+        tool_registry: &ts_runtime::ToolRegistry,
+        ts_config_loader: &ts_runtime::TypeScriptConfigLoader,
+        renderer: &dyn Renderer,
+        spinner: &Spinner,
+    ) -> Result<String> {
+        // Refused calls don't count toward the rate limit
+        Ok(if !self.tool_allowed(function_name) {
+            Denial::new("profile.tools", Some(function_name.to_string()),
+                format!("Tool {} is not allowed by the active profile", function_name)).to_tool_result()
+        } else if let Err(errors) = self.validate_arguments(function_name, args, tool_registry) {
+            tracing::info!(tool = function_name, errors = errors.len(), "tool arguments failed validation");
+            invalid_arguments(function_name, errors)
+        } else if let Err(denial) = tool_rate::admit(
+            self.config.tool_rate_limits(),
+            function_name == "run_command" || tool_registry.tools.contains_key(function_name),
+        ) {
+            tracing::warn!(tool = function_name, policy = %denial.policy, "tool call rate limited");
+            renderer.render(&AgentEvent::Notice { message: &denial.reason });
+            denial.to_tool_result()
+        } else if function_name == "run_command" {
+            // Legacy built-in command execution
+            let command = args["command"].as_str()
+                .ok_or_else(|| anyhow::anyhow!("Invalid command argument"))?;
+
+            renderer.render(&AgentEvent::Command { command });
+
+            // `@timeout 30s <command>` replaces the tool's time limit
+            let (limit, command) = match timeout::split_prefix(command) {
+                Ok(Some((limit, command))) => (Some(limit), command),
+                Ok(None) => (None, command),
+                Err(e) => return Ok(json!({ "error": "invalid_timeout", "message": e.to_string() }).to_string()),
+            };
+
+            if let Err(e) = self.config.check_nesting(command) {
+                renderer.render(&AgentEvent::CommandBlocked { command, reason: &e.to_string() });
+                Denial::new("shell.max_nesting", None, e.to_string()).to_tool_result()
+            } else {
+                match ts_runtime::guard::authorize(command) {
+                    Err(denial) => {
+                        let rule = denial.rule.as_deref().unwrap_or(&denial.policy);
+                        renderer.render(&AgentEvent::CommandBlocked { command, reason: &format!("matched {}", rule) });
+                        denial.to_tool_result()
+                    }
+                    Ok(approved) => {
+                        let (timeout, max_output) = self.config.tool_limits(function_name, None);
+                        spinner.show(format!("running {}", approved));
+                        let output = self.execute_command(&approved, limit.unwrap_or(timeout), max_output);
+                        spinner.hide();
+                        if approved == command {
+                            output?
+                        } else {
+                            format!("The user edited the command before approving it and ran: {}\n{}", approved, output?)
+                        }
+                    }
+                }
+            }
+        } else if function_name == "change_directory" {
+            renderer.render(&AgentEvent::Tool { name: function_name });
+            match self.change_directory(args) {
+                Ok(result) => result,
+                Err(e) => format!("cd failed: {}", e),
+            }
+        } else if function_name == "search_files" {
+            renderer.render(&AgentEvent::Tool { name: function_name });
+            let (timeout, max_output) = self.config.tool_limits(function_name, None);
+            spinner.show(format!("searching for {}", args["pattern"].as_str().unwrap_or_default()));
+            let result = search::search_files(args, &state::current_dir(), timeout);
+            spinner.hide();
+            match result {
+                Ok(result) => truncate_output(serde_json::to_string_pretty(&result)?, max_output),
+                Err(e) => json!({ "error": "search_failed", "message": e.to_string() }).to_string(),
+            }
+        } else if function_name == "write_file" {
+            renderer.render(&AgentEvent::Tool { name: function_name });
+            if !self.confirm_file_change(function_name, args, renderer) {
+                Denial::new("user", None, format!("The user rejected the file change from {}", function_name)).to_tool_result()
+            } else {
+                write_file(args)
+            }
+        } else if function_name == "apply_patch" {
+            renderer.render(&AgentEvent::Tool { name: function_name });
+            let approved = self.confirm_patch(args, renderer);
+            if !approved.contains(&true) {
+                Denial::new("user", None, format!("The user rejected the file change from {}", function_name)).to_tool_result()
+            } else {
+                apply_patch(args, &approved)
+            }
+        } else if function_name == "search_project" {
+            renderer.render(&AgentEvent::Tool { name: function_name });
+            let (_, max_output) = self.config.tool_limits(function_name, None);
+            spinner.show(format!("searching the project for {}", args["query"].as_str().unwrap_or_default()));
+            let result = project_index::query(&self.config, &state::current_dir(), args).await;
+            spinner.hide();
+            match result {
+                Ok(result) => truncate_output(serde_json::to_string_pretty(&result)?, max_output),
+                Err(e) => json!({ "error": "search_failed", "message": e.to_string() }).to_string(),
+            }
+        } else if function_name == "plan" {
+            match serde_json::from_value::<Vec<PlanStep>>(args["steps"].clone()) {
+                Ok(steps) => {
+                    renderer.render(&AgentEvent::Plan { steps });
+                    "Plan shown to the user".to_string()
+                }
+                Err(e) => format!("Invalid plan: {}", e),
+            }
+        } else if function_name == "propose_config_change" {
+            match self.propose_config_change(args, ts_config_loader, renderer).await {
+                Ok(result) => result,
+                Err(e) => format!("Configuration change failed: {}", e),
+            }
+        } else if tool_registry.tools.get(function_name).is_some_and(|tool| tool.writes_files)
+            && !self.confirm_file_change(function_name, args, renderer)
+        {
+            Denial::new("user", None, format!("The user rejected the file change from {}", function_name)).to_tool_result()
+        } else if tool_registry.tools.contains_key(function_name) {
+            // TypeScript-defined tool
+            renderer.render(&AgentEvent::Tool { name: function_name });
+            spinner.show(format!("running tool {}", function_name));
+            let declared = tool_registry.tools.get(function_name).map(|tool| &tool.limits);
+            let limits = self.config.tool_limits(function_name, declared);
+            let output = self.call_ts_tool(function_name, args, ts_config_loader, limits).await;
+            spinner.hide();
+            output?
+        } else {
+            format!("Unknown tool: {}", function_name)
+        })
+    }
+
+    /// Check `args` against the tool's declared parameters schema, returning
+    /// each violation as `{path, message}`. Tools without a usable schema
+    /// (unknown, degraded or with an invalid schema) are not checked here.
+    fn validate_arguments(&self, function_name: &str, args: &Value, tool_registry: &ts_runtime::ToolRegistry) -> std::result::Result<(), Vec<Value>> {
+        let Some(schema) = self.tool_schemas(tool_registry)
+            .into_iter()
+            .find(|tool| tool["function"]["name"] == function_name)
+            .map(|tool| tool["function"]["parameters"].clone())
+        else {
+            return Ok(());
+        };
+        let validator = match jsonschema::validator_for(&schema) {
+            Ok(validator) => validator,
+            Err(e) => {
+                tracing::warn!(tool = function_name, error = %e, "invalid parameters schema; arguments not validated");
+                return Ok(());
+            }
+        };
+        let errors: Vec<Value> = validator.iter_errors(args)
+            .map(|error| json!({ "path": error.instance_path.to_string(), "message": error.to_string() }))
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Function-calling schemas for the built-in tools and those in
+    /// `tool_registry` that the agent may currently use
+    pub fn tool_schemas(&self, tool_registry: &ts_runtime::ToolRegistry) -> Vec<Value> {
+        // Start with built-in run_command tool
+        let mut tools = vec![json!({
+            "type": "function",
+            "function": {
+                "name": "run_command",
+                "description": "Execute a shell command and return the output. Prefix it with `@timeout DURATION` (e.g. `@timeout 10m make test`) to change how long it may run",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "command": {
+                            "type": "string",
+                            "description": "The shell command to execute"
+                        }
+                    },
+                    "required": ["command"]
+                }
+            }
+        }), json!({
+            "type": "function",
+            "function": {
+                "name": "change_directory",
+                "description": "Change the shell's working directory. Unlike `cd` inside run_command, this persists for later commands and for the user after the conversation ends.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "Directory to change to, absolute or relative to the current directory (~ is the home directory)"
+                        }
+                    },
+                    "required": ["path"]
+                }
+            }
+        }), json!({
+            "type": "function",
+            "function": {
+                "name": "search_files",
+                "description": "Search file contents for a regular expression, recursively, skipping .gitignored, hidden and binary files. Returns matches as {file, line, snippet}. Prefer this to running grep or find.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "pattern": {
+                            "type": "string",
+                            "description": "Regular expression (Rust regex syntax) matched against each line"
+                        },
+                        "path": {
+                            "type": "string",
+                            "description": "File or directory to search (defaults to the current directory)"
+                        },
+                        "glob": {
+                            "type": "array",
+                            "items": { "type": "string" },
+                            "description": "Only search files matching these globs, e.g. [\"*.rs\"]; prefix with ! to exclude"
+                        },
+                        "case_insensitive": {
+                            "type": "boolean",
+                            "description": "Match without regard to case (default false)"
+                        },
+                        "max_results": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": "Most matches to return (default 100)"
+                        }
+                    },
+                    "required": ["pattern"]
+                }
+            }
+        }), json!({
+            "type": "function",
+            "function": {
+                "name": "search_project",
+                "description": "Find the parts of the current project most related in meaning to a question, from the index built with `index build`. Returns chunks as {file, start_line, end_line, score, text}. Use it to ground answers about the codebase; use search_files for exact text.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "query": {
+                            "type": "string",
+                            "description": "What to look for, in natural language or code terms"
+                        },
+                        "max_results": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": "Most chunks to return (default 8)"
+                        }
+                    },
+                    "required": ["query"]
+                }
+            }
+        }), json!({
+            "type": "function",
+            "function": {
+                "name": "write_file",
+                "description": "Create a file or replace its whole content. Prefer apply_patch for changes to part of an existing file. The user sees a diff and must approve it.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "File to write, absolute or relative to the current directory; missing parent directories are created"
+                        },
+                        "content": {
+                            "type": "string",
+                            "description": "The complete new content of the file"
+                        }
+                    },
+                    "required": ["path", "content"]
+                }
+            }
+        }), json!({
+            "type": "function",
+            "function": {
+                "name": "apply_patch",
+                "description": "Edit files by applying a unified diff (`--- a/path`, `+++ b/path`, `@@` hunks with a few lines of context). Use `--- /dev/null` to create a file and `+++ /dev/null` to delete one. Either every approved file is changed or none is. The user sees the patch and must approve it; with several files they may approve only some, listed as rejected_by_user in the result.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "patch": {
+                            "type": "string",
+                            "description": "The unified diff; paths are relative to the current directory"
+                        }
+                    },
+                    "required": ["patch"]
+                }
+            }
+        }), json!({
+            "type": "function",
+            "function": {
+                "name": "plan",
+                "description": "Show the user your plan for a multi-step task as a checklist. Call it before starting, then again with the whole list and updated statuses whenever a step starts, finishes or fails.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "steps": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "step": {
+                                        "type": "string",
+                                        "description": "Short description of the step"
+                                    },
+                                    "status": {
+                                        "type": "string",
+                                        "enum": ["pending", "in_progress", "done", "failed"]
+                                    }
+                                },
+                                "required": ["step", "status"]
+                            },
+                            "description": "Every step of the plan, in order"
+                        }
+                    },
+                    "required": ["steps"]
+                }
+            }
+        }), json!({
+            "type": "function",
+            "function": {
+                "name": "propose_config_change",
+                "description": "Propose a change to the user's aish configuration file (.aish.ts), e.g. to add tools, commands or hooks. The user sees a diff and must approve it; the new configuration is validated before it is written, and aish reloads it after your reply, so changes apply from the next prompt.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "new_content": {
+                            "type": "string",
+                            "description": "The complete new contents of the configuration file"
+                        },
+                        "reason": {
+                            "type": "string",
+                            "description": "Short explanation of what the change does"
+                        }
+                    },
+                    "required": ["new_content", "reason"]
+                }
+            }
+        })];
+        
+        // Add TypeScript-defined tools
+        for tool in tool_registry.tools.values() {
+            tools.push(json!({
+                "type": "function",
+                "function": {
+                    "name": tool.name,
+                    "description": tool.description,
+                    "parameters": tool.parameters
+                }
+            }));
+        }
+        
+        // What the active profile allows, minus tools that keep failing
+        tools.retain(|tool| {
+            tool["function"]["name"].as_str().is_some_and(|name| self.tool_allowed(name) && !self.tool_degraded(name))
+        });
+        tools
+    }
+
+    async fn make_openai_request(
+        &self,
+        messages: &[ChatMessage],
+        settings: &RequestSettings,
+        tool_registry: Option<&ts_runtime::ToolRegistry>,
+    ) -> Result<OpenAIResponse> {
+        let RequestSettings { api_key, model, base_url, temperature, max_tokens, top_p, seed } = settings;
+        let mut request_body = json!({
+            "model": model,
+            "messages": messages,
+            "temperature": temperature,
+            "max_tokens": max_tokens
+        });
+        // Not every provider accepts these, so they are only sent when asked for
+        if let Some(top_p) = top_p {
+            request_body["top_p"] = json!(top_p);
+        }
+        if let Some(seed) = seed {
+            request_body["seed"] = json!(seed);
+        }
+        if let Some(tool_registry) = tool_registry {
+            request_body["tools"] = json!(self.tool_schemas(tool_registry));
+            request_body["tool_choice"] = json!("auto");
+        }
+
+        let max_attempts = self.config.ai.as_ref()
+            .and_then(|ai| ai.max_attempts)
+            .unwrap_or(ts_runtime::DEFAULT_MAX_ATTEMPTS)
+            .max(1);
+
+        let cache_ttl = self.config.ai.as_ref()
+            .and_then(|ai| ai.cache_ttl_secs)
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs);
+        let cache_key = cache_ttl.map(|_| response_cache::key(base_url, &request_body));
+        if let (Some(key), Some(ttl)) = (&cache_key, cache_ttl) {
+            if let Some(cached) = response_cache::lookup(key, ttl).and_then(|body| serde_json::from_str::<OpenAIResponse>(&body).ok()) {
+                tracing::info!(%model, "API response from cache");
+                return Ok(cached);
+            }
+        }
+
+        let client = self.client.as_ref()
+            .map_err(|e| anyhow::anyhow!("{}; fix it with `config edit` to use the agent", e))?;
+        let mut attempt = 1;
+        let (response, started) = loop {
+            tracing::info!(%model, %base_url, api_key = %logging::redact(api_key), attempt, messages = messages.len(), "API request");
+            let started = Instant::now();
+            let result = client
+                .post(&format!("{}/chat/completions", base_url))
+                .header("Authorization", format!("Bearer {}", api_key))
+                .header("Content-Type", "application/json")
+                .json(&request_body)
+                .send()
+                .await;
+
+            let retry_delay = match result {
+                Ok(response) if response.status().is_success() => break (response, started),
+                Ok(response) => {
+                    let status = response.status();
+                    provider_health::record(base_url, started.elapsed(), provider_health::RequestOutcome::HttpError(status.as_u16()), Some(format!("HTTP {}", status)));
+                    let retryable = status.as_u16() == 429 || status.is_server_error();
+                    tracing::warn!(status = status.as_u16(), elapsed = ?started.elapsed(), "API request failed");
+                    if !retryable || attempt >= max_attempts {
+                        let error_text = response.text().await?;
+                        return Err(anyhow::anyhow!("OpenAI API error: {}", error_text));
+                    }
+                    retry_after(&response).unwrap_or_else(|| backoff_delay(attempt))
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, elapsed = ?started.elapsed(), "API request failed");
+                    provider_health::record(base_url, started.elapsed(), provider_health::RequestOutcome::NetworkError, Some(e.to_string()));
+                    let retryable = e.is_timeout() || e.is_connect() || e.is_request();
+                    if !retryable || attempt >= max_attempts {
+                        return Err(e.into());
+                    }
+                    backoff_delay(attempt)
+                }
+            };
+
+            {
+                // Off the spinner's line; it comes back on the next one
+                let _paused = crate::spinner::pause();
+                eprintln!("{}", ui::paint(Role::Warning, &format!(
+                    "AI request failed, retrying in {} (attempt {}/{})", humanize::duration(retry_delay), attempt + 1, max_attempts
+                )));
+            }
+            tokio::time::sleep(retry_delay).await;
+            attempt += 1;
+        };
+
+        let body = response.text().await?;
+        let openai_response: OpenAIResponse = serde_json::from_str(&body)?;
+        if let (Some(key), Some(ttl)) = (&cache_key, cache_ttl) {
+            response_cache::store(key, &body, ttl);
+        }
+        tracing::info!(
+            elapsed = ?started.elapsed(),
+            tokens = openai_response.usage.as_ref().map(|u| u.total_tokens),
+            "API response"
+        );
+        if let Some(token_usage) = &openai_response.usage {
+            usage::record(model, token_usage);
+        }
+        provider_health::record(base_url, started.elapsed(), provider_health::RequestOutcome::Success, None);
+        Ok(openai_response)
+    }
+
+    /// Show a proposed `.aish.ts` rewrite as a diff, and write it only after the
+    /// user approves and the new script loads cleanly.
+    async fn propose_config_change(&self, args: &Value, ts_config_loader: &ts_runtime::TypeScriptConfigLoader, renderer: &dyn Renderer) -> Result<String> {
+        let new_content = args["new_content"].as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid new_content argument"))?;
+        let reason = args["reason"].as_str().unwrap_or("no reason given");
+        let config_path = ts_config_loader.script_path();
+        let config_dir = config_path.parent()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        // Stage the proposal next to the real config so relative imports still resolve
+        let mut staged = tempfile::Builder::new()
+            .prefix(".aish-proposed-")
+            .suffix(".ts")
+            .tempfile_in(&config_dir)?;
+        staged.write_all(new_content.as_bytes())?;
+        staged.flush()?;
+
+        let diff = match platform::command("diff").arg("-u").arg(config_path).arg(staged.path()).output() {
+            Ok(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+            Err(_) => new_content.to_string(),
+        };
+        renderer.render(&AgentEvent::ConfigProposal {
+            path: &config_path.display().to_string(),
+            reason,
+            diff: &diff,
+        });
+
+        let decision = approval::ask(&approval::ApprovalRequest {
+            question: "Apply this change?",
+            subject: &config_path.display().to_string(),
+            details: Some(&diff),
+            editable: false,
+            always_key: None,
+            default_approve: false,
+            risky: true,
+        });
+        if !decision.approved() {
+            return Ok(Denial::new("user", None, "The user rejected the configuration change").to_tool_result());
+        }
+
+        let validator = ts_runtime::TypeScriptConfigLoader::with_path(staged.path().to_path_buf());
+        if let Err(e) = validator.load_config().await {
+            renderer.render(&AgentEvent::Notice { message: "Proposed configuration failed validation, not applied" });
+            return Ok(format!("The proposed configuration failed to load and was not applied: {}", e));
+        }
+
+        std::fs::write(config_path, new_content)?;
+        CONFIG_CHANGED.store(true, Ordering::Relaxed);
+        renderer.render(&AgentEvent::Notice { message: &format!("Configuration updated: {}", config_path.display()) });
+        Ok("The configuration change was approved and written. aish reloads it when this reply is finished: new tools, commands, hooks and AI settings apply from the next prompt, not to the rest of this one.".to_string())
+    }
+
+    /// Preview what a file-writing tool call would change and ask the user
+    /// to confirm it; "always" covers later calls to the same tool
+    fn confirm_file_change(&self, tool: &str, args: &Value, renderer: &dyn Renderer) -> bool {
+        let preview = file_change::preview(args, &state::current_dir());
+        renderer.render(&AgentEvent::FileChange { tool, path: &preview.path, diff: &preview.diff });
+        approval::ask(&approval::ApprovalRequest {
+            question: "Apply this file change?",
+            subject: &format!("{} {}", tool, preview.path),
+            details: Some(&preview.diff),
+            editable: false,
+            always_key: Some(format!("writes_files:{}", tool)),
+            default_approve: false,
+            risky: true,
+        })
+        .approved()
+    }
+
+    /// Preview an `apply_patch` call and ask the user to confirm it, file by
+    /// file when it touches several. Returns one answer per file in the
+    /// patch (a single answer when it doesn't parse).
+    fn confirm_patch(&self, args: &Value, renderer: &dyn Renderer) -> Vec<bool> {
+        let files = match patch::parse(args["patch"].as_str().unwrap_or_default()) {
+            Ok(files) if files.len() > 1 => files,
+            _ => return vec![self.confirm_file_change("apply_patch", args, renderer)],
+        };
+        let preview = file_change::preview(args, &state::current_dir());
+        renderer.render(&AgentEvent::FileChange { tool: "apply_patch", path: &preview.path, diff: &preview.diff });
+        let items: Vec<approval::ApprovalItem> = files.into_iter()
+            .map(|file| approval::ApprovalItem { label: file.path, details: Some(file.diff) })
+            .collect();
+        approval::ask_each(&approval::ApprovalRequest {
+            question: "Apply these file changes?",
+            subject: &format!("apply_patch {}", preview.path),
+            details: Some(&preview.diff),
+            editable: false,
+            always_key: Some("writes_files:apply_patch".to_string()),
+            default_approve: false,
+            risky: true,
+        }, &items)
+    }
+
+    /// Move the shell to another directory on the agent's behalf, subject to
+    /// `ai.agent_cd_policy` ("ask" by default, "allow" or "deny").
+    fn change_directory(&self, args: &Value) -> Result<String> {
+        let current_dir = state::current_dir();
+        let path = args["path"].as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid path argument"))?;
+
+        let target = platform::expand_home(path).unwrap_or_else(|| current_dir.join(path));
+        let target = target.canonicalize()
+            .map_err(|e| anyhow::anyhow!("{}: {}", target.display(), e))?;
+        if !target.is_dir() {
+            return Err(anyhow::anyhow!("{}: Not a directory", target.display()));
+        }
+
+        let policy = self.config.ai.as_ref()
+            .and_then(|ai| ai.agent_cd_policy.as_deref())
+            .unwrap_or("ask");
+        let approved = match policy {
+            "allow" => true,
+            "deny" => false,
+            _ => approval::ask(&approval::ApprovalRequest {
+                question: "Allow the agent to change directory?",
+                subject: &format!("$ cd {}", target.display()),
+                details: None,
+                editable: false,
+                always_key: Some("agent_cd".to_string()),
+                default_approve: false,
+                risky: true,
+            })
+            .approved(),
+        };
+        if !approved {
+            let reason = format!("Changing directory was not allowed; still in {}", current_dir.display());
+            let denial = if policy == "deny" {
+                Denial::new("ai.agent_cd_policy", Some("deny".to_string()), reason)
+            } else {
+                Denial::new("user", None, reason)
+            };
+            return Ok(denial.to_tool_result());
+        }
+
+        let new_dir = state::set_current_dir(&target)?;
+        Ok(format!("Changed directory to {}", new_dir.display()))
+    }
+
+    /// Run an agent command with sh, killing it after `timeout` and keeping
+    /// at most `max_output` bytes of each of stdout and stderr
+    fn execute_command(&self, command: &str, timeout: Duration, max_output: usize) -> Result<String> {
+        if self.config.safe_rm() {
+            if let Some(args) = trash::plain_rm_args(command) {
+                let cwd = state::current_dir();
+                let (status, messages) = trash::rm(&pathname::expand(&args, &cwd), &cwd);
+                let mut result = if messages.is_empty() {
+                    "Moved to the trash; the user can bring it back with `restore`".to_string()
+                } else {
+                    format!("STDERR: {}", messages)
+                };
+                if status != 0 {
+                    result.push_str(&format!("\nExit code: {}", status));
+                }
+                return Ok(result);
+            }
+        }
+        let script = if self.config.safe_rm() { trash::route_rm(command) } else { command.to_string() };
+
+        let mut child = platform::shell_command(&script);
+        child.current_dir(state::current_dir())
+            .envs(session_var_env())
+            .envs(state::child_env())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // Its own process group, so a timeout kills everything it started
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut child, 0);
+        let mut child = child.spawn()?;
+        tool_rate::track(child.id());
+        let stdout = child.stdout.take().map(|out| std::thread::spawn(move || read_capped(out, max_output)));
+        let stderr = child.stderr.take().map(|err| std::thread::spawn(move || read_capped(err, max_output)));
+
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break Some(status);
+            }
+            if started.elapsed() >= timeout {
+                #[cfg(unix)]
+                unsafe {
+                    libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                }
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        tracing::info!(%command, exit_code = status.and_then(|s| s.code()), timed_out = status.is_none(), "agent command");
+
+        let (stdout, stdout_total) = stdout.and_then(|reader| reader.join().ok()).unwrap_or_default();
+        let (stderr, stderr_total) = stderr.and_then(|reader| reader.join().ok()).unwrap_or_default();
+        let stdout = String::from_utf8_lossy(&stdout);
+        let stderr = String::from_utf8_lossy(&stderr);
+
+        let mut result = String::new();
+        if !stdout.is_empty() {
+            result.push_str(&stdout);
+            if stdout_total > max_output {
+                result.push_str(&truncation_marker(max_output, stdout_total));
+            }
+        }
+        if !stderr.is_empty() {
+            if !result.is_empty() {
+                result.push('\n');
+            }
+            result.push_str("STDERR: ");
+            result.push_str(&stderr);
+            if stderr_total > max_output {
+                result.push_str(&truncation_marker(max_output, stderr_total));
+            }
+        }
+
+        // Also show the command exit status if it failed
+        let failure = match status {
+            Some(status) if status.success() => None,
+            Some(status) => Some(format!("Exit code: {}", status.code().unwrap_or(-1))),
+            None => Some(format!(
+                "Timed out after {}; the command was killed (exit status {}). Prefix it with `@timeout DURATION` \
+                 or raise ai.tool_limits.run_command.timeoutMs to allow longer",
+                humanize::duration(timeout), timeout::TIMED_OUT_STATUS)),
+        };
+        if let Some(failure) = failure {
+            if !result.is_empty() {
+                result.push('\n');
+            }
+            result.push_str(&failure);
+        }
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,359 @@
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use std::collections::HashSet;
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{notify, share, spinner};
+use crate::ui::{self, Role};
+
+/// How long `ai.approval_command` may take to answer before it is killed
+pub const EXTERNAL_APPROVAL_TIMEOUT: Duration = Duration::from_secs(300);
+
+lazy_static::lazy_static! {
+    // Keys the user answered "always" for, approved without asking for the rest of the session
+    static ref ALWAYS_APPROVED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    // `ai.approval_command`, which must agree with every approval
+    static ref EXTERNAL_APPROVER: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// Set (or clear) the command every approval is also put to
+pub fn set_external_approver(command: Option<String>) {
+    if let Ok(mut approver) = EXTERNAL_APPROVER.lock() {
+        *approver = command;
+    }
+}
+
+/// How the user answered an approval prompt
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    Approve,
+    /// Approved, along with later requests sharing the same `always_key`
+    Always,
+    Deny,
+    /// Approved after the user edited the subject
+    Edited(String),
+}
+
+impl Decision {
+    pub fn approved(&self) -> bool {
+        !matches!(self, Decision::Deny)
+    }
+}
+
+/// A single yes/no question, e.g. whether to run a command
+pub struct ApprovalRequest<'a> {
+    /// What is being asked, e.g. "Run it anyway?"
+    pub question: &'a str,
+    /// The thing being approved, shown indented and offered for editing with `e`
+    pub subject: &'a str,
+    /// Extra information shown with `v`, such as a diff
+    pub details: Option<&'a str>,
+    pub editable: bool,
+    /// Enables `a`: answering "always" approves every later request with this key
+    pub always_key: Option<String>,
+    /// Answer used for Enter
+    pub default_approve: bool,
+    /// Whether this approves an action with side effects (running a
+    /// command, changing files, granting access); only those also go to
+    /// `ai.approval_command`
+    pub risky: bool,
+}
+
+/// One entry in a multi-item approval
+pub struct ApprovalItem {
+    pub label: String,
+    pub details: Option<String>,
+}
+
+/// Ask for approval of `request` with single-key answers:
+/// y(es), n(o), a(lways), v(iew details), e(dit), Enter for the default.
+/// Without a terminal nothing can be approved, so the answer is Deny.
+/// While the session is shared for co-approval, an approval only stands
+/// once a viewer approves too (see [`share::co_approve`]), and a risky one
+/// with `ai.approval_command` once that command approves as well.
+pub fn ask(request: &ApprovalRequest) -> Decision {
+    let _spinner = spinner::pause();
+    let approver = EXTERNAL_APPROVER.lock().ok()
+        .and_then(|approver| approver.clone())
+        .filter(|_| request.risky);
+    let decision = ask_user(request);
+    let subject = match &decision {
+        Decision::Edited(edited) => edited.as_str(),
+        _ => request.subject,
+    };
+    if !decision.approved() || !share::co_approve(request, subject) {
+        return Decision::Deny;
+    }
+    if let Some(approver) = approver {
+        if !external_approval(&approver, request, subject) {
+            return Decision::Deny;
+        }
+    }
+    decision
+}
+
+/// Run the approval command with the request as JSON on stdin; exit status 0
+/// approves. Its output goes to the terminal so it can say what it waits on.
+/// One still running after [`EXTERNAL_APPROVAL_TIMEOUT`] is killed, denying.
+fn external_approval(approver: &str, request: &ApprovalRequest, subject: &str) -> bool {
+    let payload = serde_json::json!({
+        "question": request.question,
+        "subject": subject,
+        // Command approvals show the command as `$ <command>`
+        "command": subject.strip_prefix("$ "),
+        "details": request.details,
+        "cwd": crate::state::current_dir(),
+        "user": std::env::var("USER").ok(),
+        "pid": std::process::id(),
+    });
+    println!("{}", ui::paint(Role::Muted, &format!("   waiting for {}…", approver)));
+    let child = crate::platform::shell_command(approver)
+        .current_dir(crate::state::current_dir())
+        .envs(crate::state::child_env())
+        .stdin(std::process::Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            println!("{}", ui::paint(Role::Error, &format!("   approval command failed to start: {}", e)));
+            return false;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = writeln!(stdin, "{}", payload);
+    }
+    let deadline = Instant::now() + EXTERNAL_APPROVAL_TIMEOUT;
+    let approved = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status.success(),
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(100)),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                println!("{}", ui::paint(Role::Error, &format!(
+                    "   {} gave no answer within {}", approver, crate::humanize::duration(EXTERNAL_APPROVAL_TIMEOUT)
+                )));
+                break false;
+            }
+            Err(_) => break false,
+        }
+    };
+    tracing::info!(approver, subject, approved, "external approval");
+    if approved {
+        println!("{}", ui::paint(Role::Success, &format!("   approved by {}", approver)));
+    } else {
+        println!("{}", ui::paint(Role::Error, &format!("   denied by {}", approver)));
+    }
+    approved
+}
+
+/// Whether the user already answered "always" for the request's key
+fn approved_for_session(request: &ApprovalRequest) -> bool {
+    let Some(key) = &request.always_key else {
+        return false;
+    };
+    let approved = ALWAYS_APPROVED.lock().map(|keys| keys.contains(key)).unwrap_or(false);
+    if approved {
+        println!("{}", ui::paint(Role::Muted, &format!("   {} (approved for this session)", request.subject)));
+    }
+    approved
+}
+
+fn ask_user(request: &ApprovalRequest) -> Decision {
+    if approved_for_session(request) {
+        return Decision::Approve;
+    }
+    if !io::stdin().is_terminal() {
+        return Decision::Deny;
+    }
+
+    println!("   {}", request.subject);
+    notify::approval_needed(request.question, request.subject);
+    loop {
+        print!("{} {}", ui::paint(Role::Warning, request.question), legend(request));
+        let _ = io::stdout().flush();
+
+        let Some(key) = read_key() else {
+            println!();
+            return Decision::Deny;
+        };
+        let decision = match key.code {
+            KeyCode::Char('y') | KeyCode::Char('Y') => Decision::Approve,
+            KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => Decision::Deny,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Decision::Deny,
+            KeyCode::Enter if request.default_approve => Decision::Approve,
+            KeyCode::Enter => Decision::Deny,
+            KeyCode::Char('a') if request.always_key.is_some() => {
+                if let (Some(key), Ok(mut keys)) = (&request.always_key, ALWAYS_APPROVED.lock()) {
+                    keys.insert(key.clone());
+                }
+                Decision::Always
+            }
+            KeyCode::Char('v') if request.details.is_some() => {
+                println!();
+                println!("{}", request.details.unwrap_or_default().trim_end());
+                continue;
+            }
+            KeyCode::Char('e') if request.editable => {
+                println!();
+                match edit_line(request.subject) {
+                    Some(edited) if !edited.trim().is_empty() => Decision::Edited(edited.trim().to_string()),
+                    _ => continue,
+                }
+            }
+            _ => {
+                println!();
+                continue;
+            }
+        };
+
+        if !matches!(decision, Decision::Edited(_)) {
+            println!("{}", if decision.approved() { "yes" } else { "no" });
+        }
+        return decision;
+    }
+}
+
+/// Approve or reject each of `items`, which together make up `request`
+/// (its question, default and "always" key apply to all of them). The
+/// approved items are then put to co-approval and, for a risky request,
+/// `ai.approval_command` as one subject, like [`ask`]; if either denies,
+/// every answer is false. Returns one answer per item.
+pub fn ask_each(request: &ApprovalRequest, items: &[ApprovalItem]) -> Vec<bool> {
+    let _spinner = spinner::pause();
+    let approver = EXTERNAL_APPROVER.lock().ok()
+        .and_then(|approver| approver.clone())
+        .filter(|_| request.risky);
+    let answers = if approved_for_session(request) {
+        vec![true; items.len()]
+    } else {
+        ask_user_each(request.question, items, request.default_approve)
+    };
+    let subject = items.iter().zip(&answers)
+        .filter(|(_, approved)| **approved)
+        .map(|(item, _)| item.label.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if subject.is_empty() {
+        return answers;
+    }
+    if !share::co_approve(request, &subject)
+        || approver.is_some_and(|approver| !external_approval(&approver, request, &subject))
+    {
+        return vec![false; items.len()];
+    }
+    answers
+}
+
+/// Up/Down move between items, y/n (or Space to toggle) decide the
+/// highlighted one, a approves all, v shows its details, Enter confirms and
+/// Esc rejects everything. All false without a terminal.
+fn ask_user_each(question: &str, items: &[ApprovalItem], default_approve: bool) -> Vec<bool> {
+    let mut answers = vec![default_approve; items.len()];
+    if items.is_empty() {
+        return answers;
+    }
+    if !io::stdin().is_terminal() {
+        return vec![false; items.len()];
+    }
+
+    println!("{}", ui::paint(Role::Warning, question));
+    notify::approval_needed(question, &format!("{} items", items.len()));
+    let (dim, key, reset) = (ui::style(Role::Muted), ui::style(Role::Accent), ui::reset());
+    println!(
+        "{}  {}↑/↓{} move  {}y/n{} decide  {}space{} toggle  {}a{} all  {}v{} details  {}enter{} confirm{}",
+        dim, key, dim, key, dim, key, dim, key, dim, key, dim, key, dim, reset
+    );
+
+    let mut cursor = 0;
+    let mut drawn = 0;
+    loop {
+        drawn = redraw(items, &answers, cursor, drawn);
+
+        let Some(key) = read_key() else {
+            return vec![false; items.len()];
+        };
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => cursor = cursor.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => cursor = (cursor + 1).min(items.len() - 1),
+            KeyCode::Char('y') => {
+                answers[cursor] = true;
+                cursor = (cursor + 1).min(items.len() - 1);
+            }
+            KeyCode::Char('n') => {
+                answers[cursor] = false;
+                cursor = (cursor + 1).min(items.len() - 1);
+            }
+            KeyCode::Char(' ') => answers[cursor] = !answers[cursor],
+            KeyCode::Char('a') => answers.iter_mut().for_each(|answer| *answer = true),
+            KeyCode::Char('v') => {
+                if let Some(details) = &items[cursor].details {
+                    println!("{}", details.trim_end());
+                    drawn = 0;
+                }
+            }
+            KeyCode::Enter => return answers,
+            KeyCode::Esc => return vec![false; items.len()],
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return vec![false; items.len()];
+            }
+            _ => {}
+        }
+    }
+}
+
+fn legend(request: &ApprovalRequest) -> String {
+    let mut keys = vec![if request.default_approve { "Y/n" } else { "y/N" }];
+    if request.always_key.is_some() {
+        keys.push("a");
+    }
+    if request.details.is_some() {
+        keys.push("v");
+    }
+    if request.editable {
+        keys.push("e");
+    }
+    format!("{} ", ui::paint(Role::Accent, &format!("[{}]", keys.join("/"))))
+}
+
+/// Draw the item list, replacing the `previous` lines drawn last time.
+/// Returns the number of lines drawn.
+fn redraw(items: &[ApprovalItem], answers: &[bool], cursor: usize, previous: usize) -> usize {
+    let mut out = io::stdout().lock();
+    if previous > 0 {
+        let _ = write!(out, "\x1b[{}A", previous);
+    }
+    for (index, (item, approved)) in items.iter().zip(answers).enumerate() {
+        let pointer = if index == cursor { ">" } else { " " };
+        let mark = if *approved {
+            ui::paint(Role::Success, "[x]")
+        } else {
+            ui::paint(Role::Error, "[ ]")
+        };
+        let _ = writeln!(out, "\x1b[2K{} {} {}", pointer, mark, item.label);
+    }
+    let _ = out.flush();
+    items.len()
+}
+
+/// Read one key press in raw mode, so answers don't need Enter
+fn read_key() -> Option<KeyEvent> {
+    terminal::enable_raw_mode().ok()?;
+    let key = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => break Some(key),
+            Ok(_) => continue,
+            Err(_) => break None,
+        }
+    };
+    let _ = terminal::disable_raw_mode();
+    key
+}
+
+/// Let the user edit `initial` on a readline prompt
+fn edit_line(initial: &str) -> Option<String> {
+    let mut editor = rustyline::DefaultEditor::new().ok()?;
+    editor.readline_with_initial("edit> ", (initial, "")).ok()
+}
@@ -0,0 +1,146 @@
+//! Output of commands the user runs, teed: copied to the terminal as it
+//! arrives and the tail kept in a bounded buffer, so the agent can see what
+//! `$ cargo build` printed when asked to "fix those errors". Stderr is
+//! captured, and stdout only when `shell.capture_stdout` asks for it, since
+//! a piped stdout is no terminal. Programs that need a terminal (editors,
+//! pagers, REPLs) aren't captured at all: REPLs prompt on stderr and
+//! full-screen programs check it too.
+
+use regex::Regex;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::jobs;
+
+// Output kept per command; earlier bytes are dropped, since errors are usually at the end
+pub const MAX_CAPTURE_BYTES: usize = 64 * 1024;
+
+// Full-screen or interactive programs, left attached to the terminal
+const INTERACTIVE_PROGRAMS: &[&str] = &[
+    "vi", "vim", "nvim", "nano", "emacs", "micro", "hx", "less", "more", "most", "man", "top", "htop", "btop",
+    "watch", "ssh", "mosh", "tmux", "screen", "fzf", "tig", "lazygit", "k9s",
+];
+// Programs that are REPLs when run without arguments
+const REPLS: &[&str] = &["python", "python3", "node", "deno", "irb", "ghci", "psql", "mysql", "sqlite3", "bash", "zsh", "sh", "fish"];
+// git subcommands that page their output on a terminal
+const GIT_PAGED: &[&str] = &["log", "diff", "show", "blame", "reflog"];
+
+lazy_static::lazy_static! {
+    static ref ANSI_ESCAPE: Regex = Regex::new(r"\x1b(\[[0-9;?]*[ -/]*[@-~]|\][^\x07\x1b]*(\x07|\x1b\\)|[@-Z\\-_])").unwrap();
+}
+
+/// Whether `command` (a program and its arguments) needs stdout to be a terminal
+pub fn wants_terminal(program: &str, args: &[&str]) -> bool {
+    let program = program.rsplit('/').next().unwrap_or(program);
+    INTERACTIVE_PROGRAMS.contains(&program)
+        || (args.is_empty() && REPLS.contains(&program))
+        || (program == "git" && args.iter().find(|arg| !arg.starts_with('-')).is_some_and(|sub| GIT_PAGED.contains(sub)))
+}
+
+/// Whether any stage of a shell pipeline or list needs a terminal on stdout
+pub fn line_wants_terminal(line: &str) -> bool {
+    line.split(['|', ';', '&', '\n', '(', ')'])
+        .any(|part| {
+            let words: Vec<&str> = part.split_whitespace().collect();
+            words.split_first().is_some_and(|(program, args)| wants_terminal(program, args))
+        })
+}
+
+/// Run `cmd` to completion, teeing its stderr (and stdout with
+/// `capture_stdout`) to the terminal and into a buffer. Returns the exit
+/// status and the captured tail, without ANSI escapes.
+pub fn run(cmd: &mut Command, capture_stdout: bool) -> io::Result<(ExitStatus, String)> {
+    run_with(cmd, capture_stdout, true)
+}
+
+/// Run `cmd` to completion without showing its output, returning the exit
+/// status and the tail of its stdout and stderr as [`run`] does
+pub fn collect(cmd: &mut Command) -> io::Result<(ExitStatus, String)> {
+    run_with(cmd, true, false)
+}
+
+fn run_with(cmd: &mut Command, capture_stdout: bool, echo: bool) -> io::Result<(ExitStatus, String)> {
+    if capture_stdout {
+        cmd.stdout(Stdio::piped());
+    }
+    cmd.stderr(Stdio::piped());
+    jobs::prepare(cmd);
+    let mut child = cmd.spawn()?;
+    let buffer = Arc::new(Mutex::new(TailBuffer::default()));
+    let mut tees = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        tees.push(tee(stdout, echo.then_some(false), buffer.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tees.push(tee(stderr, echo.then_some(true), buffer.clone()));
+    }
+    let status = jobs::wait(&mut child, cmd)?;
+    // A stopped command still holds its pipes; its tees carry on, detached,
+    // if it is continued
+    if !jobs::is_stopped(&status) {
+        for tee in tees {
+            let _ = tee.join();
+        }
+    }
+    let text = buffer.lock().map(|buffer| buffer.text()).unwrap_or_default();
+    Ok((status, text))
+}
+
+/// Append `reader` to `buffer` as it arrives, copying it to stderr
+/// (`Some(true)`) or stdout (`Some(false)`)
+fn tee(mut reader: impl Read + Send + 'static, to_stderr: Option<bool>, buffer: Arc<Mutex<TailBuffer>>) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut chunk = [0u8; 4096];
+        loop {
+            let n = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            let _ = match to_stderr {
+                Some(true) => io::stderr().write_all(&chunk[..n]),
+                Some(false) => {
+                    let mut stdout = io::stdout();
+                    stdout.write_all(&chunk[..n]).and_then(|_| stdout.flush())
+                }
+                None => Ok(()),
+            };
+            if let Ok(mut buffer) = buffer.lock() {
+                buffer.push(&chunk[..n]);
+            }
+        }
+    })
+}
+
+/// The last [`MAX_CAPTURE_BYTES`] of a command's output
+#[derive(Default)]
+struct TailBuffer {
+    bytes: VecDeque<u8>,
+    dropped: usize,
+}
+
+impl TailBuffer {
+    fn push(&mut self, chunk: &[u8]) {
+        self.bytes.extend(chunk);
+        let excess = self.bytes.len().saturating_sub(MAX_CAPTURE_BYTES);
+        if excess > 0 {
+            self.bytes.drain(..excess);
+            self.dropped += excess;
+        }
+    }
+
+    fn text(&self) -> String {
+        let bytes: Vec<u8> = self.bytes.iter().copied().collect();
+        let text = String::from_utf8_lossy(&bytes);
+        let text = ANSI_ESCAPE.replace_all(&text, "");
+        if self.dropped > 0 {
+            format!("[{} earlier bytes of output not kept]\n{}", self.dropped, text)
+        } else {
+            text.into_owned()
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! The clipboard, for `copy`/`paste` and TypeScript's clipboard ops. Locally
+//! it is the system clipboard; over SSH, or when there is no clipboard
+//! service, it goes through the terminal with OSC 52, which most terminals
+//! honour for writes and some (with a setting) for reads.
+
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+
+use crate::state;
+
+lazy_static::lazy_static! {
+    // Kept open for the whole session: on X11 and Wayland the copied text is
+    // served by whoever owns the clipboard, which is this handle
+    static ref SYSTEM: Mutex<Option<arboard::Clipboard>> = Mutex::new(None);
+}
+
+/// Put `text` on the clipboard
+pub fn write(text: &str) -> Result<()> {
+    if !is_remote() {
+        match with_system(|clipboard| clipboard.set_text(text.to_string())) {
+            Ok(()) => return Ok(()),
+            Err(e) => tracing::debug!(error = %e, "system clipboard unavailable; trying OSC 52"),
+        }
+    }
+    osc52_write(text)
+}
+
+/// The text on the clipboard
+pub fn read() -> Result<String> {
+    if !is_remote() {
+        match with_system(|clipboard| clipboard.get_text()) {
+            Ok(text) => return Ok(text),
+            Err(e) => tracing::debug!(error = %e, "system clipboard unavailable; trying OSC 52"),
+        }
+    }
+    osc52_read()
+}
+
+/// Over SSH the system clipboard is the remote machine's, not the user's
+fn is_remote() -> bool {
+    state::env_var("SSH_TTY").is_some() || state::env_var("SSH_CONNECTION").is_some()
+}
+
+fn with_system<T>(action: impl FnOnce(&mut arboard::Clipboard) -> Result<T, arboard::Error>) -> Result<T> {
+    let mut system = SYSTEM.lock().map_err(|_| anyhow!("clipboard lock poisoned"))?;
+    if system.is_none() {
+        *system = Some(arboard::Clipboard::new()?);
+    }
+    let clipboard = system.as_mut().ok_or_else(|| anyhow!("no clipboard"))?;
+    Ok(action(clipboard)?)
+}
+
+/// An OSC 52 sequence, passed through tmux when running inside it
+fn osc52(payload: &str) -> String {
+    let sequence = format!("\x1b]52;c;{}\x07", payload);
+    if state::env_var("TMUX").is_some() {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence
+    }
+}
+
+fn osc52_write(text: &str) -> Result<()> {
+    let mut stderr = io::stderr();
+    if !stderr.is_terminal() {
+        return Err(anyhow!("no clipboard available and no terminal to copy through"));
+    }
+    write!(stderr, "{}", osc52(&STANDARD.encode(text)))?;
+    stderr.flush()?;
+    Ok(())
+}
+
+/// Ask the terminal for its clipboard and wait briefly for the answer;
+/// terminals that don't allow reads never reply
+#[cfg(unix)]
+fn osc52_read() -> Result<String> {
+    use std::os::fd::AsRawFd;
+    use std::time::{Duration, Instant};
+
+    const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+    let stdin = io::stdin();
+    if !stdin.is_terminal() || !io::stderr().is_terminal() {
+        return Err(anyhow!("no clipboard available and no terminal to paste through"));
+    }
+    crossterm::terminal::enable_raw_mode()?;
+    let reply = (|| -> Result<Vec<u8>> {
+        let mut stderr = io::stderr();
+        write!(stderr, "{}", osc52("?"))?;
+        stderr.flush()?;
+
+        let fd = stdin.as_raw_fd();
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+        let mut reply = Vec::new();
+        while !reply.ends_with(b"\x07") && !reply.ends_with(b"\x1b\\") {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let mut poll = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+            if left.is_zero() || unsafe { libc::poll(&mut poll, 1, left.as_millis() as libc::c_int) } <= 0 {
+                return Err(anyhow!("the terminal did not share its clipboard (OSC 52 reads may be disabled)"));
+            }
+            let mut buffer = [0u8; 4096];
+            let read = unsafe { libc::read(fd, buffer.as_mut_ptr().cast(), buffer.len()) };
+            if read <= 0 {
+                return Err(anyhow!("no reply from the terminal"));
+            }
+            reply.extend_from_slice(&buffer[..read as usize]);
+        }
+        Ok(reply)
+    })();
+    let _ = crossterm::terminal::disable_raw_mode();
+
+    let reply = String::from_utf8_lossy(&reply?).into_owned();
+    let encoded = reply
+        .split_once("52;")
+        .and_then(|(_, rest)| rest.split_once(';'))
+        .map(|(_, data)| data.trim_end_matches(['\x07', '\\']).trim_end_matches('\x1b'))
+        .ok_or_else(|| anyhow!("unexpected reply from the terminal"))?;
+    let bytes = STANDARD.decode(encoded)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(not(unix))]
+fn osc52_read() -> Result<String> {
+    Err(anyhow!("no clipboard available"))
+}
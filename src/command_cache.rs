@@ -0,0 +1,96 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// How many of the most used commands are resolved ahead of time
+const PREWARM_TOP: usize = 30;
+
+lazy_static::lazy_static! {
+    // Executable names on PATH, for command-name completion; None until scanned
+    static ref COMMANDS: Mutex<Option<BTreeSet<String>>> = Mutex::new(None);
+}
+
+/// In the background, stat the executables used most often in `history`
+/// and scan PATH for completion, so neither waits on slow (e.g. NFS) PATH
+/// entries once the user starts typing
+pub fn prewarm(history: Vec<String>) {
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        return;
+    };
+    runtime.spawn_blocking(move || {
+        for command in most_frequent(&history, PREWARM_TOP) {
+            let _ = resolve(&command);
+        }
+        let commands = scan_path();
+        if let Ok(mut cache) = COMMANDS.lock() {
+            *cache = Some(commands);
+        }
+    });
+}
+
+/// Executable names on PATH starting with `prefix`. Scans PATH on the spot
+/// if the background scan hasn't finished yet.
+pub fn complete(prefix: &str) -> Vec<String> {
+    let Ok(mut cache) = COMMANDS.lock() else {
+        return Vec::new();
+    };
+    cache.get_or_insert_with(scan_path)
+        .range(prefix.to_string()..)
+        .take_while(|name| name.starts_with(prefix))
+        .cloned()
+        .collect()
+}
+
+/// Command names by how often they start a history entry, most used first
+fn most_frequent(history: &[String], limit: usize) -> Vec<String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for entry in history {
+        let entry = entry.trim_start();
+        let entry = entry.strip_prefix('$').unwrap_or(entry);
+        let Some(command) = entry.split_whitespace().next() else {
+            continue;
+        };
+        // Paths and builtins-with-arguments like `VAR=x` don't need PATH lookups
+        if !command.contains(['/', '=']) {
+            *counts.entry(command).or_default() += 1;
+        }
+    }
+    let mut ranked: Vec<(&str, usize)> = counts.into_iter().collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    ranked.into_iter().take(limit).map(|(command, _)| command.to_string()).collect()
+}
+
+/// Where `command` lives on PATH, if anywhere
+pub fn resolve(command: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(command))
+        .find(|candidate| is_executable(candidate))
+}
+
+fn scan_path() -> BTreeSet<String> {
+    let Some(path) = std::env::var_os("PATH") else {
+        return BTreeSet::new();
+    };
+    std::env::split_paths(&path)
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten())
+        .filter(|entry| is_executable(&entry.path()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect()
+}
+
+fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
@@ -0,0 +1,327 @@
+/// How a step is joined to the one before it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    /// First step, or after `;`: always runs
+    Always,
+    /// After `&&`: runs if the previous status was 0
+    And,
+    /// After `||`: runs if the previous status was non-zero
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// A command without top-level grouping (may still contain pipes)
+    Simple(String),
+    /// `( list )`: runs without affecting the parent's directory or variables
+    Subshell(String),
+    /// `{ list; }`: runs in the current shell
+    Group(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub connector: Connector,
+    pub node: Node,
+}
+
+// Reserved words of sh's compound commands
+const COMPOUND_KEYWORDS: [&str; 14] = [
+    "for", "while", "until", "if", "case", "select", "function",
+    "do", "done", "then", "elif", "else", "fi", "esac",
+];
+
+/// Split a single-line command into steps joined by `;`, `&&` and `||`,
+/// leaving quoted text, `$(...)`, and the insides of `( )` and `{ }` groups
+/// intact. A line with a compound command or function definition (see
+/// [`is_compound`]) is one step, for sh. Returns an error for unbalanced groups.
+pub fn parse(input: &str) -> Result<Vec<Step>, String> {
+    if is_compound(input) {
+        return Ok(vec![Step { connector: Connector::Always, node: Node::Simple(input.trim().to_string()) }]);
+    }
+    let chars: Vec<char> = input.chars().collect();
+    let mut steps = Vec::new();
+    let mut connector = Connector::Always;
+    let mut current = String::new();
+    let mut parens = 0usize;
+    let mut braces = 0usize;
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let prev = if i > 0 { Some(chars[i - 1]) } else { None };
+        let next = chars.get(i + 1).copied();
+
+        if in_single {
+            in_single = c != '\'';
+        } else if c == '\\' && next.is_some() {
+            current.push(c);
+            current.push(chars[i + 1]);
+            i += 2;
+            continue;
+        } else if in_double {
+            in_double = c != '"';
+        } else {
+            match c {
+                '\'' => in_single = true,
+                '"' => in_double = true,
+                '(' => parens += 1,
+                ')' => parens = parens.checked_sub(1).ok_or("unexpected `)`")?,
+                // Braces only group when they stand alone as words, unlike {a,b} or ${VAR}
+                '{' if is_word_start(prev) && next.is_none_or(char::is_whitespace) => braces += 1,
+                '}' if prev.is_none_or(|p| p.is_whitespace() || p == ';') && is_word_end(next) => {
+                    braces = braces.checked_sub(1).ok_or("unexpected `}`")?;
+                }
+                ';' | '&' | '|' if parens == 0 && braces == 0 => {
+                    let joined = match (c, next) {
+                        (';', _) => Some((Connector::Always, 1)),
+                        ('&', Some('&')) => Some((Connector::And, 2)),
+                        ('|', Some('|')) => Some((Connector::Or, 2)),
+                        // A single | or & belongs to the command (pipe, background)
+                        _ => None,
+                    };
+                    if let Some((joined, width)) = joined {
+                        push_step(&mut steps, connector, &current)?;
+                        connector = joined;
+                        current.clear();
+                        i += width;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        current.push(c);
+        i += 1;
+    }
+
+    if in_single || in_double {
+        return Err("unterminated quote".to_string());
+    }
+    if parens > 0 {
+        return Err("missing `)`".to_string());
+    }
+    if braces > 0 {
+        return Err("missing `}`".to_string());
+    }
+    if !current.trim().is_empty() || connector != Connector::Always {
+        push_step(&mut steps, connector, &current)?;
+    }
+    Ok(steps)
+}
+
+/// Whether `input` has a `for`/`while`/`until`/`if`/`case`/`select` loop or
+/// conditional, or defines a function (`function f`, `f()`), anywhere a
+/// command can start. Splitting such a line at its `;`s would break it.
+pub fn is_compound(input: &str) -> bool {
+    let chars: Vec<char> = input.chars().collect();
+    let mut word = String::new();
+    let mut quoted = false;
+    let mut in_single = false;
+    let mut in_double = false;
+    // Whether the word being read starts a command, and whether the last word did
+    let mut command_position = true;
+    let mut after_command_name = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if in_single {
+            in_single = c != '\'';
+        } else if in_double {
+            if c == '\\' {
+                i += 1;
+            } else {
+                in_double = c != '"';
+            }
+        } else if c == '\'' || c == '"' {
+            in_single = c == '\'';
+            in_double = c == '"';
+            quoted = true;
+        } else if c == '\\' {
+            quoted = true;
+            i += 1;
+        } else if c.is_whitespace() || matches!(c, ';' | '&' | '|' | '(' | ')') {
+            if !word.is_empty() || quoted {
+                if command_position && !quoted && COMPOUND_KEYWORDS.contains(&word.as_str()) {
+                    return true;
+                }
+                after_command_name = command_position && !quoted;
+                // A group's `{` and `!` are followed by a command
+                command_position = command_position && !quoted && matches!(word.as_str(), "{" | "!");
+                word.clear();
+                quoted = false;
+            }
+            if c == '(' && after_command_name && chars[i + 1..].iter().find(|c| !c.is_whitespace()) == Some(&')') {
+                return true;
+            }
+            if !c.is_whitespace() {
+                command_position = c != ')';
+                after_command_name = false;
+            }
+        } else {
+            word.push(c);
+        }
+        i += 1;
+    }
+    command_position && !quoted && COMPOUND_KEYWORDS.contains(&word.as_str())
+}
+
+fn is_word_start(prev: Option<char>) -> bool {
+    prev.is_none_or(|p| p.is_whitespace() || matches!(p, ';' | '(' | '&' | '|'))
+}
+
+fn is_word_end(next: Option<char>) -> bool {
+    next.is_none_or(|n| n.is_whitespace() || matches!(n, ';' | ')' | '&' | '|'))
+}
+
+fn push_step(steps: &mut Vec<Step>, connector: Connector, text: &str) -> Result<(), String> {
+    let text = text.trim();
+    if text.is_empty() {
+        // `;` after the last command is fine; `&&`/`||` with nothing around them is not
+        return match connector {
+            Connector::Always if !steps.is_empty() => Ok(()),
+            _ => Err("syntax error near `&&`/`||`/`;`".to_string()),
+        };
+    }
+    steps.push(Step { connector, node: classify(text) });
+    Ok(())
+}
+
+fn classify(text: &str) -> Node {
+    if let Some(inner) = text.strip_prefix('(').and_then(|rest| rest.strip_suffix(')')) {
+        if encloses_whole(text, '(', ')') {
+            return Node::Subshell(inner.trim().to_string());
+        }
+    }
+    if let Some(inner) = text.strip_prefix('{').and_then(|rest| rest.strip_suffix('}')) {
+        let inner = inner.trim();
+        if text[1..].starts_with(char::is_whitespace) && inner.ends_with(';') {
+            return Node::Group(inner.trim_end_matches(';').trim().to_string());
+        }
+    }
+    Node::Simple(text.to_string())
+}
+
+/// Whether the opening character at the start of `text` is closed by its last character,
+/// so `(a) && (b)` is not mistaken for one group
+fn encloses_whole(text: &str, open: char, close: char) -> bool {
+    let mut depth = 0usize;
+    let mut in_single = false;
+    let mut in_double = false;
+    let last = text.chars().count() - 1;
+    for (index, c) in text.chars().enumerate() {
+        match c {
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            _ if in_single || in_double => {}
+            c if c == open => depth += 1,
+            c if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return index == last;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Split a simple command into words, removing single and double quotes and
+/// backslash escapes the way sh does (no expansion is performed)
+pub fn split_words(command: &str) -> Vec<String> {
+    split_quoted_words(command).into_iter().map(|(word, _)| word).collect()
+}
+
+/// [`split_words`], also telling for each word whether any of it was quoted
+/// or escaped, which keeps sh from expanding wildcards in it
+pub fn split_quoted_words(command: &str) -> Vec<(String, bool)> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    let mut in_word = false;
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' if !in_double => {
+                in_single = !in_single;
+                in_word = true;
+                quoted = true;
+            }
+            '"' if !in_single => {
+                in_double = !in_double;
+                in_word = true;
+                quoted = true;
+            }
+            '\\' if !in_single => {
+                quoted = true;
+                if let Some(escaped) = chars.next() {
+                    // Inside double quotes only a few characters are escapable
+                    if in_double && !matches!(escaped, '"' | '\\' | '$' | '`') {
+                        word.push('\\');
+                    }
+                    word.push(escaped);
+                }
+                in_word = true;
+            }
+            c if c.is_whitespace() && !in_single && !in_double => {
+                if in_word {
+                    words.push((std::mem::take(&mut word), std::mem::take(&mut quoted)));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push((word, quoted));
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_one_step(line: &str) -> bool {
+        parse(line).unwrap() == vec![Step { connector: Connector::Always, node: Node::Simple(line.to_string()) }]
+    }
+
+    #[test]
+    fn keeps_compound_commands_whole() {
+        for line in [
+            "for f in *.log; do gzip \"$f\"; done",
+            "while read line; do echo \"$line\"; done",
+            "until make; do sleep 1; done",
+            "if test -f a; then cat a; else echo none; fi",
+            "case $1 in start) run;; *) usage;; esac",
+            "select opt in a b; do echo $opt; break; done",
+            "function greet { echo hi; }; greet",
+            "greet() { echo hi; }; greet",
+            "greet () { echo hi; }; greet",
+            "cd src && for f in *.rs; do wc -l $f; done",
+            "{ if true; then echo yes; fi; }",
+        ] {
+            assert!(is_compound(line), "{:?}", line);
+            assert!(is_one_step(line), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn keywords_as_arguments_still_split() {
+        for line in ["echo done; ls", "git commit -m 'if then fi'; git push", "echo for && echo \"do\"", "ls (x)"] {
+            assert!(!is_compound(line), "{:?}", line);
+        }
+        assert_eq!(parse("echo done; ls").unwrap().len(), 2);
+    }
+}
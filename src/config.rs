@@ -0,0 +1,709 @@
+use anyhow::Result;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::notify::{AgentNotifications, NotifyStyle};
+use crate::prompt_pipe::PipeMode;
+use crate::{context, humanize, platform, prompt_style, provider_health, state, status_cache, timeout, tool_rate, ts_runtime, usage, ShellMode};
+
+// Config is now handled by TypeScript runtime
+pub type Config = ts_runtime::TypeScriptConfig;
+pub type AiConfig = ts_runtime::TypeScriptAiConfig;
+pub type ShellConfig = ts_runtime::TypeScriptShellConfig;
+
+impl Config {
+    pub async fn load() -> Result<Self> {
+        let loader = ts_runtime::TypeScriptConfigLoader::new()?;
+        loader.load_config().await
+    }
+
+    pub fn get_prompt(&self, current_dir: &PathBuf, mode: &ShellMode) -> String {
+        let prompt_template = self.shell
+            .as_ref()
+            .and_then(|s| s.prompt.as_ref())
+            .cloned()
+            .unwrap_or_else(|| "aish> ".to_string());
+        
+        self.expand_prompt(&prompt_template, current_dir, mode)
+    }
+
+    /// `shell.banner` with the prompt's escapes expanded, if set as a string
+    pub fn get_banner(&self, current_dir: &PathBuf, mode: &ShellMode) -> Option<String> {
+        let template = self.shell.as_ref()?.banner.as_ref()?;
+        Some(self.expand_prompt(template, current_dir, mode))
+    }
+
+    /// Whether typed lines get bash-style history expansion (`shell.history_expansion`)
+    pub fn history_expansion(&self) -> bool {
+        self.shell.as_ref().and_then(|s| s.history_expansion) != Some(false)
+    }
+
+    /// Whether sessions are stored in SQLite (`shell.session_store`)
+    pub fn session_store(&self) -> bool {
+        self.shell.as_ref().and_then(|s| s.session_store) != Some(false)
+    }
+
+    /// Days stored sessions are kept (`shell.session_retention_days`); 0 is forever
+    pub fn session_retention_days(&self) -> u64 {
+        self.shell
+            .as_ref()
+            .and_then(|s| s.session_retention_days)
+            .unwrap_or(crate::session_store::DEFAULT_RETENTION_DAYS)
+    }
+
+    /// Whether commands' stdout is captured for the agent along with stderr
+    /// (`shell.capture_stdout: true`)
+    pub fn capture_stdout(&self) -> bool {
+        self.shell.as_ref().and_then(|s| s.capture_stdout) == Some(true)
+    }
+
+    /// How `command |? question` lines are handled (`shell.prompt_pipe`)
+    pub fn prompt_pipe(&self) -> PipeMode {
+        match self.shell.as_ref().and_then(|s| s.prompt_pipe.as_deref()) {
+            None => PipeMode::Show,
+            Some(mode) => PipeMode::parse(mode).unwrap_or_else(|| {
+                tracing::warn!(%mode, "unknown shell.prompt_pipe mode; using show");
+                PipeMode::Show
+            }),
+        }
+    }
+
+    /// Lines of history kept per mode (`shell.history_size`)
+    pub fn history_size(&self) -> usize {
+        self.shell.as_ref().and_then(|s| s.history_size).unwrap_or(1000)
+    }
+
+    /// Whether `shell.quiet` turns off startup output
+    pub fn quiet(&self) -> bool {
+        self.shell.as_ref().and_then(|s| s.quiet) == Some(true)
+    }
+
+    /// Threshold and style for announcing long commands, when
+    /// `shell.notify_after_secs` is set
+    pub fn long_command_notification(&self) -> Option<(Duration, NotifyStyle)> {
+        let shell = self.shell.as_ref()?;
+        let after = Duration::from_secs(shell.notify_after_secs?);
+        let style = match shell.notify.as_deref() {
+            None => NotifyStyle::Bell,
+            Some(style) => NotifyStyle::parse(style).unwrap_or_else(|| {
+                tracing::warn!(%style, "unknown shell.notify style; using bell");
+                NotifyStyle::Bell
+            }),
+        };
+        Some((after, style))
+    }
+
+    /// Desktop notifications for agent turns, when `shell.notifications` is set
+    pub fn agent_notifications(&self) -> Option<AgentNotifications> {
+        let notifications = self.shell.as_ref()?.notifications.as_ref()?;
+        Some(AgentNotifications {
+            after: Duration::from_secs(
+                notifications.after_secs.unwrap_or(ts_runtime::DEFAULT_AGENT_NOTIFY_AFTER_SECS),
+            ),
+            approvals: notifications.approvals != Some(false),
+        })
+    }
+
+    /// `shell.defaultCommandTimeout`, the limit on each typed command that
+    /// doesn't want the terminal
+    pub fn default_command_timeout(&self) -> Option<Duration> {
+        match self.shell.as_ref()?.default_command_timeout.as_ref()? {
+            ts_runtime::CommandTimeout::Seconds(seconds) if *seconds > 0.0 && seconds.is_finite() => {
+                Some(Duration::from_secs_f64(*seconds))
+            }
+            ts_runtime::CommandTimeout::Seconds(_) => None,
+            ts_runtime::CommandTimeout::Text(text) => timeout::parse_duration(text).or_else(|| {
+                tracing::warn!(%text, "invalid shell.defaultCommandTimeout; commands run without a limit");
+                None
+            }),
+        }
+    }
+
+    pub fn max_nesting(&self) -> u32 {
+        self.shell
+            .as_ref()
+            .and_then(|s| s.max_nesting)
+            .unwrap_or(ts_runtime::DEFAULT_MAX_NESTING)
+    }
+
+    /// Switch the active model for this session. `name` is either a preset
+    /// from `ai.models`, which may also change the provider, or a model name
+    /// to use with the current provider. Returns a description of the change.
+    pub fn switch_model(&mut self, name: &str) -> String {
+        let ai = self.ai.get_or_insert_with(|| {
+            Config::default().ai.expect("default config has ai settings")
+        });
+        let preset = ai.models.as_ref().and_then(|models| models.get(name)).cloned();
+
+        match preset {
+            Some(preset) => {
+                ai.model = Some(preset.model.clone());
+                if preset.base_url.is_some() {
+                    ai.base_url = preset.base_url;
+                }
+                if preset.api_key.is_some() {
+                    ai.api_key = preset.api_key;
+                }
+                format!(
+                    "Using {} ({}) via {}",
+                    name,
+                    preset.model,
+                    ai.base_url.as_deref().unwrap_or("https://api.openai.com/v1")
+                )
+            }
+            None => {
+                ai.model = Some(name.to_string());
+                format!("Using {}", name)
+            }
+        }
+    }
+
+    /// Print the active model and the presets available to the `model` builtin
+    pub fn print_models(&self) {
+        let ai = self.ai.as_ref();
+        println!("Active: {} via {}",
+            ai.and_then(|ai| ai.model.as_deref()).unwrap_or("gpt-4"),
+            ai.and_then(|ai| ai.base_url.as_deref()).unwrap_or("https://api.openai.com/v1"));
+
+        let mut presets: Vec<_> = ai
+            .and_then(|ai| ai.models.as_ref())
+            .map(|models| models.iter().collect())
+            .unwrap_or_default();
+        if presets.is_empty() {
+            println!("No presets in ai.models; `model <name>` uses the name with the current provider");
+            return;
+        }
+        presets.sort_by(|a, b| a.0.cmp(b.0));
+        println!("Presets:");
+        for (name, preset) in presets {
+            println!("  {:<20} {}{}", name, preset.model,
+                preset.base_url.as_ref().map(|url| format!(" via {}", url)).unwrap_or_default());
+        }
+    }
+
+    /// The profile for `dir`: the one whose matching pattern is most specific
+    pub fn profile_for(&self, dir: &Path) -> Option<(&str, &ts_runtime::TypeScriptProfile)> {
+        self.profiles.as_ref()?
+            .iter()
+            .filter_map(|(name, profile)| {
+                profile.paths.iter()
+                    .filter(|pattern| path_matches(pattern, dir))
+                    .map(|pattern| pattern.len())
+                    .max()
+                    .map(|specificity| (specificity, name.as_str(), profile))
+            })
+            .max_by(|a, b| a.0.cmp(&b.0).then_with(|| b.1.cmp(a.1)))
+            .map(|(_, name, profile)| (name, profile))
+    }
+
+    /// The sampling preset `name` from `ai.presets`, or the built-in
+    /// `deterministic` (temperature 0 and a fixed seed) unless redefined
+    pub fn sampling_preset(&self, name: &str) -> Result<ts_runtime::SamplingPreset> {
+        let presets = self.ai.as_ref().and_then(|ai| ai.presets.as_ref());
+        if let Some(preset) = presets.and_then(|presets| presets.get(name)) {
+            return Ok(preset.clone());
+        }
+        if name == "deterministic" {
+            return Ok(ts_runtime::SamplingPreset { temperature: Some(0.0), top_p: None, seed: Some(0) });
+        }
+        let mut names: Vec<&str> = presets.map(|presets| presets.keys().map(String::as_str).collect()).unwrap_or_default();
+        names.push("deterministic");
+        names.sort_unstable();
+        Err(anyhow::anyhow!("unknown sampling preset '{}' (available: {})", name, names.join(", ")))
+    }
+
+    /// The sampling a prompt template asks for: its preset, overridden by its own settings
+    pub fn template_sampling(&self, template: &ts_runtime::PromptTemplate) -> Result<ts_runtime::SamplingPreset> {
+        match template {
+            ts_runtime::PromptTemplate::Text(_) => Ok(Default::default()),
+            ts_runtime::PromptTemplate::Full { preset, sampling, .. } => {
+                let base = match preset {
+                    Some(name) => self.sampling_preset(name)?,
+                    None => Default::default(),
+                };
+                Ok(base.overlay(sampling))
+            }
+        }
+    }
+
+    /// External approver consulted after the user approves, if configured
+    pub fn approval_command(&self) -> Option<String> {
+        self.ai.as_ref()
+            .and_then(|ai| ai.approval_command.clone())
+            .filter(|command| !command.trim().is_empty())
+    }
+
+    /// Whether rm moves files to the trash instead of deleting them
+    pub fn safe_rm(&self) -> bool {
+        self.shell.as_ref().and_then(|s| s.safe_rm).unwrap_or(false)
+    }
+
+    /// (min_files, min_bytes) for confirming typed destructive commands, or
+    /// None when `shell.dry_run` is off
+    pub fn dry_run_thresholds(&self) -> Option<(u64, u64)> {
+        let dry_run = self.shell.as_ref()?.dry_run.as_ref()?;
+        if dry_run.enabled == Some(false) {
+            return None;
+        }
+        Some((
+            dry_run.min_files.unwrap_or(ts_runtime::DEFAULT_DRY_RUN_MIN_FILES),
+            dry_run.min_bytes.unwrap_or(ts_runtime::DEFAULT_DRY_RUN_MIN_BYTES),
+        ))
+    }
+
+    /// Whether repeated tool outputs are sent as diffs (`ai.differential_context`)
+    pub fn differential_context(&self) -> bool {
+        self.ai.as_ref().and_then(|ai| ai.differential_context) != Some(false)
+    }
+
+    /// Re-prompts allowed when `ai.validateResponse` rejects an answer
+    pub fn max_validation_retries(&self) -> u32 {
+        self.ai
+            .as_ref()
+            .and_then(|ai| ai.max_validation_retries)
+            .unwrap_or(ts_runtime::DEFAULT_MAX_VALIDATION_RETRIES)
+    }
+
+    /// Estimated tokens of agent conversation at which older steps are
+    /// summarized: `ai.summarize_at` of `ai.context_window`
+    pub fn summarize_threshold_tokens(&self) -> usize {
+        let ai = self.ai.as_ref();
+        let window = ai.and_then(|ai| ai.context_window).unwrap_or(ts_runtime::DEFAULT_CONTEXT_WINDOW);
+        let fraction = ai
+            .and_then(|ai| ai.summarize_at)
+            .filter(|fraction| *fraction > 0.0 && *fraction <= 1.0)
+            .unwrap_or(ts_runtime::DEFAULT_SUMMARIZE_AT);
+        (window as f32 * fraction) as usize
+    }
+
+    /// Recent agent messages never summarized (`ai.keep_recent_messages`)
+    pub fn keep_recent_messages(&self) -> usize {
+        self.ai
+            .as_ref()
+            .and_then(|ai| ai.keep_recent_messages)
+            .unwrap_or(ts_runtime::DEFAULT_KEEP_RECENT_MESSAGES)
+    }
+
+    /// Tool-call rounds per prompt before asking to continue
+    /// (`ai.max_iterations`); None when unlimited
+    pub fn max_iterations(&self) -> Option<u32> {
+        self.ai
+            .as_ref()
+            .and_then(|ai| ai.max_iterations)
+            .or(Some(ts_runtime::DEFAULT_MAX_ITERATIONS))
+            .filter(|max| *max > 0)
+    }
+
+    /// Tokens one prompt may use before asking to continue (`ai.max_prompt_tokens`)
+    pub fn max_prompt_tokens(&self) -> Option<u64> {
+        self.ai.as_ref().and_then(|ai| ai.max_prompt_tokens).filter(|max| *max > 0)
+    }
+
+    /// How long one prompt may run before asking to continue (`ai.max_prompt_secs`)
+    pub fn max_prompt_duration(&self) -> Option<Duration> {
+        self.ai
+            .as_ref()
+            .and_then(|ai| ai.max_prompt_secs)
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// Embedding model for the project index (`ai.embedding_model`); the
+    /// local embedder when no API key is configured
+    pub fn embedding_model(&self) -> String {
+        let ai = self.ai.as_ref();
+        match ai.and_then(|ai| ai.embedding_model.clone()) {
+            Some(model) => model,
+            None if ai.is_some_and(|ai| ai.api_key.is_some()) => ts_runtime::DEFAULT_EMBEDDING_MODEL.to_string(),
+            None => crate::project_index::LOCAL_MODEL.to_string(),
+        }
+    }
+
+    /// Settings for writing conversation summaries, with `ai.summary_model`
+    /// as the model when set
+    pub fn summary_config(&self) -> Config {
+        let mut config = self.clone();
+        if let Some(ai) = config.ai.as_mut() {
+            if let Some(model) = ai.summary_model.clone() {
+                ai.model = Some(model);
+                // A profile must not swap the cheap model back out
+                config.profiles = None;
+            }
+        }
+        config
+    }
+
+    /// The loaded configuration as JSON for `config show`, with API keys,
+    /// tokens and passwords masked
+    pub fn redacted(&self) -> serde_json::Value {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        redact(&mut value);
+        value
+    }
+
+    /// HTTP client for the AI API: `ai.headers` on every request, the CA
+    /// certificates in `ai.ca_cert` trusted and requests sent through `ai.proxy`
+    pub fn http_client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        let Some(ai) = self.ai.as_ref() else {
+            return Ok(builder.build()?);
+        };
+        if let Some(headers) = &ai.headers {
+            let mut map = reqwest::header::HeaderMap::new();
+            for (name, value) in headers {
+                let header = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|e| anyhow::anyhow!("ai.headers: {}: {}", name, e))?;
+                let mut value = reqwest::header::HeaderValue::from_str(value)
+                    .map_err(|e| anyhow::anyhow!("ai.headers: {}: {}", name, e))?;
+                value.set_sensitive(true);
+                map.insert(header, value);
+            }
+            builder = builder.default_headers(map);
+        }
+        if let Some(path) = &ai.ca_cert {
+            let path = platform::expand_home(path).unwrap_or_else(|| PathBuf::from(path));
+            let pem = std::fs::read(&path).map_err(|e| anyhow::anyhow!("ai.ca_cert: {}: {}", path.display(), e))?;
+            let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+                .map_err(|e| anyhow::anyhow!("ai.ca_cert: {}: {}", path.display(), e))?;
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if let Some(proxy) = &ai.proxy {
+            let proxy = reqwest::Proxy::all(proxy).map_err(|e| anyhow::anyhow!("ai.proxy: {}: {}", redact_url(proxy), e))?;
+            builder = builder.proxy(proxy);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// How long tool `name` may run and how many bytes of output it may
+    /// return: `ai.tool_limits[name]`, else what the tool declared, else the
+    /// `ai.tool_timeout_secs` and `ai.max_tool_output_bytes` defaults
+    pub fn tool_limits(&self, name: &str, declared: Option<&ts_runtime::ToolLimits>) -> (Duration, usize) {
+        let ai = self.ai.as_ref();
+        let configured = ai.and_then(|ai| ai.tool_limits.as_ref()).and_then(|limits| limits.get(name));
+        let timeout = configured.and_then(|limits| limits.timeout_ms)
+            .or_else(|| declared.and_then(|limits| limits.timeout_ms))
+            .map(Duration::from_millis)
+            .unwrap_or_else(|| Duration::from_secs(
+                ai.and_then(|ai| ai.tool_timeout_secs).unwrap_or(ts_runtime::DEFAULT_TOOL_TIMEOUT_SECS)));
+        let max_output = configured.and_then(|limits| limits.max_output_bytes)
+            .or_else(|| declared.and_then(|limits| limits.max_output_bytes))
+            .or_else(|| ai.and_then(|ai| ai.max_tool_output_bytes))
+            .unwrap_or(ts_runtime::DEFAULT_MAX_TOOL_OUTPUT_BYTES);
+        (timeout, max_output)
+    }
+
+    /// `ai.max_tool_calls_per_minute` and `ai.max_concurrent_tools`
+    pub fn tool_rate_limits(&self) -> tool_rate::RateLimits {
+        let ai = self.ai.as_ref();
+        tool_rate::RateLimits {
+            per_minute: ai.and_then(|ai| ai.max_tool_calls_per_minute).unwrap_or(ts_runtime::DEFAULT_MAX_TOOL_CALLS_PER_MINUTE),
+            concurrent: ai.and_then(|ai| ai.max_concurrent_tools).unwrap_or(ts_runtime::DEFAULT_MAX_CONCURRENT_TOOLS),
+        }
+    }
+
+    /// Number of recent shell commands shared with the agent
+    pub fn context_commands(&self) -> usize {
+        self.ai
+            .as_ref()
+            .and_then(|ai| ai.context_commands)
+            .unwrap_or(ts_runtime::DEFAULT_CONTEXT_COMMANDS)
+    }
+
+    pub fn context_output_chars(&self) -> usize {
+        self.ai
+            .as_ref()
+            .and_then(|ai| ai.context_output_chars)
+            .unwrap_or(ts_runtime::DEFAULT_CONTEXT_OUTPUT_CHARS)
+    }
+
+    /// Settings for inline AI suggestions, with `ai.ghost_model` as the model,
+    /// or None when `ai.ghost_suggestions` is off
+    pub fn ghost_suggestion_config(&self) -> Option<Config> {
+        let ai = self.ai.as_ref().filter(|ai| ai.ghost_suggestions == Some(true))?;
+        let mut config = self.clone();
+        if let (Some(model), Some(ai)) = (ai.ghost_model.clone(), config.ai.as_mut()) {
+            ai.model = Some(model);
+        }
+        // Suggestions are short; a profile must not swap in a slower model
+        config.profiles = None;
+        Some(config)
+    }
+
+    /// Refuse to start another aish once the nesting limit is reached
+    pub fn check_nesting(&self, command: &str) -> Result<()> {
+        if launches_aish(command) && nesting_level() >= self.max_nesting() {
+            return Err(anyhow::anyhow!(
+                "refusing to start nested aish: already at nesting level {} (shell.max_nesting = {})",
+                nesting_level(),
+                self.max_nesting()
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn get_continuation_prompt(&self, current_dir: &PathBuf, mode: &ShellMode) -> String {
+        let prompt_template = self.shell
+            .as_ref()
+            .and_then(|s| s.multiline_continuation.as_ref())
+            .cloned()
+            .unwrap_or_else(|| "... ".to_string());
+        
+        self.expand_prompt(&prompt_template, current_dir, mode)
+    }
+
+    fn expand_prompt(&self, template: &str, current_dir: &PathBuf, mode: &ShellMode) -> String {
+        let mut result = template.to_string();
+        
+        // Escape sequences and the \[ \] brackets around them, before any
+        // expansion can bring in a backslash. A \[ not followed by an escape
+        // still stands for ESC [ itself, as in `\[01;32m\]`.
+        result = result.replace("\\e", "\x1b").replace("\\033", "\x1b");
+        result = result.replace("\\[\x1b", &format!("{}\x1b", prompt_style::START_INVISIBLE));
+        result = result.replace("\\[", &format!("{}\x1b[", prompt_style::START_INVISIBLE));
+        result = result.replace("\\]", &prompt_style::END_INVISIBLE.to_string());
+        
+        // Expand environment variables using $VAR or ${VAR} syntax
+        while let Some(start) = result.find('$') {
+            if start + 1 >= result.len() {
+                break;
+            }
+            
+            let remaining = &result[start + 1..];
+            let (var_name, end_pos) = if remaining.starts_with('{') {
+                // ${VAR} syntax
+                if let Some(close) = remaining.find('}') {
+                    (&remaining[1..close], close + 2)
+                } else {
+                    break;
+                }
+            } else {
+                // $VAR syntax - find end of variable name
+                let end = remaining.find(|c: char| !c.is_alphanumeric() && c != '_')
+                    .unwrap_or(remaining.len());
+                if end == 0 {
+                    // Just a $ with no variable name
+                    break;
+                }
+                (&remaining[..end], end + 1)
+            };
+            
+            let env_value = match var_name.strip_prefix(ts_runtime::ops::SESSION_VAR_ENV_PREFIX) {
+                Some(session_var) => ts_runtime::ops::get_session_var(session_var).unwrap_or_default(),
+                None => state::env_var(var_name).unwrap_or_default(),
+            };
+            result.replace_range(start..start + end_pos, &env_value);
+        }
+        
+        // Session variables using \{var} syntax
+        let mut search_from = 0;
+        while let Some(offset) = result[search_from..].find("\\{") {
+            let start = search_from + offset;
+            let Some(close) = result[start..].find('}') else {
+                break;
+            };
+            let var_name = &result[start + 2..start + close];
+            let value = ts_runtime::ops::get_session_var(var_name).unwrap_or_default();
+            result.replace_range(start..start + close + 1, &value);
+            search_from = start + value.len();
+        }
+        
+        // PS1-style escape sequences
+        result = result.replace("\\u", &env::var("USER").unwrap_or_else(|_| "user".to_string()));
+        let hostname = gethostname();
+        // \h is the hostname up to the first dot, like bash
+        result = result.replace("\\h", hostname.split('.').next().unwrap_or_default());
+        result = result.replace("\\H", &hostname);
+        
+        // Working directory expansions
+        let home_dir = dirs::home_dir();
+        let current_dir_str = current_dir.display().to_string();
+        
+        if let Some(home) = &home_dir {
+            let home_str = home.display().to_string();
+            if current_dir_str.starts_with(&home_str) {
+                let relative = current_dir_str.strip_prefix(&home_str)
+                    .unwrap_or(&current_dir_str);
+                let tilde_path = if relative.is_empty() {
+                    "~".to_string()
+                } else {
+                    format!("~{}", relative)
+                };
+                result = result.replace("\\w", &tilde_path);
+                result = result.replace("\\W", 
+                    &current_dir.file_name()
+                        .and_then(|n| n.to_str())
+                        .unwrap_or("~")
+                );
+            } else {
+                result = result.replace("\\w", &current_dir_str);
+                result = result.replace("\\W", 
+                    &current_dir.file_name()
+                        .and_then(|n| n.to_str())
+                        .unwrap_or("/")
+                );
+            }
+        } else {
+            result = result.replace("\\w", &current_dir_str);
+            result = result.replace("\\W", 
+                &current_dir.file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or("/")
+            );
+        }
+        
+        // Mode-specific escape sequences
+        result = result.replace("\\m", mode.as_str());
+        result = result.replace("\\M", &mode.as_str().to_uppercase());
+        
+        // Tokens used by the AI this session
+        result = result.replace("\\T", &usage::session_tokens_short());
+        
+        // How long the last command took
+        if result.contains("\\D") {
+            let duration = context::last().map(|record| humanize::duration(record.duration)).unwrap_or_default();
+            result = result.replace("\\D", &duration);
+        }
+        
+        // Nesting level of this aish session
+        result = result.replace("\\L", &nesting_level().to_string());
+        
+        // Git and Kubernetes state, from the shared cache; the two-letter git
+        // escapes go before \g so it doesn't swallow their prefix
+        if result.contains("\\g") || result.contains("\\k") {
+            let status = status_cache::snapshot(current_dir);
+            result = result.replace("\\gb", status.git_branch.as_deref().unwrap_or_default());
+            result = result.replace("\\gd", if status.git_dirty { "*" } else { "" });
+            result = result.replace("\\ga", &status.git_ahead_behind());
+            result = result.replace("\\gs", &format!("{}{}", status.git_segment(), status.git_ahead_behind()));
+            result = result.replace("\\g", &status.git_segment());
+            result = result.replace("\\k", status.kube_context.as_deref().unwrap_or_default());
+        }
+        
+        // AI provider health glyph
+        result = result.replace("\\P", provider_health::current_health().glyph());
+        
+        // Other common escape sequences
+        result = result.replace("\\$", if env::var("USER").unwrap_or_default() == "root" { "#" } else { "$" });
+        result = result.replace("\\n", "\n");
+        result = result.replace("\\t", "\t");
+        
+        result
+    }
+}
+
+fn redact(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                // Any header may carry a credential
+                if key == "headers" {
+                    if let serde_json::Value::Object(headers) = value {
+                        headers.values_mut().for_each(|value| *value = serde_json::Value::String("********".to_string()));
+                    }
+                } else if key == "proxy" {
+                    // Proxy URLs often carry `user:password@`
+                    if let serde_json::Value::String(url) = value {
+                        *url = redact_url(url);
+                    }
+                } else if is_secret(key) && !value.is_null() {
+                    *value = serde_json::Value::String("********".to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// `url` with any `user:password@` masked
+fn redact_url(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(mut parsed) => {
+            if !parsed.username().is_empty() || parsed.password().is_some() {
+                let _ = parsed.set_password(None);
+                let _ = parsed.set_username("********");
+            }
+            parsed.to_string()
+        }
+        // Unparseable, so mask everything before an `@` that might be credentials
+        Err(_) => match url.rsplit_once('@') {
+            Some((_, host)) => format!("********@{}", host),
+            None => url.to_string(),
+        },
+    }
+}
+
+/// Whether a setting named `key` holds a credential (`api_key`, `token`,
+/// `password`, ...), but not e.g. `max_tokens`
+fn is_secret(key: &str) -> bool {
+    let key = key.to_lowercase();
+    ["apikey", "api_key", "secret", "password", "_token"].iter().any(|suffix| key.ends_with(suffix))
+        || matches!(key.as_str(), "token" | "authorization")
+}
+
+/// Whether `dir` is covered by a profile path pattern. Plain paths match the
+/// directory and everything below it; `*` matches within one path component
+/// and `**` across components.
+fn path_matches(pattern: &str, dir: &Path) -> bool {
+    let pattern = match pattern.strip_prefix('~') {
+        Some(rest) => match dirs::home_dir() {
+            Some(home) => format!("{}{}", home.display(), rest),
+            None => return false,
+        },
+        None => pattern.to_string(),
+    };
+    let pattern = pattern.trim_end_matches('/');
+    let dir = dir.to_string_lossy();
+
+    if !pattern.contains('*') {
+        return dir == pattern || dir.starts_with(&format!("{}/", pattern));
+    }
+
+    let mut expr = String::from("^");
+    let mut rest = pattern;
+    while let Some(star) = rest.find('*') {
+        expr.push_str(&regex::escape(&rest[..star]));
+        if rest[star..].starts_with("**") {
+            expr.push_str(".*");
+            rest = &rest[star + 2..];
+        } else {
+            expr.push_str("[^/]*");
+            rest = &rest[star + 1..];
+        }
+    }
+    expr.push_str(&regex::escape(rest));
+    // Like plain paths, a glob covers the subdirectories of what it matches
+    expr.push_str("(/.*)?$");
+    regex::Regex::new(&expr).is_ok_and(|re| re.is_match(&dir))
+}
+
+pub fn gethostname() -> String {
+    platform::hostname()
+}
+
+/// Depth of this aish session: 1 for a top-level shell, 2 when started from
+/// inside another aish, and so on. Tracked through AISH_LEVEL like bash's
+/// SHLVL: each aish passes its own level to its children.
+pub fn nesting_level() -> u32 {
+    lazy_static::lazy_static! {
+        static ref LEVEL: u32 = env::var("AISH_LEVEL")
+            .ok()
+            .and_then(|level| level.parse::<u32>().ok())
+            .unwrap_or(0) + 1;
+    }
+    *LEVEL
+}
+
+/// Whether a shell command line would start another aish
+pub fn launches_aish(command: &str) -> bool {
+    lazy_static::lazy_static! {
+        static ref AISH_INVOCATION: regex::Regex =
+            regex::Regex::new(r"(^|[;&|(`]|\$\()\s*(exec\s+)?(\S*/)?aish(\s|$|[;&|)])").unwrap();
+    }
+    AISH_INVOCATION.is_match(command)
+}
@@ -0,0 +1,68 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Commands kept regardless of the configured window, so the window can change at runtime
+const MAX_RECORDS: usize = 50;
+
+/// A command the user ran by hand, with what it printed to stderr (or stdout
+/// for post-processed commands)
+#[derive(Debug, Clone)]
+pub struct CommandRecord {
+    pub command: String,
+    pub exit_code: i32,
+    pub output: String,
+    /// Wall-clock time from start to exit
+    pub duration: Duration,
+}
+
+lazy_static::lazy_static! {
+    static ref RECENT_COMMANDS: Mutex<VecDeque<CommandRecord>> = Mutex::new(VecDeque::new());
+}
+
+/// Remember a command run in the shell so the agent can see it later
+pub fn record(command: &str, exit_code: i32, output: &str, duration: Duration) {
+    if let Ok(mut recent) = RECENT_COMMANDS.lock() {
+        if recent.len() == MAX_RECORDS {
+            recent.pop_front();
+        }
+        recent.push_back(CommandRecord {
+            command: command.to_string(),
+            exit_code,
+            output: output.to_string(),
+            duration,
+        });
+    }
+}
+
+/// The most recently recorded command
+pub fn last() -> Option<CommandRecord> {
+    RECENT_COMMANDS.lock().ok().and_then(|recent| recent.back().cloned())
+}
+
+/// Describe the last `window` commands for the agent's context, oldest first.
+/// Output is cut to its final `max_output_chars` characters, where errors usually are.
+pub fn render(window: usize, max_output_chars: usize) -> Option<String> {
+    let recent = RECENT_COMMANDS.lock().ok()?;
+    if window == 0 || recent.is_empty() {
+        return None;
+    }
+
+    let mut text = String::from("Commands the user recently ran in this shell, oldest first:\n");
+    for record in recent.iter().skip(recent.len().saturating_sub(window)) {
+        text.push_str(&format!("\n$ {}\n[exit code {}]\n", record.command, record.exit_code));
+        let output = record.output.trim_end();
+        if output.is_empty() {
+            continue;
+        }
+        let count = output.chars().count();
+        if count > max_output_chars {
+            let tail: String = output.chars().skip(count - max_output_chars).collect();
+            text.push_str(&format!("[output truncated]\n...{}\n", tail));
+        } else {
+            text.push_str(output);
+            text.push('\n');
+        }
+    }
+    Some(text)
+}
@@ -0,0 +1,155 @@
+//! What the model has already been sent in the current conversation, so a
+//! repeated tool call (re-reading a file, re-running `git status`) or the
+//! next prompt's context snapshot sends only what changed since the model
+//! last saw that source instead of the whole output. The agent keeps it
+//! across prompts, and in the session store for `sessions resume`; what the
+//! conversation no longer shows the model (a prompt that starts afresh, or
+//! steps summarized away) is forgotten, so nothing is diffed against text
+//! the model can't see.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use similar::TextDiff;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::evidence;
+
+// Lines of unchanged context around each change in a diff
+const DIFF_CONTEXT_LINES: usize = 2;
+
+/// What identifies a tool call: its name and its arguments with object keys
+/// sorted, so only the very same call is diffed against an earlier one
+pub fn key(name: &str, args: &Value) -> String {
+    fn canonical(value: &Value) -> Value {
+        match value {
+            Value::Object(map) => {
+                let sorted: BTreeMap<&String, Value> = map.iter().map(|(key, value)| (key, canonical(value))).collect();
+                Value::Object(sorted.into_iter().map(|(key, value)| (key.clone(), value)).collect())
+            }
+            Value::Array(items) => Value::Array(items.iter().map(canonical).collect()),
+            other => other.clone(),
+        }
+    }
+    format!("{} {}", name, canonical(args))
+}
+
+/// Tool outputs sent so far, by call (see [`key`]), and the evidence
+/// numbers handed out
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct ContextLedger {
+    seen: HashMap<String, Sent>,
+    // The last evidence number handed out
+    numbered: usize,
+}
+
+/// The output last sent for a call
+#[derive(Clone, Serialize, Deserialize)]
+struct Sent {
+    output: String,
+    // Evidence the model needs to rebuild `output`: where it was last sent
+    // whole, then every diff or repeat since
+    numbers: Vec<usize>,
+}
+
+impl ContextLedger {
+    /// Start a prompt whose conversation so far is `messages`: forget what
+    /// they don't show the model and number new evidence after theirs
+    pub fn resume<'a>(&mut self, messages: impl IntoIterator<Item = &'a str>) {
+        let present = self.retain_sent(messages);
+        self.numbered = present.into_iter().max().unwrap_or(0);
+    }
+
+    /// Forget the calls whose evidence is no longer among `messages`, e.g.
+    /// after older steps were summarized; returns the evidence numbers that are
+    pub fn retain_sent<'a>(&mut self, messages: impl IntoIterator<Item = &'a str>) -> HashSet<usize> {
+        let present: HashSet<usize> = messages.into_iter().filter_map(evidence::number).collect();
+        let before = self.seen.len();
+        self.seen.retain(|_, sent| sent.numbers.iter().all(|number| present.contains(number)));
+        if self.seen.len() < before {
+            tracing::debug!(forgotten = before - self.seen.len(), "tool outputs no longer in the conversation");
+        }
+        present
+    }
+
+    /// The number for the next piece of evidence
+    pub fn next_number(&mut self) -> usize {
+        self.numbered += 1;
+        self.numbered
+    }
+
+    /// The message to send for `output`, numbered as evidence `number`: the
+    /// whole output the first time the call `key` is made, and afterwards a
+    /// reference to the earlier result plus a diff when that is shorter
+    pub fn encode(&mut self, key: &str, number: usize, output: &str) -> String {
+        let relative = self.seen.remove(key)
+            .and_then(|sent| Some((relative(key, number, output, &sent)?, sent.numbers)));
+        let (message, mut numbers) = relative.unwrap_or_else(|| (evidence::label(number, output), Vec::new()));
+        numbers.push(number);
+        self.seen.insert(key.to_string(), Sent { output: output.to_string(), numbers });
+        message
+    }
+}
+
+/// `output` as a reference to what was `sent` before, with a diff if it
+/// changed, or None when that is no shorter than sending it whole
+fn relative(key: &str, number: usize, output: &str, sent: &Sent) -> Option<String> {
+    let since = *sent.numbers.last()?;
+    if sent.output == output {
+        tracing::debug!(call = %key, since, "tool output unchanged");
+        return Some(format!("[evidence {}] Same output as [evidence {}]; nothing changed.", number, since));
+    }
+    let diff = TextDiff::from_lines(&sent.output, output)
+        .unified_diff()
+        .context_radius(DIFF_CONTEXT_LINES)
+        .header(&format!("evidence {}", since), &format!("evidence {}", number))
+        .to_string();
+    if diff.len() >= output.len() {
+        return None;
+    }
+    tracing::debug!(call = %key, since, full_bytes = output.len(), diff_bytes = diff.len(), "sending tool output as a diff");
+    Some(format!("[evidence {}] Changes since [evidence {}]:\n{}", number, since, diff))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing() -> String {
+        (1..=40).map(|n| format!("file{}.txt\n", n)).collect()
+    }
+
+    fn send(ledger: &mut ContextLedger, output: &str) -> String {
+        let number = ledger.next_number();
+        ledger.encode("ls", number, output)
+    }
+
+    #[test]
+    fn repeats_are_sent_as_references() {
+        let mut ledger = ContextLedger::default();
+        assert_eq!(send(&mut ledger, &listing()), evidence::label(1, &listing()));
+        assert_eq!(send(&mut ledger, &listing()), "[evidence 2] Same output as [evidence 1]; nothing changed.");
+        let changed = listing().replace("file20.txt", "renamed.txt");
+        assert!(send(&mut ledger, &changed).starts_with("[evidence 3] Changes since [evidence 2]:"));
+    }
+
+    #[test]
+    fn carries_over_to_the_next_prompt_of_the_conversation() {
+        let mut ledger = ContextLedger::default();
+        let first = send(&mut ledger, &listing());
+        ledger.resume([first.as_str(), "the next prompt"]);
+        assert_eq!(send(&mut ledger, &listing()), "[evidence 2] Same output as [evidence 1]; nothing changed.");
+    }
+
+    #[test]
+    fn forgets_what_the_conversation_no_longer_shows() {
+        let mut ledger = ContextLedger::default();
+        send(&mut ledger, &listing());
+        let second = send(&mut ledger, &listing());
+        // The first result was summarized away, so the second can't be rebuilt
+        ledger.retain_sent([second.as_str()]);
+        assert_eq!(send(&mut ledger, &listing()), evidence::label(3, &listing()));
+        // A prompt that starts afresh numbers from 1 again
+        ledger.resume(["a new prompt"]);
+        assert_eq!(send(&mut ledger, &listing()), evidence::label(1, &listing()));
+    }
+}
@@ -0,0 +1,162 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::{approval, humanize};
+
+// Paths listed in the confirmation, largest first
+const LARGEST_SHOWN: usize = 5;
+// Walking stops here so a huge tree doesn't stall the prompt; counts are then lower bounds
+const WALK_LIMIT: u64 = 200_000;
+
+/// What a destructive command would touch
+#[derive(Debug, Default)]
+pub struct Impact {
+    pub verb: &'static str,
+    pub files: u64,
+    pub dirs: u64,
+    pub bytes: u64,
+    pub largest: Vec<(PathBuf, u64)>,
+    /// The walk hit WALK_LIMIT before finishing
+    pub truncated: bool,
+}
+
+impl Impact {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} {}{} files and {} directories ({})",
+            self.verb,
+            if self.truncated { "at least " } else { "" },
+            self.files,
+            self.dirs,
+            humanize::bytes(self.bytes)
+        )
+    }
+
+    pub fn details(&self) -> String {
+        let mut details = String::from("Largest paths:\n");
+        for (path, size) in &self.largest {
+            details.push_str(&format!("  {:>10}  {}\n", humanize::bytes(*size), path.display()));
+        }
+        details
+    }
+
+    fn add(&mut self, path: &Path) {
+        let mut pending = vec![path.to_path_buf()];
+        while let Some(path) = pending.pop() {
+            if self.files + self.dirs >= WALK_LIMIT {
+                self.truncated = true;
+                return;
+            }
+            // Symlinks are affected themselves, never what they point to
+            let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+                continue;
+            };
+            if metadata.is_dir() {
+                self.dirs += 1;
+                if let Ok(entries) = std::fs::read_dir(&path) {
+                    pending.extend(entries.flatten().map(|entry| entry.path()));
+                }
+            } else {
+                self.files += 1;
+                self.bytes += metadata.len();
+                self.largest.push((path, metadata.len()));
+                self.largest.sort_by(|a, b| b.1.cmp(&a.1));
+                self.largest.truncate(LARGEST_SHOWN);
+            }
+        }
+    }
+}
+
+/// The impact of a user-typed `rm`, `mv` or `chmod -R` with already-expanded
+/// arguments, or None for any other command
+pub fn assess(command: &str, args: &[OsString], cwd: &Path) -> Option<Impact> {
+    let (flags, operands) = split_flags(command, args);
+    let recursive = flags.iter().any(|flag| {
+        flag == "--recursive" || (!flag.starts_with("--") && flag.contains(['r', 'R']))
+    });
+
+    let (verb, targets): (&'static str, Vec<&OsString>) = match command {
+        "rm" => ("Removes", operands),
+        "mv" => {
+            let target_dir = flags.iter().any(|flag| flag == "-t" || flag.starts_with("--target-directory"));
+            if target_dir || operands.len() < 2 {
+                ("Moves", operands)
+            } else {
+                // The destination is only affected when an existing file gets overwritten
+                let (destination, sources) = operands.split_last()?;
+                let mut targets = sources.to_vec();
+                if cwd.join(destination).is_file() {
+                    targets.push(destination);
+                }
+                ("Moves or overwrites", targets)
+            }
+        }
+        // The first operand is the mode, unless it comes from --reference
+        "chmod" if recursive => {
+            let skip = usize::from(!flags.iter().any(|flag| flag.starts_with("--reference")));
+            ("Changes permissions of", operands.into_iter().skip(skip).collect())
+        }
+        _ => return None,
+    };
+
+    let mut impact = Impact { verb, ..Impact::default() };
+    for target in targets {
+        let path = cwd.join(target);
+        if command == "rm" && !recursive && path.is_dir() {
+            // rm refuses directories without -r
+            continue;
+        }
+        impact.add(&path);
+    }
+    Some(impact)
+}
+
+/// Split arguments into flags and operands; everything after `--` is an operand
+fn split_flags<'a>(command: &str, args: &'a [OsString]) -> (Vec<String>, Vec<&'a OsString>) {
+    let mut flags = Vec::new();
+    let mut operands = Vec::new();
+    let mut only_operands = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy();
+        if only_operands || !text.starts_with('-') || text == "-" {
+            operands.push(arg);
+        } else if text == "--" {
+            only_operands = true;
+        } else if command == "chmod" && is_chmod_mode(&text) {
+            // `chmod -R -w dir`: -w is a mode, not a flag
+            operands.push(arg);
+        } else {
+            if command == "mv" && text == "-t" {
+                // -t takes the next argument as its value
+                args.next();
+            }
+            flags.push(text.into_owned());
+        }
+    }
+    (flags, operands)
+}
+
+fn is_chmod_mode(arg: &str) -> bool {
+    !arg.starts_with("--") && arg[1..].contains(|c: char| !matches!(c, 'R' | 'c' | 'f' | 'v'))
+}
+
+/// Ask before a command that touches at least `min_files` files or
+/// `min_bytes` bytes. Returns whether it may run.
+pub fn confirm(command_line: &str, impact: &Impact, min_files: u64, min_bytes: u64) -> bool {
+    if impact.files + impact.dirs < min_files && impact.bytes < min_bytes {
+        return true;
+    }
+    println!("!!!! {}", impact.summary());
+    print!("{}", impact.details());
+    approval::ask(&approval::ApprovalRequest {
+        question: "Run it?",
+        subject: &format!("$ {}", command_line),
+        details: None,
+        editable: false,
+        always_key: None,
+        default_approve: false,
+        risky: true,
+    })
+    .approved()
+}
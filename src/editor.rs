@@ -0,0 +1,395 @@
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hinter, HistoryHinter};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Cmd, ConditionalEventHandler, Context, Event, EventContext, Helper, RepeatCount};
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::agent::AiAgent;
+use crate::config::Config;
+use crate::flag_stats::FlagStats;
+use crate::prompt_style::StyledPrompt;
+use crate::ui::{self, Role};
+use crate::{command_cache, context, prompt_pipe, ShellMode};
+
+// How long typing must pause before a model is asked for a suggestion
+const GHOST_DEBOUNCE: Duration = Duration::from_millis(400);
+// Shorter lines give the model too little to go on
+const GHOST_MIN_CHARS: usize = 3;
+
+const GHOST_SYSTEM_PROMPT: &str = "You complete shell commands as the user types them. \
+Reply with only the complete command line, starting with exactly what the user has typed so far. \
+No explanation, no code fences. If there is no likely completion, repeat the input unchanged.";
+
+/// rustyline helper that keeps reading while shell input is incomplete
+/// (open quotes, unbalanced brackets or an unterminated heredoc), and
+/// completes/hints flags learned from previous commands. Other hints come
+/// from history, fish-style, or from a model when ghost suggestions are on.
+pub struct AishHelper {
+    mode: Arc<Mutex<ShellMode>>,
+    flag_stats: Arc<Mutex<FlagStats>>,
+    history_hinter: HistoryHinter,
+    ghost: Option<GhostSuggestions>,
+    // Prompts rustyline is given by their visible text, drawn styled
+    prompts: Vec<StyledPrompt>,
+}
+
+impl AishHelper {
+    pub fn new(mode: Arc<Mutex<ShellMode>>, flag_stats: Arc<Mutex<FlagStats>>) -> Self {
+        Self { mode, flag_stats, history_hinter: HistoryHinter::new(), ghost: None, prompts: Vec::new() }
+    }
+
+    /// Turn AI ghost suggestions on or off to match `config`
+    pub fn configure_ghost(&mut self, config: &Config) {
+        self.ghost = config.ghost_suggestion_config().map(GhostSuggestions::new);
+    }
+
+    /// The prompts about to be read with; rustyline gets their `visible`
+    /// text and this draws the `styled` one over it
+    pub fn set_prompts(&mut self, prompts: Vec<StyledPrompt>) {
+        self.prompts = prompts;
+    }
+
+    /// Key handler that accepts the hint on screen with Right-arrow. An AI
+    /// suggestion that arrived after the line was last drawn isn't on screen
+    /// yet, so it is only accepted once a redraw has shown it.
+    pub fn accept_hint_handler(&self) -> AcceptHintHandler {
+        AcceptHintHandler
+    }
+
+    fn history_hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
+        self.history_hinter.hint(line, pos, ctx)
+            // Multi-line entries would wreck the prompt's layout
+            .filter(|hint| !hint.contains('\n'))
+    }
+
+    fn ghost_hint(&self, line: &str) -> Option<String> {
+        let ghost = self.ghost.as_ref()?;
+        self.shell_command(line)?;
+        ghost.hint(line)
+    }
+
+    /// The shell command portion of the line, without Agent mode's `$` prefix
+    fn shell_command<'l>(&self, line: &'l str) -> Option<&'l str> {
+        if !self.is_shell_input(line) {
+            return None;
+        }
+        let line = line.trim_start();
+        Some(line.strip_prefix('$').map(str::trim_start).unwrap_or(line))
+    }
+
+    fn flag_suggestions(&self, command: &str, prefix: &str) -> Vec<String> {
+        self.flag_stats.lock()
+            .map(|stats| stats.suggestions(command, prefix))
+            .unwrap_or_default()
+    }
+
+    /// Only shell input is checked; natural language prompts routinely
+    /// contain lone apostrophes and must submit on Enter.
+    fn is_shell_input(&self, input: &str) -> bool {
+        match self.mode.lock().map(|m| m.clone()) {
+            Ok(ShellMode::Command) => ask_prompt(input).is_none(),
+            _ => input.trim_start().starts_with('$'),
+        }
+    }
+}
+
+impl Helper for AishHelper {}
+
+impl Completer for AishHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let before_cursor = &line[..pos];
+        let Some(command_line) = self.shell_command(before_cursor) else {
+            return Ok((pos, Vec::new()));
+        };
+
+        let word_start = before_cursor.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let word = &before_cursor[word_start..];
+
+        // The first word is a command name, completed from the PATH cache
+        if !command_line.contains(char::is_whitespace) {
+            let (start, name) = match word.strip_prefix('$') {
+                Some(name) => (word_start + 1, name),
+                None => (word_start, word),
+            };
+            if name.is_empty() || name.contains('/') {
+                return Ok((pos, Vec::new()));
+            }
+            return Ok((start, command_cache::complete(name)));
+        }
+
+        let command = command_line.split_whitespace().next().unwrap_or_default();
+        if !word.starts_with('-') || command.is_empty() || command == word {
+            return Ok((pos, Vec::new()));
+        }
+
+        Ok((word_start, self.flag_suggestions(command, word)))
+    }
+}
+
+impl Hinter for AishHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
+        if pos < line.len() {
+            return None;
+        }
+        self.flag_hint(line)
+            .or_else(|| self.history_hint(line, pos, ctx))
+            .or_else(|| self.ghost_hint(line))
+    }
+}
+
+impl AishHelper {
+    fn flag_hint(&self, line: &str) -> Option<String> {
+        let command_line = self.shell_command(line)?;
+        let tokens: Vec<&str> = command_line.split_whitespace().collect();
+
+        match tokens.as_slice() {
+            // `tar ` -> hint the most common flags for tar
+            [command] if command_line.ends_with(char::is_whitespace) => {
+                self.flag_suggestions(command, "").into_iter().next()
+            }
+            // `tar -x` -> complete the rest of the most common matching combination
+            [command, args @ .., last] if last.starts_with('-') && !command_line.ends_with(char::is_whitespace) => {
+                let typed: Vec<&str> = args.iter()
+                    .chain(std::iter::once(last))
+                    .filter(|t| t.starts_with('-'))
+                    .copied()
+                    .collect();
+                let prefix = typed.join(" ");
+                self.flag_suggestions(command, &prefix)
+                    .into_iter()
+                    .next()
+                    .map(|combo| combo[prefix.len()..].to_string())
+                    .filter(|hint| !hint.is_empty())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Completions from a model for the line being typed. A request goes out once
+/// typing pauses; the answer is shown the next time the line is drawn, and
+/// Right-arrow accepts it from then on, never before it was seen.
+struct GhostSuggestions {
+    agent: Arc<AiAgent>,
+    suggestion: Arc<Mutex<GhostState>>,
+}
+
+#[derive(Default)]
+struct GhostState {
+    // Line the latest request is for; older requests drop their answers
+    pending: String,
+    // The full suggested line
+    suggested: Option<String>,
+}
+
+impl GhostState {
+    /// The rest of the suggestion, if it still continues `line`
+    fn remainder(&self, line: &str) -> Option<String> {
+        self.suggested.as_deref()
+            .and_then(|suggested| suggested.strip_prefix(line))
+            .filter(|rest| !rest.is_empty())
+            .map(str::to_string)
+    }
+}
+
+impl GhostSuggestions {
+    fn new(config: Config) -> Self {
+        Self {
+            agent: Arc::new(AiAgent::new(config)),
+            suggestion: Arc::new(Mutex::new(GhostState::default())),
+        }
+    }
+
+    fn hint(&self, line: &str) -> Option<String> {
+        let mut state = self.suggestion.lock().ok()?;
+        if let Some(rest) = state.remainder(line) {
+            return Some(rest);
+        }
+        if line.trim().chars().count() < GHOST_MIN_CHARS || line.contains('\n') || state.pending == line {
+            return None;
+        }
+        state.pending = line.to_string();
+        state.suggested = None;
+        drop(state);
+
+        // Hints are computed inside readline, which runs on the tokio runtime
+        let runtime = tokio::runtime::Handle::try_current().ok()?;
+        let agent = self.agent.clone();
+        let suggestion = self.suggestion.clone();
+        let line = line.to_string();
+        runtime.spawn(async move {
+            tokio::time::sleep(GHOST_DEBOUNCE).await;
+            let still_pending = |suggestion: &Mutex<GhostState>| {
+                suggestion.lock().is_ok_and(|state| state.pending == line)
+            };
+            if !still_pending(&suggestion) {
+                return;
+            }
+
+            let prompt = format!(
+                "Directory: {}\n{}\nComplete this command line:\n{}",
+                crate::state::current_dir().display(),
+                context::render(5, 200).unwrap_or_default(),
+                line
+            );
+            let Ok(reply) = agent.complete(GHOST_SYSTEM_PROMPT, &prompt).await else {
+                return;
+            };
+            let reply = reply.trim().trim_matches('`').lines().next().unwrap_or_default().to_string();
+            if let Ok(mut state) = suggestion.lock() {
+                if state.pending == line && reply.starts_with(&line) {
+                    state.suggested = Some(reply);
+                }
+            }
+        });
+        None
+    }
+}
+
+/// Right-arrow at the end of the line accepts the displayed hint; elsewhere
+/// it moves as usual
+pub struct AcceptHintHandler;
+
+impl ConditionalEventHandler for AcceptHintHandler {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        (ctx.pos() == ctx.line().len() && ctx.has_hint()).then_some(Cmd::CompleteHint)
+    }
+}
+
+impl Highlighter for AishHelper {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _default: bool) -> Cow<'b, str> {
+        match self.prompts.iter().find(|styled| styled.visible == prompt) {
+            Some(styled) => Cow::Borrowed(&styled.styled),
+            None => Cow::Borrowed(prompt),
+        }
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(ui::paint(Role::Muted, hint))
+    }
+}
+
+impl Validator for AishHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = ctx.input();
+        let shell_part = prompt_pipe::split(input).map_or(input, |(command, _)| command);
+        if self.is_shell_input(input) && is_incomplete(shell_part) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+/// The prompt in `ask <prompt>` or `? <prompt>`, which goes to the agent
+/// in any mode
+pub fn ask_prompt(input: &str) -> Option<&str> {
+    let input = input.trim();
+    let (word, prompt) = input.strip_prefix(':').unwrap_or(input).split_once(char::is_whitespace)?;
+    matches!(word, "ask" | "?").then_some(prompt.trim()).filter(|prompt| !prompt.is_empty())
+}
+
+/// Whether shell input needs more lines before it can run
+pub fn is_incomplete(input: &str) -> bool {
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut escaped = false;
+    let mut brackets: Vec<char> = Vec::new();
+    let mut heredocs: Vec<(String, bool)> = Vec::new();
+
+    for line in input.split('\n') {
+        // Heredoc bodies are consumed verbatim until their terminator
+        if !heredocs.is_empty() && !in_single && !in_double {
+            let (delimiter, strip_tabs) = &heredocs[0];
+            let candidate = if *strip_tabs { line.trim_start_matches('\t') } else { line };
+            if candidate == delimiter {
+                heredocs.remove(0);
+            }
+            continue;
+        }
+
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if escaped {
+                escaped = false;
+            } else if in_single {
+                if c == '\'' {
+                    in_single = false;
+                }
+            } else if c == '\\' {
+                escaped = true;
+            } else if in_double {
+                if c == '"' {
+                    in_double = false;
+                }
+            } else {
+                match c {
+                    '\'' => in_single = true,
+                    '"' => in_double = true,
+                    '#' if i == 0 || chars[i - 1].is_whitespace() => break,
+                    '(' | '{' | '[' => brackets.push(c),
+                    ')' | '}' | ']' => {
+                        brackets.pop();
+                    }
+                    '<' if chars.get(i + 1) == Some(&'<') && chars.get(i + 2) != Some(&'<') => {
+                        let (delimiter, strip_tabs, consumed) = parse_heredoc(&chars[i + 2..]);
+                        if !delimiter.is_empty() {
+                            heredocs.push((delimiter, strip_tabs));
+                        }
+                        i += 1 + consumed;
+                    }
+                    _ => {}
+                }
+            }
+            i += 1;
+        }
+        // A trailing backslash escapes the newline, not the next line's first char
+        escaped = false;
+    }
+
+    in_single || in_double || !brackets.is_empty() || !heredocs.is_empty()
+}
+
+/// Parse the delimiter after `<<`, returning (delimiter, strip_tabs, chars consumed)
+fn parse_heredoc(chars: &[char]) -> (String, bool, usize) {
+    let mut i = 0;
+    let strip_tabs = chars.first() == Some(&'-');
+    if strip_tabs {
+        i += 1;
+    }
+    while i < chars.len() && chars[i] == ' ' {
+        i += 1;
+    }
+
+    let mut delimiter = String::new();
+    let quote = match chars.get(i) {
+        Some(&q) if q == '\'' || q == '"' => {
+            i += 1;
+            Some(q)
+        }
+        _ => None,
+    };
+    while i < chars.len() {
+        let c = chars[i];
+        match quote {
+            Some(q) if c == q => {
+                i += 1;
+                break;
+            }
+            None if c.is_whitespace() || matches!(c, ';' | '|' | '&' | '<' | '>' | ')') => break,
+            _ => delimiter.push(c),
+        }
+        i += 1;
+    }
+
+    (delimiter, strip_tabs, i)
+}
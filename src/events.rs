@@ -0,0 +1,46 @@
+//! The shell-wide event bus. The shell, tools and TypeScript (`aish.emit`)
+//! queue events here; the shell delivers them to `aish.on` handlers in the
+//! configuration before the next prompt and between script lines, so
+//! handlers never run in the middle of a command.
+
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// A command line finished: `{ command, exit_code, duration_ms, cwd }`
+pub const COMMAND_FINISHED: &str = "command:finished";
+/// The input mode changed: `{ from, to }`
+pub const MODE_CHANGED: &str = "mode:changed";
+/// The shell's directory changed: `{ from, to }`
+pub const DIRECTORY_CHANGED: &str = "directory:changed";
+/// An agent tool wrote or deleted a file: `{ path, tool }`
+pub const FILE_CHANGED: &str = "file:changed";
+/// Handlers for this name receive every event as `{ event, payload }`
+pub const ANY: &str = "*";
+
+// Events delivered per drain, so handlers that emit in a loop can't hang the shell
+pub const MAX_EVENTS_PER_DRAIN: usize = 64;
+
+lazy_static::lazy_static! {
+    static ref PENDING: Mutex<VecDeque<(String, Value)>> = Mutex::new(VecDeque::new());
+}
+
+/// Queue an event for delivery
+pub fn emit(name: &str, payload: Value) {
+    tracing::debug!(event = name, "event emitted");
+    if let Ok(mut pending) = PENDING.lock() {
+        pending.push_back((name.to_string(), payload));
+    }
+}
+
+/// The oldest queued event, if any
+pub fn next() -> Option<(String, Value)> {
+    PENDING.lock().ok()?.pop_front()
+}
+
+/// Drop queued events, e.g. when nothing listens for them
+pub fn clear() {
+    if let Ok(mut pending) = PENDING.lock() {
+        pending.clear();
+    }
+}
@@ -0,0 +1,90 @@
+//! Tool results behind the agent's last answer, so claims can be audited
+//! before acting on them. Each result is numbered as it goes to the model,
+//! the answer cites them inline as `[N]`, and the cited ones are listed as
+//! footnotes under the response and expanded with Alt+e or `evidence`.
+
+use regex::Regex;
+use serde_json::Value;
+use std::sync::Mutex;
+
+// Longest argument shown in a footnote before it is cut short
+const MAX_SOURCE_ARG_CHARS: usize = 60;
+
+/// A tool result the model was given, numbered as it will cite it
+#[derive(Debug, Clone)]
+pub struct Evidence {
+    pub number: usize,
+    /// The tool and what it looked at, e.g. `read_file src/main.rs:120-160`
+    pub source: String,
+    pub output: String,
+}
+
+lazy_static::lazy_static! {
+    static ref CITATION: Regex = Regex::new(r"\[(\d+)\]").unwrap();
+    // Evidence cited by the most recent answer
+    static ref LAST: Mutex<Vec<Evidence>> = Mutex::new(Vec::new());
+}
+
+/// The tool name plus the argument that says what it looked at
+pub fn source(name: &str, args: &Value) -> String {
+    let text = |key: &str| args.get(key).and_then(Value::as_str);
+    let number = |keys: &[&str]| keys.iter().find_map(|key| args.get(*key).and_then(Value::as_u64));
+
+    let mut source = name.to_string();
+    if let Some(path) = ["path", "file", "file_path", "filename"].iter().find_map(|key| text(key)) {
+        source.push(' ');
+        source.push_str(&shorten(path));
+        match (number(&["start_line", "line_start", "start"]), number(&["end_line", "line_end", "end"])) {
+            (Some(start), Some(end)) => source.push_str(&format!(":{}-{}", start, end)),
+            (Some(start), None) => source.push_str(&format!(":{}", start)),
+            _ => {}
+        }
+    } else if let Some(detail) = ["command", "query", "pattern", "url", "name"].iter().find_map(|key| text(key)) {
+        source.push(' ');
+        source.push_str(&shorten(detail));
+    }
+    source
+}
+
+fn shorten(text: &str) -> String {
+    let text = text.lines().next().unwrap_or_default();
+    if text.chars().count() <= MAX_SOURCE_ARG_CHARS {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(MAX_SOURCE_ARG_CHARS).collect();
+    format!("{}…", cut)
+}
+
+/// The tool result as sent to the model, labelled with its number
+pub fn label(number: usize, output: &str) -> String {
+    format!("[evidence {}]\n{}", number, output)
+}
+
+/// The number of the evidence a message sent to the model starts with
+pub fn number(message: &str) -> Option<usize> {
+    message.strip_prefix("[evidence ")?.split(']').next()?.parse().ok()
+}
+
+/// The evidence `answer` cites, in order of number
+pub fn cited(answer: &str, evidence: &[Evidence]) -> Vec<Evidence> {
+    let mut numbers: Vec<usize> = CITATION.captures_iter(answer)
+        .filter_map(|captures| captures[1].parse().ok())
+        .collect();
+    numbers.sort_unstable();
+    numbers.dedup();
+    numbers.into_iter()
+        .filter_map(|number| evidence.iter().find(|e| e.number == number).cloned())
+        .collect()
+}
+
+/// Remember what the latest answer cited, replacing the previous answer's
+pub fn set_last(evidence: Vec<Evidence>) {
+    if let Ok(mut last) = LAST.lock() {
+        *last = evidence;
+    }
+}
+
+/// Evidence cited by the latest answer
+pub fn last() -> Vec<Evidence> {
+    LAST.lock().map(|last| last.clone()).unwrap_or_default()
+}
@@ -0,0 +1,75 @@
+use anyhow::Result;
+use clap::Subcommand;
+
+use crate::ts_runtime::TypeScriptConfigLoader;
+
+/// Example plugins bundled into the binary: (name, description, source)
+const EXAMPLES: &[(&str, &str, &str)] = &[
+    (
+        "devops",
+        "Docker/Kubernetes tools, port listing and incident templates",
+        include_str!("../assets/examples/devops.ts"),
+    ),
+    (
+        "rust-dev",
+        "cargo check/test/clippy tools and Rust review templates",
+        include_str!("../assets/examples/rust-dev.ts"),
+    ),
+    (
+        "data-science",
+        "CSV summaries, Python evaluation and Jupyter helpers",
+        include_str!("../assets/examples/data-science.ts"),
+    ),
+];
+
+#[derive(Subcommand)]
+pub enum ExampleAction {
+    /// List the bundled example plugins
+    List,
+    /// Install a bundled example plugin into ~/.aish/plugins
+    Install {
+        /// Example name (see `aish example list`)
+        name: String,
+        /// Overwrite the plugin if it is already installed
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+pub fn run_example(action: ExampleAction) -> Result<()> {
+    match action {
+        ExampleAction::List => {
+            for (name, description, _) in EXAMPLES {
+                println!("{:<14} {}", name, description);
+            }
+            Ok(())
+        }
+        ExampleAction::Install { name, force } => install(&name, force),
+    }
+}
+
+fn install(name: &str, force: bool) -> Result<()> {
+    let (_, _, source) = EXAMPLES.iter()
+        .find(|(example, _, _)| *example == name)
+        .ok_or_else(|| {
+            let names: Vec<&str> = EXAMPLES.iter().map(|(n, _, _)| *n).collect();
+            anyhow::anyhow!("Unknown example '{}'. Available: {}", name, names.join(", "))
+        })?;
+
+    let plugins_dir = TypeScriptConfigLoader::plugins_dir()
+        .ok_or_else(|| anyhow::anyhow!("Could not determine home directory"))?;
+    std::fs::create_dir_all(&plugins_dir)?;
+
+    let path = plugins_dir.join(format!("{}.ts", name));
+    if path.exists() && !force {
+        return Err(anyhow::anyhow!(
+            "{} already exists; use --force to overwrite it",
+            path.display()
+        ));
+    }
+
+    std::fs::write(&path, source)?;
+    println!("Installed example '{}' to {}", name, path.display());
+    println!("It will be loaded the next time aish starts.");
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::{platform, AiAgent};
+
+// Man page text beyond this is cut off to keep the request small
+const MAN_PAGE_LIMIT: usize = 6000;
+
+const EXPLAIN_SYSTEM_PROMPT: &str = "You explain shell command lines without running them, \
+    like explainshell.com. Split the command line into its meaningful parts (program names, \
+    flags or flag groups, arguments, pipes, redirections) in the order they appear, using the \
+    exact text from the command line for each part. Reply with JSON only, no code fences, in \
+    the form {\"parts\": [{\"text\": \"...\", \"explanation\": \"...\"}], \"summary\": \"...\"}. \
+    Keep each explanation to one short line. When a man page excerpt is provided, base flag \
+    explanations on it.";
+
+#[derive(Debug, Deserialize)]
+struct Explanation {
+    parts: Vec<ExplainedPart>,
+    #[serde(default)]
+    summary: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExplainedPart {
+    text: String,
+    explanation: String,
+}
+
+/// Ask the model to annotate `command_line` and print the result as a tree
+/// hanging off the command, without executing anything.
+pub async fn explain(agent: &AiAgent, command_line: &str) -> Result<()> {
+    let mut prompt = format!("Command line:\n{}\n", command_line);
+    if let Some(man_page) = command_line.split_whitespace().next().and_then(man_page_excerpt) {
+        prompt.push_str("\nMan page excerpt:\n");
+        prompt.push_str(&man_page);
+    }
+
+    let response = agent.complete(EXPLAIN_SYSTEM_PROMPT, &prompt).await?;
+    let json = response.trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+    let explanation: Explanation = serde_json::from_str(json)
+        .map_err(|e| anyhow::anyhow!("Could not parse explanation ({}):\n{}", e, response))?;
+
+    print!("{}", render_tree(command_line, &explanation.parts));
+    if let Some(summary) = explanation.summary.filter(|s| !s.trim().is_empty()) {
+        println!();
+        println!("{}", summary.trim());
+    }
+    Ok(())
+}
+
+/// Plain-text man page for `program`, truncated, if one is installed
+fn man_page_excerpt(program: &str) -> Option<String> {
+    // Only look up plain program names; the command line is untrusted text
+    if !program.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '+' | '-')) {
+        return None;
+    }
+
+    let output = platform::command("man")
+        .arg(program)
+        .env("MANPAGER", "cat")
+        .env("MANWIDTH", "100")
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+
+    // Strip overstrike sequences used for bold/underline (c\bc, _\bc)
+    let raw = String::from_utf8_lossy(&output.stdout);
+    let mut text = String::with_capacity(raw.len());
+    for c in raw.chars() {
+        if c == '\u{8}' {
+            text.pop();
+        } else {
+            text.push(c);
+        }
+    }
+
+    let mut excerpt: String = text.chars().take(MAN_PAGE_LIMIT).collect();
+    if excerpt.len() < text.len() {
+        excerpt.push_str("\n[truncated]");
+    }
+    Some(excerpt)
+}
+
+/// Render parts as branches under the command line:
+///
+/// ```text
+/// tar -xzvf backup.tgz
+/// │   │     └── backup.tgz: archive to read
+/// │   └── -xzvf: extract, gunzip, verbose, from file
+/// └── tar: archiving utility
+/// ```
+fn render_tree(command_line: &str, parts: &[ExplainedPart]) -> String {
+    // Column of each part in the command line, searching left to right
+    let mut columns = Vec::with_capacity(parts.len());
+    let mut search_from = 0;
+    for part in parts {
+        let column = command_line[search_from..]
+            .find(part.text.as_str())
+            .map(|offset| search_from + offset);
+        if let Some(column) = column {
+            search_from = column + part.text.len();
+        }
+        columns.push(column);
+    }
+
+    let mut output = format!("{}\n", command_line);
+    let placed: Vec<(usize, &ExplainedPart)> = columns.iter()
+        .zip(parts)
+        .filter_map(|(column, part)| column.map(|c| (command_line[..c].chars().count(), part)))
+        .collect();
+
+    for (index, (column, part)) in placed.iter().enumerate().rev() {
+        let mut line = String::new();
+        for (earlier_column, _) in &placed[..index] {
+            line.push_str(&" ".repeat(earlier_column.saturating_sub(line.chars().count())));
+            line.push('│');
+        }
+        line.push_str(&" ".repeat(column.saturating_sub(line.chars().count())));
+        line.push_str(&format!("└── {}: {}", part.text, part.explanation));
+        output.push_str(&line);
+        output.push('\n');
+    }
+
+    // Parts the model reworded and that can't be located are listed at the end
+    for (column, part) in columns.iter().zip(parts) {
+        if column.is_none() {
+            output.push_str(&format!("    {}: {}\n", part.text, part.explanation));
+        }
+    }
+
+    output
+}
@@ -0,0 +1,79 @@
+//! Previews of what a file-writing agent tool is about to do, shown before
+//! the user confirms it. Tools are recognised by their arguments: `patch` is
+//! a unified diff shown as is, `path` with `content` replaces the file, `path`
+//! with `old_string` / `new_string` edits it; anything else is shown as the
+//! raw arguments.
+
+use serde_json::Value;
+use similar::TextDiff;
+use std::path::Path;
+
+use crate::patch;
+
+// Lines of unchanged context around each change in a preview
+const DIFF_CONTEXT_LINES: usize = 3;
+
+/// A change a tool wants to make: the file, as given, and a unified diff
+/// (or a description when the change can't be worked out from the arguments)
+pub struct Preview {
+    pub path: String,
+    pub diff: String,
+}
+
+/// What calling a file-writing tool with `args` would change, relative to `cwd`
+pub fn preview(args: &Value, cwd: &Path) -> Preview {
+    if let Some(diff) = args["patch"].as_str() {
+        let path = match patch::parse(diff) {
+            Ok(files) => files.iter().map(|file| file.path.as_str()).collect::<Vec<_>>().join(", "),
+            Err(_) => "(unparsed patch)".to_string(),
+        };
+        return Preview { path, diff: diff.to_string() };
+    }
+    let Some(path) = args["path"].as_str() else {
+        return Preview {
+            path: "(unknown file)".to_string(),
+            diff: format!("No preview available; the tool was called with:\n{}\n", pretty(args)),
+        };
+    };
+    let resolved = patch::resolve(path, cwd);
+    let current = std::fs::read_to_string(&resolved).ok();
+
+    let proposed = if let Some(content) = args["content"].as_str() {
+        Some(content.to_string())
+    } else if let (Some(old), Some(new)) = (args["old_string"].as_str(), args["new_string"].as_str()) {
+        current.as_deref().filter(|current| current.contains(old)).map(|current| {
+            if args["replace_all"].as_bool().unwrap_or(false) {
+                current.replace(old, new)
+            } else {
+                current.replacen(old, new, 1)
+            }
+        })
+    } else {
+        None
+    };
+
+    let diff = match proposed {
+        Some(proposed) => {
+            let old_header = if current.is_some() { format!("a/{}", path) } else { "/dev/null".to_string() };
+            let diff = TextDiff::from_lines(current.as_deref().unwrap_or_default(), &proposed)
+                .unified_diff()
+                .context_radius(DIFF_CONTEXT_LINES)
+                .header(&old_header, &format!("b/{}", path))
+                .to_string();
+            if diff.is_empty() {
+                "No changes to the file's content.\n".to_string()
+            } else {
+                diff
+            }
+        }
+        None if current.is_none() && !resolved.exists() => {
+            format!("{} does not exist; the tool was called with:\n{}\n", path, pretty(args))
+        }
+        None => format!("No preview available; the tool was called with:\n{}\n", pretty(args)),
+    };
+    Preview { path: path.to_string(), diff }
+}
+
+fn pretty(args: &Value) -> String {
+    serde_json::to_string_pretty(args).unwrap_or_else(|_| args.to_string())
+}
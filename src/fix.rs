@@ -0,0 +1,51 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::context::CommandRecord;
+use crate::AiAgent;
+
+// Stderr beyond this is cut from the front; the end is where the error usually is
+const OUTPUT_LIMIT: usize = 4000;
+
+const FIX_SYSTEM_PROMPT: &str = "You diagnose shell commands that failed. Given the command, \
+    its exit code, the working directory and what it printed, explain briefly why it failed and, \
+    if there is one, give a single corrected command that does what the user meant. Reply with \
+    JSON only, no code fences, in the form {\"diagnosis\": \"...\", \"command\": \"...\"}, using \
+    null for command when no fix is possible. Keep the diagnosis to one or two sentences.";
+
+/// The model's take on a failed command
+#[derive(Debug, Deserialize)]
+pub struct Fix {
+    pub diagnosis: String,
+    #[serde(default)]
+    pub command: Option<String>,
+}
+
+/// Ask the model why `record` failed and how to correct it
+pub async fn suggest_fix(agent: &AiAgent, record: &CommandRecord, current_dir: &Path) -> Result<Fix> {
+    let output = record.output.trim_end();
+    let count = output.chars().count();
+    let output: String = output.chars().skip(count.saturating_sub(OUTPUT_LIMIT)).collect();
+
+    let prompt = format!(
+        "Command: {}\nExit code: {}\nWorking directory: {}\nOutput:\n{}",
+        record.command,
+        record.exit_code,
+        current_dir.display(),
+        if output.is_empty() { "(none captured)" } else { &output },
+    );
+
+    let response = agent.complete(FIX_SYSTEM_PROMPT, &prompt).await?;
+    let json = response.trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+    let mut fix: Fix = serde_json::from_str(json)
+        .map_err(|e| anyhow::anyhow!("Could not parse suggestion ({}):\n{}", e, response))?;
+    fix.command = fix.command
+        .map(|command| command.trim().to_string())
+        .filter(|command| !command.is_empty() && *command != record.command);
+    Ok(fix)
+}
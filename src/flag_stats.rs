@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Per-command frequency of flag combinations seen in successful commands,
+/// used for local completion and hints (e.g. `tar ` hints `-xzvf`).
+#[derive(Debug, Default)]
+pub struct FlagStats {
+    counts: HashMap<String, HashMap<String, u32>>,
+}
+
+impl FlagStats {
+    fn path() -> Option<PathBuf> {
+        dirs::home_dir().map(|mut p| { p.push(".aish"); p.push("flag_stats.json"); p })
+    }
+
+    pub fn load() -> Self {
+        let counts = Self::path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Self { counts }
+    }
+
+    fn save(&self) {
+        let Some(path) = Self::path() else {
+            return;
+        };
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if let Ok(content) = serde_json::to_string(&self.counts) {
+            let _ = std::fs::write(path, content);
+        }
+    }
+
+    /// Learn the flags used in a command line that ran successfully
+    pub fn record(&mut self, command_line: &str) {
+        let Some((command, flags)) = split_flags(command_line) else {
+            return;
+        };
+        if flags.is_empty() {
+            return;
+        }
+        *self.counts.entry(command)
+            .or_default()
+            .entry(flags.join(" "))
+            .or_insert(0) += 1;
+        self.save();
+    }
+
+    /// Flag combinations for `command` starting with `prefix`, most frequent first
+    pub fn suggestions(&self, command: &str, prefix: &str) -> Vec<String> {
+        let Some(combos) = self.counts.get(command) else {
+            return Vec::new();
+        };
+        let mut ranked: Vec<(&String, &u32)> = combos.iter()
+            .filter(|(combo, _)| combo.starts_with(prefix))
+            .collect();
+        ranked.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        ranked.into_iter().map(|(combo, _)| combo.clone()).collect()
+    }
+}
+
+/// Split a simple command line into its command name and flag tokens
+fn split_flags(command_line: &str) -> Option<(String, Vec<String>)> {
+    let mut tokens = command_line.split_whitespace();
+    let command = tokens.next()?;
+    let name = command.rsplit('/').next().unwrap_or(command).to_string();
+    let flags = tokens
+        .take_while(|t| !matches!(*t, "|" | "||" | "&&" | ";" | "&" | ">" | ">>" | "<"))
+        .filter(|t| t.starts_with('-') && t.len() > 1 && *t != "--")
+        .map(|t| t.to_string())
+        .collect();
+    Some((name, flags))
+}
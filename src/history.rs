@@ -0,0 +1,208 @@
+//! Tagged history for the `history` builtin (`~/.aish/history.jsonl`): each
+//! command or prompt the user ran, with whether it ran as a shell command or
+//! went to the agent, so entries can be listed, searched, deleted and re-run
+//! the way they first ran.
+//!
+//! Arrow-key recall is kept per mode alongside it, so Up in Agent mode cycles
+//! through prompts and in Command mode through commands; a line starting
+//! with `$` in the other modes recalls commands (see `CommandRecallHandler`
+//! in the shell). The tagged history is the merged view of all of them.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rustyline::history::{DefaultHistory, History};
+
+use crate::ShellMode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    Command,
+    Prompt,
+}
+
+impl EntryKind {
+    pub fn tag(self) -> &'static str {
+        match self {
+            EntryKind::Command => "cmd",
+            EntryKind::Prompt => "ai",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub kind: EntryKind,
+    /// The command or prompt, without the `$` or `ask` that routed it
+    pub text: String,
+    /// Seconds since the Unix epoch
+    pub time: u64,
+}
+
+fn path() -> Option<PathBuf> {
+    dirs::home_dir().map(|mut p| { p.push(".aish"); p.push("history.jsonl"); p })
+}
+
+/// The recall file for `mode`; commands keep the original `~/.aish/history`
+pub fn recall_path(mode: &ShellMode) -> Option<PathBuf> {
+    let name = match mode {
+        ShellMode::Command => "history",
+        ShellMode::Agent => "history.agent",
+        ShellMode::Translate => "history.translate",
+    };
+    dirs::home_dir().map(|mut p| { p.push(".aish"); p.push(name); p })
+}
+
+/// Which mode's recall a line typed in `mode` belongs to, and the text to
+/// recall: `$ command` in Agent or Translate mode is recalled with commands,
+/// without the `$`; everything else stays in the mode it was typed in
+pub fn recall_target(line: &str, mode: &ShellMode) -> (ShellMode, String) {
+    match (mode, line.trim_start().strip_prefix('$')) {
+        (ShellMode::Agent | ShellMode::Translate, Some(command)) if !command.trim().is_empty() => {
+            (ShellMode::Command, command.trim_start().to_string())
+        }
+        _ => (mode.clone(), line.to_string()),
+    }
+}
+
+/// `mode`'s recall lines, oldest first
+pub fn recall_entries(mode: &ShellMode, max_len: usize) -> Vec<String> {
+    load_recall(mode, max_len).iter().cloned().collect()
+}
+
+/// Add `line` to the recall file of `mode` when the editor holds another mode's
+pub fn append_recall(mode: &ShellMode, line: &str, max_len: usize) {
+    let mut recall = load_recall(mode, max_len);
+    if recall.add(line).unwrap_or(false) {
+        save_recall(mode, &mut recall);
+    }
+}
+
+/// Remove the newest line of `mode`'s recall that is one of `lines`;
+/// returns whether one was found
+pub fn forget_recall(mode: &ShellMode, lines: &[String], max_len: usize) -> bool {
+    let mut kept = recall_entries(mode, max_len);
+    let Some(index) = kept.iter().rposition(|line| lines.contains(line)) else {
+        return false;
+    };
+    kept.remove(index);
+    let mut recall = DefaultHistory::new();
+    let _ = recall.set_max_len(max_len);
+    for line in &kept {
+        let _ = recall.add(line);
+    }
+    save_recall(mode, &mut recall);
+    true
+}
+
+fn load_recall(mode: &ShellMode, max_len: usize) -> DefaultHistory {
+    let mut recall = DefaultHistory::new();
+    let _ = recall.set_max_len(max_len);
+    if let Some(path) = recall_path(mode) {
+        let _ = recall.load(&path);
+    }
+    recall
+}
+
+fn save_recall(mode: &ShellMode, recall: &mut DefaultHistory) {
+    let Some(path) = recall_path(mode) else {
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    if let Err(e) = recall.save(&path) {
+        tracing::warn!(path = %path.display(), error = %e, "could not save history");
+    }
+}
+
+/// Append a command or prompt the user ran to the history
+pub fn record(kind: EntryKind, text: &str) {
+    let Some(path) = path() else {
+        return;
+    };
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let Ok(mut json) = serde_json::to_string(&Entry { kind, text: text.to_string(), time }) else {
+        return;
+    };
+    json.push('\n');
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    let appended = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(json.as_bytes()));
+    if let Err(e) = appended {
+        tracing::warn!(path = %path.display(), error = %e, "could not record history");
+    }
+}
+
+/// Every entry, oldest first; entry N in the builtin's numbering is `entries()[N - 1]`
+pub fn entries() -> Vec<Entry> {
+    let Some(content) = path().and_then(|path| std::fs::read_to_string(path).ok()) else {
+        return Vec::new();
+    };
+    content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Drop all but the newest `max_len` entries
+pub fn trim(max_len: usize) {
+    let entries = entries();
+    if entries.len() > max_len {
+        let _ = save(&entries[entries.len() - max_len..]);
+    }
+}
+
+/// Entry `number` (1-based)
+pub fn get(number: usize) -> Option<Entry> {
+    entries().into_iter().nth(number.checked_sub(1)?)
+}
+
+/// Remove entry `number` (1-based), returning it
+pub fn delete(number: usize) -> Result<Entry> {
+    let mut entries = entries();
+    if number == 0 || number > entries.len() {
+        return Err(anyhow!("{}: no such history entry", number));
+    }
+    let removed = entries.remove(number - 1);
+    save(&entries)?;
+    Ok(removed)
+}
+
+/// Entries whose text contains `term` (ignoring case), with their numbers
+pub fn search(term: &str) -> Vec<(usize, Entry)> {
+    let term = term.to_lowercase();
+    entries()
+        .into_iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.text.to_lowercase().contains(&term))
+        .map(|(index, entry)| (index + 1, entry))
+        .collect()
+}
+
+/// An entry as the builtin lists it
+pub fn format(number: usize, entry: &Entry) -> String {
+    // Multi-line entries are indented under their number
+    let text = entry.text.replace('\n', "\n            ");
+    format!("{:>5}  {:<4} {}", number, entry.kind.tag(), text)
+}
+
+fn save(entries: &[Entry]) -> Result<()> {
+    let path = path().ok_or_else(|| anyhow!("no home directory"))?;
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(&serde_json::to_string(entry)?);
+        content.push('\n');
+    }
+    std::fs::write(path, content)?;
+    Ok(())
+}
@@ -0,0 +1,143 @@
+//! Bash-style history expansion, applied to typed lines before routing:
+//! `!!` (the last entry), `!n` / `!-n` (entry n, or n back), `!prefix` (the
+//! last entry starting with prefix) and the word designators `!$`, `!^` and
+//! `!*` (last, first and all arguments of the last entry). Nothing expands
+//! inside single quotes or after a backslash, and `!` before a space, `=`,
+//! `(` or the end of the line stays literal. In prompts, where "it works!!"
+//! and "!important" are just text, only a reference that starts the line
+//! expands, and one that finds nothing leaves the prompt as typed.
+
+use anyhow::Result;
+
+/// `line` with history references replaced using `history` (oldest first),
+/// or None when it has none. `prose` is set for prompts rather than commands.
+pub fn expand(line: &str, history: &[String], prose: bool) -> Result<Option<String>> {
+    if prose {
+        let Some(rest) = line.strip_prefix('!') else {
+            return Ok(None);
+        };
+        return Ok(match designator(rest, true, history) {
+            Ok(Some((replacement, consumed))) => Some(format!("{}{}", replacement, &rest[consumed..])),
+            _ => None,
+        });
+    }
+
+    let mut result = String::new();
+    let mut changed = false;
+    let mut in_single_quotes = false;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\'' => in_single_quotes = !in_single_quotes,
+            '\\' if !in_single_quotes => {
+                result.push(c);
+                if let Some((_, escaped)) = chars.next() {
+                    result.push(escaped);
+                }
+                continue;
+            }
+            '!' if !in_single_quotes => {
+                let rest = &line[index + 1..];
+                if let Some((replacement, consumed)) = designator(rest, index == 0, history)? {
+                    result.push_str(&replacement);
+                    for _ in rest[..consumed].chars() {
+                        chars.next();
+                    }
+                    changed = true;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        result.push(c);
+    }
+    Ok(changed.then_some(result))
+}
+
+/// The text for the reference after a `!`, and how many bytes of `rest` it
+/// used; None when the `!` is literal
+fn designator(rest: &str, line_start: bool, history: &[String]) -> Result<Option<(String, usize)>> {
+    let Some(first) = rest.chars().next() else {
+        return Ok(None);
+    };
+    if first.is_whitespace() || matches!(first, '=' | '(' | '"') {
+        return Ok(None);
+    }
+
+    let last = || history.last().ok_or_else(|| anyhow::anyhow!("!{}: event not found", first));
+    let words = |entry: &str| command_part(entry).split_whitespace().map(str::to_string).collect::<Vec<_>>();
+    match first {
+        '!' => Ok(Some((event(last()?, line_start), 1))),
+        '$' => Ok(Some((words(last()?).last().cloned().unwrap_or_default(), 1))),
+        '^' => Ok(Some((words(last()?).get(1).cloned().unwrap_or_default(), 1))),
+        '*' => Ok(Some((words(last()?).get(1..).map(|args| args.join(" ")).unwrap_or_default(), 1))),
+        _ => {
+            let end = rest.find(|c: char| c.is_whitespace() || matches!(c, ';' | '|' | '&' | '\'' | '"' | ')'))
+                .unwrap_or(rest.len());
+            let spec = &rest[..end];
+            let entry = if let Ok(number) = spec.parse::<i64>() {
+                let position = if number < 0 {
+                    history.len().checked_sub(number.unsigned_abs() as usize)
+                } else {
+                    (number as usize).checked_sub(1)
+                };
+                position.and_then(|position| history.get(position))
+            } else {
+                history.iter().rev().find(|entry| command_part(entry).starts_with(spec))
+            };
+            let entry = entry.ok_or_else(|| anyhow::anyhow!("!{}: event not found", spec))?;
+            Ok(Some((event(entry, line_start), end)))
+        }
+    }
+}
+
+/// A whole history entry: as recorded (keeping an agent-mode `$ ` prefix)
+/// when it starts the line, otherwise just its command
+fn event(entry: &str, line_start: bool) -> String {
+    if line_start {
+        entry.to_string()
+    } else {
+        command_part(entry).to_string()
+    }
+}
+
+fn command_part(entry: &str) -> &str {
+    let entry = entry.trim_start();
+    entry.strip_prefix('$').map(str::trim_start).unwrap_or(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> Vec<String> {
+        vec!["$ cargo build".to_string(), "why did the build fail?".to_string()]
+    }
+
+    #[test]
+    fn prose_keeps_references_mid_sentence() {
+        assert_eq!(expand("it works!! but why?", &history(), true).unwrap(), None);
+        assert_eq!(expand("why does !important not apply", &history(), true).unwrap(), None);
+        assert_eq!(expand("run !cargo again", &history(), true).unwrap(), None);
+    }
+
+    #[test]
+    fn prose_expands_a_reference_starting_the_line() {
+        assert_eq!(expand("!!", &history(), true).unwrap(), Some("why did the build fail?".to_string()));
+        assert_eq!(expand("!why and now?", &history(), true).unwrap(), Some("why did the build fail? and now?".to_string()));
+    }
+
+    #[test]
+    fn prose_leaves_a_failed_lookup_alone() {
+        assert_eq!(expand("!important: read this", &history(), true).unwrap(), None);
+    }
+
+    #[test]
+    fn commands_expand_anywhere() {
+        let history = vec!["cargo build".to_string()];
+        assert_eq!(expand("sudo !!", &history, false).unwrap(), Some("sudo cargo build".to_string()));
+        assert_eq!(expand("echo '!!'", &history, false).unwrap(), None);
+        assert!(expand("echo !nothing", &history, false).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+//! Compact, consistently formatted sizes, durations and relative times for
+//! output shown to the user and to the model. Also available to TypeScript
+//! tools as `op_format_bytes`, `op_format_duration` and `op_format_relative_time`.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// `512 B`, `1.5 KB`, `20.0 MB` (powers of 1024)
+pub fn bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KB", "MB", "GB", "TB", "PB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// `350ms`, `2.4s`, `3m 12s`, `2h 5m`, `3d 4h`
+pub fn duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0 => format!("{}ms", duration.as_millis()),
+        1..60 => format!("{:.1}s", duration.as_secs_f64()),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        3600..86_400 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86_400, secs % 86_400 / 3600),
+    }
+}
+
+/// `just now`, `5m ago`, `3h ago`, `2d ago`, or `in 5m` for future times,
+/// for a time given as seconds since the epoch
+pub fn relative_time(epoch_secs: u64) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (distance, future) = if epoch_secs > now {
+        (epoch_secs - now, true)
+    } else {
+        (now - epoch_secs, false)
+    };
+    let amount = match distance {
+        0..60 => return "just now".to_string(),
+        60..3600 => format!("{}m", distance / 60),
+        3600..86_400 => format!("{}h", distance / 3600),
+        _ => format!("{}d", distance / 86_400),
+    };
+    if future {
+        format!("in {}", amount)
+    } else {
+        format!("{} ago", amount)
+    }
+}
+
+/// Today's local date as `2024-05-17` (UTC where the time zone is unknown)
+pub fn local_date() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let time = local_time(now);
+    format!("{:04}-{:02}-{:02}", time.year, time.month, time.day)
+}
+
+/// A calendar date and time of day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    pub hour: i64,
+    pub minute: i64,
+    pub second: i64,
+}
+
+/// Seconds since the epoch in local time (UTC where the time zone is unknown)
+pub fn local_time(epoch_secs: u64) -> LocalTime {
+    #[cfg(unix)]
+    {
+        let time = epoch_secs as libc::time_t;
+        let mut local: libc::tm = unsafe { std::mem::zeroed() };
+        if !unsafe { libc::localtime_r(&time, &mut local) }.is_null() {
+            return LocalTime {
+                year: i64::from(local.tm_year) + 1900,
+                month: i64::from(local.tm_mon) + 1,
+                day: i64::from(local.tm_mday),
+                hour: i64::from(local.tm_hour),
+                minute: i64::from(local.tm_min),
+                second: i64::from(local.tm_sec),
+            };
+        }
+    }
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let days = (epoch_secs / 86_400) as i64 + 719_468;
+    let seconds_of_day = (epoch_secs % 86_400) as i64;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    LocalTime {
+        year: year_of_era + era * 400 + if month <= 2 { 1 } else { 0 },
+        month,
+        day,
+        hour: seconds_of_day / 3600,
+        minute: seconds_of_day % 3600 / 60,
+        second: seconds_of_day % 60,
+    }
+}
+
+/// The first line of a command or message, trimmed, for one-line summaries
+pub fn first_line(text: &str) -> &str {
+    text.lines().next().unwrap_or_default().trim()
+}
@@ -0,0 +1,385 @@
+//! Job control for commands the user runs in an interactive session: each
+//! runs in its own process group that is given the terminal, so Ctrl-C and
+//! Ctrl-Z reach the command and not aish. A command stopped with Ctrl-Z goes
+//! into the jobs table for `jobs`, `fg` and `bg`, and the terminal's modes
+//! are put back after every command, however it left them.
+
+use std::io;
+use std::process::{Child, Command, ExitStatus};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+}
+
+/// A job in the table, as `jobs` lists it
+#[derive(Debug, Clone)]
+pub struct JobInfo {
+    pub number: usize,
+    pub state: JobState,
+    pub command: String,
+}
+
+/// The command line a `Command` runs, for job listings: the script of
+/// `sh -c`, or the program and its arguments
+fn describe(cmd: &Command) -> String {
+    let args: Vec<String> = cmd.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect();
+    match args.as_slice() {
+        [flag, script, ..] if matches!(flag.as_str(), "-c" | "/C" | "-Command") => script.clone(),
+        _ => std::iter::once(cmd.get_program().to_string_lossy().into_owned())
+            .chain(args)
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    // Ignored by the shell, which would otherwise be stopped or killed by
+    // them; children get the default dispositions back
+    const SHELL_IGNORED: [libc::c_int; 4] = [libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU, libc::SIGQUIT];
+
+    static ENABLED: AtomicBool = AtomicBool::new(false);
+
+    struct Job {
+        number: usize,
+        // Process group, led by the process aish started
+        pgid: libc::pid_t,
+        command: String,
+        state: JobState,
+        // The job's terminal modes when it stopped, given back on `fg`
+        termios: Option<libc::termios>,
+    }
+
+    lazy_static::lazy_static! {
+        static ref JOBS: Mutex<Vec<Job>> = Mutex::new(Vec::new());
+    }
+
+    fn enabled() -> bool {
+        ENABLED.load(Ordering::Relaxed)
+    }
+
+    pub fn init() {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 0 {
+                return;
+            }
+            // Started in the background by another shell: wait to be brought forward
+            loop {
+                let pgrp = libc::getpgrp();
+                if libc::tcgetpgrp(libc::STDIN_FILENO) == pgrp {
+                    break;
+                }
+                libc::kill(-pgrp, libc::SIGTTIN);
+            }
+            for signal in SHELL_IGNORED {
+                libc::signal(signal, libc::SIG_IGN);
+            }
+            let pid = libc::getpid();
+            if libc::getpgrp() != pid && libc::setpgid(pid, pid) < 0 {
+                tracing::warn!(error = %io::Error::last_os_error(), "job control unavailable");
+                return;
+            }
+            libc::tcsetpgrp(libc::STDIN_FILENO, pid);
+        }
+        ENABLED.store(true, Ordering::Relaxed);
+    }
+
+    pub fn prepare(cmd: &mut Command) {
+        if !enabled() {
+            // A time limit stops the command's whole group; without job
+            // control that is only safe off a terminal, where a background
+            // group reading it would be stopped
+            if crate::timeout::active() && unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+                cmd.process_group(0);
+            }
+            return;
+        }
+        cmd.process_group(0);
+        unsafe {
+            cmd.pre_exec(|| {
+                // The parent hands over the terminal too; whichever runs first wins the race
+                libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+                Ok(())
+            });
+        }
+    }
+
+    pub fn default_signals(cmd: &mut Command) {
+        unsafe {
+            cmd.pre_exec(|| {
+                for signal in SHELL_IGNORED {
+                    libc::signal(signal, libc::SIG_DFL);
+                }
+                Ok(())
+            });
+        }
+    }
+
+    pub fn wait(child: &mut Child, command: &str) -> io::Result<ExitStatus> {
+        if !enabled() {
+            return child.wait();
+        }
+        let pgid = child.id() as libc::pid_t;
+        let shell_termios = termios_now();
+        unsafe {
+            libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        }
+        let status = wait_for(pgid);
+        finish_foreground(pgid, command, None, shell_termios, status)
+    }
+
+    pub fn is_stopped(status: &ExitStatus) -> bool {
+        status.stopped_signal().is_some()
+    }
+
+    pub fn reap() {
+        let Ok(mut jobs) = JOBS.lock() else {
+            return;
+        };
+        jobs.retain_mut(|job| {
+            let mut status = 0;
+            let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+            match unsafe { libc::waitpid(job.pgid, &mut status, flags) } {
+                0 => true,
+                pid if pid < 0 => false,
+                _ if libc::WIFSTOPPED(status) => {
+                    job.state = JobState::Stopped;
+                    true
+                }
+                _ if libc::WIFCONTINUED(status) => {
+                    job.state = JobState::Running;
+                    true
+                }
+                _ => {
+                    eprintln!("[{}]  Done{:>20}{}", job.number, "", job.command);
+                    false
+                }
+            }
+        });
+    }
+
+    pub fn list() -> Vec<JobInfo> {
+        let Ok(jobs) = JOBS.lock() else {
+            return Vec::new();
+        };
+        jobs.iter()
+            .map(|job| JobInfo { number: job.number, state: job.state, command: job.command.clone() })
+            .collect()
+    }
+
+    pub fn foreground(spec: &str) -> Result<ExitStatus, String> {
+        if !enabled() {
+            return Err("no job control in this shell".to_string());
+        }
+        let job = {
+            let mut jobs = JOBS.lock().map_err(|_| "jobs table unavailable".to_string())?;
+            let index = find(&jobs, spec)?;
+            jobs.remove(index)
+        };
+        eprintln!("{}", job.command);
+        let shell_termios = termios_now();
+        unsafe {
+            libc::tcsetpgrp(libc::STDIN_FILENO, job.pgid);
+            if let Some(termios) = &job.termios {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, termios);
+            }
+            libc::kill(-job.pgid, libc::SIGCONT);
+        }
+        let status = wait_for(job.pgid);
+        finish_foreground(job.pgid, &job.command, Some(job.number), shell_termios, status).map_err(|e| e.to_string())
+    }
+
+    pub fn background(spec: &str) -> Result<(), String> {
+        let mut jobs = JOBS.lock().map_err(|_| "jobs table unavailable".to_string())?;
+        let index = find(&jobs, spec)?;
+        let job = &mut jobs[index];
+        unsafe {
+            libc::kill(-job.pgid, libc::SIGCONT);
+        }
+        job.state = JobState::Running;
+        eprintln!("[{}]+ {} &", job.number, job.command);
+        Ok(())
+    }
+
+    pub fn hangup() {
+        let Ok(mut jobs) = JOBS.lock() else {
+            return;
+        };
+        for job in jobs.drain(..) {
+            unsafe {
+                libc::kill(-job.pgid, libc::SIGHUP);
+                libc::kill(-job.pgid, libc::SIGCONT);
+            }
+        }
+    }
+
+    /// Wait for process `pid` to exit, be killed or stop; the raw wait status
+    fn wait_for(pid: libc::pid_t) -> io::Result<i32> {
+        let mut status = 0;
+        loop {
+            if unsafe { libc::waitpid(pid, &mut status, libc::WUNTRACED) } >= 0 {
+                return Ok(status);
+            }
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+    }
+
+    /// Take the terminal back once the foreground job `pgid` exits or stops,
+    /// restoring the shell's terminal modes; a stopped job joins the table
+    fn finish_foreground(
+        pgid: libc::pid_t,
+        command: &str,
+        number: Option<usize>,
+        shell_termios: Option<libc::termios>,
+        status: io::Result<i32>,
+    ) -> io::Result<ExitStatus> {
+        let job_termios = termios_now();
+        unsafe {
+            libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+            if let Some(termios) = &shell_termios {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, termios);
+            }
+        }
+        let status = status?;
+        if libc::WIFSTOPPED(status) {
+            if let Ok(mut jobs) = JOBS.lock() {
+                let number = number.unwrap_or_else(|| jobs.iter().map(|job| job.number).max().unwrap_or(0) + 1);
+                eprintln!("\n[{}]+  Stopped{:>17}{}", number, "", command);
+                jobs.push(Job {
+                    number,
+                    pgid,
+                    command: command.to_string(),
+                    state: JobState::Stopped,
+                    termios: job_termios,
+                });
+            }
+        }
+        Ok(ExitStatus::from_raw(status))
+    }
+
+    /// The job `spec` names: `%N` or `N`, or the most recent job when empty
+    fn find(jobs: &[Job], spec: &str) -> Result<usize, String> {
+        let spec = spec.trim();
+        if spec.is_empty() || spec == "%+" || spec == "%%" {
+            return jobs.len().checked_sub(1).ok_or_else(|| "no current job".to_string());
+        }
+        let number: usize = spec.trim_start_matches('%').parse().map_err(|_| format!("{}: no such job", spec))?;
+        jobs.iter().position(|job| job.number == number).ok_or_else(|| format!("{}: no such job", spec))
+    }
+
+    fn termios_now() -> Option<libc::termios> {
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        (unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } == 0).then_some(termios)
+    }
+}
+
+#[cfg(not(unix))]
+mod unix {
+    use super::*;
+
+    pub fn init() {}
+
+    pub fn prepare(_cmd: &mut Command) {}
+
+    pub fn default_signals(_cmd: &mut Command) {}
+
+    pub fn wait(child: &mut Child, _command: &str) -> io::Result<ExitStatus> {
+        child.wait()
+    }
+
+    pub fn is_stopped(_status: &ExitStatus) -> bool {
+        false
+    }
+
+    pub fn reap() {}
+
+    pub fn list() -> Vec<JobInfo> {
+        Vec::new()
+    }
+
+    pub fn foreground(_spec: &str) -> Result<ExitStatus, String> {
+        Err("job control needs Unix".to_string())
+    }
+
+    pub fn background(_spec: &str) -> Result<(), String> {
+        Err("job control needs Unix".to_string())
+    }
+
+    pub fn hangup() {}
+}
+
+/// Turn on job control when aish runs interactively on a terminal: take a
+/// process group of its own and the terminal, and ignore the signals that
+/// would stop it
+pub fn init() {
+    unix::init()
+}
+
+/// Set up `cmd` to run as its own foreground process group, when job
+/// control is on
+pub fn prepare(cmd: &mut Command) {
+    unix::prepare(cmd)
+}
+
+/// Give `cmd` back the signals [`init`] ignores. Ignored signals stay
+/// ignored across exec, so without this a child of aish could not be
+/// stopped with Ctrl-Z or quit with Ctrl-\\; [`crate::platform::command`]
+/// does it for every command aish starts.
+pub fn default_signals(cmd: &mut Command) {
+    unix::default_signals(cmd)
+}
+
+/// Wait for a child started after [`prepare`] to exit or stop, giving it the
+/// terminal meanwhile, and stop it if a time limit passes first. A stopped
+/// child is added to the jobs table and its stopped status returned.
+pub fn wait(child: &mut Child, cmd: &Command) -> io::Result<ExitStatus> {
+    let _watchdog = crate::timeout::watch(child.id());
+    unix::wait(child, &describe(cmd))
+}
+
+/// Run `cmd` in the foreground like `Command::status`, with job control
+pub fn status(cmd: &mut Command) -> io::Result<ExitStatus> {
+    prepare(cmd);
+    let mut child = cmd.spawn()?;
+    wait(&mut child, cmd)
+}
+
+/// Whether `status` is that of a child stopped by a signal (e.g. Ctrl-Z)
+pub fn is_stopped(status: &ExitStatus) -> bool {
+    unix::is_stopped(status)
+}
+
+/// Forget finished jobs, announcing them; call before each prompt
+pub fn reap() {
+    unix::reap()
+}
+
+/// The jobs table, oldest first
+pub fn list() -> Vec<JobInfo> {
+    unix::list()
+}
+
+/// Continue a job (`%N`, or the most recent) in the foreground and wait for it
+pub fn foreground(spec: &str) -> Result<ExitStatus, String> {
+    unix::foreground(spec)
+}
+
+/// Continue a stopped job in the background
+pub fn background(spec: &str) -> Result<(), String> {
+    unix::background(spec)
+}
+
+/// Hang up every job when the shell exits, so stopped jobs don't linger
+pub fn hangup() {
+    unix::hangup()
+}
@@ -0,0 +1,92 @@
+//! aish: an AI-powered shell.
+//!
+//! The library exposes the agent loop ([`AiAgent`]), configuration loading
+//! ([`Config`]), the TypeScript runtime and the interactive shell
+//! ([`AishShell`]) so they can be embedded in other programs. The `aish`
+//! binary is a thin CLI on top of it.
+
+pub mod agent;
+pub mod approval;
+pub mod capture;
+pub mod clipboard;
+pub mod command_cache;
+pub mod command_list;
+pub mod config;
+pub mod context;
+pub mod context_ledger;
+pub mod dry_run;
+pub mod editor;
+pub mod events;
+pub mod evidence;
+pub mod examples;
+pub mod explain;
+pub mod file_change;
+pub mod fix;
+pub mod flag_stats;
+pub mod history;
+pub mod history_expansion;
+pub mod humanize;
+pub mod jobs;
+pub mod logging;
+pub mod migrate;
+pub mod notify;
+pub mod patch;
+pub mod pathname;
+pub mod platform;
+pub mod project_index;
+pub mod prompt_pipe;
+pub mod prompt_style;
+pub mod provider_health;
+pub mod render;
+pub mod response_cache;
+pub mod run_report;
+pub mod share;
+pub mod search;
+pub mod self_update;
+pub mod session_store;
+pub mod shell;
+pub mod spinner;
+pub mod state;
+pub mod status_cache;
+pub mod substitution;
+pub mod summary;
+pub mod timeout;
+pub mod tool_rate;
+pub mod tool_server;
+pub mod transcript;
+pub mod translate;
+pub mod trash;
+pub mod ts_runtime;
+pub mod ui;
+pub mod usage;
+pub mod wasm_plugins;
+
+pub use agent::AiAgent;
+pub use config::{AiConfig, Config, ShellConfig};
+pub use shell::AishShell;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShellMode {
+    Agent,
+    Command,
+    /// Natural language is turned into a command that is pre-filled for review
+    Translate,
+}
+
+impl ShellMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShellMode::Agent => "agent",
+            ShellMode::Command => "command",
+            ShellMode::Translate => "translate",
+        }
+    }
+    
+    pub(crate) fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "command" => ShellMode::Command,
+            "translate" => ShellMode::Translate,
+            _ => ShellMode::Agent, // default to agent
+        }
+    }
+}
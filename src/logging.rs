@@ -0,0 +1,91 @@
+use std::path::{Path, PathBuf};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_LEVEL: &str = "info";
+// Daily files kept before the oldest is deleted
+const MAX_LOG_FILES: usize = 7;
+
+/// Where logs are written: `~/.local/state/aish` (the XDG state directory)
+pub fn log_dir() -> Option<PathBuf> {
+    dirs::state_dir()
+        .or_else(|| dirs::home_dir().map(|home| home.join(".local").join("state")))
+        .map(|dir| dir.join("aish"))
+}
+
+/// Send tracing events to a daily-rotated `aish.*.log` in [`log_dir`].
+/// `AISH_LOG` (a filter such as `debug` or `aish::agent=trace`) overrides
+/// `level`. Logging is silently skipped if the directory can't be created.
+/// The directory is private (0700) and the log files 0600.
+pub fn init(level: Option<&str>) {
+    let filter = std::env::var("AISH_LOG")
+        .ok()
+        .and_then(|directives| EnvFilter::try_new(directives).ok())
+        .or_else(|| level.and_then(|level| EnvFilter::try_new(level).ok()))
+        .unwrap_or_else(|| EnvFilter::new(DEFAULT_LEVEL));
+
+    let Some(dir) = log_dir() else {
+        return;
+    };
+    if create_private_dir(&dir).is_err() {
+        return;
+    }
+    let Ok(appender) = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("aish")
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(&dir)
+    else {
+        return;
+    };
+    // The appender opens files itself, so tighten the one it just opened
+    // (and those a rotation left behind) afterwards
+    make_files_private(&dir);
+
+    // Written synchronously so nothing is lost when aish exits via process::exit
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(appender)
+        .with_ansi(false)
+        .try_init();
+}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    std::fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)
+}
+
+#[cfg(unix)]
+fn make_files_private(dir: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("aish.") && name.ends_with(".log") {
+            let _ = std::fs::set_permissions(entry.path(), std::fs::Permissions::from_mode(0o600));
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn make_files_private(_dir: &Path) {}
+
+/// Enough of a secret to tell keys apart in logs, and no more
+pub fn redact(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() <= 8 {
+        return "****".to_string();
+    }
+    format!("****{}", chars[chars.len() - 4..].iter().collect::<String>())
+}
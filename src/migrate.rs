@@ -0,0 +1,328 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::ts_runtime::PromptTemplate;
+
+/// AI CLIs whose configuration `aish import` understands
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ImportSource {
+    #[value(name = "shell-gpt")]
+    ShellGpt,
+    Aichat,
+    Opencommit,
+}
+
+/// Settings extracted from another tool's configuration
+#[derive(Debug, Default)]
+struct ImportedConfig {
+    model: Option<String>,
+    base_url: Option<String>,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+    api_key: Option<String>,
+    /// Environment variable the original tool reads its key from
+    api_key_env: &'static str,
+    /// Role/alias name -> system prompt text
+    templates: BTreeMap<String, PromptTemplate>,
+    sources: Vec<PathBuf>,
+}
+
+/// Read the configuration of `source` and write an equivalent .aish.ts
+pub fn run_import(source: ImportSource, output: Option<PathBuf>, force: bool) -> Result<()> {
+    let imported = match source {
+        ImportSource::ShellGpt => import_shell_gpt()?,
+        ImportSource::Aichat => import_aichat()?,
+        ImportSource::Opencommit => import_opencommit()?,
+    };
+
+    if imported.sources.is_empty() {
+        return Err(anyhow::anyhow!("No configuration found for {:?}", source));
+    }
+
+    let script = render_config(source, &imported);
+
+    if output.as_deref() == Some(Path::new("-")) {
+        print!("{}", script);
+        return Ok(());
+    }
+
+    let output = output
+        .or_else(|| dirs::home_dir().map(|mut p| { p.push(".aish.ts"); p }))
+        .unwrap_or_else(|| PathBuf::from(".aish.ts"));
+
+    if output.exists() && !force {
+        return Err(anyhow::anyhow!(
+            "{} already exists; use --force to overwrite or --output - to print instead",
+            output.display()
+        ));
+    }
+
+    std::fs::write(&output, script)?;
+    for path in &imported.sources {
+        println!("Imported {}", path.display());
+    }
+    println!("Wrote {}", output.display());
+    Ok(())
+}
+
+fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .or_else(|| dirs::home_dir().map(|h| h.join(".config")))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Parse KEY=VALUE lines, ignoring comments and surrounding quotes
+fn parse_env_file(content: &str) -> BTreeMap<String, String> {
+    content.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim().trim_matches('"').trim_matches('\'');
+            (key.trim().to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/// Minimal reader for the block-style parts of a YAML file: every `key: value`
+/// scalar with the path of the keys it is nested under, like
+/// `clients.0.api_key` (sequence items are numbered from 0)
+fn parse_yaml_scalars(content: &str) -> Vec<(String, String)> {
+    let mut scalars = Vec::new();
+    // Enclosing keys and sequence items: (indentation, path segment, is an item, items seen)
+    let mut parents: Vec<(usize, String, bool, usize)> = Vec::new();
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let mut indent = line.len() - trimmed.len();
+        let mut rest = trimmed;
+
+        if let Some(item) = rest.strip_prefix("- ").or_else(|| (rest == "-").then_some("")) {
+            // Items may sit at their key's indentation, so only earlier items end here
+            while parents.last().is_some_and(|&(parent, _, is_item, _)| parent > indent || (parent == indent && is_item)) {
+                parents.pop();
+            }
+            let index = parents.last_mut().map_or(0, |parent| {
+                parent.3 += 1;
+                parent.3 - 1
+            });
+            parents.push((indent, index.to_string(), true, 0));
+            let item_start = item.len() - item.trim_start().len();
+            indent += 2 + item_start;
+            rest = item.trim_start();
+        } else {
+            while parents.last().is_some_and(|&(parent, ..)| parent >= indent) {
+                parents.pop();
+            }
+        }
+
+        let Some((key, value)) = rest.split_once(':') else {
+            continue;
+        };
+        let key = key.trim().trim_matches('"').trim_matches('\'');
+        let value = value.trim().trim_matches('"').trim_matches('\'');
+        if value.is_empty() {
+            parents.push((indent, key.to_string(), false, 0));
+        } else {
+            let path: Vec<&str> = parents.iter().map(|(_, segment, ..)| segment.as_str()).chain([key]).collect();
+            scalars.push((path.join("."), value.to_string()));
+        }
+    }
+    scalars
+}
+
+fn import_shell_gpt() -> Result<ImportedConfig> {
+    let dir = config_dir().join("shell_gpt");
+    let mut imported = ImportedConfig {
+        api_key_env: "OPENAI_API_KEY",
+        ..Default::default()
+    };
+
+    let rc_path = dir.join(".sgptrc");
+    if let Ok(content) = std::fs::read_to_string(&rc_path) {
+        let values = parse_env_file(&content);
+        imported.model = values.get("DEFAULT_MODEL").cloned();
+        imported.api_key = values.get("OPENAI_API_KEY").cloned();
+        imported.base_url = values.get("API_BASE_URL")
+            .filter(|url| url.as_str() != "default")
+            .cloned();
+        imported.sources.push(rc_path);
+    }
+
+    // Roles are stored as one JSON file per role: {"name": ..., "role": ...}
+    let roles_dir = dir.join("roles");
+    if let Ok(entries) = std::fs::read_dir(&roles_dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let Ok(content) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            let Ok(role) = serde_json::from_str::<Value>(&content) else {
+                continue;
+            };
+            if let (Some(name), Some(text)) = (role["name"].as_str(), role["role"].as_str()) {
+                imported.templates.insert(name.to_string(), PromptTemplate::Text(text.to_string()));
+                imported.sources.push(path);
+            }
+        }
+    }
+
+    Ok(imported)
+}
+
+fn import_aichat() -> Result<ImportedConfig> {
+    let dir = config_dir().join("aichat");
+    let mut imported = ImportedConfig {
+        api_key_env: "OPENAI_API_KEY",
+        ..Default::default()
+    };
+
+    let config_path = dir.join("config.yaml");
+    if let Ok(content) = std::fs::read_to_string(&config_path) {
+        let values: BTreeMap<String, String> = parse_yaml_scalars(&content).into_iter().collect();
+        // aichat models are written as client:model; use that client's settings
+        let (client_type, model) = match values.get("model") {
+            Some(model) => match model.split_once(':') {
+                Some((client, model)) => (Some(client), Some(model)),
+                None => (None, Some(model.as_str())),
+            },
+            None => (None, None),
+        };
+        imported.model = model.map(str::to_string);
+        imported.temperature = values.get("temperature").and_then(|value| value.parse().ok());
+
+        let client = (0..)
+            .map(|index| format!("clients.{}", index))
+            .take_while(|client| values.contains_key(&format!("{}.type", client)))
+            .find(|client| {
+                let name = values.get(&format!("{}.name", client)).or_else(|| values.get(&format!("{}.type", client)));
+                client_type.is_none() || name.map(String::as_str) == client_type
+            })
+            .unwrap_or_else(|| "clients.0".to_string());
+        imported.api_key = values.get(&format!("{}.api_key", client)).cloned();
+        imported.base_url = values.get(&format!("{}.api_base", client)).cloned();
+        imported.sources.push(config_path);
+    }
+
+    // Roles are markdown files whose body is the prompt
+    let roles_dir = dir.join("roles");
+    if let Ok(entries) = std::fs::read_dir(&roles_dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("md") {
+                continue;
+            }
+            let (Some(name), Ok(content)) = (
+                path.file_stem().and_then(|s| s.to_str()).map(|s| s.to_string()),
+                std::fs::read_to_string(&path),
+            ) else {
+                continue;
+            };
+            imported.templates.insert(name, PromptTemplate::Text(strip_front_matter(&content).trim().to_string()));
+            imported.sources.push(path);
+        }
+    }
+
+    Ok(imported)
+}
+
+fn strip_front_matter(content: &str) -> &str {
+    content.strip_prefix("---")
+        .and_then(|rest| rest.split_once("\n---"))
+        .map(|(_, body)| body)
+        .unwrap_or(content)
+}
+
+fn import_opencommit() -> Result<ImportedConfig> {
+    let mut imported = ImportedConfig {
+        api_key_env: "OCO_API_KEY",
+        ..Default::default()
+    };
+
+    let Some(rc_path) = dirs::home_dir().map(|h| h.join(".opencommit")) else {
+        return Ok(imported);
+    };
+    if let Ok(content) = std::fs::read_to_string(&rc_path) {
+        let values = parse_env_file(&content);
+        imported.model = values.get("OCO_MODEL").cloned();
+        imported.api_key = values.get("OCO_API_KEY")
+            .or_else(|| values.get("OCO_OPENAI_API_KEY"))
+            .cloned();
+        imported.base_url = values.get("OCO_API_URL")
+            .or_else(|| values.get("OCO_OPENAI_BASE_PATH"))
+            .cloned();
+        imported.max_tokens = values.get("OCO_TOKENS_MAX_OUTPUT").and_then(|v| v.parse().ok());
+        imported.sources.push(rc_path);
+
+        // opencommit is a commit message generator, so carry that over as a
+        // template, pinned to deterministic sampling so reruns agree
+        let language = values.get("OCO_LANGUAGE").map(String::as_str).unwrap_or("en");
+        imported.templates.insert(
+            "commit".to_string(),
+            PromptTemplate::Full {
+                prompt: format!(
+                    "Write a concise conventional commit message (language: {}) for the staged changes shown by `git diff --staged`.",
+                    language
+                ),
+                preset: Some("deterministic".to_string()),
+                sampling: Default::default(),
+            },
+        );
+    }
+
+    Ok(imported)
+}
+
+fn render_config(source: ImportSource, imported: &ImportedConfig) -> String {
+    let mut ai = serde_json::Map::new();
+    if let Some(model) = &imported.model {
+        ai.insert("model".to_string(), json!(model));
+    }
+    if let Some(base_url) = &imported.base_url {
+        ai.insert("base_url".to_string(), json!(base_url));
+    }
+    ai.insert("temperature".to_string(), json!(imported.temperature.unwrap_or(0.7)));
+    ai.insert("max_tokens".to_string(), json!(imported.max_tokens.unwrap_or(1000)));
+
+    let ai_fields: String = ai.iter()
+        .map(|(key, value)| format!("    {}: {},\n", key, value))
+        .collect();
+
+    // Keep a literal key only if the source tool stored one; otherwise read the same env var
+    let api_key_line = match &imported.api_key {
+        Some(key) => format!("    api_key: {},\n", json!(key)),
+        None => format!("    api_key: Deno.core.ops.op_get_env({}),\n", json!(imported.api_key_env)),
+    };
+
+    let templates = serde_json::to_string_pretty(&imported.templates).unwrap_or_else(|_| "{}".to_string());
+
+    format!(
+        r#"// aish configuration imported from {source:?}
+// Generated by `aish import`; review and adjust as needed.
+
+const config = {{
+  ai: {{
+{ai_fields}{api_key_line}  }},
+  shell: {{
+    prompt: "aish> ",
+    history_size: 1000,
+    multiline_continuation: "... ",
+  }}
+}};
+
+// Roles and aliases from {source:?}, as named prompt templates; `template <name>` runs one
+const promptTemplates = {templates};
+
+globalThis.config = config;
+globalThis.promptTemplates = promptTemplates;
+"#
+    )
+}
@@ -0,0 +1,140 @@
+//! Telling the user a long command has finished. Both ways go through the
+//! terminal, which knows whether its window is focused: a bell only alerts
+//! (flash, urgency hint, dock bounce) when it isn't, and the notification
+//! escapes below are shown by the terminal when unfocused.
+//!
+//! Agent turns use real desktop notifications instead (`shell.notifications`),
+//! for long autonomous runs the user has switched away from.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::humanize;
+
+/// When agent turns are announced on the desktop
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AgentNotifications {
+    /// Turns that ran at least this long announce that they finished
+    pub after: Duration,
+    /// Approvals the agent waits for are announced right away
+    pub approvals: bool,
+}
+
+lazy_static::lazy_static! {
+    static ref AGENT_NOTIFICATIONS: Mutex<Option<AgentNotifications>> = Mutex::new(None);
+    // When the agent turn in progress started
+    static ref TURN_STARTED: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+/// Set (or clear) desktop notifications for agent turns
+pub fn configure_agent(notifications: Option<AgentNotifications>) {
+    if let Ok(mut current) = AGENT_NOTIFICATIONS.lock() {
+        *current = notifications;
+    }
+}
+
+fn agent_notifications() -> Option<AgentNotifications> {
+    AGENT_NOTIFICATIONS.lock().ok().and_then(|current| *current)
+}
+
+/// An agent turn is starting
+pub fn agent_turn_started() {
+    if let Ok(mut started) = TURN_STARTED.lock() {
+        *started = Some(Instant::now());
+    }
+}
+
+/// The agent turn for `prompt` is over; announce it if it ran long enough
+pub fn agent_turn_finished(prompt: &str, succeeded: bool) {
+    let Some(started) = TURN_STARTED.lock().ok().and_then(|mut started| started.take()) else {
+        return;
+    };
+    let Some(notifications) = agent_notifications() else {
+        return;
+    };
+    let elapsed = started.elapsed();
+    if elapsed < notifications.after {
+        return;
+    }
+    let summary = if succeeded { "Agent finished" } else { "Agent stopped with an error" };
+    let body = format!("{} ({})", humanize::first_line(prompt), humanize::duration(elapsed));
+    show_desktop(summary, &body);
+}
+
+/// The agent is waiting for the user to approve `subject`
+pub fn approval_needed(question: &str, subject: &str) {
+    let running = TURN_STARTED.lock().is_ok_and(|started| started.is_some());
+    if running && agent_notifications().is_some_and(|notifications| notifications.approvals) {
+        show_desktop(&format!("Approval needed: {}", question), humanize::first_line(subject));
+    }
+}
+
+/// Show a notification through the desktop's notification service, off
+/// the shell's thread since it may wait on D-Bus
+fn show_desktop(summary: &str, body: &str) {
+    let (summary, body) = (summary.to_string(), body.to_string());
+    std::thread::spawn(move || {
+        let shown = notify_rust::Notification::new()
+            .appname("aish")
+            .summary(&summary)
+            .body(&body)
+            .show();
+        if let Err(e) = shown {
+            tracing::debug!(error = %e, "desktop notification failed");
+        }
+    });
+}
+
+/// How a finished long command is announced (`shell.notify`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NotifyStyle {
+    Bell,
+    Desktop,
+    Both,
+}
+
+impl NotifyStyle {
+    pub fn parse(style: &str) -> Option<Self> {
+        match style {
+            "bell" => Some(NotifyStyle::Bell),
+            "desktop" => Some(NotifyStyle::Desktop),
+            "both" => Some(NotifyStyle::Both),
+            _ => None,
+        }
+    }
+}
+
+/// Announce that `command` finished after `elapsed` with `exit_code`
+pub fn command_finished(command: &str, exit_code: i32, elapsed: Duration, style: NotifyStyle) {
+    let mut stderr = io::stderr();
+    if !stderr.is_terminal() {
+        return;
+    }
+    let status = if exit_code == 0 { "done".to_string() } else { format!("failed ({})", exit_code) };
+    let body = format!("{} {} after {}", humanize::first_line(command), status, humanize::duration(elapsed));
+    if style != NotifyStyle::Bell {
+        let _ = write!(stderr, "{}", desktop_notification(&body));
+    }
+    if style != NotifyStyle::Desktop {
+        let _ = write!(stderr, "\x07");
+    }
+    let _ = stderr.flush();
+}
+
+/// The notification escape the running terminal understands: OSC 9 for
+/// iTerm2, OSC 99 (unfocused only) for kitty, OSC 777 elsewhere (foot,
+/// WezTerm, Ghostty, urxvt, VTE-based terminals)
+fn desktop_notification(body: &str) -> String {
+    // Control characters would end the escape sequence early
+    let body: String = body.chars().filter(|c| !c.is_control()).collect();
+    let term_program = std::env::var("TERM_PROGRAM").unwrap_or_default();
+    let term = std::env::var("TERM").unwrap_or_default();
+    if term_program == "iTerm.app" {
+        format!("\x1b]9;aish: {}\x07", body)
+    } else if term == "xterm-kitty" {
+        format!("\x1b]99;i=aish:o=unfocused;aish: {}\x1b\\", body)
+    } else {
+        format!("\x1b]777;notify;aish;{}\x07", body.replace(';', ","))
+    }
+}
@@ -0,0 +1,434 @@
+//! Unified diffs as the agent's `apply_patch` tool writes them: one or more
+//! files, each with `---`/`+++` headers and `@@` hunks. Hunks are matched on
+//! their context and removed lines, searching outward from the stated line
+//! so a patch still applies when earlier lines have shifted. Hunks must have
+//! as many lines as their headers count. A patch applies to every file or
+//! to none.
+
+use anyhow::{anyhow, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tempfile::{NamedTempFile, TempPath};
+
+use crate::platform;
+
+/// The changes to one file
+#[derive(Debug)]
+pub struct FilePatch {
+    /// Path after the change, or before it for a deletion (`+++ /dev/null`)
+    pub path: String,
+    pub creates: bool,
+    pub deletes: bool,
+    /// This file's part of the patch, headers included
+    pub diff: String,
+    hunks: Vec<Hunk>,
+}
+
+#[derive(Debug)]
+struct Hunk {
+    // 1-based line where the hunk starts in the original file
+    old_start: usize,
+    // (' ' | '-' | '+', text)
+    lines: Vec<(char, String)>,
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines.iter().filter(|(kind, _)| *kind != '+').map(|(_, text)| text.as_str()).collect()
+    }
+
+    fn new_lines(&self) -> Vec<&str> {
+        self.lines.iter().filter(|(kind, _)| *kind != '-').map(|(_, text)| text.as_str()).collect()
+    }
+}
+
+/// The result of applying a patch to one file
+#[derive(Debug)]
+pub struct Applied {
+    pub path: PathBuf,
+    /// New content, or None when the file is deleted
+    pub content: Option<String>,
+    pub hunks: usize,
+}
+
+/// Parse a unified diff into per-file patches
+pub fn parse(patch: &str) -> Result<Vec<FilePatch>> {
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut old_path: Option<String> = None;
+    let mut old_header = "";
+    let mut lines = patch.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        if let Some(path) = line.strip_prefix("--- ") {
+            old_path = Some(header_path(path));
+            old_header = line;
+        } else if let Some(path) = line.strip_prefix("+++ ") {
+            let old = old_path.take().ok_or_else(|| anyhow!("`+++ {}` without a preceding `---` line", path))?;
+            let new = header_path(path);
+            let creates = old == "/dev/null";
+            let deletes = new == "/dev/null";
+            files.push(FilePatch {
+                path: if deletes { old } else { new },
+                creates,
+                deletes,
+                diff: format!("{}\n{}\n", old_header, line),
+                hunks: Vec::new(),
+            });
+        } else if let Some(range) = line.strip_prefix("@@ ") {
+            let file = files.last_mut().ok_or_else(|| anyhow!("hunk before any `---`/`+++` file header"))?;
+            let (old_start, mut old_count, mut new_count) = hunk_header(range)?;
+            let mut hunk = Hunk { old_start, lines: Vec::new() };
+            let mut raw = format!("{}\n", line);
+            let number = file.hunks.len() + 1;
+            let short = || anyhow!("{}: hunk {} has fewer lines than its `@@ {}` header counts", file.path, number, range);
+            while old_count > 0 || new_count > 0 {
+                let line = lines.next().ok_or_else(short)?;
+                if line.starts_with("@@ ") || is_file_header(line, lines.peek().copied()) {
+                    return Err(short());
+                }
+                raw.push_str(line);
+                raw.push('\n');
+                // Editors often strip the lone space from blank context lines
+                let (kind, text) = match line.chars().next() {
+                    None => (' ', ""),
+                    Some(kind @ (' ' | '-' | '+')) => (kind, &line[1..]),
+                    Some('\\') => continue,
+                    Some(_) => return Err(anyhow!("unexpected line in hunk for {}: {}", file.path, line)),
+                };
+                if kind != '+' {
+                    old_count = old_count.saturating_sub(1);
+                }
+                if kind != '-' {
+                    new_count = new_count.saturating_sub(1);
+                }
+                hunk.lines.push((kind, text.to_string()));
+            }
+            // "\ No newline at end of file" after the last line
+            if let Some(line) = lines.next_if(|line| line.starts_with('\\')) {
+                raw.push_str(line);
+                raw.push('\n');
+            }
+            let next = lines.peek().copied().unwrap_or_default();
+            if next.starts_with([' ', '-', '+']) && !is_file_header(next, lines.clone().nth(1)) {
+                return Err(anyhow!("{}: hunk {} has more lines than its `@@ {}` header counts", file.path, number, range));
+            }
+            file.hunks.push(hunk);
+            file.diff.push_str(&raw);
+        }
+    }
+
+    if files.is_empty() {
+        return Err(anyhow!("no file headers (`--- a/path` / `+++ b/path`) found in the patch"));
+    }
+    if let Some(file) = files.iter().find(|file| file.hunks.is_empty() && !file.deletes) {
+        return Err(anyhow!("no hunks for {}", file.path));
+    }
+    Ok(files)
+}
+
+/// Work out the new content of every file the patch touches, relative to
+/// `cwd`, without writing anything
+pub fn apply(files: &[FilePatch], cwd: &Path) -> Result<Vec<Applied>> {
+    files.iter().map(|file| {
+        let path = resolve(&file.path, cwd);
+        if file.deletes {
+            if !path.is_file() {
+                return Err(anyhow!("{}: cannot delete, no such file", file.path));
+            }
+            return Ok(Applied { path, content: None, hunks: file.hunks.len() });
+        }
+
+        let original = if file.creates {
+            if path.exists() {
+                return Err(anyhow!("{}: already exists", file.path));
+            }
+            String::new()
+        } else {
+            std::fs::read_to_string(&path).map_err(|e| anyhow!("{}: {}", file.path, e))?
+        };
+        let trailing_newline = original.is_empty() || original.ends_with('\n');
+        let mut lines: Vec<String> = original.lines().map(str::to_string).collect();
+
+        // How far earlier hunks moved later ones
+        let mut offset: isize = 0;
+        for (index, hunk) in file.hunks.iter().enumerate() {
+            let old = hunk.old_lines();
+            let expected = (hunk.old_start.saturating_sub(1) as isize + offset).max(0) as usize;
+            let position = find_hunk(&lines, &old, expected)
+                .ok_or_else(|| anyhow!("{}: hunk {} (at line {}) does not match the file", file.path, index + 1, hunk.old_start))?;
+            let new: Vec<String> = hunk.new_lines().into_iter().map(str::to_string).collect();
+            offset += position as isize - expected as isize + new.len() as isize - old.len() as isize;
+            lines.splice(position..position + old.len(), new);
+        }
+
+        let mut content = lines.join("\n");
+        if trailing_newline && !lines.is_empty() {
+            content.push('\n');
+        }
+        Ok(Applied { path, content: Some(content), hunks: file.hunks.len() })
+    }).collect()
+}
+
+/// Write the results of [`apply`], all or nothing. New contents are first
+/// written to temporary files beside their targets; then each target is
+/// moved aside and replaced by a rename. If any step fails, the files
+/// already replaced are put back. Parent directories are created as needed
+/// and are left behind on failure.
+pub fn write(applied: &[Applied]) -> Result<()> {
+    let mut staged = Vec::new();
+    for file in applied {
+        let contents = match &file.content {
+            Some(content) => Some(stage(&file.path, content).map_err(|e| anyhow!("{}: {}", file.path.display(), e))?),
+            None => None,
+        };
+        staged.push(contents);
+    }
+
+    // Each replaced path with its original, kept until all are in place
+    let mut replaced: Vec<(&Path, Option<TempPath>)> = Vec::new();
+    for (file, contents) in applied.iter().zip(staged) {
+        match replace(&file.path, contents) {
+            Ok(original) => replaced.push((&file.path, original)),
+            Err(e) => {
+                for (path, original) in replaced.into_iter().rev() {
+                    match original {
+                        Some(original) => {
+                            let _ = std::fs::rename(&original, path);
+                        }
+                        None => {
+                            let _ = std::fs::remove_file(path);
+                        }
+                    }
+                }
+                return Err(anyhow!("{}: {}", file.path.display(), e));
+            }
+        }
+    }
+    // The originals are deleted as `replaced` drops
+    Ok(())
+}
+
+/// The directory `path` is in
+fn parent_dir(path: &Path) -> &Path {
+    path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."))
+}
+
+/// `content` in a temporary file next to `path`, with `path`'s permissions
+fn stage(path: &Path, content: &str) -> Result<NamedTempFile> {
+    let dir = parent_dir(path);
+    std::fs::create_dir_all(dir)?;
+    let mut staged = tempfile::Builder::new().prefix(".aish-patch-").tempfile_in(dir)?;
+    staged.write_all(content.as_bytes())?;
+    staged.flush()?;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        staged.as_file().set_permissions(metadata.permissions())?;
+    }
+    Ok(staged)
+}
+
+/// Move the file at `path` aside and put `contents` there (nothing, for a
+/// deletion); returns where the original went
+fn replace(path: &Path, contents: Option<NamedTempFile>) -> Result<Option<TempPath>> {
+    let original = if path.exists() {
+        let aside = tempfile::Builder::new().prefix(".aish-orig-").tempfile_in(parent_dir(path))?.into_temp_path();
+        std::fs::rename(path, &aside)?;
+        Some(aside)
+    } else {
+        None
+    };
+    if let Some(contents) = contents {
+        if let Err(e) = contents.persist(path) {
+            if let Some(original) = &original {
+                let _ = std::fs::rename(original, path);
+            }
+            return Err(e.error.into());
+        }
+    }
+    Ok(original)
+}
+
+/// `path` relative to `cwd`, with `~` expanded
+pub fn resolve(path: &str, cwd: &Path) -> PathBuf {
+    platform::expand_home(path).unwrap_or_else(|| cwd.join(path))
+}
+
+/// Where `old` occurs in `lines`, closest to `expected` first
+fn find_hunk(lines: &[String], old: &[&str], expected: usize) -> Option<usize> {
+    if old.is_empty() {
+        return Some(expected.min(lines.len()));
+    }
+    let last = lines.len().checked_sub(old.len())?;
+    let matches = |start: usize| lines[start..start + old.len()].iter().zip(old).all(|(line, old)| line == old);
+    let expected = expected.min(last);
+    (0..=last.max(expected)).find_map(|distance| {
+        [expected.checked_sub(distance), expected.checked_add(distance)]
+            .into_iter()
+            .flatten()
+            .filter(|&start| start <= last)
+            .find(|&start| matches(start))
+    })
+}
+
+/// Whether `line`, followed by `next`, starts a new file rather than
+/// continuing a hunk with a removed line that happens to begin with `-- `
+fn is_file_header(line: &str, next: Option<&str>) -> bool {
+    line.starts_with("--- ") && next.is_some_and(|next| next.starts_with("+++ "))
+}
+
+/// The path in a `---`/`+++` header, without a timestamp or `a/`/`b/` prefix
+fn header_path(header: &str) -> String {
+    let path = header.split('\t').next().unwrap_or_default().trim();
+    path.strip_prefix("a/").or_else(|| path.strip_prefix("b/")).unwrap_or(path).to_string()
+}
+
+/// (old start, old count, new count) from `-l,c +l,c @@`
+fn hunk_header(range: &str) -> Result<(usize, usize, usize)> {
+    let invalid = || anyhow!("invalid hunk header: @@ {}", range);
+    let mut parts = range.split_whitespace();
+    let old = parts.next().and_then(|part| part.strip_prefix('-')).ok_or_else(invalid)?;
+    let new = parts.next().and_then(|part| part.strip_prefix('+')).ok_or_else(invalid)?;
+    let span = |spec: &str| -> Result<(usize, usize)> {
+        let (start, count) = spec.split_once(',').unwrap_or((spec, "1"));
+        Ok((start.parse().map_err(|_| invalid())?, count.parse().map_err(|_| invalid())?))
+    };
+    let (old_start, old_count) = span(old)?;
+    let (_, new_count) = span(new)?;
+    Ok((old_start, old_count, new_count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWO_FILES: &str = "\
+--- a/one.txt
++++ b/one.txt
+@@ -1,3 +1,3 @@
+ first
+-second
++SECOND
+ third
+--- /dev/null
++++ b/two.txt
+@@ -0,0 +1,2 @@
++new
++file
+";
+
+    fn write_file(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn parses_files_and_hunks() {
+        let files = parse(TWO_FILES).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, "one.txt");
+        assert!(!files[0].creates && !files[0].deletes);
+        assert_eq!(files[0].hunks.len(), 1);
+        assert_eq!(files[0].hunks[0].old_start, 1);
+        assert_eq!(files[0].hunks[0].old_lines(), ["first", "second", "third"]);
+        assert_eq!(files[0].hunks[0].new_lines(), ["first", "SECOND", "third"]);
+        assert_eq!(files[1].path, "two.txt");
+        assert!(files[1].creates);
+    }
+
+    #[test]
+    fn keeps_each_files_part_of_the_patch() {
+        let files = parse(TWO_FILES).unwrap();
+        let (one, two) = TWO_FILES.split_at(TWO_FILES.find("--- /dev/null").unwrap());
+        assert_eq!(files[0].diff, one);
+        assert_eq!(files[1].diff, two);
+    }
+
+    #[test]
+    fn parses_a_removed_line_that_looks_like_a_header() {
+        let patch = "--- a/f\n+++ b/f\n@@ -1,2 +1,1 @@\n--- not a header\n keep\n";
+        let files = parse(patch).unwrap();
+        assert_eq!(files[0].hunks[0].old_lines(), ["-- not a header", "keep"]);
+    }
+
+    #[test]
+    fn rejects_a_hunk_shorter_than_its_header() {
+        let patch = "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n";
+        assert!(parse(patch).unwrap_err().to_string().contains("fewer lines"));
+        let patch = "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n a\n@@ -5,1 +5,1 @@\n-e\n+E\n";
+        assert!(parse(patch).unwrap_err().to_string().contains("fewer lines"));
+    }
+
+    #[test]
+    fn rejects_a_hunk_longer_than_its_header() {
+        let patch = "--- a/f\n+++ b/f\n@@ -1,1 +1,1 @@\n-a\n+A\n b\n";
+        assert!(parse(patch).unwrap_err().to_string().contains("more lines"));
+    }
+
+    #[test]
+    fn rejects_patches_without_headers_or_hunks() {
+        assert!(parse("just text\n").is_err());
+        assert!(parse("--- a/f\n+++ b/f\n").is_err());
+        assert!(parse("@@ -1 +1 @@\n-a\n+b\n").is_err());
+    }
+
+    #[test]
+    fn applies_hunks_that_have_shifted() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "one.txt", "zero\nfirst\nsecond\nthird\n");
+        let files = parse(TWO_FILES).unwrap();
+        let applied = apply(&files, dir.path()).unwrap();
+        assert_eq!(applied[0].content.as_deref(), Some("zero\nfirst\nSECOND\nthird\n"));
+        assert_eq!(applied[1].content.as_deref(), Some("new\nfile\n"));
+    }
+
+    #[test]
+    fn reports_a_hunk_that_does_not_match() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "one.txt", "something else\n");
+        let files = parse(TWO_FILES).unwrap();
+        let error = apply(&files, dir.path()).unwrap_err().to_string();
+        assert!(error.contains("does not match"), "{}", error);
+    }
+
+    #[test]
+    fn writes_every_file() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "one.txt", "first\nsecond\nthird\n");
+        let gone = write_file(dir.path(), "gone.txt", "bye\n");
+        let mut applied = apply(&parse(TWO_FILES).unwrap(), dir.path()).unwrap();
+        applied.push(Applied { path: gone.clone(), content: None, hunks: 0 });
+        write(&applied).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.path().join("one.txt")).unwrap(), "first\nSECOND\nthird\n");
+        assert_eq!(std::fs::read_to_string(dir.path().join("two.txt")).unwrap(), "new\nfile\n");
+        assert!(!gone.exists());
+        // No staged or set-aside files are left behind
+        let names: Vec<String> = std::fs::read_dir(dir.path()).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert!(names.iter().all(|name| !name.starts_with(".aish-")), "{:?}", names);
+    }
+
+    #[test]
+    fn rolls_back_when_a_file_cannot_be_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let one = write_file(dir.path(), "one.txt", "original\n");
+        let gone = write_file(dir.path(), "gone.txt", "kept\n");
+        let created = dir.path().join("created.txt");
+        // A directory can't be replaced by a file
+        let blocked = dir.path().join("blocked");
+        std::fs::create_dir(&blocked).unwrap();
+        std::fs::write(blocked.join("inside"), "x").unwrap();
+
+        let applied = vec![
+            Applied { path: one.clone(), content: Some("changed\n".to_string()), hunks: 1 },
+            Applied { path: created.clone(), content: Some("new\n".to_string()), hunks: 1 },
+            Applied { path: gone.clone(), content: None, hunks: 0 },
+            Applied { path: blocked.clone(), content: Some("file\n".to_string()), hunks: 1 },
+        ];
+        assert!(write(&applied).is_err());
+        assert_eq!(std::fs::read_to_string(&one).unwrap(), "original\n");
+        assert_eq!(std::fs::read_to_string(&gone).unwrap(), "kept\n");
+        assert!(!created.exists());
+        assert!(blocked.join("inside").is_file());
+    }
+}
@@ -0,0 +1,245 @@
+//! Paths as the filesystem has them: glob expansion, ordering and
+//! serialization that keep names byte for byte, so files whose names aren't
+//! UTF-8 can still be passed to commands, trashed and restored, and be the
+//! working directory. Typed lines, history and the agent's JSON tools are
+//! text, so they only see such names lossily.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+/// Expand `*`, `?` and `[...]` in each word against the filesystem, like sh's
+/// pathname expansion. Matches keep their exact bytes, so files whose names
+/// aren't valid UTF-8 can still be passed to commands. Words without
+/// wildcards, or whose pattern matches nothing, are passed through unchanged.
+pub fn expand(words: &[&str], cwd: &Path) -> Vec<OsString> {
+    let mut expanded = Vec::with_capacity(words.len());
+    for word in words {
+        let mut matches = if has_wildcard(word) { expand_word(word, cwd) } else { Vec::new() };
+        if matches.is_empty() {
+            expanded.push(OsString::from(word));
+        } else {
+            matches.sort_by(|a, b| locale_cmp(a, b));
+            expanded.extend(matches);
+        }
+    }
+    expanded
+}
+
+/// [`expand`] for words from `command_list::split_quoted_words`; words that
+/// were quoted or escaped are passed through unchanged, as in sh
+pub fn expand_unquoted(words: &[(String, bool)], cwd: &Path) -> Vec<OsString> {
+    let mut expanded = Vec::with_capacity(words.len());
+    for (word, quoted) in words {
+        if *quoted {
+            expanded.push(OsString::from(word));
+        } else {
+            expanded.extend(expand(&[word.as_str()], cwd));
+        }
+    }
+    expanded
+}
+
+/// Compare names the way `ls` and sh order them: by the locale's collation
+/// (LC_COLLATE), falling back to byte order
+pub fn locale_cmp(a: &OsStr, b: &OsStr) -> Ordering {
+    #[cfg(unix)]
+    {
+        use std::ffi::CString;
+        use std::sync::Once;
+
+        static INIT_LOCALE: Once = Once::new();
+        INIT_LOCALE.call_once(|| unsafe {
+            libc::setlocale(libc::LC_COLLATE, c"".as_ptr());
+        });
+
+        if let (Ok(ca), Ok(cb)) = (CString::new(bytes(a).into_owned()), CString::new(bytes(b).into_owned())) {
+            let order = unsafe { libc::strcoll(ca.as_ptr(), cb.as_ptr()) };
+            if order != 0 {
+                return order.cmp(&0);
+            }
+        }
+    }
+    bytes(a).cmp(&bytes(b))
+}
+
+fn has_wildcard(word: &str) -> bool {
+    // Quoted words are left alone; the simple executor doesn't unquote them
+    word.contains(['*', '?', '[']) && !word.contains(['\'', '"', '\\'])
+}
+
+fn expand_word(pattern: &str, cwd: &Path) -> Vec<OsString> {
+    let absolute = pattern.starts_with('/');
+    // (path as typed, path on disk)
+    let mut candidates: Vec<(OsString, PathBuf)> = vec![if absolute {
+        (OsString::from("/"), PathBuf::from("/"))
+    } else {
+        (OsString::new(), cwd.to_path_buf())
+    }];
+
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    for (index, component) in components.iter().enumerate() {
+        let last = index + 1 == components.len();
+        let mut next = Vec::new();
+        for (typed, on_disk) in &candidates {
+            if !has_wildcard(component) {
+                let on_disk = on_disk.join(component);
+                if last || on_disk.is_dir() {
+                    next.push((join_typed(typed, OsStr::new(component)), on_disk));
+                }
+                continue;
+            }
+            let Ok(entries) = std::fs::read_dir(on_disk) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                // Like sh, a leading dot must be matched explicitly
+                if bytes(&name).first() == Some(&b'.') && !component.starts_with('.') {
+                    continue;
+                }
+                if !glob_match(component.as_bytes(), &bytes(&name)) {
+                    continue;
+                }
+                let path = entry.path();
+                if last || path.is_dir() {
+                    next.push((join_typed(typed, &name), path));
+                }
+            }
+        }
+        candidates = next;
+    }
+
+    candidates.into_iter()
+        .filter(|(_, on_disk)| on_disk.exists())
+        .map(|(typed, _)| typed)
+        .collect()
+}
+
+fn join_typed(base: &OsStr, name: &OsStr) -> OsString {
+    let mut joined = base.to_os_string();
+    if !joined.is_empty() && !bytes(&joined).ends_with(b"/") {
+        joined.push("/");
+    }
+    joined.push(name);
+    joined
+}
+
+/// Match a single path component against a glob, byte by byte
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some(b'*') => (0..=name.len()).any(|skip| glob_match(&pattern[1..], &name[skip..])),
+        Some(b'?') => !name.is_empty() && glob_match(&pattern[1..], &name[1..]),
+        Some(b'[') => match (name.first(), bracket(&pattern[1..])) {
+            (Some(&c), Some((set, negated, rest))) => {
+                set_contains(set, c) != negated && glob_match(rest, &name[1..])
+            }
+            // An unclosed [ is an ordinary character
+            (Some(&c), None) => c == b'[' && glob_match(&pattern[1..], &name[1..]),
+            (None, _) => false,
+        },
+        Some(&p) => name.first() == Some(&p) && glob_match(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Split `[...]` (after the `[`) into its set, whether it is negated, and the rest of the pattern
+fn bracket(pattern: &[u8]) -> Option<(&[u8], bool, &[u8])> {
+    let negated = matches!(pattern.first(), Some(b'!') | Some(b'^'));
+    let start = usize::from(negated);
+    // A ] right after the opening bracket is part of the set
+    let close = pattern.iter().skip(start + 1).position(|&c| c == b']')? + start + 1;
+    Some((&pattern[start..close], negated, &pattern[close + 1..]))
+}
+
+fn set_contains(set: &[u8], c: u8) -> bool {
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == b'-' {
+            if (set[i]..=set[i + 2]).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if set[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+/// The bytes of a name, exactly on unix
+#[cfg(unix)]
+pub fn bytes(s: &OsStr) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(s.as_bytes())
+}
+
+#[cfg(not(unix))]
+pub fn bytes(s: &OsStr) -> Cow<'_, [u8]> {
+    Cow::Owned(s.to_string_lossy().into_owned().into_bytes())
+}
+
+/// The name whose bytes are `bytes`, the inverse of [`bytes`]
+#[cfg(unix)]
+pub fn from_bytes(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+pub fn from_bytes(bytes: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Serde for paths that may not be UTF-8: a string when they are, else
+/// their bytes, so such a path survives a JSON round trip
+pub mod serde_path {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::path::{Path, PathBuf};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Text(String),
+        Bytes(Vec<u8>),
+    }
+
+    fn repr(path: &Path) -> Repr {
+        match path.to_str() {
+            Some(text) => Repr::Text(text.to_string()),
+            None => Repr::Bytes(super::bytes(path.as_os_str()).into_owned()),
+        }
+    }
+
+    fn path(repr: Repr) -> PathBuf {
+        match repr {
+            Repr::Text(text) => PathBuf::from(text),
+            Repr::Bytes(bytes) => PathBuf::from(super::from_bytes(bytes)),
+        }
+    }
+
+    pub fn serialize<S: Serializer>(value: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        repr(value).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        Repr::deserialize(deserializer).map(path)
+    }
+
+    /// The same for an optional path
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(value: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+            value.as_deref().map(repr).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PathBuf>, D::Error> {
+            Ok(Option::<Repr>::deserialize(deserializer)?.map(path))
+        }
+    }
+}
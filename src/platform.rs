@@ -0,0 +1,95 @@
+//! What differs between Unix and Windows: the shell that runs command
+//! strings, how the hostname is looked up, and `~` in paths.
+
+use std::ffi::OsStr;
+use std::path::{is_separator, PathBuf};
+use std::process::Command;
+
+use crate::jobs;
+
+/// A `Command` for `program` that starts with the default signal
+/// dispositions, whatever aish itself ignores (see [`jobs::default_signals`])
+pub fn command(program: impl AsRef<OsStr>) -> Command {
+    let mut cmd = Command::new(program);
+    jobs::default_signals(&mut cmd);
+    cmd
+}
+
+/// The system shell, set up to run `command`: `sh -c` on Unix; on Windows
+/// `cmd /C`, or PowerShell when AISH_WINDOWS_SHELL is `powershell` or `pwsh`
+pub fn shell_command(command: &str) -> Command {
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+
+        match std::env::var("AISH_WINDOWS_SHELL").as_deref() {
+            Ok(shell @ ("powershell" | "pwsh")) => {
+                let mut cmd = self::command(shell);
+                cmd.args(["-NoProfile", "-NonInteractive", "-Command", command]);
+                cmd
+            }
+            _ => {
+                let mut cmd = self::command("cmd");
+                // cmd does its own parsing, so the command goes through unquoted
+                cmd.arg("/C").raw_arg(command);
+                cmd
+            }
+        }
+    }
+    #[cfg(not(windows))]
+    {
+        let mut cmd = self::command("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    }
+}
+
+/// This machine's name: $HOSTNAME if set, else asked of the OS
+pub fn hostname() -> String {
+    if let Ok(hostname) = std::env::var("HOSTNAME") {
+        return hostname;
+    }
+    os_hostname().unwrap_or_else(|| "localhost".to_string())
+}
+
+#[cfg(unix)]
+fn os_hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } != 0 {
+        return None;
+    }
+    let end = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    Some(String::from_utf8_lossy(&buffer[..end]).into_owned())
+}
+
+#[cfg(windows)]
+fn os_hostname() -> Option<String> {
+    use windows_sys::Win32::System::SystemInformation::{ComputerNameDnsHostname, GetComputerNameExW};
+
+    let mut buffer = [0u16; 256];
+    let mut size = buffer.len() as u32;
+    if unsafe { GetComputerNameExW(ComputerNameDnsHostname, buffer.as_mut_ptr(), &mut size) } == 0 {
+        return std::env::var("COMPUTERNAME").ok();
+    }
+    Some(String::from_utf16_lossy(&buffer[..size as usize]))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn os_hostname() -> Option<String> {
+    None
+}
+
+/// `path` with a leading `~` (alone or before a separator, `/` or on
+/// Windows also `\`) replaced by the home directory; None for other paths
+/// or when there is no home directory
+pub fn expand_home(path: &str) -> Option<PathBuf> {
+    let rest = path.strip_prefix('~')?;
+    let mut chars = rest.chars();
+    match chars.next() {
+        None => dirs::home_dir(),
+        Some(separator) if is_separator(separator) => {
+            Some(dirs::home_dir()?.join(chars.as_str().trim_start_matches(is_separator)))
+        }
+        Some(_) => None,
+    }
+}
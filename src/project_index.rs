@@ -0,0 +1,458 @@
+//! Embedding index over the current project for the agent's `search_project`
+//! tool. `index build` splits the project's text files into overlapping
+//! chunks of lines, embeds them with `ai.embedding_model` (an OpenAI-style
+//! `/embeddings` endpoint, or `local` for a built-in hashing embedder that
+//! needs no network) and stores them in SQLite at `<project>/.aish/index.db`,
+//! next to a `.gitignore` that keeps it out of commits. Rebuilding only
+//! re-embeds files that changed. Outside a git repository the user is asked
+//! first, with how much would be indexed.
+
+use anyhow::{anyhow, Result};
+use ignore::WalkBuilder;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{approval, humanize, search, Config};
+
+/// `ai.embedding_model` value that selects the built-in embedder
+pub const LOCAL_MODEL: &str = "local";
+
+const INDEX_FILE: &str = "index.db";
+// Lines per chunk, and how many of them the next chunk repeats
+const CHUNK_LINES: usize = 40;
+const CHUNK_OVERLAP: usize = 8;
+// Characters of a chunk that are embedded; longer chunks are cut
+const MAX_CHUNK_CHARS: usize = 6000;
+// Texts sent per embeddings request
+const EMBED_BATCH: usize = 64;
+// Dimensions of the local embedder's vectors
+const LOCAL_DIMENSIONS: usize = 384;
+// Projects with more files are indexed only up to this many
+const MAX_INDEXED_FILES: usize = 20_000;
+const DEFAULT_MAX_RESULTS: usize = 8;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS files (path TEXT PRIMARY KEY, modified INTEGER NOT NULL, size INTEGER NOT NULL);
+    CREATE TABLE IF NOT EXISTS chunks (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL,
+        start_line INTEGER NOT NULL,
+        end_line INTEGER NOT NULL,
+        text TEXT NOT NULL,
+        embedding BLOB NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS chunks_path ON chunks (path);
+";
+
+/// A chunk the `search_project` tool returns
+#[derive(Debug, Serialize)]
+pub struct ProjectMatch {
+    /// Relative to the project root
+    pub file: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    /// Cosine similarity to the query, 1.0 being identical
+    pub score: f32,
+    pub text: String,
+}
+
+struct Chunk {
+    start_line: usize,
+    end_line: usize,
+    text: String,
+}
+
+/// A file as last indexed, to tell whether it changed
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Stamp {
+    modified: i64,
+    size: i64,
+}
+
+/// The project `dir` belongs to: the nearest directory above it with a
+/// `.git`, or `dir` itself
+pub fn project_root(dir: &Path) -> PathBuf {
+    dir.ancestors()
+        .find(|ancestor| in_repository(ancestor))
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+fn in_repository(root: &Path) -> bool {
+    root.join(".git").exists()
+}
+
+/// Outside a repository the "project" may be a home directory full of
+/// private files, so ask before reading and embedding them
+fn confirm_outside_repository(root: &Path, changed: &[(String, Stamp)], model: &str) -> Result<()> {
+    let bytes: i64 = changed.iter().map(|(_, stamp)| stamp.size).sum();
+    let destination = if model == LOCAL_MODEL {
+        "embedded locally".to_string()
+    } else {
+        format!("sent to the embeddings API ({})", model)
+    };
+    let details = format!(
+        "{} is not in a git repository. {} file(s), {}, would be {} and copied into {}.",
+        root.display(),
+        changed.len(),
+        humanize::bytes(bytes.max(0) as u64),
+        destination,
+        index_path(root).display()
+    );
+    let decision = approval::ask(&approval::ApprovalRequest {
+        question: "Index this directory?",
+        subject: &root.display().to_string(),
+        details: Some(&details),
+        editable: false,
+        always_key: None,
+        default_approve: false,
+        risky: false,
+    });
+    if !decision.approved() {
+        return Err(anyhow!("not indexed; run `index build` inside a project's git repository"));
+    }
+    Ok(())
+}
+
+fn index_path(root: &Path) -> PathBuf {
+    root.join(".aish").join(INDEX_FILE)
+}
+
+fn open(root: &Path) -> Result<Connection> {
+    let path = index_path(root);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+        // The index holds copies of file contents, which must not be committed
+        let gitignore = dir.join(".gitignore");
+        if !gitignore.exists() {
+            std::fs::write(&gitignore, format!("# Written by aish: the project index holds copies of file contents\n{}*\n", INDEX_FILE))?;
+        }
+    }
+    let db = Connection::open(&path)?;
+    db.execute_batch(SCHEMA)?;
+    Ok(db)
+}
+
+/// Open the index of `root` if one has been built
+fn open_existing(root: &Path) -> Result<Connection> {
+    if !index_path(root).exists() {
+        return Err(anyhow!("{} has no index yet; run `index build` first", root.display()));
+    }
+    open(root)
+}
+
+fn meta(db: &Connection, key: &str) -> Result<Option<String>> {
+    Ok(db.query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| row.get(0)).optional()?)
+}
+
+fn set_meta(db: &Connection, key: &str, value: &str) -> Result<()> {
+    db.execute("INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)", params![key, value])?;
+    Ok(())
+}
+
+/// Index the project containing `cwd`, embedding new and changed files and
+/// dropping deleted ones; prints progress
+pub async fn build(config: &Config, cwd: &Path) -> Result<()> {
+    let root = project_root(cwd);
+    let model = config.embedding_model();
+    let started = Instant::now();
+
+    let current = project_files(&root);
+    // Vectors from different models can't be compared, so those start over
+    let indexed = if index_path(&root).exists() {
+        let db = open(&root)?;
+        if meta(&db, "model")?.is_some_and(|built| built != model) {
+            HashMap::new()
+        } else {
+            indexed_files(&db)?
+        }
+    } else {
+        HashMap::new()
+    };
+    let changed: Vec<(String, Stamp)> = current
+        .iter()
+        .filter(|(path, stamp)| indexed.get(*path) != Some(*stamp))
+        .map(|(path, stamp)| (path.clone(), *stamp))
+        .collect();
+    if !in_repository(&root) && !changed.is_empty() {
+        confirm_outside_repository(&root, &changed, &model)?;
+    }
+
+    let removed = {
+        let db = open(&root)?;
+        if meta(&db, "model")?.is_some_and(|built| built != model) {
+            db.execute_batch("DELETE FROM chunks; DELETE FROM files;")?;
+        }
+        set_meta(&db, "model", &model)?;
+        let removed: Vec<String> = indexed_files(&db)?.into_keys().filter(|path| !current.contains_key(path)).collect();
+        for path in &removed {
+            db.execute("DELETE FROM chunks WHERE path = ?1", [path])?;
+            db.execute("DELETE FROM files WHERE path = ?1", [path])?;
+        }
+        removed
+    };
+
+    if !changed.is_empty() {
+        println!("Indexing {} file(s) in {} with {}…", changed.len(), root.display(), model);
+    }
+    let mut chunk_count = 0;
+    for (path, stamp) in &changed {
+        let chunks = search::read_text(&root.join(path)).map(|text| chunk(&text)).unwrap_or_default();
+        let mut embeddings = Vec::with_capacity(chunks.len());
+        for batch in chunks.chunks(EMBED_BATCH) {
+            let texts: Vec<&str> = batch.iter().map(|chunk| chunk.text.as_str()).collect();
+            embeddings.extend(embed(config, &model, &texts).await?);
+        }
+        chunk_count += chunks.len();
+
+        let mut db = open(&root)?;
+        let tx = db.transaction()?;
+        tx.execute("DELETE FROM chunks WHERE path = ?1", [path])?;
+        for (chunk, embedding) in chunks.iter().zip(&embeddings) {
+            tx.execute(
+                "INSERT INTO chunks (path, start_line, end_line, text, embedding) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![path, chunk.start_line as i64, chunk.end_line as i64, chunk.text, to_blob(embedding)],
+            )?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO files (path, modified, size) VALUES (?1, ?2, ?3)",
+            params![path, stamp.modified, stamp.size],
+        )?;
+        tx.commit()?;
+    }
+
+    set_meta(&open(&root)?, "built", &now().to_string())?;
+    println!(
+        "Indexed {} changed file(s) ({} chunks), removed {}, in {}",
+        changed.len(),
+        chunk_count,
+        removed.len(),
+        humanize::duration(started.elapsed())
+    );
+    Ok(())
+}
+
+/// Print what the index of the project containing `cwd` holds and how many
+/// files changed since it was built
+pub fn print_status(cwd: &Path) -> Result<()> {
+    let root = project_root(cwd);
+    let db = open_existing(&root)?;
+    let files: i64 = db.query_row("SELECT COUNT(*) FROM files", [], |row| row.get(0))?;
+    let chunks: i64 = db.query_row("SELECT COUNT(*) FROM chunks", [], |row| row.get(0))?;
+    let indexed = indexed_files(&db)?;
+    let current = project_files(&root);
+    let stale = current.iter().filter(|(path, stamp)| indexed.get(*path) != Some(*stamp)).count()
+        + indexed.keys().filter(|path| !current.contains_key(*path)).count();
+
+    println!("Index:   {}", index_path(&root).display());
+    println!("Model:   {}", meta(&db, "model")?.unwrap_or_else(|| "-".to_string()));
+    println!("Files:   {} ({} chunks)", files, chunks);
+    if let Some(built) = meta(&db, "built")?.and_then(|built| built.parse().ok()) {
+        println!("Built:   {}", humanize::relative_time(built));
+    }
+    if stale == 0 {
+        println!("Status:  up to date");
+    } else {
+        println!("Status:  {} file(s) changed since; run `index build` to update", stale);
+    }
+    Ok(())
+}
+
+/// The `search_project` tool: the chunks closest in meaning to `query`
+pub async fn query(config: &Config, cwd: &Path, args: &serde_json::Value) -> Result<serde_json::Value> {
+    let query = args["query"].as_str().ok_or_else(|| anyhow!("query is required"))?;
+    let max_results = args["max_results"].as_u64().map_or(DEFAULT_MAX_RESULTS, |max| max as usize);
+    let root = project_root(cwd);
+
+    let model = {
+        let db = open_existing(&root)?;
+        meta(&db, "model")?.ok_or_else(|| anyhow!("the index is empty; run `index build` first"))?
+    };
+    let query_vector = embed(config, &model, &[query]).await?.pop().unwrap_or_default();
+
+    let db = open_existing(&root)?;
+    let mut statement = db.prepare("SELECT path, start_line, end_line, text, embedding FROM chunks")?;
+    let rows = statement.query_map([], |row| {
+        let embedding: Vec<u8> = row.get(4)?;
+        Ok(ProjectMatch {
+            file: row.get(0)?,
+            start_line: row.get::<_, i64>(1)? as usize,
+            end_line: row.get::<_, i64>(2)? as usize,
+            score: dot(&query_vector, &from_blob(&embedding)),
+            text: row.get(3)?,
+        })
+    })?;
+    let mut matches: Vec<ProjectMatch> = rows.collect::<rusqlite::Result<_>>()?;
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    matches.truncate(max_results);
+    Ok(json!({ "root": root.display().to_string(), "matches": matches }))
+}
+
+/// Text files of the project the way `search_files` walks them, by path
+/// relative to `root`
+fn project_files(root: &Path) -> HashMap<String, Stamp> {
+    let mut files = HashMap::new();
+    for entry in WalkBuilder::new(root).build().flatten() {
+        if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let Ok(relative) = entry.path().strip_prefix(root) else {
+            continue;
+        };
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_secs() as i64);
+        files.insert(relative.display().to_string(), Stamp { modified, size: metadata.len() as i64 });
+        if files.len() == MAX_INDEXED_FILES {
+            tracing::warn!(root = %root.display(), max = MAX_INDEXED_FILES, "project has too many files; index is partial");
+            break;
+        }
+    }
+    files
+}
+
+fn indexed_files(db: &Connection) -> Result<HashMap<String, Stamp>> {
+    let mut statement = db.prepare("SELECT path, modified, size FROM files")?;
+    let rows = statement.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, Stamp { modified: row.get(1)?, size: row.get(2)? }))
+    })?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+/// Overlapping runs of [`CHUNK_LINES`] lines; blank-only runs are skipped
+fn chunk(text: &str) -> Vec<Chunk> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let end = (start + CHUNK_LINES).min(lines.len());
+        let text: String = lines[start..end].join("\n").chars().take(MAX_CHUNK_CHARS).collect();
+        if !text.trim().is_empty() {
+            chunks.push(Chunk { start_line: start + 1, end_line: end, text });
+        }
+        if end == lines.len() {
+            break;
+        }
+        start = end - CHUNK_OVERLAP;
+    }
+    chunks
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// Unit-length embeddings of `texts` with `model`
+async fn embed(config: &Config, model: &str, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+    if model == LOCAL_MODEL {
+        return Ok(texts.iter().map(|text| local_embedding(text)).collect());
+    }
+    let ai = config.ai.as_ref();
+    let api_key = ai
+        .and_then(|ai| ai.api_key.as_deref())
+        .ok_or_else(|| anyhow!("no ai.api_key for embeddings; set one, or ai.embedding_model to \"local\""))?;
+    let base_url = ai.and_then(|ai| ai.base_url.as_deref()).unwrap_or("https://api.openai.com/v1");
+
+    let response = config
+        .http_client()?
+        .post(format!("{}/embeddings", base_url))
+        .header("Authorization", format!("Bearer {}", api_key))
+        .json(&json!({ "model": model, "input": texts }))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        return Err(anyhow!("embeddings request failed ({}): {}", status, response.text().await.unwrap_or_default()));
+    }
+    let mut data = response.json::<EmbeddingResponse>().await?.data;
+    data.sort_by_key(|item| item.index);
+    Ok(data.into_iter().map(|item| normalized(item.embedding)).collect())
+}
+
+/// Hashed bag of words: each lowercased word, and each part of a
+/// snake_case or camelCase identifier, adds to one of [`LOCAL_DIMENSIONS`]
+/// buckets. Matches shared vocabulary, not meaning, but needs no model.
+fn local_embedding(text: &str) -> Vec<f32> {
+    let mut vector = vec![0.0f32; LOCAL_DIMENSIONS];
+    for word in text.split(|c: char| !c.is_alphanumeric() && c != '_').filter(|word| !word.is_empty()) {
+        let mut add = |token: &str| {
+            let hash = fnv1a(token.to_lowercase().as_bytes());
+            let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+            vector[(hash % LOCAL_DIMENSIONS as u64) as usize] += sign;
+        };
+        add(word);
+        let parts = identifier_parts(word);
+        if parts.len() > 1 {
+            parts.iter().for_each(|part| add(part));
+        }
+    }
+    normalized(vector)
+}
+
+/// `parse_config` and `parseConfig` both give `["parse", "config"]`
+fn identifier_parts(word: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    for c in word.chars() {
+        if c == '_' || (c.is_uppercase() && !part.is_empty()) {
+            if !part.is_empty() {
+                parts.push(std::mem::take(&mut part));
+            }
+            if c == '_' {
+                continue;
+            }
+        }
+        part.push(c);
+    }
+    if !part.is_empty() {
+        parts.push(part);
+    }
+    parts
+}
+
+/// FNV-1a, which unlike `DefaultHasher` is the same in every build, so
+/// stored local embeddings stay comparable
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+fn normalized(mut vector: Vec<f32>) -> Vec<f32> {
+    let length = dot(&vector, &vector).sqrt();
+    if length > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= length);
+    }
+    vector
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn to_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4).map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
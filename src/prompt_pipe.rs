@@ -0,0 +1,74 @@
+//! `command |? question`: run a command and send its output, with the
+//! question, to the agent in one step, e.g. `df -h |? "which filesystem is
+//! nearly full?"`.
+
+const OPERATOR: &str = "|?";
+
+/// What `shell.prompt_pipe` does with the command's output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipeMode {
+    /// Print it as it runs, then send it (the default)
+    Show,
+    /// Send it without printing it
+    Quiet,
+    /// `|?` isn't special and goes to sh as typed
+    Off,
+}
+
+impl PipeMode {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "show" => Some(PipeMode::Show),
+            "quiet" => Some(PipeMode::Quiet),
+            "off" => Some(PipeMode::Off),
+            _ => None,
+        }
+    }
+}
+
+/// Split `command |? question` at the first `|?` outside quotes, with the
+/// question's surrounding quotes removed. None unless both sides are non-empty.
+pub fn split(line: &str) -> Option<(&str, &str)> {
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if in_single {
+            in_single = c != '\'';
+        } else if c == '\\' {
+            escaped = true;
+        } else if in_double {
+            in_double = c != '"';
+        } else if c == '\'' {
+            in_single = true;
+        } else if c == '"' {
+            in_double = true;
+        } else if line[i..].starts_with(OPERATOR) {
+            let command = line[..i].trim();
+            let question = unquote(line[i + OPERATOR.len()..].trim());
+            return (!command.is_empty() && !question.is_empty()).then_some((command, question));
+        }
+    }
+    None
+}
+
+/// The prompt sent to the agent: the question, then the command and its output
+pub fn prompt(command: &str, exit_code: i32, output: &str, question: &str) -> String {
+    let status = if exit_code == 0 { String::new() } else { format!(" (exit code {})", exit_code) };
+    format!(
+        "{}\n\nOutput of `{}`{}:\n```\n{}\n```",
+        question,
+        command,
+        status,
+        output.trim_end()
+    )
+}
+
+fn unquote(text: &str) -> &str {
+    ['"', '\'']
+        .iter()
+        .find_map(|quote| text.strip_prefix(*quote)?.strip_suffix(*quote))
+        .unwrap_or(text)
+}
@@ -0,0 +1,96 @@
+//! Colored prompts without garbled editing. rustyline places the cursor by
+//! the width of the prompt it is given, so it gets only the visible text;
+//! the styled prompt is drawn in its place by the editor's highlighter.
+//! Invisible parts are marked like readline does: `\[`/`\]` in
+//! `shell.prompt` become `\x01`/`\x02`, which `customPrompt` may return as
+//! well. Escape sequences outside such brackets are recognised too.
+
+use crate::ui::{self, Role};
+
+/// Starts a part of the prompt that takes up no room on screen
+pub const START_INVISIBLE: char = '\x01';
+/// Ends a part started by [`START_INVISIBLE`]
+pub const END_INVISIBLE: char = '\x02';
+
+/// A prompt as the terminal shows it and as rustyline measures it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StyledPrompt {
+    /// What takes up room on screen: no markers, no escape sequences
+    pub visible: String,
+    /// What is written to the terminal: escape sequences kept, markers dropped
+    pub styled: String,
+}
+
+impl StyledPrompt {
+    pub fn new(prompt: &str) -> Self {
+        let mut visible = String::new();
+        let mut styled = String::new();
+        let mut hidden = false;
+        let mut chars = prompt.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                START_INVISIBLE => hidden = true,
+                END_INVISIBLE => hidden = false,
+                '\x1b' => {
+                    styled.push(c);
+                    copy_escape(&mut chars, &mut styled);
+                }
+                _ => {
+                    styled.push(c);
+                    if !hidden {
+                        visible.push(c);
+                    }
+                }
+            }
+        }
+        Self { visible, styled }
+    }
+
+    /// `prompt` wrapped in the theme's prompt color, or with no color at all
+    /// when color is off
+    pub fn themed(prompt: &str) -> Self {
+        let mut prompt = Self::new(prompt);
+        if !ui::enabled() {
+            prompt.styled = prompt.visible.clone();
+        } else {
+            let color = ui::style(Role::Prompt);
+            if !color.is_empty() {
+                prompt.styled = format!("{}{}{}", color, prompt.styled, ui::reset());
+            }
+        }
+        prompt
+    }
+}
+
+/// Copy the rest of an escape sequence whose ESC was just read: CSI
+/// (`ESC [ ... m`), OSC (`ESC ] ... BEL` or `ESC ] ... ESC \`, as used for
+/// window titles and hyperlinks) or a two-character sequence
+fn copy_escape(chars: &mut std::iter::Peekable<std::str::Chars>, styled: &mut String) {
+    match chars.next() {
+        Some('[') => {
+            styled.push('[');
+            for c in chars.by_ref() {
+                styled.push(c);
+                if ('\x40'..='\x7e').contains(&c) {
+                    break;
+                }
+            }
+        }
+        Some(']') => {
+            styled.push(']');
+            while let Some(c) = chars.next() {
+                styled.push(c);
+                if c == '\x07' {
+                    break;
+                }
+                if c == '\x1b' && chars.peek() == Some(&'\\') {
+                    styled.push('\\');
+                    chars.next();
+                    break;
+                }
+            }
+        }
+        Some(c) => styled.push(c),
+        None => {}
+    }
+}
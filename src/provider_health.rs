@@ -0,0 +1,161 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::humanize;
+
+// Number of recent requests kept per provider for rolling stats
+const WINDOW_SIZE: usize = 20;
+
+// Median latency above which a provider is considered slow
+const SLOW_LATENCY: Duration = Duration::from_secs(8);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestOutcome {
+    Success,
+    /// Could not reach the provider at all (DNS, TLS, connection reset, timeout)
+    NetworkError,
+    /// Provider answered with a non-success HTTP status
+    HttpError(u16),
+}
+
+#[derive(Debug, Clone)]
+struct Sample {
+    latency: Duration,
+    outcome: RequestOutcome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Health {
+    Unknown,
+    Healthy,
+    Slow,
+    Degraded,
+}
+
+impl Health {
+    pub fn glyph(&self) -> &'static str {
+        match self {
+            Health::Unknown => "·",
+            Health::Healthy => "●",
+            Health::Slow => "◐",
+            Health::Degraded => "○",
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Health::Unknown => "unknown",
+            Health::Healthy => "healthy",
+            Health::Slow => "slow",
+            Health::Degraded => "degraded",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ProviderStats {
+    samples: VecDeque<Sample>,
+    total_requests: u64,
+    last_error: Option<String>,
+}
+
+impl ProviderStats {
+    fn record(&mut self, latency: Duration, outcome: RequestOutcome, error: Option<String>) {
+        if self.samples.len() == WINDOW_SIZE {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample { latency, outcome });
+        self.total_requests += 1;
+        if error.is_some() {
+            self.last_error = error;
+        }
+    }
+
+    fn median_latency(&self) -> Option<Duration> {
+        let mut latencies: Vec<Duration> = self.samples.iter()
+            .filter(|s| s.outcome == RequestOutcome::Success)
+            .map(|s| s.latency)
+            .collect();
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort();
+        Some(latencies[latencies.len() / 2])
+    }
+
+    fn count(&self, predicate: impl Fn(&RequestOutcome) -> bool) -> usize {
+        self.samples.iter().filter(|s| predicate(&s.outcome)).count()
+    }
+
+    fn health(&self) -> Health {
+        if self.samples.is_empty() {
+            return Health::Unknown;
+        }
+        let failures = self.count(|o| *o != RequestOutcome::Success);
+        if failures * 2 > self.samples.len() {
+            return Health::Degraded;
+        }
+        match self.median_latency() {
+            Some(latency) if latency > SLOW_LATENCY => Health::Slow,
+            _ => Health::Healthy,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref PROVIDER_STATS: Mutex<HashMap<String, ProviderStats>> = Mutex::new(HashMap::new());
+    static ref LAST_PROVIDER: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// Record the latency and outcome of a request to `provider` (its base URL)
+pub fn record(provider: &str, latency: Duration, outcome: RequestOutcome, error: Option<String>) {
+    if let Ok(mut stats) = PROVIDER_STATS.lock() {
+        stats.entry(provider.to_string())
+            .or_default()
+            .record(latency, outcome, error);
+    }
+    if let Ok(mut last) = LAST_PROVIDER.lock() {
+        *last = Some(provider.to_string());
+    }
+}
+
+/// Health of the most recently used provider, for the prompt glyph
+pub fn current_health() -> Health {
+    let provider = LAST_PROVIDER.lock().ok().and_then(|p| p.clone());
+    match (provider, PROVIDER_STATS.lock()) {
+        (Some(provider), Ok(stats)) => stats.get(&provider)
+            .map(|s| s.health())
+            .unwrap_or(Health::Unknown),
+        _ => Health::Unknown,
+    }
+}
+
+/// Print a per-provider summary for the `provider status` builtin
+pub fn print_status() {
+    let Ok(stats) = PROVIDER_STATS.lock() else {
+        return;
+    };
+    if stats.is_empty() {
+        println!("No AI requests made yet this session");
+        return;
+    }
+
+    let mut providers: Vec<_> = stats.iter().collect();
+    providers.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (provider, s) in providers {
+        let health = s.health();
+        println!("{} {} ({})", health.glyph(), provider, health.as_str());
+        println!("  requests:        {} total, last {} shown", s.total_requests, s.samples.len());
+        match s.median_latency() {
+            Some(latency) => println!("  median latency:  {}", humanize::duration(latency)),
+            None => println!("  median latency:  n/a"),
+        }
+        println!("  network errors:  {}", s.count(|o| *o == RequestOutcome::NetworkError));
+        println!("  provider errors: {}", s.count(|o| matches!(o, RequestOutcome::HttpError(_))));
+        if let Some(error) = &s.last_error {
+            println!("  last error:      {}", error);
+        }
+    }
+}
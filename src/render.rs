@@ -0,0 +1,274 @@
+use serde::{Deserialize, Serialize};
+use std::io::{self, IsTerminal};
+
+use crate::ui::{self, Role};
+
+/// Something the agent did or said while working on a prompt
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AgentEvent<'a> {
+    /// The agent is about to run a shell command
+    Command { command: &'a str },
+    /// A command the agent wanted to run was refused
+    CommandBlocked { command: &'a str, reason: &'a str },
+    /// A built-in or TypeScript tool is being called
+    Tool { name: &'a str },
+    /// A proposed `.aish.ts` rewrite, shown before the user approves it
+    ConfigProposal { path: &'a str, reason: &'a str, diff: &'a str },
+    /// What a file-writing tool is about to change, shown before the user confirms it
+    FileChange { tool: &'a str, path: &'a str, diff: &'a str },
+    /// A status message about the agent's progress
+    Notice { message: &'a str },
+    /// The model's final answer
+    Response { content: &'a str },
+    /// A tool result the answer cites as `[number]`, shown under it
+    Citation { number: usize, source: &'a str },
+    /// The agent's plan for a multi-step task, shown again as steps finish
+    Plan { steps: Vec<PlanStep> },
+}
+
+/// One step of the agent's plan, as the `plan` tool reports it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanStep {
+    pub step: String,
+    pub status: StepStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Pending,
+    InProgress,
+    Done,
+    Failed,
+}
+
+/// Turns agent events into output for one frontend
+pub trait Renderer: Send + Sync {
+    fn render(&self, event: &AgentEvent);
+}
+
+/// How agent output is presented, chosen once per invocation
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum RenderFormat {
+    /// Unstyled text, for pipes and pagers
+    Plain,
+    /// Markdown styled for the terminal
+    Markdown,
+    /// One JSON object per event, per line
+    Json,
+}
+
+impl RenderFormat {
+    /// Markdown on a terminal, plain text otherwise
+    pub fn detect() -> Self {
+        if io::stdout().is_terminal() {
+            RenderFormat::Markdown
+        } else {
+            RenderFormat::Plain
+        }
+    }
+
+    pub fn renderer(self) -> Box<dyn Renderer> {
+        match self {
+            RenderFormat::Plain => Box::new(PlainRenderer),
+            RenderFormat::Markdown => Box::new(MarkdownRenderer),
+            RenderFormat::Json => Box::new(JsonRenderer),
+        }
+    }
+}
+
+pub struct PlainRenderer;
+
+impl Renderer for PlainRenderer {
+    fn render(&self, event: &AgentEvent) {
+        match event {
+            AgentEvent::Command { command } => {
+                println!("**** Running command");
+                println!("   $ {}", command);
+            }
+            AgentEvent::CommandBlocked { .. } => println!("**** Command blocked"),
+            AgentEvent::Tool { name } => println!("**** Calling tool: {}", name),
+            AgentEvent::ConfigProposal { reason, diff, .. } => {
+                println!("**** Proposing configuration change");
+                println!("Reason: {}", reason);
+                print!("{}", diff);
+            }
+            AgentEvent::FileChange { tool, path, diff } => {
+                println!("**** {} will change {}", tool, path);
+                print!("{}", diff);
+            }
+            AgentEvent::Notice { message } => println!("{}", message),
+            AgentEvent::Response { content } => {
+                if !content.trim().is_empty() {
+                    println!("{}", content);
+                }
+            }
+            AgentEvent::Citation { number, source } => println!("[{}] {}", number, source),
+            AgentEvent::Plan { steps } => {
+                println!("**** Plan");
+                for (number, step) in steps.iter().enumerate() {
+                    let mark = match step.status {
+                        StepStatus::Pending => " ",
+                        StepStatus::InProgress => ">",
+                        StepStatus::Done => "x",
+                        StepStatus::Failed => "!",
+                    };
+                    println!("  [{}] {}. {}", mark, number + 1, step.step);
+                }
+            }
+        }
+    }
+}
+
+pub struct MarkdownRenderer;
+
+impl Renderer for MarkdownRenderer {
+    fn render(&self, event: &AgentEvent) {
+        let (muted, tool, reset) = (ui::style(Role::Muted), ui::style(Role::Tool), ui::reset());
+        match event {
+            AgentEvent::Command { command } => println!("{}▶ {}{}{}", muted, tool, command, reset),
+            AgentEvent::CommandBlocked { command, reason } => {
+                println!("{} {}({}){}", ui::paint(Role::Error, &format!("✗ {}", command)), muted, reason, reset);
+            }
+            AgentEvent::Tool { name } => println!("{}⚙ {}{}{}", muted, tool, name, reset),
+            AgentEvent::ConfigProposal { path, reason, diff } => {
+                println!("{}: {}", ui::paint(Role::Emphasis, &format!("Proposed change to {}", path)), reason);
+                print_diff(diff);
+            }
+            AgentEvent::FileChange { tool, path, diff } => {
+                println!("{}", ui::paint(Role::Emphasis, &format!("{} will change {}", tool, path)));
+                print_diff(diff);
+            }
+            AgentEvent::Notice { message } => println!("{}", ui::paint(Role::Muted, message)),
+            AgentEvent::Response { content } => {
+                if !content.trim().is_empty() {
+                    println!("{}", style_markdown(content));
+                }
+            }
+            AgentEvent::Citation { number, source } => {
+                println!("{}", ui::paint(Role::Muted, &format!("[{}] {}", number, source)));
+            }
+            AgentEvent::Plan { steps } => {
+                println!("{}", ui::paint(Role::Heading, "Plan"));
+                for (number, step) in steps.iter().enumerate() {
+                    let (mark, role) = match step.status {
+                        StepStatus::Pending => ("○", Role::Muted),
+                        StepStatus::InProgress => ("▶", Role::Tool),
+                        StepStatus::Done => ("✓", Role::Success),
+                        StepStatus::Failed => ("✗", Role::Error),
+                    };
+                    println!("  {}", ui::paint(role, &format!("{} {}. {}", mark, number + 1, step.step)));
+                }
+            }
+        }
+    }
+}
+
+/// A unified diff with added lines in the success color, removed lines in
+/// the error color and hunk headers in the tool color
+fn print_diff(diff: &str) {
+    for line in diff.lines() {
+        let role = match line.chars().next() {
+            Some('+') if !line.starts_with("+++") => Some(Role::Success),
+            Some('-') if !line.starts_with("---") => Some(Role::Error),
+            Some('@') => Some(Role::Tool),
+            _ => None,
+        };
+        match role {
+            Some(role) => println!("{}", ui::paint(role, line)),
+            None => println!("{}", line),
+        }
+    }
+}
+
+/// Style headings, emphasis, code and bullets with the theme's markdown
+/// colors, on top of its response color. This is a line-based
+/// approximation, not a full CommonMark renderer.
+fn style_markdown(text: &str) -> String {
+    let base = ui::style(Role::Response);
+    // Ends a nested style and goes back to the response color
+    let end = format!("{}{}", ui::reset(), base);
+    let mut styled = Vec::new();
+    let mut in_code_block = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if in_code_block {
+            styled.push(format!("    {}{}{}", ui::style(Role::Code), line, end));
+            continue;
+        }
+
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        let heading = trimmed.trim_start_matches('#');
+        if heading.len() < trimmed.len() && heading.starts_with(' ') {
+            styled.push(format!("{}{}{}", ui::style(Role::Heading), heading.trim(), end));
+        } else if let Some(item) = trimmed.strip_prefix("- ").or_else(|| trimmed.strip_prefix("* ")) {
+            styled.push(format!("{}• {}", indent, style_inline(item, &end)));
+        } else {
+            styled.push(style_inline(line, &end));
+        }
+    }
+    if base.is_empty() {
+        styled.join("\n")
+    } else {
+        format!("{}{}{}", base, styled.join("\n"), ui::reset())
+    }
+}
+
+/// `**bold**` and `` `code` ``, each closed with `end`; unmatched markers
+/// are left as typed
+fn style_inline(line: &str, end: &str) -> String {
+    let (emphasis, code) = (ui::style(Role::Emphasis), ui::style(Role::Code));
+    let mut result = String::new();
+    let mut rest = line;
+    loop {
+        let bold = rest.find("**");
+        let tick = rest.find('`');
+        let (start, marker, style) = match (bold, tick) {
+            (Some(b), Some(c)) if c < b => (c, "`", &code),
+            (Some(b), _) => (b, "**", &emphasis),
+            (None, Some(c)) => (c, "`", &code),
+            (None, None) => break,
+        };
+        let after = &rest[start + marker.len()..];
+        let Some(close) = after.find(marker) else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        result.push_str(style);
+        result.push_str(&after[..close]);
+        result.push_str(end);
+        rest = &after[close + marker.len()..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Only the final answer, as plain text on stdout, for `aish -p`; notices
+/// and refusals go to stderr and everything else is left out
+pub struct AnswerRenderer;
+
+impl Renderer for AnswerRenderer {
+    fn render(&self, event: &AgentEvent) {
+        match event {
+            AgentEvent::Response { content } if !content.trim().is_empty() => println!("{}", content.trim_end()),
+            AgentEvent::Notice { message } => eprintln!("{}", message),
+            AgentEvent::CommandBlocked { command, reason } => eprintln!("blocked: {} ({})", command, reason),
+            _ => {}
+        }
+    }
+}
+
+pub struct JsonRenderer;
+
+impl Renderer for JsonRenderer {
+    fn render(&self, event: &AgentEvent) {
+        if let Ok(line) = serde_json::to_string(event) {
+            println!("{}", line);
+        }
+    }
+}
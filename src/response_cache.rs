@@ -0,0 +1,80 @@
+//! On-disk cache of AI responses, for scripts that ask the same question
+//! again and again with `-p`. Off unless `ai.cache_ttl_secs` is set; a
+//! response is found by a hash of everything sent (model, messages, tools,
+//! sampling) plus the API's URL, and reused until it is older than the TTL.
+//! `--no-cache` skips it for one run.
+
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Neither read nor write the cache for the rest of the process (`--no-cache`)
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+fn dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("aish").join("responses"))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// The key of a request body sent to `base_url`
+pub fn key(base_url: &str, request_body: &serde_json::Value) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(base_url.as_bytes());
+    hasher.update([0]);
+    hasher.update(request_body.to_string().as_bytes());
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The response body stored under `key`, if it is younger than `ttl`
+pub fn lookup(key: &str, ttl: Duration) -> Option<String> {
+    if DISABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let content = std::fs::read_to_string(dir()?.join(format!("{}.json", key))).ok()?;
+    let (stored_at, body) = content.split_once('\n')?;
+    let age = now().saturating_sub(stored_at.trim().parse().ok()?);
+    (age < ttl.as_secs()).then(|| body.to_string())
+}
+
+/// Store `body` under `key`, dropping entries older than `ttl` on the way
+pub fn store(key: &str, body: &str, ttl: Duration) {
+    if DISABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(dir) = dir() else {
+        return;
+    };
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        tracing::debug!(error = %e, "response cache unavailable");
+        return;
+    }
+    prune(&dir, ttl);
+    if let Err(e) = std::fs::write(dir.join(format!("{}.json", key)), format!("{}\n{}", now(), body)) {
+        tracing::debug!(error = %e, "could not cache the response");
+    }
+}
+
+fn prune(dir: &std::path::Path, ttl: Duration) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let expired = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age >= ttl);
+        if expired {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! `--output json` for `-c` and `-p` runs: one JSON object on stdout when the
+//! run ends, with the final answer, the agent's tool calls, the commands run
+//! and token usage, for CI jobs and other automation. Everything the run
+//! would have printed to stdout goes to stderr meanwhile, so stdout holds
+//! only the report.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::io::{self, Write};
+
+use crate::transcript::{self, Entry};
+use crate::usage;
+
+/// What `-c` and `-p` print when they finish
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// The run's own output (the default)
+    Text,
+    /// A JSON report of the run
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub exit_code: i32,
+    /// The agent's last answer, if it gave one
+    pub answer: Option<String>,
+    pub tool_calls: Vec<ToolCallReport>,
+    pub commands: Vec<CommandReport>,
+    pub usage: UsageReport,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ToolCallReport {
+    pub name: String,
+    /// As the model sent them: JSON when they parse, the raw text otherwise
+    pub arguments: Value,
+    pub output: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommandReport {
+    pub command: String,
+    pub exit_code: i32,
+    pub duration_ms: u64,
+    /// Captured output, as the agent's context sees it
+    pub output: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    pub requests: u64,
+    pub total_tokens: u64,
+    /// Estimated, for models with known pricing
+    pub cost_usd: f64,
+}
+
+/// Keeps stdout pointed at stderr until the report is written
+pub struct Collecting {
+    #[cfg(unix)]
+    saved_stdout: Option<libc::c_int>,
+}
+
+/// Start collecting the run for a report and send stdout to stderr
+pub fn start() -> Collecting {
+    transcript::collect();
+    let _ = io::stdout().flush();
+    Collecting {
+        #[cfg(unix)]
+        saved_stdout: redirect_stdout(),
+    }
+}
+
+/// Put stdout back and print the report of the run
+pub fn finish(collecting: Collecting, exit_code: i32) {
+    let _ = io::stdout().flush();
+    restore_stdout(collecting);
+
+    let report = build(transcript::take_collected(), exit_code);
+    match serde_json::to_string_pretty(&report) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("aish: could not write the report: {}", e),
+    }
+}
+
+fn build(entries: Vec<Entry>, exit_code: i32) -> Report {
+    let mut answer = None;
+    let mut tool_calls = Vec::new();
+    let mut commands = Vec::new();
+    for entry in entries {
+        match entry {
+            Entry::Agent { event } if event["event"] == "response" => {
+                answer = event["content"].as_str().map(str::to_string);
+            }
+            Entry::ToolCall { name, arguments, output } => tool_calls.push(ToolCallReport {
+                name,
+                arguments: serde_json::from_str(&arguments).unwrap_or(Value::String(arguments)),
+                output,
+            }),
+            Entry::Command { command, exit_code, output, duration_ms } => {
+                commands.push(CommandReport { command, exit_code, duration_ms, output });
+            }
+            _ => {}
+        }
+    }
+    let (requests, total_tokens, cost_usd) = usage::session_totals();
+    Report {
+        exit_code,
+        answer,
+        tool_calls,
+        commands,
+        usage: UsageReport { requests, total_tokens, cost_usd },
+    }
+}
+
+/// Point stdout at stderr, returning a copy of the real stdout
+#[cfg(unix)]
+fn redirect_stdout() -> Option<libc::c_int> {
+    unsafe {
+        let saved = libc::dup(libc::STDOUT_FILENO);
+        if saved < 0 {
+            return None;
+        }
+        if libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            libc::close(saved);
+            return None;
+        }
+        Some(saved)
+    }
+}
+
+#[cfg(unix)]
+fn restore_stdout(collecting: Collecting) {
+    if let Some(saved) = collecting.saved_stdout {
+        unsafe {
+            libc::dup2(saved, libc::STDOUT_FILENO);
+            libc::close(saved);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn restore_stdout(_collecting: Collecting) {}
@@ -0,0 +1,127 @@
+//! The agent's `search_files` tool: a regex search over file contents,
+//! walking directories the way ripgrep does (respecting `.gitignore`, and
+//! skipping hidden and binary files), without spawning `grep` or `find`.
+
+use anyhow::{anyhow, Result};
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use regex::RegexBuilder;
+use serde::Serialize;
+use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::patch;
+
+// Matches returned when the call doesn't say
+const DEFAULT_MAX_RESULTS: usize = 100;
+// Matched lines are cut to this many characters
+const MAX_SNIPPET_CHARS: usize = 200;
+// Larger files are skipped
+const MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+// A NUL in this many leading bytes marks a file as binary
+const BINARY_CHECK_BYTES: usize = 8192;
+
+/// A matching line
+#[derive(Debug, Serialize)]
+pub struct Match {
+    /// Relative to the directory searched
+    pub file: String,
+    pub line: usize,
+    pub snippet: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    pub matches: Vec<Match>,
+    pub files_searched: usize,
+    /// More matches exist than `max_results` allowed
+    pub truncated: bool,
+    /// The search stopped at the tool's time limit
+    pub timed_out: bool,
+}
+
+/// Search with the tool's arguments: `pattern` (a regex), optional `path`
+/// (relative to `cwd`), `glob` filters, `case_insensitive` and `max_results`
+pub fn search_files(args: &serde_json::Value, cwd: &Path, timeout: Duration) -> Result<SearchResult> {
+    let pattern = args["pattern"].as_str().ok_or_else(|| anyhow!("pattern is required"))?;
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(args["case_insensitive"].as_bool().unwrap_or(false))
+        .build()?;
+    let root = patch::resolve(args["path"].as_str().unwrap_or("."), cwd);
+    if !root.exists() {
+        return Err(anyhow!("{}: no such file or directory", root.display()));
+    }
+    let max_results = args["max_results"].as_u64().map_or(DEFAULT_MAX_RESULTS, |max| max as usize);
+
+    let mut walker = WalkBuilder::new(&root);
+    let globs: Vec<&str> = match &args["glob"] {
+        serde_json::Value::String(glob) => vec![glob.as_str()],
+        serde_json::Value::Array(globs) => globs.iter().filter_map(|glob| glob.as_str()).collect(),
+        _ => Vec::new(),
+    };
+    if !globs.is_empty() {
+        let mut overrides = OverrideBuilder::new(&root);
+        for glob in globs {
+            overrides.add(glob)?;
+        }
+        walker.overrides(overrides.build()?);
+    }
+
+    let started = Instant::now();
+    let mut result = SearchResult { matches: Vec::new(), files_searched: 0, truncated: false, timed_out: false };
+    'files: for entry in walker.build().flatten() {
+        if started.elapsed() > timeout {
+            result.timed_out = true;
+            break;
+        }
+        if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+            continue;
+        }
+        let Some(content) = read_text(entry.path()) else {
+            continue;
+        };
+        result.files_searched += 1;
+        let file = entry.path().strip_prefix(&root)
+            .ok()
+            .filter(|relative| !relative.as_os_str().is_empty())
+            .unwrap_or(entry.path())
+            .display()
+            .to_string();
+        for (index, line) in content.lines().enumerate() {
+            if !regex.is_match(line) {
+                continue;
+            }
+            if result.matches.len() == max_results {
+                result.truncated = true;
+                break 'files;
+            }
+            result.matches.push(Match { file: file.clone(), line: index + 1, snippet: snippet(line) });
+        }
+    }
+    Ok(result)
+}
+
+/// A file's content, or None for large, binary or unreadable files
+pub(crate) fn read_text(path: &Path) -> Option<String> {
+    let file = std::fs::File::open(path).ok()?;
+    if file.metadata().ok()?.len() > MAX_FILE_BYTES {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    file.take(MAX_FILE_BYTES).read_to_end(&mut bytes).ok()?;
+    if bytes[..bytes.len().min(BINARY_CHECK_BYTES)].contains(&0) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn snippet(line: &str) -> String {
+    let line = line.trim();
+    if line.chars().count() <= MAX_SNIPPET_CHARS {
+        return line.to_string();
+    }
+    let mut cut: String = line.chars().take(MAX_SNIPPET_CHARS).collect();
+    cut.push('…');
+    cut
+}
@@ -0,0 +1,164 @@
+//! `aish update`: replace the running binary with the latest GitHub release.
+//! Each release carries one binary per platform, named
+//! `aish-<os>-<arch>[.exe]`, a `SHA256SUMS` file and its minisign signature
+//! `SHA256SUMS.minisig`. The signature is checked against the release key
+//! built into aish, and the download against the signed checksum, before
+//! anything is replaced; a release that merely matches its own checksums
+//! proves nothing about who published it.
+
+use anyhow::{anyhow, Result};
+use minisign_verify::{PublicKey, Signature};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::Path;
+
+const REPOSITORY: &str = "nurv/aish";
+const CHECKSUMS: &str = "SHA256SUMS";
+const CHECKSUMS_SIGNATURE: &str = "SHA256SUMS.minisig";
+// The minisign public key releases are signed with, set by the release
+// build; builds without it (from source, distribution packages) can't
+// verify releases and leave updating to whatever installed them
+const RELEASE_PUBLIC_KEY: Option<&str> = option_env!("AISH_RELEASE_PUBLIC_KEY");
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn asset(&self, name: &str) -> Result<&Asset> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or_else(|| anyhow!("release {} has no {}", self.tag_name, name))
+    }
+}
+
+/// The release asset built for this platform
+fn asset_name() -> String {
+    format!("aish-{}-{}{}", std::env::consts::OS, std::env::consts::ARCH, std::env::consts::EXE_SUFFIX)
+}
+
+/// Check for a newer release and, unless `check_only`, install it over the
+/// running executable
+pub async fn run(check_only: bool) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .user_agent(concat!("aish/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let url = format!("https://api.github.com/repos/{}/releases/latest", REPOSITORY);
+    let release: Release = client.get(&url).send().await?.error_for_status()?.json().await?;
+
+    let current = env!("CARGO_PKG_VERSION");
+    let latest = release.tag_name.trim_start_matches('v');
+    if !is_newer(latest, current) {
+        println!("aish {} is up to date", current);
+        return Ok(());
+    }
+    if check_only {
+        println!("aish {} is available (installed: {}); run `aish update` to install it", latest, current);
+        return Ok(());
+    }
+
+    let public_key = RELEASE_PUBLIC_KEY
+        .ok_or_else(|| anyhow!("this build of aish has no release signing key, so it can't verify updates; update it the way it was installed"))?;
+    let public_key = PublicKey::from_base64(public_key).map_err(|e| anyhow!("invalid release signing key: {}", e))?;
+
+    let name = asset_name();
+    let binary = release.asset(&name)?;
+    let checksums = release.asset(CHECKSUMS)?;
+    let signature = release.asset(CHECKSUMS_SIGNATURE)?;
+    println!("Downloading aish {} ({})...", latest, name);
+    let bytes = client.get(&binary.browser_download_url).send().await?.error_for_status()?.bytes().await?;
+    let sums = client.get(&checksums.browser_download_url).send().await?.error_for_status()?.text().await?;
+    let signature = client.get(&signature.browser_download_url).send().await?.error_for_status()?.text().await?;
+
+    let signature = Signature::decode(&signature).map_err(|e| anyhow!("{}: {}", CHECKSUMS_SIGNATURE, e))?;
+    public_key
+        .verify(sums.as_bytes(), &signature, false)
+        .map_err(|e| anyhow!("{} is not signed by the aish release key: {}", CHECKSUMS, e))?;
+
+    let expected = expected_checksum(&sums, &name).ok_or_else(|| anyhow!("{} has no entry for {}", CHECKSUMS, name))?;
+    let actual: String = Sha256::digest(&bytes).iter().map(|byte| format!("{:02x}", byte)).collect();
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(anyhow!("checksum mismatch for {}: expected {}, got {}", name, expected, actual));
+    }
+
+    // A symlink (e.g. Homebrew's) stays; the file it points to is replaced
+    let exe = std::env::current_exe()?.canonicalize()?;
+    replace_executable(&exe, &bytes)?;
+    println!("Updated aish {} -> {} ({})", current, latest, exe.display());
+    Ok(())
+}
+
+/// Whether dotted version `candidate` is later than `current`; a missing
+/// component counts as 0 and a pre-release suffix is ignored
+fn is_newer(candidate: &str, current: &str) -> bool {
+    fn parts(version: &str) -> Vec<u64> {
+        version
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    }
+    let (candidate, current) = (parts(candidate), parts(current));
+    let len = candidate.len().max(current.len());
+    let at = |parts: &[u64], i: usize| parts.get(i).copied().unwrap_or(0);
+    (0..len)
+        .map(|i| at(&candidate, i).cmp(&at(&current, i)))
+        .find(|order| order.is_ne())
+        .is_some_and(|order| order.is_gt())
+}
+
+/// The hex digest for `name` in `sha256sum` output (`<hex>  <name>`, or
+/// `<hex> *<name>` for binary mode)
+fn expected_checksum<'a>(sums: &'a str, name: &str) -> Option<&'a str> {
+    sums.lines().find_map(|line| {
+        let (digest, file) = line.trim().split_once(char::is_whitespace)?;
+        (file.trim_start().trim_start_matches('*') == name).then_some(digest)
+    })
+}
+
+/// Write the new binary next to `exe` and move it into place. A running
+/// executable can be renamed but not overwritten on Windows, so the old one
+/// is moved aside first there, and back if the new one can't take its place.
+fn replace_executable(exe: &Path, bytes: &[u8]) -> Result<()> {
+    let dir = exe.parent().ok_or_else(|| anyhow!("{}: no parent directory", exe.display()))?;
+    let mut staged = tempfile::NamedTempFile::new_in(dir)
+        .map_err(|e| anyhow!("cannot write to {}: {}", dir.display(), e))?;
+    staged.write_all(bytes)?;
+    staged.flush()?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(staged.path(), std::fs::Permissions::from_mode(0o755))?;
+    }
+    #[cfg(not(unix))]
+    {
+        let old = exe.with_extension("old.exe");
+        let _ = std::fs::remove_file(&old);
+        std::fs::rename(exe, &old)?;
+        if let Err(e) = staged.persist(exe) {
+            std::fs::rename(&old, exe)
+                .map_err(|restore| anyhow!("cannot replace {}: {}; the old binary is at {} ({})", exe.display(), e.error, old.display(), restore))?;
+            return Err(anyhow!("cannot replace {}: {}", exe.display(), e.error));
+        }
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    {
+        staged.persist(exe).map_err(|e| anyhow!("cannot replace {}: {}", exe.display(), e.error))?;
+        Ok(())
+    }
+}
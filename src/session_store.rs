@@ -0,0 +1,332 @@
+//! Sessions kept in SQLite (`~/.local/share/aish/aish.db`): the commands each
+//! session ran and its agent conversation, message by message with tool
+//! calls and results, and what its model has been sent (`context_ledger`).
+//! `sessions resume <id>` picks an earlier conversation back up, after a
+//! restart too: later prompts see it and are added to it.
+//! The store is best effort; if it can't be written, aish carries on.
+//! `shell.session_store: false` turns it off, and sessions older than
+//! `shell.session_retention_days` are dropped. The file is private (0600).
+
+use anyhow::{anyhow, Result};
+use rusqlite::{params, Connection};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, Once};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::state;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sessions (
+        id TEXT PRIMARY KEY,
+        started INTEGER NOT NULL,
+        cwd TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS messages (
+        id INTEGER PRIMARY KEY,
+        session TEXT NOT NULL,
+        time INTEGER NOT NULL,
+        role TEXT NOT NULL,
+        message TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS messages_session ON messages (session);
+    CREATE TABLE IF NOT EXISTS commands (
+        id INTEGER PRIMARY KEY,
+        session TEXT NOT NULL,
+        time INTEGER NOT NULL,
+        cwd TEXT NOT NULL,
+        command TEXT NOT NULL,
+        exit_code INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS commands_session ON commands (session);
+    CREATE TABLE IF NOT EXISTS ledgers (
+        session TEXT PRIMARY KEY,
+        ledger TEXT NOT NULL
+    );
+";
+
+// Characters of the first prompt shown as a session's title
+const TITLE_CHARS: usize = 60;
+
+/// Days sessions are kept unless `shell.session_retention_days` says otherwise
+pub const DEFAULT_RETENTION_DAYS: u64 = 90;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static RETENTION_DAYS: AtomicU64 = AtomicU64::new(DEFAULT_RETENTION_DAYS);
+// Old sessions are dropped once per process, on the first open
+static PRUNE: Once = Once::new();
+
+lazy_static::lazy_static! {
+    // The session whose conversation agent prompts continue, once one is resumed
+    static ref RESUMED: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// A session as `sessions list` shows it
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: String,
+    /// Seconds since the Unix epoch
+    pub started: u64,
+    pub cwd: String,
+    pub prompts: u64,
+    pub commands: u64,
+    /// The first prompt, shortened
+    pub title: Option<String>,
+}
+
+fn path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("aish").join("aish.db"))
+}
+
+/// Turn the store on or off and set how many days sessions are kept (0 is forever)
+pub fn configure(enabled: bool, retention_days: u64) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+    RETENTION_DAYS.store(retention_days, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn open() -> Result<Connection> {
+    if !enabled() {
+        return Err(anyhow!("the session store is off (shell.session_store: false)"));
+    }
+    let path = path().ok_or_else(|| anyhow!("no data directory"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    create_private(&path)?;
+    let db = Connection::open(&path)?;
+    db.execute_batch(SCHEMA)?;
+    PRUNE.call_once(|| warn_on_error(prune(&db), "retention pruning"));
+    Ok(db)
+}
+
+/// Create the database file readable by its owner only, and make an
+/// existing one so; SQLite gives its journal the same mode
+#[cfg(unix)]
+fn create_private(path: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    std::fs::OpenOptions::new().create(true).append(true).mode(0o600).open(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_private(_path: &std::path::Path) -> Result<()> {
+    Ok(())
+}
+
+/// Drop sessions, with their messages and commands, that started more
+/// than the retention limit ago
+fn prune(db: &Connection) -> Result<()> {
+    let days = RETENTION_DAYS.load(Ordering::Relaxed);
+    if days == 0 {
+        return Ok(());
+    }
+    let cutoff = now().saturating_sub(days.saturating_mul(24 * 60 * 60)) as i64;
+    db.execute_batch("BEGIN")?;
+    let pruned = (|| -> Result<()> {
+        let old = "SELECT id FROM sessions WHERE started < ?1";
+        db.execute(&format!("DELETE FROM messages WHERE session IN ({})", old), [cutoff])?;
+        db.execute(&format!("DELETE FROM commands WHERE session IN ({})", old), [cutoff])?;
+        db.execute(&format!("DELETE FROM ledgers WHERE session IN ({})", old), [cutoff])?;
+        db.execute("DELETE FROM sessions WHERE started < ?1", [cutoff])?;
+        Ok(())
+    })();
+    db.execute_batch(if pruned.is_ok() { "COMMIT" } else { "ROLLBACK" })?;
+    pruned
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+/// Add this process's session on its first write
+fn ensure_session(db: &Connection) -> Result<()> {
+    db.execute(
+        "INSERT OR IGNORE INTO sessions (id, started, cwd) VALUES (?1, ?2, ?3)",
+        params![state::session_id(), now() as i64, state::current_dir().display().to_string()],
+    )?;
+    Ok(())
+}
+
+/// The session agent messages belong to: the resumed one, or this one
+pub fn conversation_id() -> String {
+    RESUMED
+        .lock()
+        .ok()
+        .and_then(|resumed| resumed.clone())
+        .unwrap_or_else(|| state::session_id().to_string())
+}
+
+fn warn_on_error(result: Result<()>, what: &str) {
+    if let Err(e) = result {
+        tracing::warn!(error = %e, "could not store {}", what);
+    }
+}
+
+/// Store a command this session ran
+pub fn record_command(command: &str, exit_code: i32) {
+    if !enabled() {
+        return;
+    }
+    warn_on_error(
+        open().and_then(|db| {
+            ensure_session(&db)?;
+            db.execute(
+                "INSERT INTO commands (session, time, cwd, command, exit_code) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![state::session_id(), now() as i64, state::current_dir().display().to_string(), command, exit_code],
+            )?;
+            Ok(())
+        }),
+        "command",
+    );
+}
+
+/// Store one agent conversation message (a chat completion message as JSON)
+pub fn record_message(message: &Value) {
+    if !enabled() {
+        return;
+    }
+    let role = message["role"].as_str().unwrap_or_default();
+    warn_on_error(
+        open().and_then(|db| {
+            ensure_session(&db)?;
+            db.execute(
+                "INSERT INTO messages (session, time, role, message) VALUES (?1, ?2, ?3, ?4)",
+                params![conversation_id(), now() as i64, role, message.to_string()],
+            )?;
+            Ok(())
+        }),
+        "message",
+    );
+}
+
+/// Store what the model of the current conversation has been sent (see
+/// `context_ledger`), replacing what was stored for it before
+pub fn record_ledger(ledger: &Value) {
+    if !enabled() {
+        return;
+    }
+    warn_on_error(
+        open().and_then(|db| {
+            ensure_session(&db)?;
+            db.execute(
+                "INSERT OR REPLACE INTO ledgers (session, ledger) VALUES (?1, ?2)",
+                params![conversation_id(), ledger.to_string()],
+            )?;
+            Ok(())
+        }),
+        "context ledger",
+    );
+}
+
+/// The stored context ledger of the current conversation, if any
+pub fn ledger() -> Option<Value> {
+    if !enabled() {
+        return None;
+    }
+    let ledger = open().and_then(|db| {
+        Ok(db.query_row("SELECT ledger FROM ledgers WHERE session = ?1", [conversation_id()], |row| row.get::<_, String>(0))?)
+    });
+    match ledger {
+        Ok(ledger) => serde_json::from_str(&ledger).ok(),
+        Err(e) => {
+            tracing::debug!(error = %e, "no stored context ledger");
+            None
+        }
+    }
+}
+
+/// The resumed conversation's messages, oldest first; empty unless a
+/// session was resumed, so each prompt otherwise starts afresh
+pub fn conversation() -> Vec<Value> {
+    if !enabled() {
+        return Vec::new();
+    }
+    let Some(id) = RESUMED.lock().ok().and_then(|resumed| resumed.clone()) else {
+        return Vec::new();
+    };
+    let messages = open().and_then(|db| {
+        let mut statement = db.prepare("SELECT message FROM messages WHERE session = ?1 ORDER BY id")?;
+        let rows = statement.query_map([&id], |row| row.get::<_, String>(0))?;
+        Ok(rows.collect::<rusqlite::Result<Vec<String>>>()?)
+    });
+    match messages {
+        Ok(messages) => messages.iter().filter_map(|message| serde_json::from_str(message).ok()).collect(),
+        Err(e) => {
+            tracing::warn!(error = %e, session = %id, "could not load conversation");
+            Vec::new()
+        }
+    }
+}
+
+const SESSION_COLUMNS: &str = "SELECT s.id, s.started, s.cwd,
+        (SELECT COUNT(*) FROM messages m WHERE m.session = s.id AND m.role = 'user'),
+        (SELECT COUNT(*) FROM commands c WHERE c.session = s.id),
+        (SELECT m.message FROM messages m WHERE m.session = s.id AND m.role = 'user' ORDER BY m.id LIMIT 1)
+    FROM sessions s";
+
+fn session_info(row: &rusqlite::Row) -> rusqlite::Result<SessionInfo> {
+    let first: Option<String> = row.get(5)?;
+    Ok(SessionInfo {
+        id: row.get(0)?,
+        started: row.get::<_, i64>(1)? as u64,
+        cwd: row.get(2)?,
+        prompts: row.get::<_, i64>(3)? as u64,
+        commands: row.get::<_, i64>(4)? as u64,
+        title: first.as_deref().and_then(title),
+    })
+}
+
+/// The most recent sessions, newest first
+pub fn list(limit: usize) -> Result<Vec<SessionInfo>> {
+    let db = open()?;
+    let mut statement = db.prepare(&format!("{} ORDER BY s.started DESC LIMIT ?1", SESSION_COLUMNS))?;
+    let rows = statement.query_map([limit as i64], session_info)?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+/// A stored user message's text on one line, shortened
+fn title(message: &str) -> Option<String> {
+    let message: Value = serde_json::from_str(message).ok()?;
+    let text = message["content"].as_str()?.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut title: String = text.chars().take(TITLE_CHARS).collect();
+    if title.len() < text.len() {
+        title.push('…');
+    }
+    Some(title)
+}
+
+/// Continue the conversation of the session whose id starts with `prefix`
+/// in later agent prompts
+pub fn resume(prefix: &str) -> Result<SessionInfo> {
+    let db = open()?;
+    let pattern = format!("{}%", prefix.replace(['%', '_'], ""));
+    let mut statement = db.prepare(&format!("{} WHERE s.id LIKE ?1 LIMIT 2", SESSION_COLUMNS))?;
+    let mut matches: Vec<SessionInfo> = statement.query_map([&pattern], session_info)?.collect::<rusqlite::Result<_>>()?;
+    let info = match matches.len() {
+        1 => matches.remove(0),
+        0 => return Err(anyhow!("{}: no such session", prefix)),
+        _ => return Err(anyhow!("{}: matches more than one session", prefix)),
+    };
+    if let Ok(mut resumed) = RESUMED.lock() {
+        *resumed = Some(info.id.clone());
+    }
+    Ok(info)
+}
+
+/// Go back to a fresh conversation per prompt
+pub fn forget_resumed() {
+    if let Ok(mut resumed) = RESUMED.lock() {
+        *resumed = None;
+    }
+}
+
+/// The id of the resumed session, if any
+pub fn resumed() -> Option<String> {
+    RESUMED.lock().ok().and_then(|resumed| resumed.clone())
+}
@@ -0,0 +1,394 @@
+//! Pair-ops sessions: `aish share` streams the live session (inputs, command
+//! output and agent activity, as in a transcript) to teammates over a Unix
+//! socket, and can require one of them to co-approve every risky step.
+//! `aish watch` connects, locally or through an SSH-forwarded socket.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::render::Renderer;
+use crate::transcript::Entry;
+use crate::ui::{self, Role};
+
+/// What viewers of a shared session may do
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShareMode {
+    /// Viewers only watch
+    ReadOnly,
+    /// Steps the user approves (commands, config changes…) are also put to
+    /// the viewers and only go ahead once one of them approves
+    CoApprove,
+}
+
+/// Sent to viewers between the session's transcript entries, one JSON
+/// object per line
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Control {
+    Hello { pid: u32, co_approve: bool },
+    ApprovalRequest { id: u64, question: String, subject: String, details: Option<String> },
+    ApprovalClosed { id: u64, approved: bool },
+}
+
+/// Sent by viewers
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    Approval { id: u64, approve: bool },
+}
+
+fn share_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|mut p| { p.push(".aish"); p.push("share"); p })
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::net::Shutdown;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{mpsc, Mutex};
+    use std::time::Duration;
+
+    use crate::approval::{self, ApprovalRequest};
+    use crate::transcript;
+
+    // How long a co-approval waits for a viewer to answer
+    const CO_APPROVAL_TIMEOUT: Duration = Duration::from_secs(120);
+    // A viewer that can't take output this quickly is dropped
+    const VIEWER_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+    // Lines queued for a viewer; one that falls this far behind is dropped
+    // rather than stall the session
+    const VIEWER_QUEUE: usize = 1024;
+
+    struct Share {
+        path: PathBuf,
+        mode: ShareMode,
+        viewers: Vec<Viewer>,
+    }
+
+    /// A connected viewer, written to by its own thread so a slow one never
+    /// blocks the session or the other viewers
+    struct Viewer {
+        lines: mpsc::SyncSender<String>,
+        stream: UnixStream,
+    }
+
+    impl Viewer {
+        fn start(stream: UnixStream) -> std::io::Result<Self> {
+            let (lines, queued) = mpsc::sync_channel::<String>(VIEWER_QUEUE);
+            let mut writer = stream.try_clone()?;
+            std::thread::spawn(move || {
+                for line in queued {
+                    if writeln!(writer, "{}", line).is_err() {
+                        let _ = writer.shutdown(Shutdown::Both);
+                        return;
+                    }
+                }
+            });
+            Ok(Self { lines, stream })
+        }
+
+        /// Queue a line without waiting; false once the viewer is gone or too far behind
+        fn send(&self, line: &str) -> bool {
+            self.lines.try_send(line.to_string()).is_ok()
+        }
+    }
+
+    impl Drop for Viewer {
+        fn drop(&mut self) {
+            let _ = self.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    lazy_static::lazy_static! {
+        static ref SHARE: Mutex<Option<Share>> = Mutex::new(None);
+        // Co-approvals waiting on a viewer, by request id
+        static ref PENDING: Mutex<HashMap<u64, mpsc::Sender<bool>>> = Mutex::new(HashMap::new());
+    }
+    static NEXT_APPROVAL: AtomicU64 = AtomicU64::new(1);
+
+    pub fn start(path: Option<&Path>, mode: ShareMode) -> Result<PathBuf> {
+        stop();
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                let dir = share_dir().ok_or_else(|| anyhow::anyhow!("could not determine home directory"))?;
+                std::fs::DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+                dir.join(format!("{}.sock", std::process::id()))
+            }
+        };
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+
+        if let Ok(mut share) = SHARE.lock() {
+            *share = Some(Share { path: path.clone(), mode, viewers: Vec::new() });
+        }
+        let accepting = path.clone();
+        std::thread::spawn(move || accept(listener, &accepting));
+        tracing::info!(path = %path.display(), ?mode, "sharing session");
+        Ok(path)
+    }
+
+    pub fn stop() -> Option<PathBuf> {
+        let share = SHARE.lock().ok()?.take()?;
+        // Dropping the streams disconnects the viewers; connecting wakes the
+        // accept loop so it notices sharing stopped
+        drop(share.viewers);
+        let _ = UnixStream::connect(&share.path);
+        let _ = std::fs::remove_file(&share.path);
+        Some(share.path)
+    }
+
+    pub fn status() -> Option<(PathBuf, ShareMode, usize)> {
+        let share = SHARE.lock().ok()?;
+        share.as_ref().map(|share| (share.path.clone(), share.mode, share.viewers.len()))
+    }
+
+    fn accept(listener: UnixListener, path: &Path) {
+        for stream in listener.incoming() {
+            let Ok(mut share) = SHARE.lock() else {
+                return;
+            };
+            let Some(share) = share.as_mut().filter(|share| share.path == path) else {
+                return;
+            };
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            let _ = stream.set_write_timeout(Some(VIEWER_WRITE_TIMEOUT));
+            let hello = Control::Hello { pid: std::process::id(), co_approve: share.mode == ShareMode::CoApprove };
+            if send(&mut stream, &hello).is_err() {
+                continue;
+            }
+            if let Ok(reader) = stream.try_clone() {
+                std::thread::spawn(move || read_replies(reader));
+            }
+            let Ok(viewer) = Viewer::start(stream) else {
+                continue;
+            };
+            share.viewers.push(viewer);
+            eprintln!("{}", ui::paint(Role::Muted, &format!("[share] a viewer joined ({} watching)", share.viewers.len())));
+            tracing::info!(viewers = share.viewers.len(), "viewer joined shared session");
+        }
+    }
+
+    fn read_replies(stream: UnixStream) {
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            match serde_json::from_str::<Reply>(&line) {
+                Ok(Reply::Approval { id, approve }) => {
+                    if let Some(waiting) = PENDING.lock().ok().and_then(|mut pending| pending.remove(&id)) {
+                        let _ = waiting.send(approve);
+                    }
+                }
+                Err(e) => tracing::debug!(error = %e, "ignoring malformed message from viewer"),
+            }
+        }
+    }
+
+    fn send(stream: &mut UnixStream, message: &impl Serialize) -> std::io::Result<()> {
+        let line = serde_json::to_string(message)?;
+        writeln!(stream, "{}", line)
+    }
+
+    /// Queue a message for every viewer, dropping any that have gone away
+    /// or fallen behind; the writing happens on the viewers' threads
+    fn send_all(share: &mut Share, message: &impl Serialize) {
+        let Ok(line) = serde_json::to_string(message) else {
+            return;
+        };
+        share.viewers.retain(|viewer| viewer.send(&line));
+    }
+
+    pub fn broadcast(entry: &Entry) {
+        if let Ok(mut share) = SHARE.lock() {
+            if let Some(share) = share.as_mut() {
+                send_all(share, entry);
+            }
+        }
+    }
+
+    pub fn co_approve(request: &ApprovalRequest, subject: &str) -> bool {
+        let id = NEXT_APPROVAL.fetch_add(1, Ordering::Relaxed);
+        let (sender, answer) = mpsc::channel();
+        {
+            let Ok(mut share) = SHARE.lock() else {
+                return false;
+            };
+            let Some(share) = share.as_mut().filter(|share| share.mode == ShareMode::CoApprove) else {
+                return true;
+            };
+            if share.viewers.is_empty() {
+                println!("{}", ui::paint(Role::Error, "No viewer is connected to co-approve this; denied"));
+                return false;
+            }
+            if let Ok(mut pending) = PENDING.lock() {
+                pending.insert(id, sender);
+            }
+            send_all(share, &Control::ApprovalRequest {
+                id,
+                question: request.question.to_string(),
+                subject: subject.to_string(),
+                details: request.details.map(str::to_string),
+            });
+        }
+
+        println!("{}", ui::paint(Role::Muted, &format!("   waiting up to {}s for a viewer to co-approve…", CO_APPROVAL_TIMEOUT.as_secs())));
+        let approved = answer.recv_timeout(CO_APPROVAL_TIMEOUT).unwrap_or(false);
+        if let Ok(mut pending) = PENDING.lock() {
+            pending.remove(&id);
+        }
+        if let Ok(mut share) = SHARE.lock() {
+            if let Some(share) = share.as_mut() {
+                send_all(share, &Control::ApprovalClosed { id, approved });
+            }
+        }
+        if approved {
+            println!("{}", ui::paint(Role::Success, "   co-approved by a viewer"));
+        } else {
+            println!("{}", ui::paint(Role::Error, "   not co-approved"));
+        }
+        tracing::info!(subject, approved, "co-approval");
+        approved
+    }
+
+    pub fn watch(path: Option<&Path>, renderer: &dyn Renderer) -> Result<()> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => only_session()?,
+        };
+        let stream = UnixStream::connect(&path)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+        let mut replies = stream.try_clone()?;
+
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            let Ok(value) = serde_json::from_str::<Value>(&line) else {
+                continue;
+            };
+            match serde_json::from_value::<Control>(value.clone()) {
+                Ok(Control::Hello { pid, co_approve }) => {
+                    let role = if co_approve { "you will be asked to co-approve risky steps" } else { "read-only" };
+                    println!("{}", ui::paint(Role::Muted, &format!("Watching aish session {} ({}); Ctrl-C to leave", pid, role)));
+                }
+                Ok(Control::ApprovalRequest { id, question, subject, details }) => {
+                    println!("{}", ui::paint(Role::Warning, "The session asks you to co-approve:"));
+                    let decision = approval::ask(&ApprovalRequest {
+                        question: &question,
+                        subject: &subject,
+                        details: details.as_deref(),
+                        editable: false,
+                        always_key: None,
+                        default_approve: false,
+                        risky: false,
+                    });
+                    send(&mut replies, &Reply::Approval { id, approve: decision.approved() })?;
+                }
+                Ok(Control::ApprovalClosed { approved, .. }) => {
+                    println!("{}", ui::paint(Role::Muted, &format!("   {}", if approved { "approved" } else { "denied or timed out" })));
+                }
+                Err(_) => match serde_json::from_value::<Entry>(value) {
+                    Ok(entry) => transcript::show(&entry, renderer),
+                    Err(e) => tracing::debug!(error = %e, "ignoring unknown message from shared session"),
+                },
+            }
+        }
+        println!("{}", ui::paint(Role::Muted, "The session stopped sharing"));
+        Ok(())
+    }
+
+    /// The shared session to watch when none is named: the only live one
+    fn only_session() -> Result<PathBuf> {
+        let dir = share_dir().ok_or_else(|| anyhow::anyhow!("could not determine home directory"))?;
+        let sessions: Vec<PathBuf> = std::fs::read_dir(&dir)
+            .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|path| {
+                // Sockets left behind by sessions that exited
+                path.file_stem()
+                    .and_then(|stem| stem.to_str()?.parse::<libc::pid_t>().ok())
+                    .is_some_and(|pid| unsafe { libc::kill(pid, 0) } == 0)
+            })
+            .collect();
+        match sessions.as_slice() {
+            [] => Err(anyhow::anyhow!("no shared session found in {}; start one with `aish share`", dir.display())),
+            [path] => Ok(path.clone()),
+            _ => Err(anyhow::anyhow!("several shared sessions; name one:\n{}",
+                sessions.iter().map(|path| format!("  {}", path.display())).collect::<Vec<_>>().join("\n"))),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod unix {
+    use super::*;
+    use crate::approval::ApprovalRequest;
+
+    pub fn start(_path: Option<&Path>, _mode: ShareMode) -> Result<PathBuf> {
+        Err(anyhow::anyhow!("sharing a session needs Unix domain sockets"))
+    }
+
+    pub fn stop() -> Option<PathBuf> {
+        None
+    }
+
+    pub fn status() -> Option<(PathBuf, ShareMode, usize)> {
+        None
+    }
+
+    pub fn broadcast(_entry: &Entry) {}
+
+    pub fn co_approve(_request: &ApprovalRequest, _subject: &str) -> bool {
+        true
+    }
+
+    pub fn watch(_path: Option<&Path>, _renderer: &dyn Renderer) -> Result<()> {
+        Err(anyhow::anyhow!("watching a session needs Unix domain sockets"))
+    }
+}
+
+/// Start sharing this session on `path` (default `~/.aish/share/<pid>.sock`),
+/// replacing any share in progress. Returns the socket path.
+pub fn start(path: Option<&Path>, mode: ShareMode) -> Result<PathBuf> {
+    unix::start(path, mode)
+}
+
+/// Stop sharing and disconnect viewers, returning the socket that was used
+pub fn stop() -> Option<PathBuf> {
+    unix::stop()
+}
+
+/// The socket, mode and number of connected viewers, if sharing
+pub fn status() -> Option<(PathBuf, ShareMode, usize)> {
+    unix::status()
+}
+
+/// Send a session event to every viewer
+pub fn broadcast(entry: &Entry) {
+    unix::broadcast(entry)
+}
+
+/// When sharing in [`ShareMode::CoApprove`] mode, put a request the user
+/// approved (with `subject` as finally approved) to the viewers and wait
+/// for one to answer. True when no co-approval is needed.
+pub fn co_approve(request: &crate::approval::ApprovalRequest, subject: &str) -> bool {
+    unix::co_approve(request, subject)
+}
+
+/// Follow a shared session, answering its co-approval requests, until it
+/// stops sharing. Without `path`, joins the only session shared from this
+/// account.
+pub fn watch(path: Option<&Path>, renderer: &dyn Renderer) -> Result<()> {
+    unix::watch(path, renderer)
+}
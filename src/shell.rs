@@ -0,0 +1,2526 @@
+use anyhow::Result;
+use rustyline::error::ReadlineError;
+use rustyline::history::{DefaultHistory, History};
+use rustyline::{Editor, KeyCode, KeyEvent, Modifiers, EventHandler, ConditionalEventHandler, Event, RepeatCount, EventContext, Cmd, Movement};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use crate::config::nesting_level;
+use crate::ts_runtime::ops::session_var_env;
+use crate::command_list::{self, Connector, Node};
+use crate::command_cache;
+use crate::render::{RenderFormat, Renderer};
+use crate::share::ShareMode;
+use crate::prompt_pipe::PipeMode;
+use crate::prompt_style::StyledPrompt;
+use crate::ui::Role;
+use crate::history::EntryKind;
+use crate::{agent, approval, capture, clipboard, context, dry_run, editor, events, evidence, explain, fix, flag_stats, history, history_expansion, humanize, jobs, notify, pathname, platform, project_index, prompt_pipe, provider_health, self_update, session_store, share, state, substitution, summary, timeout, transcript, translate, trash, ts_runtime, ui, usage, wasm_plugins, AiAgent, Config, ShellMode};
+
+/// Run `cmd` to completion; with `capture`, its output is also kept (see
+/// [`capture::run`]) and returned so the agent can see what happened
+fn run_teed(cmd: &mut Command, capture: bool, capture_stdout: bool) -> std::io::Result<(ExitStatus, String)> {
+    if !capture {
+        return Ok((jobs::status(cmd)?, String::new()));
+    }
+    capture::run(cmd, capture_stdout)
+}
+
+/// Exit status as sh reports it: the exit code, or 128 + N for a child
+/// killed or stopped by signal N
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal().or(status.stopped_signal()) {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(-1)
+}
+
+// Status of a command interrupted with Ctrl-C (128 + SIGINT)
+const INTERRUPTED_STATUS: i32 = 130;
+// Status of a command stopped with Ctrl-Z (128 + SIGTSTP), now a job
+#[cfg(unix)]
+const STOPPED_STATUS: i32 = 128 + libc::SIGTSTP;
+#[cfg(not(unix))]
+const STOPPED_STATUS: i32 = 148;
+
+/// Conditions `trap` accepts, by canonical name
+const TRAP_CONDITIONS: [&str; 3] = ["EXIT", "INT", "ERR"];
+
+// `source` nesting allowed before a script sourcing itself is stopped
+const MAX_SOURCE_DEPTH: usize = 64;
+
+/// How a run of script lines ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScriptEnd {
+    Finished,
+    // A line asked the shell to exit
+    Exit,
+    // A command failed under `set -e`
+    Failed,
+}
+
+/// Canonical trap condition for `EXIT`/`0`, `INT`/`SIGINT`/`2` or `ERR`
+fn trap_condition(name: &str) -> Option<&'static str> {
+    let upper = name.to_uppercase();
+    match upper.strip_prefix("SIG").unwrap_or(&upper) {
+        "EXIT" | "0" => Some("EXIT"),
+        "INT" | "2" => Some("INT"),
+        "ERR" => Some("ERR"),
+        _ => None,
+    }
+}
+
+/// The builtin invocation in `line`, without its sigil: any line in Command
+/// mode, but only `:word ...` in Agent and Translate mode, where a line that
+/// merely starts with a builtin's name ("update the README") is a prompt
+fn builtin_line<'a>(line: &'a str, mode: &ShellMode) -> Option<&'a str> {
+    let explicit = line.strip_prefix(':').filter(|rest| rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '?'));
+    match (explicit, mode) {
+        (Some(rest), _) => Some(rest),
+        (None, ShellMode::Command) => Some(line),
+        (None, _) => None,
+    }
+}
+
+/// Whether `args` fit builtin `name`'s syntax; a line that doesn't is left
+/// to normal routing (the agent, or a program of that name)
+fn builtin_syntax(name: &str, args: &str) -> bool {
+    let args = args.trim();
+    match name {
+        "usage" => args.is_empty(),
+        "model" => !args.contains(char::is_whitespace),
+        "update" => matches!(args, "" | "--check"),
+        "fix" => args.is_empty(),
+        // `record [file|stop]`: a sentence isn't a file name
+        "record" => !args.contains(char::is_whitespace),
+        "history" => history_syntax(args),
+        "copy" => matches!(args, "" | "answer" | "output"),
+        _ => true,
+    }
+}
+
+/// `history [N] | history search TERM | history delete N | history run N`
+fn history_syntax(args: &str) -> bool {
+    let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+    match subcommand {
+        "" => true,
+        "search" => !rest.is_empty(),
+        "delete" | "run" => rest.parse::<usize>().is_ok(),
+        count => rest.is_empty() && count.parse::<usize>().is_ok(),
+    }
+}
+
+/// The `record` builtin: start recording to a file, stop, or show the status
+fn record_builtin(args: &str) -> i32 {
+    match args {
+        "" => match transcript::recording() {
+            Some(path) => println!("Recording to {}", path.display()),
+            None => println!("Not recording; `record <file>` starts"),
+        },
+        "stop" => match transcript::stop() {
+            Some(path) => println!("Transcript saved to {}", path.display()),
+            None => println!("Not recording"),
+        },
+        file => {
+            let path = state::current_dir().join(file);
+            if let Err(e) = transcript::start(&path) {
+                eprintln!("record: {}: {}", path.display(), e);
+                return 1;
+            }
+            println!("Recording to {} (`record stop` to finish; replay with `aish --replay`)", path.display());
+        }
+    }
+    0
+}
+
+/// The `copy` builtin: put the agent's last answer, or the last command's
+/// output, on the clipboard
+fn copy_builtin(args: &str) -> i32 {
+    let (what, text) = match args {
+        "" | "answer" => ("answer", agent::last_answer()),
+        "output" => ("output", context::last().map(|record| record.output)),
+        _ => {
+            eprintln!("copy: usage: copy [answer|output]");
+            return 2;
+        }
+    };
+    let Some(text) = text.filter(|text| !text.trim().is_empty()) else {
+        eprintln!("copy: no {} to copy yet", if what == "answer" { "agent answer" } else { "command output" });
+        return 1;
+    };
+    if let Err(e) = clipboard::write(text.trim_end()) {
+        eprintln!("copy: {}", e);
+        return 1;
+    }
+    println!("Copied the last {} ({} lines)", what, text.trim_end().lines().count());
+    0
+}
+
+/// The `paste` builtin: print what is on the clipboard
+fn paste_builtin() -> i32 {
+    match clipboard::read() {
+        Ok(text) => {
+            println!("{}", text.trim_end_matches('\n'));
+            0
+        }
+        Err(e) => {
+            eprintln!("paste: {}", e);
+            1
+        }
+    }
+}
+
+/// The `evidence` builtin: print the tool results the last answer cited,
+/// or just result `N`
+fn evidence_builtin(args: &str) -> i32 {
+    let cited = evidence::last();
+    let shown: Vec<&evidence::Evidence> = match args {
+        "" => cited.iter().collect(),
+        number => match number.trim_start_matches('[').trim_end_matches(']').parse::<usize>() {
+            Ok(number) => cited.iter().filter(|e| e.number == number).collect(),
+            Err(_) => {
+                eprintln!("evidence: usage: evidence [N]");
+                return 2;
+            }
+        },
+    };
+    if shown.is_empty() {
+        println!("No cited evidence{}", if args.is_empty() { " for the last answer" } else { " with that number" });
+        return 1;
+    }
+    for evidence in shown {
+        println!("{}", ui::paint(Role::Emphasis, &format!("[{}] {}", evidence.number, evidence.source)));
+        println!("{}", evidence.output.trim_end());
+    }
+    0
+}
+
+/// The `share` builtin: share this session read-only or for co-approval,
+/// stop sharing, or show who is watching
+fn share_builtin(args: &str) -> i32 {
+    let mode = match args {
+        "" => {
+            match share::status() {
+                Some((path, mode, viewers)) => {
+                    let mode = if mode == ShareMode::CoApprove { "co-approval" } else { "read-only" };
+                    println!("Sharing {} on {} ({} watching)", mode, path.display(), viewers);
+                }
+                None => println!("Not sharing; `share [--approve]` starts"),
+            }
+            return 0;
+        }
+        "stop" => {
+            match share::stop() {
+                Some(_) => println!("Stopped sharing"),
+                None => println!("Not sharing"),
+            }
+            return 0;
+        }
+        "--read-only" => ShareMode::ReadOnly,
+        "--approve" => ShareMode::CoApprove,
+        other => {
+            eprintln!("share: unknown option: {} (usage: share [--approve|--read-only|stop])", other);
+            return 2;
+        }
+    };
+    match share::start(None, mode) {
+        Ok(path) => {
+            println!("Sharing on {}", path.display());
+            println!("Teammates join with `aish watch {}` (over SSH: forward the socket with `ssh -L`)", path.display());
+            0
+        }
+        Err(e) => {
+            eprintln!("share: {}", e);
+            1
+        }
+    }
+}
+
+/// The `restore` builtin: without arguments, list what rm moved to the trash
+/// (newest first); otherwise put back items given by number or original path
+fn restore_trashed(args: &str) -> i32 {
+    let items = trash::recent();
+    if args.is_empty() {
+        if items.is_empty() {
+            println!("Nothing trashed from aish sessions");
+        }
+        for (index, item) in items.iter().enumerate() {
+            println!("{:>3}  {:>9}  {}", index + 1, humanize::relative_time(item.deleted_at), item.original.display());
+        }
+        return 0;
+    }
+
+    let cwd = state::current_dir();
+    let mut status = 0;
+    for arg in command_list::split_words(args) {
+        let item = match arg.parse::<usize>() {
+            Ok(number) => items.get(number.wrapping_sub(1)),
+            // The most recently trashed item with that original path
+            Err(_) => items.iter().find(|item| item.original == cwd.join(&arg)),
+        };
+        let Some(item) = item else {
+            eprintln!("restore: {}: not found in the trash (see `restore`)", arg);
+            status = 1;
+            continue;
+        };
+        match trash::restore(item) {
+            Ok(()) => println!("Restored {}", item.original.display()),
+            Err(e) => {
+                eprintln!("restore: {}: {}", arg, e);
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+/// Whether a command uses pipes, redirection, substitution or compound
+/// commands, which only sh can run
+pub(crate) fn needs_sh(command: &str) -> bool {
+    command.contains(['|', '<', '>', '`']) || command.contains("$(") || command.trim_end().ends_with('&')
+        || command_list::is_compound(command)
+}
+
+// Custom event handler for mode toggle (ESC-x)
+#[derive(Clone)]
+struct ModeToggleHandler {
+    toggle_flag: Arc<Mutex<bool>>,
+}
+
+impl ModeToggleHandler {
+    fn new() -> Self {
+        Self {
+            toggle_flag: Arc::new(Mutex::new(false)),
+        }
+    }
+    
+    fn check_toggle(&self) -> bool {
+        if let Ok(mut flag) = self.toggle_flag.lock() {
+            if *flag {
+                *flag = false;
+                return true;
+            }
+        }
+        false
+    }
+    
+    fn set_toggle(&self) {
+        if let Ok(mut flag) = self.toggle_flag.lock() {
+            *flag = true;
+        }
+    }
+}
+
+// Alt+e on an empty line: expand the evidence cited by the last answer
+#[derive(Clone, Default)]
+struct EvidenceKeyHandler {
+    pressed: Arc<Mutex<bool>>,
+}
+
+impl EvidenceKeyHandler {
+    fn take_pressed(&self) -> bool {
+        self.pressed.lock().map(|mut pressed| std::mem::take(&mut *pressed)).unwrap_or(false)
+    }
+}
+
+impl ConditionalEventHandler for EvidenceKeyHandler {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if !ctx.line().is_empty() {
+            return None;
+        }
+        if let Ok(mut pressed) = self.pressed.lock() {
+            *pressed = true;
+        }
+        Some(Cmd::Interrupt)
+    }
+}
+
+// Up/Down on a line starting with `$` in Agent or Translate mode: cycle
+// through command history (whose entries match what follows the `$`)
+// instead of the mode's own prompts
+#[derive(Clone)]
+struct CommandRecallHandler {
+    mode: Arc<Mutex<ShellMode>>,
+    max_len: usize,
+    cursor: Arc<Mutex<RecallCursor>>,
+}
+
+#[derive(Default)]
+struct RecallCursor {
+    // The line as typed before recall started, restored by Down past the newest
+    typed: String,
+    matches: Vec<String>,
+    // Position in `matches`; `matches.len()` is the typed line
+    index: usize,
+    // What recall last put on the line; anything else starts a new recall
+    shown: Option<String>,
+}
+
+impl CommandRecallHandler {
+    fn new(mode: Arc<Mutex<ShellMode>>, max_len: usize) -> Self {
+        Self { mode, max_len, cursor: Arc::default() }
+    }
+}
+
+impl ConditionalEventHandler for CommandRecallHandler {
+    fn handle(&self, evt: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if !self.mode.lock().is_ok_and(|mode| *mode != ShellMode::Command) {
+            return None;
+        }
+        let line = ctx.line();
+        let prefix = line.trim_start().strip_prefix('$')?.trim_start();
+        let older = evt.get(0) == Some(&KeyEvent(KeyCode::Up, Modifiers::NONE));
+        // Moving between the lines of a multi-line buffer keeps its default
+        let (before, after) = line.split_at(ctx.pos());
+        if (older && before.contains('\n')) || (!older && after.contains('\n')) {
+            return None;
+        }
+        
+        let mut cursor = self.cursor.lock().ok()?;
+        if cursor.shown.as_deref() != Some(line) {
+            let matches: Vec<String> = history::recall_entries(&ShellMode::Command, self.max_len)
+                .into_iter()
+                .filter(|command| command.starts_with(prefix))
+                .collect();
+            *cursor = RecallCursor { typed: line.to_string(), index: matches.len(), matches, shown: None };
+        }
+        if older {
+            if cursor.index == 0 {
+                return Some(Cmd::Noop);
+            }
+            cursor.index -= 1;
+        } else {
+            if cursor.index >= cursor.matches.len() {
+                return Some(Cmd::Noop);
+            }
+            cursor.index += 1;
+        }
+        let shown = match cursor.matches.get(cursor.index) {
+            Some(command) => format!("$ {}", command),
+            None => cursor.typed.clone(),
+        };
+        cursor.shown = Some(shown.clone());
+        Some(Cmd::Replace(Movement::WholeBuffer, Some(shown)))
+    }
+}
+
+impl ConditionalEventHandler for ModeToggleHandler {
+    fn handle(&self, evt: &Event, _: RepeatCount, _: bool, _ctx: &EventContext) -> Option<Cmd> {
+        if let Some(k) = evt.get(0) {
+            // ESC followed by 'x' is typically represented as Alt+x in many terminals
+            if *k == KeyEvent::alt('x') {
+                self.set_toggle();
+                // Return Interrupt to break out of readline loop
+                return Some(Cmd::Interrupt);
+            }
+        }
+        None // default behavior
+    }
+}
+
+/// Interactive shell: readline loop, mode handling and command routing
+pub struct AishShell {
+    editor: Editor<editor::AishHelper, DefaultHistory>,
+    config: Config,
+    ai_agent: AiAgent,
+    // How agent output is shown: styled, plain or JSON events
+    renderer: Box<dyn Renderer>,
+    // Sampling set for the session (`--preset`, `--seed`…), over any template's
+    sampling: ts_runtime::SamplingPreset,
+    // pushd/popd stack, most recently pushed last
+    dir_stack: Vec<PathBuf>,
+    mode: ShellMode,
+    // Mirror of `mode` shared with the line validator
+    shared_mode: Arc<Mutex<ShellMode>>,
+    mode_toggle_handler: ModeToggleHandler,
+    evidence_key_handler: EvidenceKeyHandler,
+    ts_config_loader: ts_runtime::TypeScriptConfigLoader,
+    hooks: HashSet<String>,
+    custom_commands: HashSet<String>,
+    postprocessors: HashSet<String>,
+    // Events the configuration handles with `aish.on`
+    listened_events: HashSet<String>,
+    flag_stats: Arc<Mutex<flag_stats::FlagStats>>,
+    key_check: Option<tokio::task::JoinHandle<Option<String>>>,
+    last_exit_code: i32,
+    // Output captured from the last command, shared with the agent as context
+    last_output: String,
+    // Text to pre-fill into the next prompt, e.g. a suggested fix
+    prefill: Option<String>,
+    // `set -e`: stop lists and scripts at the first untested failure
+    errexit: bool,
+    // Commands registered with `trap`, keyed by EXIT, INT or ERR
+    traps: HashMap<String, String>,
+    // Set while a trap runs, so a failing ERR trap doesn't trigger itself
+    in_trap: bool,
+    exit_handlers_ran: bool,
+    // No banner or configuration notices at startup (`shell.quiet`, `--quiet`)
+    quiet: bool,
+    // Set by `run`, so only interactive sessions print an exit summary
+    interactive: bool,
+    // How many `source` scripts are running inside each other
+    source_depth: usize,
+}
+
+impl AishShell {
+    pub async fn new() -> Result<Self> {
+        // Bracketed paste keeps pasted newlines in the buffer instead of submitting each line
+        let editor_config = rustyline::Config::builder()
+            .bracketed_paste(true)
+            .build();
+        let mut editor = Editor::with_config(editor_config)
+            .map_err(|e| anyhow::anyhow!("Failed to create editor: {}", e))?;
+        
+        // Create mode toggle handler
+        let mode_toggle_handler = ModeToggleHandler::new();
+        
+        // Bind ESC-x (Alt+x) to mode toggle
+        editor.bind_sequence(
+            KeyEvent::alt('x'),
+            EventHandler::Conditional(Box::new(mode_toggle_handler.clone())),
+        );
+        let evidence_key_handler = EvidenceKeyHandler::default();
+        editor.bind_sequence(
+            KeyEvent::alt('e'),
+            EventHandler::Conditional(Box::new(evidence_key_handler.clone())),
+        );
+        
+        let ts_config_loader = ts_runtime::TypeScriptConfigLoader::new()?;
+        // A broken .aish.ts must not lock the user out of their shell
+        let config = match ts_config_loader.load_config().await {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::error!(path = %ts_config_loader.script_path().display(), error = %e, "failed to load configuration");
+                eprintln!("{} {}:\n{}", ui::paint(Role::Error, "Error loading"), ts_config_loader.script_path().display(), e);
+                eprintln!("Using the default configuration; run `config edit` to fix it");
+                Config::default()
+            }
+        };
+        ts_runtime::guard::install(config.safety.as_ref());
+        ts_runtime::permissions::install(config.permissions.as_ref(), ts_config_loader.config_dir());
+        approval::set_external_approver(config.approval_command());
+        ui::install(config.theme.as_ref());
+        notify::configure_agent(config.agent_notifications());
+        session_store::configure(config.session_store(), config.session_retention_days());
+        wasm_plugins::load();
+        let ai_agent = AiAgent::new(config.clone());
+
+        // Inherited from a parent aish through AISH_MODE, else Agent
+        let mode = state::session_snapshot().mode;
+        
+        let history_size = config.history_size();
+        let _ = editor.history_mut().set_max_len(history_size);
+        if let Some(path) = history::recall_path(&mode) {
+            let _ = editor.load_history(&path);
+        }
+        history::trim(history_size);
+        // Stat frequently used executables and scan PATH off the startup path,
+        // so the first Tab press doesn't wait on slow PATH entries
+        command_cache::prewarm(history::recall_entries(&ShellMode::Command, history_size));
+        
+        let shared_mode = Arc::new(Mutex::new(mode.clone()));
+        let command_recall = CommandRecallHandler::new(shared_mode.clone(), history_size);
+        for key in [KeyCode::Up, KeyCode::Down] {
+            editor.bind_sequence(
+                KeyEvent(key, Modifiers::NONE),
+                EventHandler::Conditional(Box::new(command_recall.clone())),
+            );
+        }
+        let flag_stats = Arc::new(Mutex::new(flag_stats::FlagStats::load()));
+        let mut helper = editor::AishHelper::new(shared_mode.clone(), flag_stats.clone());
+        helper.configure_ghost(&config);
+        editor.bind_sequence(
+            KeyEvent(KeyCode::Right, Modifiers::NONE),
+            EventHandler::Conditional(Box::new(helper.accept_hint_handler())),
+        );
+        editor.set_helper(Some(helper));
+        
+        // Discover which lifecycle hooks the configuration defines
+        let hooks = ts_config_loader
+            .defined_functions(&ts_runtime::LIFECYCLE_HOOKS)
+            .await
+            .unwrap_or_default();
+        let custom_commands = ts_config_loader
+            .load_custom_commands()
+            .await
+            .unwrap_or_default();
+        let postprocessors = ts_config_loader
+            .load_postprocessors()
+            .await
+            .unwrap_or_default();
+        let listened_events = ts_config_loader
+            .load_listened_events()
+            .await
+            .unwrap_or_default();
+        
+        let config_quiet = config.quiet();
+        summary::start();
+        Ok(Self {
+            editor,
+            config,
+            ai_agent,
+            renderer: RenderFormat::detect().renderer(),
+            sampling: Default::default(),
+            dir_stack: Vec::new(),
+            mode,
+            shared_mode,
+            mode_toggle_handler,
+            evidence_key_handler,
+            ts_config_loader,
+            hooks,
+            custom_commands,
+            postprocessors,
+            listened_events,
+            flag_stats,
+            key_check: None,
+            last_exit_code: 0,
+            last_output: String::new(),
+            prefill: None,
+            errexit: false,
+            traps: HashMap::new(),
+            in_trap: false,
+            exit_handlers_ran: false,
+            quiet: config_quiet,
+            interactive: false,
+            source_depth: 0,
+        })
+    }
+    
+    /// Suppress the banner and configuration notices, e.g. for `--quiet`
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    /// Where the configuration came from, then `shell.banner` (a template
+    /// or function) or the built-in welcome
+    async fn print_banner(&self) {
+        let script_path = self.ts_config_loader.script_path().display();
+        if self.ts_config_loader.created_default() {
+            println!("Created default TypeScript configuration at: {}", script_path);
+        } else {
+            println!("Found TypeScript configuration at: {}", script_path);
+        }
+
+        let cwd = state::current_dir();
+        let context = json!({
+            "current_dir": cwd.display().to_string(),
+            "mode": self.mode.as_str(),
+            "level": nesting_level(),
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        let banner = match self.ts_config_loader.call_banner(&context).await {
+            Ok(Some(banner)) => Some(banner),
+            Ok(None) => self.config.get_banner(&cwd, &self.mode),
+            Err(e) => {
+                eprintln!("banner error, using the default: {}", e);
+                None
+            }
+        };
+        if let Some(banner) = banner {
+            println!("{}", banner.trim_end());
+            return;
+        }
+
+        println!("Welcome to aish (AI Shell) v{}", env!("CARGO_PKG_VERSION"));
+        println!("Current mode: {}", self.mode.as_str().to_uppercase());
+        if nesting_level() > 1 {
+            println!("Nested aish session (level {} of at most {})", nesting_level(), self.config.max_nesting());
+        }
+        println!("Type 'exit' to quit, 'help' for help, press ESC then x to cycle modes");
+        match self.mode {
+            ShellMode::Agent => println!("Prefix commands with '$' for Unix shell execution"),
+            ShellMode::Command => println!("All commands are executed as Unix shell commands"),
+            ShellMode::Translate => println!("Describe a command in words; it is filled in for you to review before running"),
+        }
+        println!("Use '\\' at the end of a line for multiline commands");
+        println!();
+    }
+
+    /// Choose how agent output is rendered, e.g. JSON events for `--json`
+    pub fn set_render_format(&mut self, format: RenderFormat) {
+        self.renderer = format.renderer();
+    }
+
+    pub fn set_renderer(&mut self, renderer: Box<dyn Renderer>) {
+        self.renderer = renderer;
+    }
+
+    /// One agent turn for `aish -p`, whatever the mode; the exit status is 1
+    /// if the prompt failed
+    pub async fn run_prompt(&mut self, prompt: &str) -> i32 {
+        self.set_exit_code(0);
+        if let Err(e) = self.handle_ai_prompt(prompt).await {
+            eprintln!("{} {}", ui::paint(Role::Error, "AI Error:"), e);
+            self.set_exit_code(1);
+        }
+        self.last_exit_code
+    }
+
+    /// Pin agent sampling for the session: the named preset from `ai.presets`,
+    /// with `overrides` on top
+    pub fn set_sampling(&mut self, preset: Option<&str>, overrides: ts_runtime::SamplingPreset) -> Result<()> {
+        let base = match preset {
+            Some(name) => self.config.sampling_preset(name)?,
+            None => Default::default(),
+        };
+        self.sampling = base.overlay(&overrides);
+        Ok(())
+    }
+    
+    /// Exit status of the most recently handled input
+    pub fn last_exit_code(&self) -> i32 {
+        self.last_exit_code
+    }
+
+    /// Record a command's exit status here and in the session state
+    fn set_exit_code(&mut self, code: i32) {
+        self.last_exit_code = code;
+        state::set_last_exit_code(code);
+    }
+    
+    fn toggle_mode(&mut self) {
+        let previous = self.mode.clone();
+        self.mode = match self.mode {
+            ShellMode::Agent => ShellMode::Command,
+            ShellMode::Command => ShellMode::Translate,
+            ShellMode::Translate => ShellMode::Agent,
+        };
+        if let Ok(mut shared_mode) = self.shared_mode.lock() {
+            *shared_mode = self.mode.clone();
+        }
+        
+        // Up-arrow recall follows the mode
+        let _ = self.editor.clear_history();
+        if let Some(path) = history::recall_path(&self.mode) {
+            let _ = self.editor.load_history(&path);
+        }
+        
+        // Children and TypeScript read the mode from the session state
+        state::set_mode(self.mode.clone());
+        events::emit(events::MODE_CHANGED, json!({ "from": previous.as_str(), "to": self.mode.as_str() }));
+        
+        // Print mode change notification
+        println!("\nMode switched to: {}", self.mode.as_str().to_uppercase());
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        self.interactive = true;
+        jobs::init();
+        if !self.quiet {
+            self.print_banner().await;
+        }
+        
+        self.run_hook("onStart", &[]).await;
+        self.key_check = Some(self.ai_agent.spawn_key_check());
+
+        loop {
+            let command = self.read_command().await?;
+            
+            if command.is_empty() {
+                continue;
+            }
+
+            if let Some(should_exit) = self.handle_input(&command).await {
+                if should_exit {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run aish input non-interactively, one command per line. Lines ending in
+    /// `\` and shell commands with open quotes, brackets or heredocs continue
+    /// onto the next line; blank lines and `#` comments are skipped.
+    /// Returns the exit status of the last command.
+    pub async fn run_script(&mut self, reader: impl BufRead + 'static) -> Result<i32> {
+        self.run_lines(Box::new(reader)).await?;
+        let code = self.last_exit_code;
+        self.run_exit_handlers().await;
+        Ok(code)
+    }
+
+    /// Feed `reader` through `handle_input` as `run_script` describes, without
+    /// running exit handlers. Boxed because `source` calls it from
+    /// `handle_input`.
+    fn run_lines<'a>(&'a mut self, reader: Box<dyn BufRead + 'a>) -> Pin<Box<dyn Future<Output = Result<ScriptEnd>> + 'a>> {
+        Box::pin(async move {
+            let mut pending = String::new();
+            
+            for line in reader.lines() {
+                let line = line?;
+                if pending.is_empty() && (line.trim().is_empty() || line.trim_start().starts_with('#')) {
+                    continue;
+                }
+                
+                if !pending.is_empty() {
+                    pending.push('\n');
+                }
+                pending.push_str(&line);
+                
+                let trimmed = pending.trim_end();
+                if trimmed.ends_with('\\') && !trimmed.ends_with("\\\\") {
+                    continue;
+                }
+                let is_shell = if self.mode == ShellMode::Command {
+                    editor::ask_prompt(trimmed).is_none()
+                } else {
+                    trimmed.trim_start().starts_with('$')
+                };
+                let shell_part = prompt_pipe::split(trimmed).map_or(trimmed, |(command, _)| command);
+                if is_shell && editor::is_incomplete(shell_part) {
+                    continue;
+                }
+                
+                let input = std::mem::take(&mut pending).replace("\\\n", " ");
+                if let Some(true) = self.handle_input(&input).await {
+                    return Ok(ScriptEnd::Exit);
+                }
+                self.dispatch_events().await;
+                if self.errexit && self.last_exit_code != 0 {
+                    return Ok(ScriptEnd::Failed);
+                }
+            }
+            
+            if !pending.trim().is_empty() {
+                let input = pending.replace("\\\n", " ");
+                if let Some(true) = self.handle_input(&input).await {
+                    return Ok(ScriptEnd::Exit);
+                }
+            }
+            Ok(ScriptEnd::Finished)
+        })
+    }
+
+    /// `source FILE` / `. FILE`: run an aish script in this session, so its
+    /// `cd`, `setvar`, traps and mode changes stay in effect afterwards.
+    /// Returns whether the script asked the shell to exit.
+    async fn source_script(&mut self, args: &str) -> bool {
+        let path = args.trim();
+        if self.source_depth >= MAX_SOURCE_DEPTH {
+            eprintln!("source: {}: nested too deeply ({} levels)", path, MAX_SOURCE_DEPTH);
+            self.set_exit_code(1);
+            return false;
+        }
+        let resolved = platform::expand_home(path).unwrap_or_else(|| state::current_dir().join(path));
+        let file = match std::fs::File::open(&resolved) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("source: {}: {}", path, e);
+                self.set_exit_code(1);
+                return false;
+            }
+        };
+        
+        self.source_depth += 1;
+        let result = self.run_lines(Box::new(std::io::BufReader::new(file))).await;
+        self.source_depth -= 1;
+        match result {
+            Ok(end) => end == ScriptEnd::Exit,
+            Err(e) => {
+                eprintln!("source: {}: {}", path, e);
+                self.set_exit_code(1);
+                false
+            }
+        }
+    }
+
+    async fn read_command(&mut self) -> Result<String> {
+        jobs::reap();
+        self.dispatch_events().await;
+        let mut command = String::new();
+        let mut continuation = false;
+        // Lines exactly as typed, so history recalls an editable multi-line entry
+        let mut history_lines: Vec<String> = Vec::new();
+        
+        // Surface the background API key check once it has finished
+        if self.key_check.as_ref().is_some_and(|handle| handle.is_finished()) {
+            if let Some(handle) = self.key_check.take() {
+                if let Ok(Some(warning)) = handle.await {
+                    eprintln!("Warning: {}", warning);
+                }
+            }
+        }
+        
+        // Try to get custom prompt from TypeScript function first
+        let prompt = match self.ts_config_loader.call_prompt_function("customPrompt").await {
+            Ok(Some(custom_prompt)) => custom_prompt,
+            result => {
+                if let Err(e) = result {
+                    tracing::warn!(error = %e, "customPrompt failed, using shell.prompt");
+                }
+                self.config.get_prompt(&state::current_dir(), &self.mode)
+            }
+        };
+        
+        let continuation_prompt = self.config.get_continuation_prompt(&state::current_dir(), &self.mode);
+        let (prompt, continuation_prompt) = (StyledPrompt::themed(&prompt), StyledPrompt::themed(&continuation_prompt));
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.set_prompts(vec![prompt.clone(), continuation_prompt.clone()]);
+        }
+
+        loop {
+            let current_prompt = if continuation { &continuation_prompt.visible } else { &prompt.visible };
+            
+            // Check if mode toggle was triggered by ESC-x
+            if self.mode_toggle_handler.check_toggle() {
+                self.toggle_mode();
+                if continuation {
+                    command.clear();
+                    history_lines.clear();
+                    continuation = false;
+                }
+                continue; // Re-prompt with new mode
+            }
+            
+            let readline = match self.prefill.take() {
+                Some(initial) if !continuation => self.editor.readline_with_initial(current_prompt, (&initial, "")),
+                _ => self.editor.readline(current_prompt),
+            };
+            match readline {
+                Ok(line) => {
+                    let trimmed = line.trim();
+                    
+                    if trimmed.is_empty() && !continuation {
+                        return Ok(String::new());
+                    }
+                    
+
+                    history_lines.push(line.trim_end().to_string());
+                    
+                    // A recalled multi-line entry arrives as one buffer; fold its
+                    // backslash-newlines the same way typed continuations are joined
+                    let folded = trimmed.replace("\\\n", " ");
+                    let trimmed = folded.as_str();
+                    
+                    if trimmed.ends_with('\\') && !trimmed.ends_with("\\\\") {
+                        let line_without_backslash = &trimmed[..trimmed.len() - 1];
+                        if !command.is_empty() {
+                            command.push(' ');
+                        }
+                        command.push_str(line_without_backslash);
+                        continuation = true;
+                    } else {
+                        if !command.is_empty() {
+                            command.push(' ');
+                        }
+                        command.push_str(trimmed);
+                        
+                        if self.config.history_expansion() {
+                            // `$ !!` in Agent mode refers to commands, not prompts
+                            let (recall_mode, _) = history::recall_target(&command, &self.mode);
+                            let history: Vec<String> = if recall_mode != self.mode {
+                                history::recall_entries(&recall_mode, self.config.history_size())
+                            } else {
+                                self.editor.history().iter().cloned().collect()
+                            };
+                            let prose = recall_mode != ShellMode::Command;
+                            match history_expansion::expand(&command, &history, prose) {
+                                Ok(Some(expanded)) => {
+                                    // Shown like bash does, and recorded expanded
+                                    println!("{}", expanded);
+                                    history_lines = vec![expanded.clone()];
+                                    command = expanded;
+                                }
+                                Ok(None) => {}
+                                Err(e) => {
+                                    eprintln!("aish: {}", e);
+                                    return Ok(String::new());
+                                }
+                            }
+                        }
+                        
+                        if !command.trim().is_empty() {
+                            let (recall_mode, line) = history::recall_target(history_lines.join("\n").trim(), &self.mode);
+                            if recall_mode == self.mode {
+                                self.editor.add_history_entry(line.as_str())?;
+                                if let Some(path) = history::recall_path(&self.mode) {
+                                    let _ = self.editor.append_history(&path);
+                                }
+                            } else {
+                                history::append_recall(&recall_mode, &line, self.config.history_size());
+                            }
+                        }
+                        break;
+                    }
+                }
+                Err(ReadlineError::Interrupted) => {
+                    // Check if this was a mode toggle
+                    if self.mode_toggle_handler.check_toggle() {
+                        self.toggle_mode();
+                        if continuation {
+                            command.clear();
+                            history_lines.clear();
+                            continuation = false;
+                        }
+                        continue; // Re-prompt with new mode
+                    }
+                    if self.evidence_key_handler.take_pressed() {
+                        evidence_builtin("");
+                        continue;
+                    }
+                    
+                    // Regular Ctrl+C handling
+                    self.run_trap("INT").await;
+                    if continuation {
+                        println!("^C");
+                        command.clear();
+                        history_lines.clear();
+                        continuation = false;
+                        continue;
+                    } else {
+                        println!("^C");
+                        return Ok(String::new());
+                    }
+                }
+                Err(ReadlineError::Eof) => {
+                    if continuation {
+                        println!("^D");
+                        return Ok(command);
+                    } else {
+                        println!("^D");
+                        self.run_exit_handlers().await;
+                        std::process::exit(0);
+                    }
+                }
+                Err(err) => {
+                    return Err(anyhow::anyhow!("Readline error: {:?}", err));
+                }
+            }
+        }
+
+        Ok(command)
+    }
+
+    pub async fn handle_input(&mut self, input: &str) -> Option<bool> {
+        let trimmed = input.trim();
+        self.set_exit_code(0);
+        transcript::record(transcript::Entry::Input {
+            text: trimmed.to_string(),
+            mode: self.mode.as_str().to_string(),
+        });
+        
+        match trimmed {
+            "exit" | "quit" => {
+                self.run_exit_handlers().await;
+                println!("Goodbye!");
+                return Some(true);
+            }
+            "help" => {
+                self.show_help();
+                return Some(false);
+            }
+            _ => {}
+        }
+        
+        let builtin_line = builtin_line(trimmed, &self.mode).unwrap_or_default();
+        let (builtin, builtin_args) = builtin_line.split_once(char::is_whitespace).unwrap_or((builtin_line, ""));
+        let builtin = if builtin_syntax(builtin, builtin_args) { builtin } else { "" };
+        match builtin {
+            "setvar" => {
+                self.handle_setvar(builtin_args.trim());
+                return Some(false);
+            }
+            "provider" => {
+                match builtin_args.trim() {
+                    "" | "status" => provider_health::print_status(),
+                    other => eprintln!("provider: unknown subcommand: {}", other),
+                }
+                return Some(false);
+            }
+            "explain" => {
+                let command_line = builtin_args.trim();
+                let command_line = command_line.strip_prefix('$').map(str::trim).unwrap_or(command_line);
+                if command_line.is_empty() {
+                    eprintln!("explain: usage: explain <command line>");
+                    self.set_exit_code(2);
+                } else if let Err(e) = explain::explain(&self.ai_agent, command_line).await {
+                    eprintln!("{} {}", ui::paint(Role::Error, "AI Error:"), e);
+                    self.set_exit_code(1);
+                }
+                return Some(false);
+            }
+            "usage" => {
+                usage::print_usage();
+                return Some(false);
+            }
+            "profile" => {
+                match self.ai_agent.active_profile() {
+                    Some((name, profile)) => {
+                        println!("Active profile: {}", name);
+                        if let Some(model) = &profile.model {
+                            println!("  model: {}", model);
+                        }
+                        if let Some(tools) = &profile.tools {
+                            println!("  tools: {}", tools.join(", "));
+                        }
+                    }
+                    None => println!("No profile matches {}", state::current_dir().display()),
+                }
+                return Some(false);
+            }
+            "model" => {
+                match builtin_args.trim() {
+                    "" | "list" => self.config.print_models(),
+                    name => {
+                        let message = self.config.switch_model(name);
+                        self.ai_agent = AiAgent::new(self.config.clone());
+                        println!("{}", message);
+                    }
+                }
+                return Some(false);
+            }
+            "record" => {
+                self.set_exit_code(record_builtin(builtin_args.trim()));
+                return Some(false);
+            }
+            "evidence" => {
+                self.set_exit_code(evidence_builtin(builtin_args.trim()));
+                return Some(false);
+            }
+            "copy" => {
+                self.set_exit_code(copy_builtin(builtin_args.trim()));
+                return Some(false);
+            }
+            "paste" if builtin_args.trim().is_empty() => {
+                self.set_exit_code(paste_builtin());
+                return Some(false);
+            }
+            "share" => {
+                self.set_exit_code(share_builtin(builtin_args.trim()));
+                return Some(false);
+            }
+            "restore" => {
+                self.set_exit_code(restore_trashed(builtin_args.trim()));
+                return Some(false);
+            }
+            "template" => {
+                if let Err(e) = self.run_template(builtin_args.trim()).await {
+                    eprintln!("template: {}", e);
+                    self.set_exit_code(1);
+                }
+                return Some(false);
+            }
+            "fix" => {
+                if let Err(e) = self.fix_last_command().await {
+                    eprintln!("{} {}", ui::paint(Role::Error, "AI Error:"), e);
+                    self.set_exit_code(1);
+                }
+                return Some(false);
+            }
+            "config" => {
+                match builtin_args.trim() {
+                    "edit" => {
+                        if let Err(e) = self.edit_config().await {
+                            eprintln!("config edit: {}", e);
+                            self.set_exit_code(1);
+                        }
+                    }
+                    "show" => match serde_json::to_string_pretty(&self.config.redacted()) {
+                        Ok(json) => println!("{}", json),
+                        Err(e) => {
+                            eprintln!("config show: {}", e);
+                            self.set_exit_code(1);
+                        }
+                    },
+                    "path" => println!("{}", self.ts_config_loader.script_path().display()),
+                    _ => {
+                        eprintln!("config: usage: config show|edit|path");
+                        self.set_exit_code(2);
+                    }
+                }
+                return Some(false);
+            }
+            "unsetvar" => {
+                for name in builtin_args.split_whitespace() {
+                    ts_runtime::ops::remove_session_var(name);
+                }
+                return Some(false);
+            }
+            "jobs" => {
+                for job in jobs::list() {
+                    let state = match job.state {
+                        jobs::JobState::Running => "Running",
+                        jobs::JobState::Stopped => "Stopped",
+                    };
+                    println!("[{}]  {:<24}{}", job.number, state, job.command);
+                }
+                return Some(false);
+            }
+            "fg" => {
+                match jobs::foreground(builtin_args) {
+                    Ok(status) => self.set_exit_code(exit_code(status)),
+                    Err(e) => {
+                        eprintln!("fg: {}", e);
+                        self.set_exit_code(1);
+                    }
+                }
+                return Some(false);
+            }
+            "bg" => {
+                if let Err(e) = jobs::background(builtin_args) {
+                    eprintln!("bg: {}", e);
+                    self.set_exit_code(1);
+                }
+                return Some(false);
+            }
+            "index" => {
+                let cwd = state::current_dir();
+                let result = match builtin_args.trim() {
+                    "build" => project_index::build(&self.config, &cwd).await,
+                    "status" | "" => project_index::print_status(&cwd),
+                    _ => Err(anyhow::anyhow!("usage: index build|status")),
+                };
+                match result {
+                    Ok(()) => self.set_exit_code(0),
+                    Err(e) => {
+                        eprintln!("index: {}", e);
+                        self.set_exit_code(1);
+                    }
+                }
+                return Some(false);
+            }
+            "sessions" => {
+                let status = self.sessions_builtin(builtin_args.trim());
+                self.set_exit_code(status);
+                return Some(false);
+            }
+            "update" => {
+                let check = match builtin_args.trim() {
+                    "" => false,
+                    "--check" => true,
+                    other => {
+                        eprintln!("update: unknown argument '{}' (usage: update [--check])", other);
+                        self.set_exit_code(2);
+                        return Some(false);
+                    }
+                };
+                match self_update::run(check).await {
+                    Ok(()) => self.set_exit_code(0),
+                    Err(e) => {
+                        eprintln!("update: {}", e);
+                        self.set_exit_code(1);
+                    }
+                }
+                return Some(false);
+            }
+            "history" => {
+                let status = self.history_builtin(builtin_args.trim()).await;
+                self.set_exit_code(status);
+                return Some(false);
+            }
+            "source" | "." if !builtin_args.is_empty() => {
+                return Some(self.source_script(builtin_args).await);
+            }
+            "ask" | "?" if editor::ask_prompt(builtin_line).is_some() => {
+                let prompt = editor::ask_prompt(builtin_line).unwrap_or_default();
+                self.remember(EntryKind::Prompt, prompt);
+                if let Err(e) = self.handle_ai_prompt(prompt).await {
+                    eprintln!("{} {}", ui::paint(Role::Error, "AI Error:"), e);
+                    self.set_exit_code(1);
+                }
+                return Some(false);
+            }
+            _ => {}
+        }
+        
+        let shell_line = match self.mode {
+            ShellMode::Command => Some(trimmed),
+            ShellMode::Agent | ShellMode::Translate => trimmed.strip_prefix('$').map(str::trim),
+        };
+        if let Some((command, question)) = shell_line.and_then(prompt_pipe::split) {
+            let mode = self.config.prompt_pipe();
+            if mode != PipeMode::Off {
+                self.remember(EntryKind::Command, shell_line.unwrap_or_default());
+                self.pipe_to_prompt(command, question, mode).await;
+                return Some(false);
+            }
+        }
+        
+        match self.mode {
+            ShellMode::Agent => {
+                // Agent mode: $ prefix for Unix commands, everything else for AI
+                if trimmed.starts_with('$') {
+                    let command = trimmed[1..].trim();
+                    if !command.is_empty() {
+                        self.remember(EntryKind::Command, command);
+                        self.run_unix_command(command).await;
+                    }
+                } else {
+                    self.remember(EntryKind::Prompt, trimmed);
+                    if let Err(e) = self.handle_ai_prompt(trimmed).await {
+                        eprintln!("{} {}", ui::paint(Role::Error, "Error:"), e);
+                    }
+                }
+            }
+            ShellMode::Command => {
+                // Command mode: everything is a Unix command
+                self.remember(EntryKind::Command, trimmed);
+                self.run_unix_command(trimmed).await;
+            }
+            ShellMode::Translate => {
+                // Translate mode: $ runs a command (the reviewed translation),
+                // anything else is translated and pre-filled, never run directly
+                if let Some(command) = trimmed.strip_prefix('$') {
+                    let command = command.trim();
+                    if !command.is_empty() {
+                        self.remember(EntryKind::Command, command);
+                        self.run_unix_command(command).await;
+                    }
+                } else {
+                    match translate::translate(&self.ai_agent, trimmed, &state::current_dir()).await {
+                        Ok(Some(command)) => self.prefill = Some(format!("$ {}", command)),
+                        Ok(None) => println!("No shell command for that request"),
+                        Err(e) => {
+                            eprintln!("{} {}", ui::paint(Role::Error, "AI Error:"), e);
+                            self.set_exit_code(1);
+                        }
+                    }
+                }
+            }
+        }
+        
+        Some(false)
+    }
+
+    /// The `config edit` builtin: open the configuration in $VISUAL/$EDITOR,
+    /// validate it after each save and reload it once it loads cleanly
+    async fn edit_config(&mut self) -> Result<()> {
+        let path = self.ts_config_loader.script_path().to_path_buf();
+        let editor = env::var("VISUAL")
+            .or_else(|_| env::var("EDITOR"))
+            .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+        
+        loop {
+            // Through the shell so editors configured with arguments (e.g. "code -w") work
+            #[cfg(unix)]
+            let mut command = {
+                let mut command = platform::command("sh");
+                command.arg("-c").arg(format!("{} \"$1\"", editor)).arg("sh").arg(&path);
+                command
+            };
+            #[cfg(not(unix))]
+            let mut command = platform::shell_command(&format!("{} \"{}\"", editor, path.display()));
+            let status = command.current_dir(state::current_dir()).envs(state::child_env()).status()?;
+            if !status.success() {
+                return Err(anyhow::anyhow!("{} exited with {}", editor, status));
+            }
+            
+            match self.ts_config_loader.load_config().await {
+                Ok(config) => {
+                    self.apply_config(config).await;
+                    println!("Configuration reloaded from {}", path.display());
+                    return Ok(());
+                }
+                Err(e) => {
+                    eprintln!("{} {}:\n{}", ui::paint(Role::Error, "Error loading"), path.display(), e);
+                    let again = approval::ask(&approval::ApprovalRequest {
+                        question: "Edit again?",
+                        subject: &path.display().to_string(),
+                        details: None,
+                        editable: false,
+                        always_key: None,
+                        default_approve: true,
+                        risky: false,
+                    });
+                    if !again.approved() {
+                        println!("Keeping the configuration that was already loaded");
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+    
+    /// Swap in a freshly loaded configuration and rediscover what it defines
+    async fn apply_config(&mut self, config: Config) {
+        self.ts_config_loader.reset();
+        ts_runtime::guard::install(config.safety.as_ref());
+        ts_runtime::permissions::install(config.permissions.as_ref(), self.ts_config_loader.config_dir());
+        approval::set_external_approver(config.approval_command());
+        ui::install(config.theme.as_ref());
+        notify::configure_agent(config.agent_notifications());
+        session_store::configure(config.session_store(), config.session_retention_days());
+        self.ai_agent = AiAgent::new(config.clone());
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.configure_ghost(&config);
+            let accept_hint = helper.accept_hint_handler();
+            self.editor.bind_sequence(
+                KeyEvent(KeyCode::Right, Modifiers::NONE),
+                EventHandler::Conditional(Box::new(accept_hint)),
+            );
+        }
+        self.config = config;
+        self.hooks = self.ts_config_loader
+            .defined_functions(&ts_runtime::LIFECYCLE_HOOKS)
+            .await
+            .unwrap_or_default();
+        self.custom_commands = self.ts_config_loader
+            .load_custom_commands()
+            .await
+            .unwrap_or_default();
+        self.postprocessors = self.ts_config_loader
+            .load_postprocessors()
+            .await
+            .unwrap_or_default();
+        self.listened_events = self.ts_config_loader
+            .load_listened_events()
+            .await
+            .unwrap_or_default();
+    }
+
+    fn handle_setvar(&self, args: &str) {
+        if args.is_empty() {
+            for (name, value) in ts_runtime::ops::session_vars() {
+                println!("{}={}", name, value);
+            }
+            return;
+        }
+
+        let (name, value) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        if !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            eprintln!("setvar: invalid variable name: {}", name);
+            return;
+        }
+        ts_runtime::ops::set_session_var(name, value.trim());
+    }
+
+    async fn handle_ai_prompt(&mut self, prompt: &str) -> Result<()> {
+        let sampling = self.sampling.clone();
+        self.prompt_agent(prompt, &sampling).await
+    }
+
+    async fn prompt_agent(&mut self, prompt: &str, sampling: &ts_runtime::SamplingPreset) -> Result<()> {
+        if prompt.is_empty() {
+            return Ok(());
+        }
+        
+        summary::record_prompt();
+        let renderer = transcript::Recording(self.renderer.as_ref());
+        notify::agent_turn_started();
+        let result = self.ai_agent.process_prompt(prompt, &self.ts_config_loader, &renderer, sampling).await;
+        notify::agent_turn_finished(prompt, result.is_ok());
+        if agent::take_config_change() {
+            match self.ts_config_loader.load_config().await {
+                Ok(config) => {
+                    self.apply_config(config).await;
+                    println!("Configuration reloaded from {}", self.ts_config_loader.script_path().display());
+                }
+                Err(e) => tracing::error!(error = %e, "could not reload the configuration the agent changed"),
+            }
+        }
+        match result {
+            Ok(true) => Ok(()),
+            // Stopped by the budget, or no answer passed validation
+            Ok(false) => {
+                self.set_exit_code(1);
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!("{} {}", ui::paint(Role::Error, "AI Error:"), e);
+                self.set_exit_code(1);
+                Ok(())
+            }
+        }
+    }
+
+    /// The `template` builtin: list `promptTemplates`, or run one with the
+    /// rest of the line appended, using the sampling preset it pins
+    async fn run_template(&mut self, args: &str) -> Result<()> {
+        let templates = self.ts_config_loader.load_prompt_templates().await?;
+        let (name, text) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        if name.is_empty() {
+            let mut names: Vec<&String> = templates.keys().collect();
+            names.sort();
+            if names.is_empty() {
+                println!("No templates; define them in the promptTemplates global");
+            }
+            for name in names {
+                let preset = match &templates[name] {
+                    ts_runtime::PromptTemplate::Full { preset: Some(preset), .. } => format!(" (preset {})", preset),
+                    _ => String::new(),
+                };
+                println!("{}{}", name, preset);
+            }
+            return Ok(());
+        }
+
+        let template = templates.get(name)
+            .ok_or_else(|| anyhow::anyhow!("no template named '{}' (see `template`)", name))?;
+        let sampling = self.config.template_sampling(template)?.overlay(&self.sampling);
+        let prompt = match text.trim() {
+            "" => template.prompt().to_string(),
+            text => format!("{}\n\n{}", template.prompt(), text),
+        };
+        self.prompt_agent(&prompt, &sampling).await
+    }
+
+    fn show_help(&self) {
+        println!("aish (AI Shell) - A shell that handles both natural language and Unix commands");
+        println!();
+        println!("Current mode: {}", self.mode.as_str().to_uppercase());
+        println!();
+        println!("Built-in commands (in AGENT and TRANSLATE mode, type them with a colon: ':usage', ':model list'):");
+        println!("  help     - Show this help message");
+        println!("  exit     - Exit the shell");
+        println!("  quit     - Exit the shell");
+        println!("  setvar [name value] - Set a session variable (lists all without arguments)");
+        println!("  unsetvar <name>     - Remove a session variable");
+        println!("  provider status     - Show AI provider latency and error stats");
+        println!("  usage               - Show AI token usage and estimated cost this session");
+        println!("  config show|path    - Print the loaded configuration (secrets masked) or the file it came from");
+        println!("  config edit         - Edit .aish.ts in $EDITOR and reload it");
+        println!("  fix                 - Ask the AI why the last command failed and suggest a fix");
+        println!("  model [list|<name>] - Show or switch the AI model/provider for this session");
+        println!("  profile             - Show the agent profile selected for this directory");
+        println!("  explain <command>   - Explain a command line part by part without running it");
+        println!("  record [file|stop]  - Record inputs, command output and agent activity to a transcript");
+        println!("  restore [n|path...] - List files trashed by rm (shell.safe_rm) or put them back");
+        println!("  template [name [text]] - List prompt templates, or run one with its sampling preset");
+        println!("  evidence [N]        - Show the tool results the last answer cited as [N] (Alt+e)");
+        println!("  copy [answer|output] - Copy the agent's last answer or the last command's output to the clipboard");
+        println!("  paste               - Print the clipboard");
+        println!("  share [--approve|stop] - Let teammates watch this session (`aish watch`), optionally co-approving risky steps");
+        println!("  pushd [dir] / popd / dirs - Directory stack");
+        println!("  set -e / set +e     - Stop lists and scripts at the first failing command");
+        println!("  @timeout 30s COMMAND - Stop COMMAND (and everything it started) after 30s, with status 124");
+        println!("  ask <prompt> / ? <prompt> - Send a prompt to the agent from any mode");
+        println!("  jobs / fg [%N] / bg [%N] - List jobs stopped with Ctrl-Z, continue one in the foreground or background");
+        println!("  history [N] - List commands and prompts (tagged cmd/ai), or the last N");
+        println!("  history search TERM / delete N / run N - Find, remove or re-run history entries");
+        println!("  index build|status  - Index this project for the agent's search_project tool, or show the index");
+        println!("  sessions [list|resume ID|new] - List stored sessions, continue one's agent conversation, or start afresh");
+        println!("  update [--check]    - Install the latest aish release over this binary (--check only reports it)");
+        println!("  COMMAND |? QUESTION - Run a command and ask the agent about its output");
+        println!("  source FILE / . FILE - Run an aish script in this session, keeping its cd, setvar and traps");
+        println!("  trap 'cmd' EXIT|INT|ERR - Run cmd when the shell exits, on Ctrl-C, or when a command fails");
+        println!("  (a && b) / {{ a; b; }} - Group commands in a subshell / in the current shell");
+        if !self.custom_commands.is_empty() {
+            let mut names: Vec<&String> = self.custom_commands.iter().collect();
+            names.sort();
+            println!();
+            println!("Custom commands (from .aish.ts):");
+            for name in names {
+                println!("  {}", name);
+            }
+        }
+        let plugin_commands = wasm_plugins::commands();
+        if !plugin_commands.is_empty() {
+            println!();
+            println!("Plugin commands (WASM):");
+            for (name, description) in plugin_commands {
+                println!("  {:<19} - {}", name, description);
+            }
+        }
+        println!("  ESC then x - Cycle between AGENT, COMMAND and TRANSLATE modes (Alt+x)");
+        println!();
+        
+        match self.mode {
+            ShellMode::Agent => {
+                println!("AGENT MODE - Command routing:");
+                println!("  $ <command>  - Execute Unix shell command (e.g., '$ ls -la')");
+                println!("  <text>       - AI prompt for natural language processing");
+                println!();
+                println!("Examples:");
+                println!("  $ echo 'Hello World'     - Execute echo command");
+                println!("  list all files           - AI prompt to list files");
+                println!("  what is the weather?      - AI prompt for weather");
+            }
+            ShellMode::Command => {
+                println!("COMMAND MODE - All input is executed as Unix commands:");
+                println!("  <command>    - Execute Unix shell command directly");
+                println!();
+                println!("Examples:");
+                println!("  ls -la                    - Execute ls command");
+                println!("  echo 'Hello World'        - Execute echo command");
+                println!("  cd /tmp                   - Change directory");
+            }
+            ShellMode::Translate => {
+                println!("TRANSLATE MODE - Describe what you want in words:");
+                println!("  <text>       - AI writes a shell command and fills it in for review");
+                println!("  $ <command>  - Execute it (press Enter on the filled-in command)");
+                println!();
+                println!("Examples:");
+                println!("  find files over 100MB     - Fills in '$ find . -size +100M'");
+            }
+        }
+    }
+
+    /// Call a lifecycle hook if the configuration defines it. Hook errors are
+    /// reported but never abort the shell.
+    async fn run_hook(&self, hook_name: &str, args: &[Value]) -> Option<Value> {
+        if !self.hooks.contains(hook_name) {
+            return None;
+        }
+        
+        match self.ts_config_loader.call_hook(hook_name, args).await {
+            Ok(value) => Some(value),
+            Err(e) => {
+                tracing::error!(hook = hook_name, error = %e, "TypeScript hook failed");
+                None
+            }
+        }
+    }
+
+    /// Deliver queued events to the configuration's `aish.on` handlers.
+    /// Handler errors are logged but never abort the shell.
+    async fn dispatch_events(&self) {
+        if self.listened_events.is_empty() {
+            events::clear();
+            return;
+        }
+        for _ in 0..events::MAX_EVENTS_PER_DRAIN {
+            let Some((event, payload)) = events::next() else {
+                return;
+            };
+            if !self.listened_events.contains(&event) && !self.listened_events.contains(events::ANY) {
+                continue;
+            }
+            if let Err(e) = self.ts_config_loader.dispatch_event(&event, &payload).await {
+                tracing::error!(%event, error = %e, "event handler failed");
+            }
+        }
+        tracing::warn!(limit = events::MAX_EVENTS_PER_DRAIN, "too many events at once; dropping the rest");
+        events::clear();
+    }
+
+    /// Execute a Unix command wrapped in the preExec/postExec hooks.
+    /// preExec may return a string to rewrite the command or `false` to veto it.
+    async fn run_unix_command(&mut self, input: &str) {
+        let command = match self.run_hook("preExec", &[json!(input)]).await {
+            Some(Value::Bool(false)) => {
+                eprintln!("Command blocked by preExec hook: {}", input);
+                self.set_exit_code(1);
+                return;
+            }
+            Some(Value::String(rewritten)) => rewritten,
+            _ => input.to_string(),
+        };
+        
+        if let Err(e) = self.config.check_nesting(&command) {
+            eprintln!("{} {}", ui::paint(Role::Error, "Error:"), e);
+            self.set_exit_code(1);
+            return;
+        }
+        
+        let started = Instant::now();
+        self.last_output.clear();
+        let result = self.execute_list(&command).await;
+        let exit_code = match result {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("{} {}", ui::paint(Role::Error, "Error:"), e);
+                1
+            }
+        };
+        self.set_exit_code(exit_code);
+        let output = self.last_output.clone();
+        self.finish_command(&command, exit_code, &output, started.elapsed()).await;
+        
+        if exit_code != 0 && exit_code != STOPPED_STATUS && std::io::stdin().is_terminal() {
+            let fix_on_error = self.config.ai.as_ref().and_then(|ai| ai.fix_on_error.as_deref());
+            match fix_on_error {
+                Some("auto") => {
+                    if let Err(e) = self.fix_last_command().await {
+                        eprintln!("{} {}", ui::paint(Role::Error, "AI Error:"), e);
+                    }
+                }
+                Some("offer") => println!("Run `fix` for an AI diagnosis of this failure"),
+                _ => {}
+            }
+        }
+    }
+
+    /// Everything that follows a command the user ran, however it ran: the
+    /// transcript, the agent's context, traps, the postExec hook and the
+    /// `command:finished` event
+    async fn finish_command(&mut self, command: &str, exit_code: i32, output: &str, elapsed: Duration) {
+        tracing::info!(%command, exit_code, ?elapsed, "command");
+        transcript::record(transcript::Entry::Command {
+            command: command.to_string(),
+            exit_code,
+            output: output.to_string(),
+            duration_ms: elapsed.as_millis() as u64,
+        });
+        context::record(command, exit_code, output, elapsed);
+        session_store::record_command(command, exit_code);
+        summary::record_command(command, exit_code, elapsed);
+        match exit_code {
+            0 | STOPPED_STATUS => {}
+            INTERRUPTED_STATUS => self.run_trap("INT").await,
+            _ => self.run_trap("ERR").await,
+        }
+        
+        if exit_code == 0 {
+            if let Ok(mut stats) = self.flag_stats.lock() {
+                stats.record(command);
+            }
+        }
+        
+        self.run_hook("postExec", &[json!({
+            "command": command,
+            "exit_code": exit_code,
+            "duration_ms": elapsed.as_millis() as u64,
+        })]).await;
+        events::emit(events::COMMAND_FINISHED, json!({
+            "command": command,
+            "exit_code": exit_code,
+            "duration_ms": elapsed.as_millis() as u64,
+            "cwd": state::current_dir().display().to_string(),
+        }));
+        
+        if let Some((after, style)) = self.config.long_command_notification() {
+            if elapsed >= after {
+                notify::command_finished(command, exit_code, elapsed, style);
+            }
+        }
+    }
+
+    /// Add a command or prompt the user typed to the tagged history; lines
+    /// from scripts and `source` aren't recorded
+    fn remember(&self, kind: EntryKind, text: &str) {
+        if self.interactive && self.source_depth == 0 {
+            history::record(kind, text);
+        }
+    }
+
+    /// `sessions`: list stored sessions, resume one's agent conversation, or
+    /// go back to a fresh conversation per prompt
+    fn sessions_builtin(&self, args: &str) -> i32 {
+        const USAGE: &str = "sessions: usage: sessions [list] | sessions resume ID | sessions new";
+        let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        match (subcommand, rest.trim()) {
+            ("" | "list", "") => match session_store::list(20) {
+                Ok(sessions) => {
+                    let resumed = session_store::resumed();
+                    for session in sessions {
+                        let marker = if resumed.as_deref() == Some(session.id.as_str()) {
+                            "*"
+                        } else if session.id == state::session_id() {
+                            "+"
+                        } else {
+                            " "
+                        };
+                        println!(
+                            "{}{:<20} {:>8}  {:>3} prompts {:>4} cmds  {}  {}",
+                            marker,
+                            session.id,
+                            humanize::relative_time(session.started),
+                            session.prompts,
+                            session.commands,
+                            session.cwd,
+                            session.title.unwrap_or_default()
+                        );
+                    }
+                    0
+                }
+                Err(e) => {
+                    eprintln!("sessions: {}", e);
+                    1
+                }
+            },
+            ("resume", id) if !id.is_empty() => match session_store::resume(id) {
+                Ok(session) => {
+                    println!("Resumed session {} ({} prompts); agent prompts now continue its conversation", session.id, session.prompts);
+                    0
+                }
+                Err(e) => {
+                    eprintln!("sessions: {}", e);
+                    1
+                }
+            },
+            ("new", "") => {
+                session_store::forget_resumed();
+                println!("Agent prompts start a fresh conversation again");
+                0
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                2
+            }
+        }
+    }
+
+    /// The `history` builtin: list entries (all, or the last N), `search`
+    /// them, `delete` one from both histories or `run` one again the way it
+    /// first ran
+    async fn history_builtin(&mut self, args: &str) -> i32 {
+        const USAGE: &str = "history: usage: history [N] | history search TERM | history delete N | history run N";
+        let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let rest = rest.trim();
+        match subcommand {
+            "" => {
+                for (index, entry) in history::entries().iter().enumerate() {
+                    println!("{}", history::format(index + 1, entry));
+                }
+                0
+            }
+            "search" if !rest.is_empty() => {
+                let matches = history::search(rest);
+                for (number, entry) in &matches {
+                    println!("{}", history::format(*number, entry));
+                }
+                if matches.is_empty() { 1 } else { 0 }
+            }
+            "delete" => {
+                let Ok(number) = rest.parse::<usize>() else {
+                    eprintln!("{}", USAGE);
+                    return 2;
+                };
+                match history::delete(number) {
+                    Ok(entry) => {
+                        self.forget_recall(&entry);
+                        0
+                    }
+                    Err(e) => {
+                        eprintln!("history: {}", e);
+                        1
+                    }
+                }
+            }
+            "run" => {
+                let Ok(number) = rest.parse::<usize>() else {
+                    eprintln!("{}", USAGE);
+                    return 2;
+                };
+                let Some(entry) = history::get(number) else {
+                    eprintln!("history: {}: no such history entry", number);
+                    return 1;
+                };
+                println!("{}", history::format(number, &entry));
+                self.remember(entry.kind, &entry.text);
+                match entry.kind {
+                    EntryKind::Prompt => {
+                        if let Err(e) = self.handle_ai_prompt(&entry.text).await {
+                            eprintln!("{} {}", ui::paint(Role::Error, "AI Error:"), e);
+                            return 1;
+                        }
+                    }
+                    EntryKind::Command => match prompt_pipe::split(&entry.text) {
+                        Some((command, question)) if self.config.prompt_pipe() != PipeMode::Off => {
+                            self.pipe_to_prompt(command, question, self.config.prompt_pipe()).await;
+                        }
+                        _ => self.run_unix_command(&entry.text).await,
+                    },
+                }
+                self.last_exit_code()
+            }
+            count => match count.parse::<usize>() {
+                Ok(count) if rest.is_empty() => {
+                    let entries = history::entries();
+                    let skip = entries.len().saturating_sub(count);
+                    for (index, entry) in entries.iter().enumerate().skip(skip) {
+                        println!("{}", history::format(index + 1, entry));
+                    }
+                    0
+                }
+                _ => {
+                    eprintln!("{}", USAGE);
+                    2
+                }
+            },
+        }
+    }
+
+    /// Remove the most recent recall line that would run `entry` from each
+    /// mode's recall, so a deleted line can't be brought back with Up either
+    fn forget_recall(&mut self, entry: &history::Entry) {
+        let typed = [
+            entry.text.clone(),
+            format!("$ {}", entry.text),
+            format!("${}", entry.text),
+            format!("ask {}", entry.text),
+            format!("? {}", entry.text),
+        ];
+        let max_len = self.config.history_size();
+        for mode in [ShellMode::Agent, ShellMode::Command, ShellMode::Translate] {
+            history::forget_recall(&mode, &typed, max_len);
+        }
+        let _ = self.editor.clear_history();
+        if let Some(path) = history::recall_path(&self.mode) {
+            let _ = self.editor.load_history(&path);
+        }
+    }
+
+    /// `command |? question`: run `command` through sh, capturing its output,
+    /// and send the output with `question` to the agent
+    async fn pipe_to_prompt(&mut self, command: &str, question: &str, mode: PipeMode) {
+        let command = match self.run_hook("preExec", &[json!(command)]).await {
+            Some(Value::Bool(false)) => {
+                eprintln!("Command blocked by preExec hook: {}", command);
+                self.set_exit_code(1);
+                return;
+            }
+            Some(Value::String(rewritten)) => rewritten,
+            _ => command.to_string(),
+        };
+        
+        let started = Instant::now();
+        let mut cmd = platform::shell_command(&command);
+        cmd.current_dir(state::current_dir())
+            .envs(session_var_env())
+            .envs(state::child_env());
+        let result = match mode {
+            PipeMode::Quiet => capture::collect(&mut cmd),
+            _ => capture::run(&mut cmd, true),
+        };
+        let (exit_code, output) = match result {
+            Ok((status, output)) => (exit_code(status), output),
+            Err(e) => {
+                eprintln!("{} {}", ui::paint(Role::Error, "Error:"), e);
+                self.set_exit_code(1);
+                return;
+            }
+        };
+        self.finish_command(&command, exit_code, &output, started.elapsed()).await;
+        
+        let prompt = prompt_pipe::prompt(&command, exit_code, &output, question);
+        if let Err(e) = self.handle_ai_prompt(&prompt).await {
+            eprintln!("{} {}", ui::paint(Role::Error, "AI Error:"), e);
+            self.set_exit_code(1);
+        }
+    }
+
+    /// The `fix` builtin: ask the AI why the last command failed and pre-fill
+    /// the suggested correction into the next prompt, so Enter runs it
+    async fn fix_last_command(&mut self) -> Result<()> {
+        let Some(record) = context::last() else {
+            println!("fix: no command has been run yet");
+            return Ok(());
+        };
+        if record.exit_code == 0 {
+            println!("fix: `{}` succeeded", record.command);
+            return Ok(());
+        }
+        
+        println!("**** Diagnosing `{}`", record.command);
+        let suggestion = fix::suggest_fix(&self.ai_agent, &record, &state::current_dir()).await?;
+        println!("{}", suggestion.diagnosis.trim());
+        if let Some(command) = suggestion.command {
+            println!("Suggested fix (Enter to run, edit or Ctrl-C to discard):");
+            self.prefill = Some(match self.mode {
+                ShellMode::Agent | ShellMode::Translate => format!("$ {}", command),
+                ShellMode::Command => command,
+            });
+        }
+        Ok(())
+    }
+
+    /// Run a command line made of `;`/`&&`/`||` lists, `( )` subshells and
+    /// `{ ; }` groups, returning the status of the last command run. With
+    /// `set -e`, a failing command that isn't tested by `&&`/`||` ends the list.
+    fn execute_list<'a>(&'a mut self, input: &'a str) -> Pin<Box<dyn Future<Output = Result<i32>> + 'a>> {
+        Box::pin(async move {
+            // Multi-line input goes to sh as a whole
+            if input.contains('\n') {
+                return self.execute_simple(input).await;
+            }
+            let steps = command_list::parse(input)
+                .map_err(|e| anyhow::anyhow!("syntax error: {}", e))?;
+            
+            let mut status = 0;
+            for (index, step) in steps.iter().enumerate() {
+                let runs = match step.connector {
+                    Connector::Always => true,
+                    Connector::And => status == 0,
+                    Connector::Or => status != 0,
+                };
+                if !runs {
+                    continue;
+                }
+                
+                status = match &step.node {
+                    Node::Simple(command) => self.execute_simple(command).await?,
+                    Node::Group(list) => self.execute_list(list).await?,
+                    Node::Subshell(list) => self.execute_subshell(list).await?,
+                };
+                
+                let tested = steps.get(index + 1)
+                    .is_some_and(|next| next.connector != Connector::Always);
+                if status != 0 && self.errexit && !tested {
+                    break;
+                }
+            }
+            Ok(status)
+        })
+    }
+    
+    /// Run a list with the directory, directory stack, session variables and
+    /// shell options restored afterwards, as if in a child shell
+    async fn execute_subshell(&mut self, list: &str) -> Result<i32> {
+        let saved_state = state::snapshot();
+        let saved_dir_stack = self.dir_stack.clone();
+        let saved_vars = ts_runtime::ops::session_vars();
+        let saved_errexit = self.errexit;
+        
+        let result = self.execute_list(list).await;
+        
+        state::restore(saved_state);
+        self.dir_stack = saved_dir_stack;
+        self.sync_dir_stack();
+        ts_runtime::ops::replace_session_vars(saved_vars);
+        self.errexit = saved_errexit;
+        result
+    }
+    
+    /// Run one command: a custom command, a post-processed command or a Unix command
+    async fn execute_simple(&mut self, command: &str) -> Result<i32> {
+        match command.split_whitespace().next() {
+            Some(name) if self.custom_commands.contains(name) => self.execute_custom_command(command).await,
+            Some(name) if wasm_plugins::provides_command(name) => Ok(self.execute_plugin_command(command)),
+            Some(name) if self.postprocessors.contains(name) && !command.contains('\n') && !needs_sh(command) => {
+                self.execute_postprocessed_command(command).await
+            }
+            Some("trap") => Ok(self.trap(&command_list::split_words(command)[1..])),
+            _ => self.execute_unix_command(command),
+        }
+    }
+    
+    /// The `trap` builtin: `trap 'cmd' EXIT INT ERR` registers, `trap - SIG`
+    /// resets, and bare `trap` lists what is registered
+    fn trap(&mut self, args: &[String]) -> i32 {
+        let (action, conditions) = match args {
+            [] => {
+                for condition in TRAP_CONDITIONS {
+                    if let Some(command) = self.traps.get(condition) {
+                        println!("trap -- '{}' {}", command.replace('\'', "'\\''"), condition);
+                    }
+                }
+                return 0;
+            }
+            [action, conditions @ ..] if !conditions.is_empty() => (action, conditions),
+            _ => {
+                eprintln!("trap: usage: trap 'command' EXIT|INT|ERR ... or trap - EXIT|INT|ERR ...");
+                return 2;
+            }
+        };
+        
+        let mut status = 0;
+        for name in conditions {
+            let Some(condition) = trap_condition(name) else {
+                eprintln!("trap: {}: unsupported signal (EXIT, INT and ERR are supported)", name);
+                status = 1;
+                continue;
+            };
+            if action == "-" {
+                self.traps.remove(condition);
+            } else {
+                // An empty action is kept so the condition is explicitly ignored
+                self.traps.insert(condition.to_string(), action.clone());
+            }
+        }
+        status
+    }
+    
+    /// Run the command trapped for `condition`, keeping `$?` as it was
+    async fn run_trap(&mut self, condition: &str) {
+        if self.in_trap {
+            return;
+        }
+        let Some(command) = self.traps.get(condition).filter(|c| !c.trim().is_empty()).cloned() else {
+            return;
+        };
+        
+        self.in_trap = true;
+        let saved_exit_code = self.last_exit_code;
+        if let Err(e) = self.execute_list(&command).await {
+            eprintln!("trap {}: {}", condition, e);
+        }
+        self.set_exit_code(saved_exit_code);
+        self.in_trap = false;
+    }
+    
+    /// Run the EXIT trap and the `onExit` hook, once, when the shell or script ends
+    pub async fn run_exit_handlers(&mut self) {
+        if self.exit_handlers_ran {
+            return;
+        }
+        self.exit_handlers_ran = true;
+        self.dispatch_events().await;
+        self.run_trap("EXIT").await;
+        let summary = summary::Summary::collect();
+        self.run_hook("onExit", &[serde_json::to_value(&summary).unwrap_or_default()]).await;
+        if self.interactive {
+            self.write_session_summary(&summary).await;
+        }
+        share::stop();
+        jobs::hangup();
+    }
+
+    /// Print and/or append the exit summary as `shell.session_summary` asks,
+    /// with the model's narrative when enabled
+    async fn write_session_summary(&self, summary: &summary::Summary) {
+        let Some(settings) = self.config.shell.as_ref().and_then(|s| s.session_summary.as_ref()) else {
+            return;
+        };
+        let mut text = summary.render();
+        if settings.narrative == Some(true) {
+            const NARRATIVE_PROMPT: &str = "Write two or three plain sentences summarizing this shell \
+                session for the user's standup notes: what they worked on and what went wrong. \
+                Use only the facts given.";
+            match self.ai_agent.complete(NARRATIVE_PROMPT, &text).await {
+                Ok(narrative) => {
+                    text.push('\n');
+                    text.push_str(narrative.trim());
+                    text.push('\n');
+                }
+                Err(e) => eprintln!("Session summary narrative failed: {}", e),
+            }
+        }
+
+        if settings.print != Some(false) {
+            print!("{}", text);
+        }
+        if let Some(notes_file) = &settings.notes_file {
+            let notes_file = notes_file.replace("{date}", &humanize::local_date());
+            let path = platform::expand_home(&notes_file).unwrap_or_else(|| state::current_dir().join(&notes_file));
+            let entry = format!("\n## aish session, {}\n\n{}", humanize::local_date(), text);
+            let appended = path.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|_| {
+                std::fs::OpenOptions::new().create(true).append(true).open(&path)?.write_all(entry.as_bytes())
+            });
+            if let Err(e) = appended {
+                eprintln!("Could not write the session summary to {}: {}", path.display(), e);
+            }
+        }
+    }
+    
+    /// The `set` builtin; only errexit (`set -e`, `set +e`, `set -o/+o errexit`) is supported
+    fn set_option(&mut self, args: &[&str]) -> i32 {
+        match args {
+            [] => {
+                println!("errexit\t{}", if self.errexit { "on" } else { "off" });
+                0
+            }
+            ["-e"] | ["-o", "errexit"] => {
+                self.errexit = true;
+                0
+            }
+            ["+e"] | ["+o", "errexit"] => {
+                self.errexit = false;
+                0
+            }
+            _ => {
+                eprintln!("set: unsupported option: {}", args.join(" "));
+                2
+            }
+        }
+    }
+
+    /// Run a command defined in `.aish.ts` under `commands`, printing its result
+    async fn execute_custom_command(&self, input: &str) -> Result<i32> {
+        let words = command_list::split_words(input);
+        let Some((name, args)) = words.split_first() else {
+            return Ok(0);
+        };
+        
+        match self.ts_config_loader.call_custom_command(name, args).await? {
+            Value::Null => {}
+            Value::String(output) => println!("{}", output),
+            other => println!("{}", serde_json::to_string_pretty(&other)?),
+        }
+        Ok(0)
+    }
+
+    /// Run a command provided by a WASM plugin, printing its output
+    fn execute_plugin_command(&mut self, input: &str) -> i32 {
+        let words = command_list::split_words(input);
+        let Some((name, args)) = words.split_first() else {
+            return 0;
+        };
+        match wasm_plugins::run_command(name, args) {
+            Ok(output) => {
+                print!("{}", output);
+                if !output.is_empty() && !output.ends_with('\n') {
+                    println!();
+                }
+                self.last_output.push_str(&output);
+                0
+            }
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                1
+            }
+        }
+    }
+
+    /// Run a command with its stdout captured and passed through the matching
+    /// `postprocess` function from `.aish.ts` before display. stdin and stderr
+    /// stay attached to the terminal.
+    async fn execute_postprocessed_command(&mut self, input: &str) -> Result<i32> {
+        let words = command_list::split_words(input);
+        let Some((command, args)) = words.split_first() else {
+            return Ok(0);
+        };
+        
+        let output = match platform::command(command)
+            .args(args)
+            .current_dir(state::current_dir())
+            .envs(session_var_env())
+            .envs(state::child_env())
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .output()
+        {
+            Ok(output) => output,
+            Err(e) => {
+                eprintln!("Failed to execute command '{}': {}", command, e);
+                return Ok(127);
+            }
+        };
+        let exit_code = exit_code(output.status);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        self.last_output.push_str(&stdout);
+        
+        let context = json!({ "command": input, "args": args, "exit_code": exit_code });
+        match self.ts_config_loader.call_postprocessor(command, &stdout, &context).await {
+            Ok(Value::String(text)) => print!("{}", text),
+            Ok(Value::Null) => print!("{}", stdout),
+            Ok(other) => println!("{}", serde_json::to_string_pretty(&other)?),
+            Err(e) => {
+                tracing::error!(%command, error = %e, "postprocessor failed");
+                print!("{}", stdout);
+            }
+        }
+        
+        if exit_code != 0 {
+            eprintln!("Command exited with code: {}", exit_code);
+        }
+        Ok(exit_code)
+    }
+
+    /// The `cd` builtin: supports `cd` (home), `cd -` (OLDPWD), `~` expansion and
+    /// CDPATH lookup for relative names. Returns the exit status.
+    fn change_dir(&mut self, arg: Option<&str>) -> i32 {
+        let home = || dirs::home_dir().unwrap_or_else(|| PathBuf::from("/"));
+        
+        let target_dir = match arg {
+            None | Some("~") => home(),
+            Some("-") => match state::previous_dir() {
+                Some(previous) => previous,
+                None => {
+                    eprintln!("cd: OLDPWD not set");
+                    return 1;
+                }
+            },
+            Some(path) => match platform::expand_home(path) {
+                Some(expanded) => expanded,
+                None if Path::new(path).is_absolute() => PathBuf::from(path),
+                None => self.resolve_cdpath(path).unwrap_or_else(|| PathBuf::from(path)),
+            },
+        };
+        
+        self.change_dir_to(&target_dir)
+    }
+    
+    /// Change to an exact path, such as one saved on the directory stack;
+    /// paths that aren't valid UTF-8 are used as-is
+    fn change_dir_to(&mut self, target_dir: &Path) -> i32 {
+        match state::set_current_dir(target_dir) {
+            Ok(new_dir) => {
+                println!("Changed directory to: {}", new_dir.display());
+                0
+            }
+            Err(e) => {
+                eprintln!("cd: {}: {}", target_dir.display(), e);
+                1
+            }
+        }
+    }
+    
+    /// `pushd dir` saves the current directory and changes to `dir`; bare
+    /// `pushd` swaps the current directory with the top of the stack.
+    fn pushd(&mut self, arg: Option<&str>) -> i32 {
+        let previous = state::current_dir();
+        let status = match arg {
+            Some(dir) => self.change_dir(Some(dir)),
+            None => match self.dir_stack.pop() {
+                Some(top) => {
+                    let status = self.change_dir_to(&top);
+                    if status != 0 {
+                        self.dir_stack.push(top);
+                    }
+                    status
+                }
+                None => {
+                    eprintln!("pushd: no other directory");
+                    return 1;
+                }
+            },
+        };
+        
+        if status == 0 {
+            self.dir_stack.push(previous);
+            self.sync_dir_stack();
+            self.print_dirs();
+        }
+        status
+    }
+    
+    /// `popd` removes the top of the stack and changes to it
+    fn popd(&mut self) -> i32 {
+        let Some(top) = self.dir_stack.pop() else {
+            eprintln!("popd: directory stack empty");
+            return 1;
+        };
+        
+        let status = self.change_dir_to(&top);
+        if status != 0 {
+            self.dir_stack.push(top);
+            return status;
+        }
+        self.sync_dir_stack();
+        self.print_dirs();
+        0
+    }
+    
+    /// The current directory followed by the stack, most recent first, with ~ for home
+    fn dirs_listing(&self) -> Vec<String> {
+        let home = dirs::home_dir();
+        std::iter::once(state::current_dir())
+            .chain(self.dir_stack.iter().rev().cloned())
+            .map(|dir| match home.as_ref().and_then(|h| dir.strip_prefix(h).ok()) {
+                Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+                Some(rest) => format!("~/{}", rest.display()),
+                None => dir.display().to_string(),
+            })
+            .collect()
+    }
+    
+    fn print_dirs(&self) {
+        println!("{}", self.dirs_listing().join(" "));
+    }
+    
+    /// Mirror the stack into the TS runtime for op_get_dir_stack
+    fn sync_dir_stack(&self) {
+        ts_runtime::ops::set_dir_stack(
+            self.dir_stack.iter().rev().map(|dir| dir.display().to_string()).collect()
+        );
+    }
+    
+    /// Look a relative directory name up in CDPATH. Names starting with `.` or
+    /// `..` are always relative to the current directory, as in POSIX shells.
+    fn resolve_cdpath(&self, path: &str) -> Option<PathBuf> {
+        if path.starts_with("./") || path.starts_with("../") || path == "." || path == ".." {
+            return None;
+        }
+        let cdpath = env::var_os("CDPATH")?;
+        env::split_paths(&cdpath)
+            .filter(|entry| !entry.as_os_str().is_empty())
+            .map(|entry| state::current_dir().join(entry).join(path))
+            .find(|candidate| candidate.is_dir())
+    }
+
+    /// Run a command line, under `@timeout` or `shell.defaultCommandTimeout`
+    /// when one applies. The default leaves programs that want the terminal
+    /// (editors, pagers, ssh) alone, so it never kills unsaved work.
+    fn execute_unix_command(&mut self, input: &str) -> Result<i32> {
+        let (limit, input) = match timeout::split_prefix(input) {
+            Ok(Some((limit, command))) => (Some(limit), command),
+            Ok(None) if capture::line_wants_terminal(input) => (None, input),
+            Ok(None) => (self.config.default_command_timeout(), input),
+            Err(e) => {
+                eprintln!("aish: {}", e);
+                return Ok(2);
+            }
+        };
+        let Some(limit) = limit else {
+            return self.spawn_unix_command(input);
+        };
+        let limited = timeout::Limited::start(limit);
+        let code = self.spawn_unix_command(input)?;
+        if limited.expired() {
+            eprintln!("{} {}", ui::paint(Role::Error, &format!("aish: timed out after {}:", humanize::duration(limit))), input);
+            return Ok(timeout::TIMED_OUT_STATUS);
+        }
+        Ok(code)
+    }
+
+    fn spawn_unix_command(&mut self, input: &str) -> Result<i32> {
+        // Viewers of a shared session see what commands print, stdout included
+        let sharing = share::status().is_some();
+        let capture = self.config.context_commands() > 0 || sharing;
+        let capture_stdout = capture && (self.config.capture_stdout() || sharing);
+        
+        // `$(...)` and backticks in an otherwise simple command are run here,
+        // so `cd` and the other builtins see their output
+        let substituted;
+        let input = if !input.contains('\n') && substitution::contains(input) && !needs_sh(&substitution::without(input)) {
+            substituted = match substitution::expand(input) {
+                Ok(expanded) => expanded,
+                Err(e) => {
+                    eprintln!("aish: {}", e);
+                    return Ok(1);
+                }
+            };
+            substituted.as_str()
+        } else {
+            input
+        };
+
+        // Multi-line input (pasted scripts, heredocs) and pipelines or
+        // redirections are handed to sh verbatim
+        if input.contains('\n') || needs_sh(input) {
+            let mut cmd = platform::shell_command(input);
+            cmd.current_dir(state::current_dir())
+                .envs(session_var_env())
+                .envs(state::child_env());
+            let interactive = capture::line_wants_terminal(input);
+            let (status, output) = run_teed(&mut cmd, capture && !interactive, capture_stdout && !interactive)?;
+            self.last_output.push_str(&output);
+            return Ok(exit_code(status));
+        }
+
+        // Quotes and backslashes group and protect words as in sh
+        let words = command_list::split_quoted_words(input);
+        let parts: Vec<&str> = words.iter().map(|(word, _)| word.as_str()).collect();
+        if parts.is_empty() {
+            return Ok(0);
+        }
+
+        let command = parts[0];
+        let args = &parts[1..];
+
+        // Handle cd and the directory stack builtins specially
+        match command {
+            "cd" => return Ok(self.change_dir(args.first().copied())),
+            "pushd" => return Ok(self.pushd(args.first().copied())),
+            "popd" => return Ok(self.popd()),
+            "dirs" => {
+                self.print_dirs();
+                return Ok(0);
+            }
+            "set" => return Ok(self.set_option(args)),
+            _ => {}
+        }
+
+        let cwd = state::current_dir();
+        let args = pathname::expand_unquoted(&words[1..], &cwd);
+        if let Some((min_files, min_bytes)) = self.config.dry_run_thresholds() {
+            if let Some(impact) = dry_run::assess(command, &args, &cwd) {
+                if !dry_run::confirm(input, &impact, min_files, min_bytes) {
+                    println!("Cancelled");
+                    return Ok(1);
+                }
+            }
+        }
+
+        if command == "rm" && self.config.safe_rm() {
+            let (status, messages) = trash::rm(&args, &cwd);
+            eprint!("{}", messages);
+            self.last_output.push_str(&messages);
+            return Ok(status);
+        }
+
+        let mut cmd = platform::command(command);
+        cmd.args(args);
+        cmd.current_dir(&cwd);
+        cmd.envs(session_var_env());
+        cmd.envs(state::child_env());
+        cmd.stdin(Stdio::inherit());
+        cmd.stdout(Stdio::inherit());
+        cmd.stderr(Stdio::inherit());
+
+        let interactive = capture::wants_terminal(command, &parts[1..]);
+        match run_teed(&mut cmd, capture && !interactive, capture_stdout && !interactive) {
+            Ok((status, output)) => {
+                self.last_output.push_str(&output);
+                if !status.success() {
+                    if let Some(code) = status.code() {
+                        eprintln!("Command exited with code: {}", code);
+                    }
+                }
+                Ok(exit_code(status))
+            }
+            Err(e) => {
+                eprintln!("Failed to execute command '{}': {}", command, e);
+                Ok(127)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUILTINS: [&str; 7] = ["fix", "record", "history", "copy", "model", "update", "usage"];
+
+    #[test]
+    fn agent_prompts_starting_with_a_builtin_go_to_the_agent() {
+        for word in BUILTINS {
+            let prompt = format!("{} the thing in src/foo.rs please", word);
+            assert_eq!(builtin_line(&prompt, &ShellMode::Agent), None, "{}", word);
+            assert_eq!(builtin_line(&prompt, &ShellMode::Translate), None, "{}", word);
+            assert_eq!(builtin_line(word, &ShellMode::Agent), None, "{}", word);
+        }
+    }
+
+    #[test]
+    fn a_colon_runs_builtins_in_agent_mode() {
+        assert_eq!(builtin_line(":usage", &ShellMode::Agent), Some("usage"));
+        assert_eq!(builtin_line(":model list", &ShellMode::Translate), Some("model list"));
+        assert_eq!(builtin_line(": > out.log", &ShellMode::Agent), None);
+    }
+
+    #[test]
+    fn command_mode_takes_the_first_word() {
+        assert_eq!(builtin_line("usage", &ShellMode::Command), Some("usage"));
+        assert_eq!(builtin_line(":usage", &ShellMode::Command), Some("usage"));
+    }
+
+    #[test]
+    fn builtins_only_take_their_own_syntax() {
+        assert!(builtin_syntax("usage", ""));
+        assert!(!builtin_syntax("usage", "of the disk"));
+        assert!(builtin_syntax("model", "gpt-4o"));
+        assert!(!builtin_syntax("model", "the data as a graph"));
+        assert!(builtin_syntax("update", "--check"));
+        assert!(!builtin_syntax("update", "the README"));
+    }
+
+    #[test]
+    fn fix_with_arguments_is_not_the_builtin() {
+        assert!(builtin_syntax("fix", ""));
+        assert!(!builtin_syntax("fix", "the failing test in src/foo.rs"));
+        assert!(!builtin_syntax("fix", "--help"));
+    }
+
+    #[test]
+    fn record_takes_one_file_or_stop() {
+        assert!(builtin_syntax("record", ""));
+        assert!(builtin_syntax("record", "stop"));
+        assert!(builtin_syntax("record", "session.jsonl"));
+        assert!(!builtin_syntax("record", "how long the build takes"));
+    }
+
+    #[test]
+    fn history_takes_only_its_subcommands() {
+        for args in ["", "20", "search cargo", "delete 3", "run 12"] {
+            assert!(builtin_syntax("history", args), "{:?}", args);
+        }
+        for args in ["of this file please", "run the tests", "delete everything", "20 lines"] {
+            assert!(!builtin_syntax("history", args), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn copy_takes_answer_or_output() {
+        for args in ["", "answer", "output"] {
+            assert!(builtin_syntax("copy", args), "{:?}", args);
+        }
+        assert!(!builtin_syntax("copy", "the config to /tmp"));
+    }
+}
@@ -0,0 +1,134 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::humanize;
+use crate::ui::{self, Role};
+
+const FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+const FRAME_INTERVAL: Duration = Duration::from_millis(80);
+
+// Pauses in effect; spinners draw nothing while any is
+static PAUSED: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static::lazy_static! {
+    // Whether a frame is on screen and must be erased; held while drawing
+    // or erasing one, so a pause can't race a frame
+    static ref DRAWN: Mutex<bool> = Mutex::new(false);
+}
+
+fn erase(drawn: &mut bool) {
+    if *drawn {
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "\r\x1b[2K");
+        let _ = stderr.flush();
+        *drawn = false;
+    }
+}
+
+/// Keeps every spinner off the screen until dropped, for prompts that wait
+/// on the user
+pub struct Paused;
+
+impl Drop for Paused {
+    fn drop(&mut self) {
+        PAUSED.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Erase any spinner frame and stop drawing until the guard is dropped
+pub fn pause() -> Paused {
+    PAUSED.fetch_add(1, Ordering::SeqCst);
+    if let Ok(mut drawn) = DRAWN.lock() {
+        erase(&mut drawn);
+    }
+    Paused
+}
+
+#[derive(Default)]
+struct SpinnerState {
+    // What is being waited on and since when; None while hidden
+    current: Option<(String, Instant)>,
+}
+
+/// Animated status line on stderr ("⠹ thinking… 2.4s") shown while the agent
+/// waits on the API or a tool. Hide it before printing anything else;
+/// [`pause`] keeps every spinner off the screen while a prompt is up.
+/// Does nothing when stderr is not a terminal.
+pub struct Spinner {
+    state: Arc<Mutex<SpinnerState>>,
+    task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Spinner {
+    pub fn new() -> Self {
+        let state = Arc::new(Mutex::new(SpinnerState::default()));
+        let task = io::stderr().is_terminal().then(|| {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let mut frame = 0;
+                loop {
+                    tokio::time::sleep(FRAME_INTERVAL).await;
+                    let Ok(state) = state.lock() else {
+                        return;
+                    };
+                    let Some((label, started)) = &state.current else {
+                        continue;
+                    };
+                    // Drawing under the locks keeps hide() and pause() from racing a frame
+                    let Ok(mut drawn) = DRAWN.lock() else {
+                        return;
+                    };
+                    if PAUSED.load(Ordering::SeqCst) > 0 {
+                        continue;
+                    }
+                    let mut stderr = io::stderr();
+                    let line = format!("{} {} {}", FRAMES[frame % FRAMES.len()], label, humanize::duration(started.elapsed()));
+                    let _ = write!(stderr, "\r\x1b[2K{}", ui::paint(Role::Muted, &line));
+                    let _ = stderr.flush();
+                    *drawn = true;
+                    frame += 1;
+                }
+            })
+        });
+        Self { state, task }
+    }
+
+    /// A spinner that never draws, for when stderr is in use by something else
+    pub fn disabled() -> Self {
+        Self { state: Arc::new(Mutex::new(SpinnerState::default())), task: None }
+    }
+
+    /// Show the spinner with a new label, restarting the elapsed time
+    pub fn show(&self, label: impl Into<String>) {
+        if let Ok(mut state) = self.state.lock() {
+            state.current = Some((label.into(), Instant::now()));
+        }
+    }
+
+    /// Erase the spinner so output can be printed
+    pub fn hide(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.current = None;
+            if let Ok(mut drawn) = DRAWN.lock() {
+                erase(&mut drawn);
+            }
+        }
+    }
+}
+
+impl Default for Spinner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.hide();
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
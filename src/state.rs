@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::config::nesting_level;
+use crate::ShellMode;
+
+/// The shell's working directory, owned here rather than by the process.
+/// aish never calls `env::set_current_dir`: the shell, the agent and the
+/// TypeScript ops all read the directory from this state and spawn children
+/// with `.current_dir()`, so they cannot drift apart.
+#[derive(Debug, Clone)]
+pub struct ShellState {
+    pub current_dir: PathBuf,
+    pub previous_dir: Option<PathBuf>,
+}
+
+pub type SharedShellState = Arc<Mutex<ShellState>>;
+
+lazy_static::lazy_static! {
+    static ref SHELL_STATE: SharedShellState = Arc::new(Mutex::new(ShellState {
+        current_dir: std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/")),
+        previous_dir: std::env::var_os("OLDPWD").map(PathBuf::from),
+    }));
+}
+
+/// Handle to the directory state, which each TypeScript isolate keeps in
+/// its OpState so ops run in the shell's directory
+pub fn shell_state() -> SharedShellState {
+    SHELL_STATE.clone()
+}
+
+pub fn current_dir() -> PathBuf {
+    SHELL_STATE.lock()
+        .map(|state| state.current_dir.clone())
+        .unwrap_or_else(|_| PathBuf::from("/"))
+}
+
+/// Directory before the last change, for `cd -`
+pub fn previous_dir() -> Option<PathBuf> {
+    SHELL_STATE.lock().ok().and_then(|state| state.previous_dir.clone())
+}
+
+/// Move the shell to `target`, resolved against the current directory and
+/// canonicalized so `..` and symlinks don't leak into the prompt. Children
+/// see it as `PWD` and `OLDPWD` through [`child_env`].
+pub fn set_current_dir(target: &Path) -> io::Result<PathBuf> {
+    let mut state = SHELL_STATE.lock()
+        .map_err(|_| io::Error::other("shell state lock poisoned"))?;
+    let target = state.current_dir.join(target).canonicalize()?;
+    if !target.is_dir() {
+        return Err(io::Error::other("Not a directory"));
+    }
+
+    let previous = std::mem::replace(&mut state.current_dir, target.clone());
+    if previous != target {
+        crate::events::emit(crate::events::DIRECTORY_CHANGED, serde_json::json!({
+            "from": previous.display().to_string(),
+            "to": target.display().to_string(),
+        }));
+    }
+    state.previous_dir = Some(previous);
+    Ok(target)
+}
+
+/// Start the session in `dir` instead of the process's directory (`-C`),
+/// leaving OLDPWD as inherited
+pub fn start_in(dir: &Path) -> io::Result<PathBuf> {
+    let previous = previous_dir();
+    let target = set_current_dir(dir)?;
+    if let Ok(mut state) = SHELL_STATE.lock() {
+        state.previous_dir = previous;
+    }
+    Ok(target)
+}
+
+/// Copy of the state, for subshells to restore when they finish
+pub fn snapshot() -> ShellState {
+    SHELL_STATE.lock()
+        .map(|state| state.clone())
+        .unwrap_or_else(|_| ShellState { current_dir: PathBuf::from("/"), previous_dir: None })
+}
+
+pub fn restore(saved: ShellState) {
+    if let Ok(mut state) = SHELL_STATE.lock() {
+        *state = saved;
+    }
+}
+
+/// What the TypeScript ops and child processes learn about the session.
+/// The shell updates it as the mode and exit status change; the process
+/// environment is never modified, since other threads may be reading it.
+#[derive(Debug, Clone)]
+pub struct SessionState {
+    pub mode: ShellMode,
+    pub last_exit_code: i32,
+    /// Variables set from TypeScript with `op_set_env`, passed to children
+    pub env: HashMap<String, String>,
+}
+
+pub type SharedSession = Arc<RwLock<SessionState>>;
+
+lazy_static::lazy_static! {
+    static ref SESSION: SharedSession = Arc::new(RwLock::new(SessionState {
+        // A nested aish starts in the mode of the one that launched it
+        mode: std::env::var("AISH_MODE").map(|mode| ShellMode::from_str(&mode)).unwrap_or(ShellMode::Agent),
+        last_exit_code: 0,
+        env: HashMap::new(),
+    }));
+}
+
+lazy_static::lazy_static! {
+    // Unique enough to tell sessions apart in logs and temp file names
+    static ref SESSION_ID: String = {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos());
+        format!("{:x}-{:x}", std::process::id(), nanos)
+    };
+}
+
+/// This shell's session id, which children see as `AISH_SESSION_ID`; each
+/// aish process, nested ones included, has its own
+pub fn session_id() -> &'static str {
+    &SESSION_ID
+}
+
+/// Handle to the session state, which the shell updates and each TypeScript
+/// isolate keeps in its OpState
+pub fn session() -> SharedSession {
+    SESSION.clone()
+}
+
+/// Copy of the session state
+pub fn session_snapshot() -> SessionState {
+    SESSION.read().map(|session| session.clone()).unwrap_or_else(|poisoned| poisoned.into_inner().clone())
+}
+
+pub fn set_mode(mode: ShellMode) {
+    if let Ok(mut session) = SESSION.write() {
+        session.mode = mode;
+    }
+}
+
+pub fn set_last_exit_code(code: i32) {
+    if let Ok(mut session) = SESSION.write() {
+        session.last_exit_code = code;
+    }
+}
+
+/// Set a variable for child processes (and `env_var`) without touching the
+/// process environment
+pub fn set_env(name: &str, value: &str) {
+    if let Ok(mut session) = SESSION.write() {
+        session.env.insert(name.to_string(), value.to_string());
+    }
+}
+
+/// Variables every child process gets from the shell's state: PWD, OLDPWD,
+/// those set with `set_env`, and the AISH_* contract scripts and tools use
+/// to tell they run under aish: AISH_MODE, AISH_LEVEL (its nesting depth),
+/// AISH_VERSION, AISH_SESSION_ID and AISH_LAST_EXIT
+pub fn child_env() -> Vec<(String, OsString)> {
+    let state = snapshot();
+    let session = session_snapshot();
+    let mut env: Vec<(String, OsString)> = session.env.into_iter()
+        .map(|(name, value)| (name, OsString::from(value)))
+        .collect();
+    // The directories as they are on disk, even when they aren't UTF-8
+    env.push(("PWD".to_string(), state.current_dir.into_os_string()));
+    if let Some(previous) = state.previous_dir {
+        env.push(("OLDPWD".to_string(), previous.into_os_string()));
+    }
+    env.push(("AISH_MODE".to_string(), session.mode.as_str().into()));
+    env.push(("AISH_LEVEL".to_string(), nesting_level().to_string().into()));
+    env.push(("AISH_VERSION".to_string(), env!("CARGO_PKG_VERSION").into()));
+    env.push(("AISH_SESSION_ID".to_string(), session_id().into()));
+    env.push(("AISH_LAST_EXIT".to_string(), session.last_exit_code.to_string().into()));
+    env
+}
+
+/// An environment variable as a child would see it: the shell's own
+/// variables first, then the inherited environment
+pub fn env_var(name: &str) -> Option<String> {
+    child_env().into_iter()
+        .find(|(key, _)| key == name)
+        .and_then(|(_, value)| value.into_string().ok())
+        .or_else(|| std::env::var(name).ok())
+}
@@ -0,0 +1,188 @@
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::platform;
+
+// Within this window every caller shares one git run; file events end it early
+const TTL: Duration = Duration::from_millis(500);
+
+/// Git and Kubernetes state for a directory, as shown in prompts, ShellInfo
+/// and the agent's context
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StatusSnapshot {
+    pub git_branch: Option<String>,
+    pub git_dirty: bool,
+    /// Commits ahead of and behind the upstream branch (0 without one)
+    pub git_ahead: u32,
+    pub git_behind: u32,
+    pub kube_context: Option<String>,
+}
+
+impl StatusSnapshot {
+    /// Branch with a trailing `*` when there are uncommitted changes
+    pub fn git_segment(&self) -> String {
+        match &self.git_branch {
+            Some(branch) if self.git_dirty => format!("{}*", branch),
+            Some(branch) => branch.clone(),
+            None => String::new(),
+        }
+    }
+
+    /// `↑2↓1`-style counts against the upstream; empty when in sync
+    pub fn git_ahead_behind(&self) -> String {
+        let mut counts = String::new();
+        if self.git_ahead > 0 {
+            counts.push_str(&format!("↑{}", self.git_ahead));
+        }
+        if self.git_behind > 0 {
+            counts.push_str(&format!("↓{}", self.git_behind));
+        }
+        counts
+    }
+}
+
+#[derive(Default)]
+struct GitStatus {
+    branch: Option<String>,
+    dirty: bool,
+    ahead: u32,
+    behind: u32,
+}
+
+#[derive(Default)]
+struct Cache {
+    dir: PathBuf,
+    snapshot: StatusSnapshot,
+    fetched: Option<Instant>,
+    // Paths the watcher was set up for, and the watcher keeping them watched
+    watched: Vec<PathBuf>,
+    watcher: Option<RecommendedWatcher>,
+}
+
+lazy_static::lazy_static! {
+    static ref CACHE: Mutex<Cache> = Mutex::new(Cache::default());
+}
+
+// Set by the watcher when .git or the kubeconfig changes
+static INVALIDATED: AtomicBool = AtomicBool::new(false);
+
+/// Current status for `dir`, re-running git at most once per TTL unless a
+/// watched file changed in the meantime
+pub fn snapshot(dir: &Path) -> StatusSnapshot {
+    let Ok(mut cache) = CACHE.lock() else {
+        return StatusSnapshot::default();
+    };
+
+    let invalidated = INVALIDATED.swap(false, Ordering::SeqCst);
+    let fresh = cache.fetched.is_some_and(|fetched| fetched.elapsed() < TTL);
+    if !invalidated && fresh && cache.dir == dir {
+        return cache.snapshot.clone();
+    }
+
+    let git = git_status(dir).unwrap_or_default();
+    cache.snapshot = StatusSnapshot {
+        git_branch: git.branch,
+        git_dirty: git.dirty,
+        git_ahead: git.ahead,
+        git_behind: git.behind,
+        kube_context: kube_context(),
+    };
+    cache.dir = dir.to_path_buf();
+    cache.fetched = Some(Instant::now());
+
+    let watch: Vec<PathBuf> = find_git_dir(dir).into_iter().chain(kubeconfig_path()).collect();
+    if watch != cache.watched {
+        cache.watcher = start_watcher(&watch);
+        cache.watched = watch;
+    }
+
+    cache.snapshot.clone()
+}
+
+/// Branch name (or short commit when detached), whether the tree is dirty,
+/// and how far it is from its upstream, all from one porcelain status
+fn git_status(dir: &Path) -> Option<GitStatus> {
+    // --no-optional-locks keeps status from rewriting the index, which would
+    // wake the watcher and defeat the cache
+    let output = platform::command("git")
+        .args(["--no-optional-locks", "status", "--porcelain=v2", "--branch"])
+        .current_dir(dir)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    let mut status = GitStatus::default();
+    let mut commit = None;
+    for line in text.lines() {
+        if let Some(head) = line.strip_prefix("# branch.head ") {
+            status.branch = Some(head.to_string()).filter(|head| head != "(detached)");
+        } else if let Some(oid) = line.strip_prefix("# branch.oid ") {
+            commit = oid.get(..7).map(str::to_string);
+        } else if let Some(counts) = line.strip_prefix("# branch.ab ") {
+            // "+<ahead> -<behind>"
+            for count in counts.split_whitespace() {
+                if let Some(ahead) = count.strip_prefix('+') {
+                    status.ahead = ahead.parse().unwrap_or(0);
+                } else if let Some(behind) = count.strip_prefix('-') {
+                    status.behind = behind.parse().unwrap_or(0);
+                }
+            }
+        } else if !line.starts_with('#') {
+            status.dirty = true;
+        }
+    }
+    status.branch = status.branch.or(commit);
+    Some(status)
+}
+
+/// `current-context` from the active kubeconfig, read directly rather than
+/// through kubectl
+fn kube_context() -> Option<String> {
+    let content = std::fs::read_to_string(kubeconfig_path()?).ok()?;
+    content.lines()
+        .find_map(|line| line.strip_prefix("current-context:"))
+        .map(|value| value.trim().trim_matches('"').trim_matches('\'').to_string())
+        .filter(|context| !context.is_empty())
+}
+
+fn kubeconfig_path() -> Option<PathBuf> {
+    match std::env::var_os("KUBECONFIG") {
+        Some(paths) => std::env::split_paths(&paths).next(),
+        None => dirs::home_dir().map(|home| home.join(".kube").join("config")),
+    }
+    .filter(|path| path.exists())
+}
+
+fn find_git_dir(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|ancestor| ancestor.join(".git"))
+        .find(|candidate| candidate.exists())
+}
+
+fn start_watcher(paths: &[PathBuf]) -> Option<RecommendedWatcher> {
+    if paths.is_empty() {
+        return None;
+    }
+    let mut watcher = notify::recommended_watcher(|event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        // Lock files come and go during every git operation; the real files follow
+        let relevant = event.paths.iter().any(|path| {
+            path.extension().is_none_or(|extension| extension != "lock")
+        });
+        if relevant {
+            INVALIDATED.store(true, Ordering::SeqCst);
+        }
+    })
+    .ok()?;
+    for path in paths {
+        let _ = watcher.watch(path, RecursiveMode::NonRecursive);
+    }
+    Some(watcher)
+}
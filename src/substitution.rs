@@ -0,0 +1,233 @@
+//! Command substitution in simple commands: `$(...)` and backticks are run
+//! by aish itself and replaced by their output, so builtins such as
+//! `cd $(git rev-parse --show-toplevel)` see the result. Commands that go
+//! to sh anyway (pipes, redirections) keep their substitutions for sh.
+
+use anyhow::{anyhow, Result};
+use std::process::Stdio;
+
+use crate::ts_runtime::ops::session_var_env;
+use crate::{platform, state};
+
+/// How deeply substitutions may nest; `$(a $(b))` is two deep
+pub const MAX_DEPTH: usize = 8;
+
+/// A substitution in a command line: the byte range it covers, the command
+/// inside it, and whether it sits inside double quotes
+struct Span {
+    start: usize,
+    end: usize,
+    command: String,
+    in_double: bool,
+}
+
+/// Whether `input` has a `$(...)` or backtick substitution outside single quotes
+pub fn contains(input: &str) -> bool {
+    spans(input).is_ok_and(|spans| !spans.is_empty())
+}
+
+/// `input` with each substitution replaced by a plain word, to tell whether
+/// the rest of the command still needs sh
+pub fn without(input: &str) -> String {
+    let Ok(spans) = spans(input) else {
+        return input.to_string();
+    };
+    let mut rest = String::new();
+    let mut last = 0;
+    for span in spans {
+        rest.push_str(&input[last..span.start]);
+        rest.push('x');
+        last = span.end;
+    }
+    rest.push_str(&input[last..]);
+    rest
+}
+
+/// Run the substitutions in `input` and put their output in its place:
+/// trailing newlines removed, and quoted so that outside double quotes it
+/// splits into words on whitespace and nothing else in it is special
+pub fn expand(input: &str) -> Result<String> {
+    expand_at(input, 0)
+}
+
+fn expand_at(input: &str, depth: usize) -> Result<String> {
+    let spans = spans(input).map_err(|e| anyhow!("command substitution: {}", e))?;
+    if spans.is_empty() {
+        return Ok(input.to_string());
+    }
+    if depth >= MAX_DEPTH {
+        return Err(anyhow!("command substitution nested more than {} deep", MAX_DEPTH));
+    }
+    let mut expanded = String::new();
+    let mut last = 0;
+    for span in spans {
+        expanded.push_str(&input[last..span.start]);
+        let output = run(&expand_at(&span.command, depth + 1)?)?;
+        if span.in_double {
+            expanded.push_str(&escape_double(&output));
+        } else {
+            expanded.push_str(&quote_words(&output));
+        }
+        last = span.end;
+    }
+    expanded.push_str(&input[last..]);
+    Ok(expanded)
+}
+
+fn spans(input: &str) -> Result<Vec<Span>, String> {
+    let chars: Vec<(usize, char)> = input.char_indices().collect();
+    let byte_at = |index: usize| chars.get(index).map_or(input.len(), |(at, _)| *at);
+    let mut spans = Vec::new();
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (at, c) = chars[i];
+        let next = chars.get(i + 1).map(|(_, c)| *c);
+        if in_single {
+            in_single = c != '\'';
+        } else if c == '\\' {
+            i += 2;
+            continue;
+        } else if c == '$' && next == Some('(') && chars.get(i + 2).map(|(_, c)| *c) != Some('(') {
+            // `$((...))` is arithmetic, which only sh does
+            let close = closing_paren(&chars, i + 2).ok_or("missing `)`")?;
+            spans.push(Span {
+                start: at,
+                end: byte_at(close + 1),
+                command: input[byte_at(i + 2)..byte_at(close)].to_string(),
+                in_double,
+            });
+            i = close + 1;
+            continue;
+        } else if c == '`' {
+            let close = closing_backtick(&chars, i + 1).ok_or("unterminated `")?;
+            spans.push(Span {
+                start: at,
+                end: byte_at(close + 1),
+                command: unescape_backticks(&input[byte_at(i + 1)..byte_at(close)]),
+                in_double,
+            });
+            i = close + 1;
+            continue;
+        } else if in_double {
+            in_double = c != '"';
+        } else if c == '\'' {
+            in_single = true;
+        } else if c == '"' {
+            in_double = true;
+        }
+        i += 1;
+    }
+    Ok(spans)
+}
+
+/// Index of the `)` closing a `$(` whose contents start at `from`
+fn closing_paren(chars: &[(usize, char)], from: usize) -> Option<usize> {
+    let mut depth = 1usize;
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut i = from;
+    while i < chars.len() {
+        let c = chars[i].1;
+        if in_single {
+            in_single = c != '\'';
+        } else if c == '\\' {
+            i += 1;
+        } else if in_double {
+            in_double = c != '"';
+        } else {
+            match c {
+                '\'' => in_single = true,
+                '"' => in_double = true,
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(i);
+                    }
+                }
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Index of the unescaped backtick closing one whose contents start at `from`
+fn closing_backtick(chars: &[(usize, char)], from: usize) -> Option<usize> {
+    let mut i = from;
+    while i < chars.len() {
+        match chars[i].1 {
+            '\\' => i += 1,
+            '`' => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Inside backticks, `\``, `\\` and `\$` stand for the character itself
+fn unescape_backticks(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(&next @ ('`' | '\\' | '$')) if c == '\\' => {
+                unescaped.push(next);
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Run `command` with sh in the shell's directory and environment and
+/// return its standard output without trailing newlines; its errors go to
+/// the terminal, and its exit status doesn't stop the outer command
+fn run(command: &str) -> Result<String> {
+    let output = platform::shell_command(command)
+        .current_dir(state::current_dir())
+        .envs(session_var_env())
+        .envs(state::child_env())
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        tracing::debug!(command, status = ?output.status.code(), "command substitution failed");
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim_end_matches(['\n', '\r']).to_string())
+}
+
+/// Output for outside double quotes: one word per whitespace-separated
+/// piece, single-quoted when it has anything sh would treat specially
+fn quote_words(output: &str) -> String {
+    output
+        .split_whitespace()
+        .map(|word| {
+            if word.chars().all(|c| c.is_alphanumeric() || "-_./:=@%+,".contains(c)) {
+                word.to_string()
+            } else {
+                format!("'{}'", word.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Output for inside double quotes, where only `"`, `\`, `$` and backticks
+/// need escaping
+fn escape_double(output: &str) -> String {
+    let mut escaped = String::new();
+    for c in output.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
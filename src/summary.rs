@@ -0,0 +1,131 @@
+//! What happened in a session, summed up on exit: how long it lasted, what
+//! ran, what failed and what the AI cost. Built locally from counters kept
+//! as the session goes; a model-written narrative is opt-in.
+
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{humanize, usage};
+
+// Failed commands kept for the summary, most recent last
+const MAX_FAILURES: usize = 10;
+
+#[derive(Default)]
+struct Counters {
+    commands: u64,
+    prompts: u64,
+    failed: u64,
+    failures: Vec<(String, i32)>,
+    // The longest command and how long it took
+    longest: Option<(String, Duration)>,
+}
+
+lazy_static::lazy_static! {
+    static ref STARTED: Instant = Instant::now();
+    static ref COUNTERS: Mutex<Counters> = Mutex::new(Counters::default());
+}
+
+/// Start the session clock; called when the shell starts
+pub fn start() {
+    lazy_static::initialize(&STARTED);
+}
+
+/// Count a command the user ran, remembering it if it failed
+pub fn record_command(command: &str, exit_code: i32, duration: Duration) {
+    let Ok(mut counters) = COUNTERS.lock() else {
+        return;
+    };
+    counters.commands += 1;
+    if exit_code != 0 {
+        counters.failed += 1;
+        if counters.failures.len() == MAX_FAILURES {
+            counters.failures.remove(0);
+        }
+        counters.failures.push((command.to_string(), exit_code));
+    }
+    if counters.longest.as_ref().is_none_or(|(_, longest)| duration > *longest) {
+        counters.longest = Some((command.to_string(), duration));
+    }
+}
+
+/// Count a prompt sent to the agent
+pub fn record_prompt() {
+    if let Ok(mut counters) = COUNTERS.lock() {
+        counters.prompts += 1;
+    }
+}
+
+/// The session so far; also what the `onExit` hook receives
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub duration_ms: u64,
+    pub commands: u64,
+    pub prompts: u64,
+    pub failed: u64,
+    /// Recent failed commands with their exit codes
+    pub failures: Vec<FailedCommand>,
+    pub longest_command: Option<String>,
+    pub longest_command_ms: Option<u64>,
+    pub ai_requests: u64,
+    pub ai_tokens: u64,
+    /// Estimated USD, counting models with known pricing
+    pub ai_cost: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedCommand {
+    pub command: String,
+    pub exit_code: i32,
+}
+
+impl Summary {
+    pub fn collect() -> Self {
+        let (ai_requests, ai_tokens, ai_cost) = usage::session_totals();
+        let counters = COUNTERS.lock();
+        let (commands, prompts, failed, failures, longest) = match &counters {
+            Ok(counters) => (counters.commands, counters.prompts, counters.failed, counters.failures.clone(), counters.longest.clone()),
+            Err(_) => (0, 0, 0, Vec::new(), None),
+        };
+        Self {
+            duration_ms: STARTED.elapsed().as_millis() as u64,
+            commands,
+            prompts,
+            failed,
+            failures: failures.into_iter().map(|(command, exit_code)| FailedCommand { command, exit_code }).collect(),
+            longest_command_ms: longest.as_ref().map(|(_, duration)| duration.as_millis() as u64),
+            longest_command: longest.map(|(command, _)| command),
+            ai_requests,
+            ai_tokens,
+            ai_cost,
+        }
+    }
+
+    /// Plain-text summary, a few lines long
+    pub fn render(&self) -> String {
+        let mut text = format!(
+            "Session: {}, {} command{} ({} failed), {} agent prompt{}\n",
+            humanize::duration(Duration::from_millis(self.duration_ms)),
+            self.commands,
+            if self.commands == 1 { "" } else { "s" },
+            self.failed,
+            self.prompts,
+            if self.prompts == 1 { "" } else { "s" },
+        );
+        if self.ai_requests > 0 {
+            text.push_str(&format!("AI: {} requests, {} tokens, ~${:.4}\n", self.ai_requests, self.ai_tokens, self.ai_cost));
+        }
+        if let (Some(command), Some(ms)) = (&self.longest_command, self.longest_command_ms) {
+            if ms >= 1000 {
+                text.push_str(&format!("Longest: {} ({})\n", humanize::first_line(command), humanize::duration(Duration::from_millis(ms))));
+            }
+        }
+        if !self.failures.is_empty() {
+            text.push_str(if self.failed > self.failures.len() as u64 { "Recent failures:\n" } else { "Failures:\n" });
+            for failure in &self.failures {
+                text.push_str(&format!("  [{}] {}\n", failure.exit_code, humanize::first_line(&failure.command)));
+            }
+        }
+        text
+    }
+}
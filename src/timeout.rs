@@ -0,0 +1,209 @@
+//! Time limits for commands: `@timeout 30s <command>` from the user or the
+//! agent, or `shell.defaultCommandTimeout` for every typed command. When the
+//! limit passes, the command's process group gets SIGTERM, then SIGKILL if
+//! it is still there after a grace period, and the command's status is
+//! [`TIMED_OUT_STATUS`].
+
+use anyhow::{anyhow, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Status of a command stopped for running too long, as GNU `timeout` reports it
+pub const TIMED_OUT_STATUS: i32 = 124;
+
+const PREFIX: &str = "@timeout";
+// Between SIGTERM and SIGKILL
+const GRACE: Duration = Duration::from_secs(2);
+
+/// The limit on the command running now: when it ends, and whether it was hit
+struct Limit {
+    deadline: Instant,
+    expired: Arc<AtomicBool>,
+}
+
+lazy_static::lazy_static! {
+    static ref CURRENT: Mutex<Option<Limit>> = Mutex::new(None);
+}
+
+/// `input` split into its `@timeout DURATION` and the command after it, or
+/// None without the prefix
+pub fn split_prefix(input: &str) -> Result<Option<(Duration, &str)>> {
+    let trimmed = input.trim_start();
+    let Some(rest) = trimmed.strip_prefix(PREFIX) else {
+        return Ok(None);
+    };
+    if !rest.starts_with(char::is_whitespace) {
+        return Ok(None);
+    }
+    let rest = rest.trim_start();
+    let (duration, command) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let duration = parse_duration(duration)
+        .ok_or_else(|| anyhow!("{}: invalid duration `{}` (e.g. 30s, 5m, 1h, 500ms)", PREFIX, duration))?;
+    let command = command.trim_start();
+    if command.is_empty() {
+        return Err(anyhow!("usage: {} DURATION COMMAND", PREFIX));
+    }
+    Ok(Some((duration, command)))
+}
+
+/// A duration such as `30`, `30s`, `1.5m`, `2h` or `500ms`; plain numbers are seconds
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return None,
+    };
+    (seconds > 0.0 && seconds.is_finite()).then(|| Duration::from_secs_f64(seconds))
+}
+
+/// A limit for the commands started until it is dropped
+pub struct Limited {
+    expired: Arc<AtomicBool>,
+}
+
+impl Limited {
+    pub fn start(limit: Duration) -> Self {
+        let expired = Arc::new(AtomicBool::new(false));
+        if let Ok(mut current) = CURRENT.lock() {
+            *current = Some(Limit { deadline: Instant::now() + limit, expired: expired.clone() });
+        }
+        Self { expired }
+    }
+
+    /// Whether a command was stopped for running past the limit
+    pub fn expired(&self) -> bool {
+        self.expired.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Limited {
+    fn drop(&mut self) {
+        if let Ok(mut current) = CURRENT.lock() {
+            *current = None;
+        }
+    }
+}
+
+/// Whether commands started now run under a limit
+pub fn active() -> bool {
+    CURRENT.lock().is_ok_and(|current| current.is_some())
+}
+
+/// Stops the process (group) it watches when the current limit passes,
+/// unless it is dropped first
+pub struct Watchdog {
+    cancel: Option<Sender<()>>,
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            let _ = cancel.send(());
+        }
+    }
+}
+
+/// Watch process `pid`, which leads its own process group when job control
+/// or a limit put it in one, while it is waited for
+pub fn watch(pid: u32) -> Watchdog {
+    let Some((deadline, expired)) = CURRENT
+        .lock()
+        .ok()
+        .and_then(|current| current.as_ref().map(|limit| (limit.deadline, limit.expired.clone())))
+    else {
+        return Watchdog { cancel: None };
+    };
+    let (cancel, cancelled) = mpsc::channel::<()>();
+    std::thread::spawn(move || {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if cancelled.recv_timeout(left) != Err(RecvTimeoutError::Timeout) {
+            return;
+        }
+        expired.store(true, Ordering::Relaxed);
+        tracing::info!(pid, "command timed out");
+        stop(pid, false);
+        if cancelled.recv_timeout(GRACE) == Err(RecvTimeoutError::Timeout) {
+            stop(pid, true);
+        }
+    });
+    Watchdog { cancel: Some(cancel) }
+}
+
+/// Kill process `pid` and its process group at once, without a grace period
+pub fn kill(pid: u32) {
+    stop(pid, true);
+}
+
+#[cfg(unix)]
+fn stop(pid: u32, force: bool) {
+    let signal = if force { libc::SIGKILL } else { libc::SIGTERM };
+    let pid = pid as libc::pid_t;
+    unsafe {
+        // The whole group, so nothing the command started outlives it
+        if libc::kill(-pid, signal) < 0 {
+            libc::kill(pid, signal);
+        }
+        // A stopped group would never see SIGTERM
+        libc::kill(-pid, libc::SIGCONT);
+    }
+}
+
+#[cfg(not(unix))]
+fn stop(pid: u32, force: bool) {
+    let mut taskkill = std::process::Command::new("taskkill");
+    taskkill.args(["/PID", &pid.to_string(), "/T"]);
+    if force {
+        taskkill.arg("/F");
+    }
+    let _ = taskkill.stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null()).status();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("1.5m"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration(" 5m "), Some(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        for text in ["", "s", "0", "0s", "-5s", "5d", "5 m", "abc", "1.2.3s"] {
+            assert_eq!(parse_duration(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn splits_the_prefix() {
+        assert_eq!(split_prefix("@timeout 30s make test").unwrap(), Some((Duration::from_secs(30), "make test")));
+        assert_eq!(split_prefix("  @timeout\t5m   sleep 1000").unwrap(), Some((Duration::from_secs(300), "sleep 1000")));
+    }
+
+    #[test]
+    fn leaves_other_lines_alone() {
+        assert_eq!(split_prefix("make test").unwrap(), None);
+        assert_eq!(split_prefix("@timeouts 30s make").unwrap(), None);
+        assert_eq!(split_prefix("echo @timeout 30s").unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_a_bad_prefix() {
+        assert!(split_prefix("@timeout soon make").is_err());
+        assert!(split_prefix("@timeout 30s").is_err());
+        assert!(split_prefix("@timeout 30s   ").is_err());
+    }
+}
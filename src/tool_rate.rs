@@ -0,0 +1,100 @@
+//! Limits on how fast the agent starts tools and how many of the commands
+//! it started may still be running, so a model stuck in a loop of
+//! `run_command` calls (or one leaving servers behind with `&`) can't swamp
+//! the machine. A call over a limit is refused with a result the model can
+//! read, and isn't counted.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::humanize;
+use crate::ts_runtime::guard::Denial;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// `ai.max_tool_calls_per_minute` and `ai.max_concurrent_tools`; 0 is no limit
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimits {
+    pub per_minute: u32,
+    pub concurrent: u32,
+}
+
+lazy_static::lazy_static! {
+    // When each tool call of the last minute started
+    static ref STARTED: Mutex<VecDeque<Instant>> = Mutex::new(VecDeque::new());
+    // Process groups of the commands the agent started that may still be running
+    static ref GROUPS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+}
+
+/// Count a tool call as started, unless that would go over `limits`. Only
+/// calls that run commands (`runs_commands`) are held to `concurrent`.
+pub fn admit(limits: RateLimits, runs_commands: bool) -> Result<(), Denial> {
+    if runs_commands && limits.concurrent > 0 {
+        let running = running();
+        if running >= limits.concurrent as usize {
+            return Err(Denial::new(
+                "ai.max_concurrent_tools",
+                None,
+                format!(
+                    "{} commands started earlier are still running, counting ones left in the background; \
+                     wait for them to finish or stop them",
+                    running
+                ),
+            ));
+        }
+    }
+    let Ok(mut started) = STARTED.lock() else {
+        return Ok(());
+    };
+    let now = Instant::now();
+    while started.front().is_some_and(|oldest| now.duration_since(*oldest) >= WINDOW) {
+        started.pop_front();
+    }
+    if limits.per_minute > 0 && started.len() >= limits.per_minute as usize {
+        let wait = started.front().map_or(WINDOW, |oldest| WINDOW.saturating_sub(now.duration_since(*oldest)));
+        return Err(Denial::new(
+            "ai.max_tool_calls_per_minute",
+            None,
+            format!(
+                "{} tool calls in the last minute; the next is allowed in {}. Do fewer, larger steps",
+                started.len(),
+                humanize::duration(wait)
+            ),
+        ));
+    }
+    started.push_back(now);
+    Ok(())
+}
+
+/// Count the command whose process group is `group` as running until every
+/// process in the group, including ones it put in the background, has
+/// exited or been killed
+pub fn track(group: u32) {
+    if let Ok(mut groups) = GROUPS.lock() {
+        groups.push(group);
+    }
+}
+
+/// Commands started by the agent that still have a process running,
+/// forgetting the ones that are done
+fn running() -> usize {
+    let Ok(mut groups) = GROUPS.lock() else {
+        return 0;
+    };
+    groups.retain(|group| group_alive(*group));
+    groups.len()
+}
+
+#[cfg(unix)]
+fn group_alive(group: u32) -> bool {
+    // Signal 0 only checks; EPERM still means the group exists
+    let alive = unsafe { libc::kill(-(group as libc::pid_t), 0) } == 0;
+    alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// Without process groups, commands can't be followed once they return
+#[cfg(not(unix))]
+fn group_alive(_group: u32) -> bool {
+    false
+}
@@ -0,0 +1,146 @@
+//! `aish tools serve --stdio`: the agent's tools (built-ins and those from
+//! .aish.ts) behind the same policies as in the shell, for other agents to
+//! use without the REPL.
+//!
+//! The protocol is one JSON object per line. Requests are
+//! `{"id": 1, "method": "list_tools"}` and
+//! `{"id": 2, "method": "call_tool", "params": {"name": "run_command", "arguments": {"command": "ls"}}}`;
+//! each gets `{"id": …, "result": …}` or `{"id": …, "error": {"message": …}}`.
+//! `list_tools` returns `{"tools": [{name, description, parameters}]}` and
+//! `call_tool` returns `{"output": "…"}`, where policy refusals are the same
+//! structured denials the model sees. `shutdown` ends the server.
+
+use anyhow::Result;
+use clap::Subcommand;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::Write;
+use tokio::io::AsyncBufReadExt;
+
+use crate::render::RenderFormat;
+use crate::spinner::Spinner;
+use crate::{approval, ts_runtime, wasm_plugins, AiAgent, Config};
+
+#[derive(Subcommand)]
+pub enum ToolsAction {
+    /// Serve the agent's tools over a JSON protocol
+    Serve {
+        /// Speak the protocol on stdin/stdout (currently the only transport)
+        #[arg(long)]
+        stdio: bool,
+    },
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+pub async fn run_tools(action: ToolsAction) -> Result<()> {
+    match action {
+        ToolsAction::Serve { stdio: true } => serve_stdio().await,
+        ToolsAction::Serve { stdio: false } => Err(anyhow::anyhow!("only --stdio is supported")),
+    }
+}
+
+async fn serve_stdio() -> Result<()> {
+    let mut out = protocol_stdout()?;
+
+    let ts_config_loader = ts_runtime::TypeScriptConfigLoader::new()?;
+    let config = match ts_config_loader.load_config().await {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error loading {}:\n{}", ts_config_loader.script_path().display(), e);
+            Config::default()
+        }
+    };
+    ts_runtime::guard::install(config.safety.as_ref());
+    ts_runtime::permissions::install(config.permissions.as_ref(), ts_config_loader.config_dir());
+    approval::set_external_approver(config.approval_command());
+    wasm_plugins::load();
+    let agent = AiAgent::new(config);
+    // Agent events go to stderr, where stdout now points
+    let renderer = RenderFormat::Plain.renderer();
+    let spinner = Spinner::disabled();
+
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request: Request = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                respond(&mut out, json!({ "id": null, "error": { "message": format!("invalid request: {}", e) } }))?;
+                continue;
+            }
+        };
+        tracing::info!(method = %request.method, "tool server request");
+
+        // Reloaded per request so configuration changes are picked up
+        let tool_registry = ts_config_loader.load_agent_tools().await
+            .unwrap_or_else(|_| ts_runtime::ToolRegistry { tools: Default::default() });
+        let result = match request.method.as_str() {
+            "list_tools" => {
+                let tools: Vec<Value> = agent.tool_schemas(&tool_registry)
+                    .into_iter()
+                    .map(|schema| schema["function"].clone())
+                    .collect();
+                Ok(json!({ "tools": tools }))
+            }
+            "call_tool" => match request.params["name"].as_str() {
+                Some(name) => {
+                    let arguments = match &request.params["arguments"] {
+                        Value::Null => json!({}),
+                        arguments => arguments.clone(),
+                    };
+                    agent.call_tool(name, &arguments, &tool_registry, &ts_config_loader, renderer.as_ref(), &spinner).await
+                        .map(|output| json!({ "output": output }))
+                }
+                None => Err(anyhow::anyhow!("call_tool needs params.name")),
+            },
+            "shutdown" => {
+                respond(&mut out, json!({ "id": request.id, "result": null }))?;
+                break;
+            }
+            other => Err(anyhow::anyhow!("unknown method: {}", other)),
+        };
+
+        let response = match result {
+            Ok(result) => json!({ "id": request.id, "result": result }),
+            Err(e) => json!({ "id": request.id, "error": { "message": e.to_string() } }),
+        };
+        respond(&mut out, response)?;
+    }
+    Ok(())
+}
+
+fn respond(out: &mut impl Write, response: Value) -> Result<()> {
+    writeln!(out, "{}", response)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Keep stdout for the protocol alone: take a copy of it for responses and
+/// point the process's stdout at stderr, so prompts and messages printed by
+/// tools or approvals can't corrupt the stream
+#[cfg(unix)]
+fn protocol_stdout() -> Result<Box<dyn Write>> {
+    use std::os::unix::io::FromRawFd;
+
+    std::io::stdout().flush()?;
+    let protocol = unsafe { libc::dup(1) };
+    if protocol < 0 || unsafe { libc::dup2(2, 1) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(Box::new(unsafe { std::fs::File::from_raw_fd(protocol) }))
+}
+
+#[cfg(not(unix))]
+fn protocol_stdout() -> Result<Box<dyn Write>> {
+    Ok(Box::new(std::io::stdout()))
+}
@@ -0,0 +1,213 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::render::{AgentEvent, Renderer};
+use crate::ui::{self, Role};
+use crate::{humanize, share};
+
+const TRANSCRIPT_VERSION: u32 = 1;
+
+/// One recorded moment of a session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Entry {
+    /// A line the user entered, and the mode it was entered in
+    Input { text: String, mode: String },
+    /// A shell command with its exit code and captured output (stderr and
+    /// post-processed output; plain stdout goes straight to the terminal)
+    Command { command: String, exit_code: i32, output: String, duration_ms: u64 },
+    /// Something the agent did or said, as rendered (see [`AgentEvent`])
+    Agent { event: Value },
+    /// A tool call's arguments and the result sent back to the model
+    ToolCall { name: String, arguments: String, output: String },
+}
+
+/// A recorded session. On disk it is JSON Lines: a header line with
+/// `version` and `started_at`, then one [`TimedEntry`] per line, appended as
+/// the session goes so a crash loses at most the entry being written.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Transcript {
+    pub version: u32,
+    /// Seconds since the epoch when recording started
+    pub started_at: u64,
+    pub entries: Vec<TimedEntry>,
+}
+
+/// The first line of a transcript file
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    started_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimedEntry {
+    /// Milliseconds since recording started
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+struct Recorder {
+    path: PathBuf,
+    started: Instant,
+    file: File,
+}
+
+lazy_static::lazy_static! {
+    static ref RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+    // Entries kept in memory for `--output json`, while collecting
+    static ref COLLECTED: Mutex<Option<Vec<Entry>>> = Mutex::new(None);
+}
+
+/// Start recording to `path`, replacing any recording in progress
+pub fn start(path: &Path) -> Result<()> {
+    let header = Header {
+        version: TRANSCRIPT_VERSION,
+        started_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+    };
+    let mut file = File::create(path)?;
+    append(&mut file, &header)?;
+    let recorder = Recorder { path: path.to_path_buf(), started: Instant::now(), file };
+    if let Ok(mut current) = RECORDER.lock() {
+        *current = Some(recorder);
+    }
+    Ok(())
+}
+
+/// Stop recording, returning where the transcript was written
+pub fn stop() -> Option<PathBuf> {
+    RECORDER.lock().ok()?.take().map(|recorder| recorder.path)
+}
+
+/// The file being recorded to, if any
+pub fn recording() -> Option<PathBuf> {
+    RECORDER.lock().ok()?.as_ref().map(|recorder| recorder.path.clone())
+}
+
+/// Keep every entry from now on in memory too, until [`take_collected`]
+pub fn collect() {
+    if let Ok(mut collected) = COLLECTED.lock() {
+        *collected = Some(Vec::new());
+    }
+}
+
+/// The entries kept since [`collect`], which stops collecting
+pub fn take_collected() -> Vec<Entry> {
+    COLLECTED.lock().ok().and_then(|mut collected| collected.take()).unwrap_or_default()
+}
+
+/// Add an entry if a recording is in progress, and send it to anyone
+/// watching a shared session. Each entry is appended as its own line, so a
+/// crashed session still leaves every entry recorded before it.
+pub fn record(entry: Entry) {
+    share::broadcast(&entry);
+    if let Ok(mut collected) = COLLECTED.lock() {
+        if let Some(entries) = collected.as_mut() {
+            entries.push(entry.clone());
+        }
+    }
+    let Ok(mut current) = RECORDER.lock() else {
+        return;
+    };
+    let Some(recorder) = current.as_mut() else {
+        return;
+    };
+    let at_ms = recorder.started.elapsed().as_millis() as u64;
+    if let Err(e) = append(&mut recorder.file, &TimedEntry { at_ms, entry }) {
+        tracing::warn!(path = %recorder.path.display(), error = %e, "failed to write transcript");
+    }
+}
+
+fn append(file: &mut File, value: &impl Serialize) -> Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Read a transcript: JSON Lines, or the single JSON document earlier
+/// versions wrote
+fn load(content: &str) -> Result<Transcript> {
+    if let Ok(transcript) = serde_json::from_str::<Transcript>(content) {
+        return Ok(transcript);
+    }
+    let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+    let header: Header = serde_json::from_str(lines.next().unwrap_or_default())?;
+    let entries = lines.map(serde_json::from_str::<TimedEntry>).collect::<serde_json::Result<_>>()?;
+    Ok(Transcript { version: header.version, started_at: header.started_at, entries })
+}
+
+/// Renderer that records agent events before passing them on
+pub struct Recording<'r>(pub &'r dyn Renderer);
+
+impl Renderer for Recording<'_> {
+    fn render(&self, event: &AgentEvent) {
+        if let Ok(event) = serde_json::to_value(event) {
+            record(Entry::Agent { event });
+        }
+        self.0.render(event);
+    }
+}
+
+/// Print a recorded session again, with agent output through `renderer`
+pub fn replay(path: &Path, renderer: &dyn Renderer) -> Result<()> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    let transcript = load(&content)
+        .map_err(|e| anyhow::anyhow!("{}: not an aish transcript: {}", path.display(), e))?;
+
+    for timed in &transcript.entries {
+        if let Entry::Input { .. } = timed.entry {
+            print!("{} ", ui::paint(Role::Muted, &format!("+{}", humanize::duration(Duration::from_millis(timed.at_ms)))));
+        }
+        show(&timed.entry, renderer);
+    }
+    Ok(())
+}
+
+/// Print one entry as it appeared in the session
+pub fn show(entry: &Entry, renderer: &dyn Renderer) {
+    match entry {
+        Entry::Input { text, mode } => println!("{} {}", ui::paint(Role::Emphasis, &format!("{}>", mode)), text),
+        Entry::Command { output, exit_code, .. } => {
+            print!("{}", output);
+            if *exit_code != 0 {
+                println!("{}", ui::paint(Role::Muted, &format!("[exit {}]", exit_code)));
+            }
+        }
+        Entry::Agent { event } => {
+            if let Some(event) = agent_event(event) {
+                renderer.render(&event);
+            }
+        }
+        // Shown through the agent events; kept in the file for debugging
+        Entry::ToolCall { .. } => {}
+    }
+}
+
+/// Borrow a recorded event back into an [`AgentEvent`]
+fn agent_event(value: &Value) -> Option<AgentEvent<'_>> {
+    fn text<'v>(value: &'v Value, name: &str) -> &'v str {
+        value[name].as_str().unwrap_or_default()
+    }
+    let field = |name| text(value, name);
+    Some(match value["event"].as_str()? {
+        "command" => AgentEvent::Command { command: field("command") },
+        "command_blocked" => AgentEvent::CommandBlocked { command: field("command"), reason: field("reason") },
+        "tool" => AgentEvent::Tool { name: field("name") },
+        "config_proposal" => AgentEvent::ConfigProposal { path: field("path"), reason: field("reason"), diff: field("diff") },
+        "file_change" => AgentEvent::FileChange { tool: field("tool"), path: field("path"), diff: field("diff") },
+        "notice" => AgentEvent::Notice { message: field("message") },
+        "response" => AgentEvent::Response { content: field("content") },
+        "citation" => AgentEvent::Citation { number: value["number"].as_u64()? as usize, source: field("source") },
+        "plan" => AgentEvent::Plan { steps: serde_json::from_value(value["steps"].clone()).ok()? },
+        _ => return None,
+    })
+}
@@ -0,0 +1,33 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::AiAgent;
+
+const TRANSLATE_SYSTEM_PROMPT: &str = "You translate a natural-language request into a single \
+    POSIX shell command that does what the user asks. Reply with the command only: no \
+    explanation, no code fences, no leading '$'. Prefer one line; use pipes or && rather than \
+    several commands. If the request cannot be expressed as a shell command, reply with an \
+    empty string.";
+
+/// Ask the model for a shell command that does what `request` describes.
+/// The command is returned for the user to review; nothing is executed here.
+pub async fn translate(agent: &AiAgent, request: &str, current_dir: &Path) -> Result<Option<String>> {
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+    let prompt = format!(
+        "Working directory: {}\nOS: {}\nShell: {}\nRequest: {}",
+        current_dir.display(),
+        std::env::consts::OS,
+        shell,
+        request,
+    );
+
+    let response = agent.complete(TRANSLATE_SYSTEM_PROMPT, &prompt).await?;
+    let command = response.trim()
+        .trim_start_matches("```sh")
+        .trim_start_matches("```bash")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+    let command = command.strip_prefix("$ ").unwrap_or(command).trim();
+    Ok(Some(command.to_string()).filter(|c| !c.is_empty()))
+}
@@ -0,0 +1,326 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{humanize, pathname};
+
+// Entries kept in the session log that `restore` reads
+const MAX_LOGGED: usize = 200;
+
+/// A file or directory aish moved to the trash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedItem {
+    #[serde(with = "pathname::serde_path")]
+    pub original: PathBuf,
+    #[serde(with = "pathname::serde_path")]
+    pub trashed: PathBuf,
+    /// The .trashinfo file written alongside, per the XDG trash spec
+    #[serde(default, with = "pathname::serde_path::option")]
+    pub info: Option<PathBuf>,
+    /// Seconds since the epoch
+    pub deleted_at: u64,
+}
+
+fn log_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|mut p| { p.push(".aish"); p.push("trash.json"); p })
+}
+
+/// Items trashed from aish sessions that are still in the trash, most recent first
+pub fn recent() -> Vec<TrashedItem> {
+    let mut items: Vec<TrashedItem> = log_path()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    items.retain(|item| item.trashed.symlink_metadata().is_ok());
+    items.reverse();
+    items
+}
+
+fn save_log(mut newest_first: Vec<TrashedItem>) {
+    let Some(path) = log_path() else {
+        return;
+    };
+    newest_first.truncate(MAX_LOGGED);
+    newest_first.reverse();
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    if let Ok(content) = serde_json::to_string(&newest_first) {
+        let _ = std::fs::write(path, content);
+    }
+}
+
+/// `rm` that moves its operands to the trash. Accepts rm's usual flags
+/// (-r, -R, -f, -d, -i and -v; -i is ignored since nothing is lost) and
+/// returns the exit status with any error messages, rm-style.
+pub fn rm(args: &[OsString], cwd: &Path) -> (i32, String) {
+    let mut recursive = false;
+    let mut force = false;
+    let mut empty_dirs = false;
+    let mut verbose = false;
+    let mut operands = Vec::new();
+    let mut only_operands = false;
+    for arg in args {
+        let text = arg.to_string_lossy();
+        if only_operands || !text.starts_with('-') || text == "-" {
+            operands.push(arg);
+        } else if text == "--" {
+            only_operands = true;
+        } else {
+            match text.as_ref() {
+                "--recursive" => recursive = true,
+                "--force" => force = true,
+                "--dir" => empty_dirs = true,
+                "--verbose" => verbose = true,
+                long if long.starts_with("--") => {}
+                short => {
+                    recursive |= short.contains(['r', 'R']);
+                    force |= short.contains('f');
+                    empty_dirs |= short.contains('d');
+                    verbose |= short.contains('v');
+                }
+            }
+        }
+    }
+
+    if operands.is_empty() && !force {
+        return (1, "rm: missing operand\n".to_string());
+    }
+
+    let mut status = 0;
+    let mut messages = String::new();
+    let mut trashed = Vec::new();
+    for operand in operands {
+        let path = cwd.join(operand);
+        let shown = Path::new(operand).display();
+        let last_component = operand.to_string_lossy().trim_end_matches('/').rsplit('/').next().map(str::to_string);
+        if matches!(last_component.as_deref(), Some(".") | Some("..") | Some("")) {
+            messages.push_str(&format!("rm: refusing to remove '.' or '..' directory: skipping '{}'\n", shown));
+            status = 1;
+            continue;
+        }
+        let Ok(metadata) = path.symlink_metadata() else {
+            if !force {
+                messages.push_str(&format!("rm: cannot remove '{}': No such file or directory\n", shown));
+                status = 1;
+            }
+            continue;
+        };
+        if metadata.is_dir() && !recursive {
+            let empty = std::fs::read_dir(&path).is_ok_and(|mut entries| entries.next().is_none());
+            if !(empty_dirs && empty) {
+                messages.push_str(&format!("rm: cannot remove '{}': Is a directory\n", shown));
+                status = 1;
+                continue;
+            }
+        }
+        match move_to_trash(&path) {
+            Ok(item) => {
+                if verbose {
+                    messages.push_str(&format!("trashed '{}'\n", shown));
+                }
+                trashed.push(item);
+            }
+            Err(e) => {
+                messages.push_str(&format!("rm: cannot remove '{}': {}\n", shown, e));
+                status = 1;
+            }
+        }
+    }
+
+    if !trashed.is_empty() {
+        let mut log = recent();
+        for item in trashed {
+            log.insert(0, item);
+        }
+        save_log(log);
+    }
+    (status, messages)
+}
+
+/// The arguments of a command line that is nothing but an unquoted `rm`, which
+/// can be run through [`rm`]; anything more involved goes to sh as usual
+pub fn plain_rm_args(command: &str) -> Option<Vec<&str>> {
+    if command.contains(['\'', '"', '\\', '\n', ';', '&', '(', '{', '$']) || crate::shell::needs_sh(command) {
+        return None;
+    }
+    let mut words = command.split_whitespace();
+    (words.next() == Some("rm")).then(|| words.collect())
+}
+
+/// `command` with `rm` defined as a shell function that trashes through this
+/// binary, for lines [`plain_rm_args`] turns away (`cd build && rm -r out`).
+/// `command rm`, `/bin/rm` and rm run by other programs (`xargs rm`,
+/// `find -exec rm`) still delete.
+#[cfg(unix)]
+pub fn route_rm(command: &str) -> String {
+    let runs_rm = command
+        .split(|c: char| c.is_whitespace() || matches!(c, ';' | '&' | '|' | '(' | ')' | '{' | '}' | '`'))
+        .any(|word| word == "rm");
+    let Some(exe) = std::env::current_exe().ok().filter(|_| runs_rm) else {
+        return command.to_string();
+    };
+    let quoted = format!("'{}'", exe.to_string_lossy().replace('\'', "'\\''"));
+    format!("rm() {{ {} trash -- \"$@\"; }}\n{}", quoted, command)
+}
+
+#[cfg(not(unix))]
+pub fn route_rm(command: &str) -> String {
+    command.to_string()
+}
+
+/// Put a trashed item back where it came from
+pub fn restore(item: &TrashedItem) -> Result<()> {
+    if item.original.symlink_metadata().is_ok() {
+        return Err(anyhow::anyhow!("{} already exists", item.original.display()));
+    }
+    if let Some(parent) = item.original.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::rename(&item.trashed, &item.original)?;
+    if let Some(info) = &item.info {
+        let _ = std::fs::remove_file(info);
+    }
+
+    let mut log = recent();
+    log.retain(|logged| logged.trashed != item.trashed);
+    save_log(log);
+    Ok(())
+}
+
+fn move_to_trash(path: &Path) -> Result<TrashedItem> {
+    // Resolve the parent but not the entry itself, so symlinks are trashed, not their targets
+    let name = path.file_name()
+        .ok_or_else(|| anyhow::anyhow!("refusing to remove this path"))?;
+    let original = path.parent()
+        .map(|parent| parent.canonicalize())
+        .transpose()?
+        .unwrap_or_default()
+        .join(name);
+
+    let trash = trash_dir_for(&original)?;
+    let files = trash.join("files");
+    let info_dir = trash.join("info");
+    std::fs::create_dir_all(&files)?;
+    let has_info = cfg!(not(target_os = "macos"));
+    if has_info {
+        std::fs::create_dir_all(&info_dir)?;
+    }
+
+    // Find a free name; creating the .trashinfo first claims it (O_EXCL)
+    let deleted_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    for attempt in 0.. {
+        // Names are kept byte for byte, UTF-8 or not
+        let mut entry = name.to_os_string();
+        if attempt > 0 {
+            entry.push(format!(".{}", attempt));
+        }
+        let trashed = files.join(&entry);
+        if trashed.symlink_metadata().is_ok() {
+            continue;
+        }
+        let info = if has_info {
+            let mut info_name = entry.clone();
+            info_name.push(".trashinfo");
+            let info = info_dir.join(info_name);
+            match std::fs::OpenOptions::new().write(true).create_new(true).open(&info) {
+                Ok(mut file) => {
+                    use std::io::Write;
+                    write!(file, "[Trash Info]\nPath={}\nDeletionDate={}\n",
+                        encode_path(info_path(&trash, &original)), local_timestamp(deleted_at))?;
+                    Some(info)
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        } else {
+            None
+        };
+
+        if let Err(e) = std::fs::rename(path, &trashed) {
+            if let Some(info) = &info {
+                let _ = std::fs::remove_file(info);
+            }
+            return Err(e.into());
+        }
+        return Ok(TrashedItem { original, trashed, info, deleted_at });
+    }
+    unreachable!("the attempt counter is unbounded")
+}
+
+/// The home trash, or `$topdir/.Trash-$uid` for files on another filesystem
+/// (renaming across filesystems would mean copying)
+#[cfg(all(unix, not(target_os = "macos")))]
+fn trash_dir_for(path: &Path) -> Result<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    let home_trash = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| dirs::home_dir().map(|home| home.join(".local").join("share")))
+        .ok_or_else(|| anyhow::anyhow!("could not determine home directory"))?
+        .join("Trash");
+
+    let device = path.symlink_metadata()?.dev();
+    let home_device = home_trash.ancestors()
+        .find_map(|dir| dir.metadata().ok())
+        .map(|metadata| metadata.dev());
+    if home_device == Some(device) {
+        return Ok(home_trash);
+    }
+
+    let top = path.ancestors()
+        .skip(1)
+        .take_while(|dir| dir.metadata().is_ok_and(|metadata| metadata.dev() == device))
+        .last()
+        .ok_or_else(|| anyhow::anyhow!("could not find the mount point"))?;
+    let trash = top.join(format!(".Trash-{}", unsafe { libc::getuid() }));
+    std::fs::DirBuilder::new().recursive(true).mode(0o700).create(&trash)?;
+    Ok(trash)
+}
+
+#[cfg(target_os = "macos")]
+fn trash_dir_for(_path: &Path) -> Result<PathBuf> {
+    // Finder's trash has no info files; `files` keeps the layout uniform
+    dirs::home_dir()
+        .map(|home| home.join(".Trash").join("aish"))
+        .ok_or_else(|| anyhow::anyhow!("could not determine home directory"))
+}
+
+#[cfg(not(unix))]
+fn trash_dir_for(_path: &Path) -> Result<PathBuf> {
+    dirs::data_local_dir()
+        .map(|dir| dir.join("aish").join("Trash"))
+        .ok_or_else(|| anyhow::anyhow!("could not determine data directory"))
+}
+
+/// The `Path=` of a trashed file: relative to the top directory in a
+/// `$topdir/.Trash-$uid`, absolute in the home trash
+fn info_path<'a>(trash: &Path, original: &'a Path) -> &'a Path {
+    let in_top_trash = trash.file_name()
+        .is_some_and(|name| pathname::bytes(name).starts_with(b".Trash-"));
+    match trash.parent() {
+        Some(top) if in_top_trash => original.strip_prefix(top).unwrap_or(original),
+        _ => original,
+    }
+}
+
+/// Percent-encode a path for a .trashinfo `Path=` line
+fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for &byte in pathname::bytes(path.as_os_str()).iter() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// `YYYY-MM-DDThh:mm:ss` in local time, as the trash spec asks
+fn local_timestamp(secs: u64) -> String {
+    let time = humanize::local_time(secs);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", time.year, time.month, time.day, time.hour, time.minute, time.second)
+}
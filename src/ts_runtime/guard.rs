@@ -0,0 +1,168 @@
+use regex::Regex;
+use serde::Serialize;
+use std::sync::RwLock;
+
+use super::TypeScriptSafetyConfig;
+use crate::approval::{self, ApprovalRequest, Decision};
+
+/// Patterns for commands that can destroy data or take the machine down.
+/// Used unless `safety.use_default_deny` is set to false.
+pub const DEFAULT_DENY_PATTERNS: &[&str] = &[
+    // rm -rf of /, /*, ~ or $HOME
+    r#"\brm\s+(-\S+\s+)*-\S*[rR]\S*\s+(-\S+\s+)*["']?(/\*?|~/?|\$HOME/?)["']?(\s|;|&|\||$)"#,
+    // Creating filesystems
+    r"\bmkfs(\.\w+)?\b",
+    // Writing raw devices
+    r"\bdd\b.*\bof=/dev/",
+    r">\s*/dev/(sd[a-z]|nvme\d|hd[a-z]|disk\d)",
+    // Classic fork bomb
+    r":\(\)\s*\{\s*:\s*\|\s*:\s*&\s*\}\s*;\s*:",
+    // Recursively opening up or taking over the root filesystem
+    r"\bchmod\s+(-\S+\s+)*-R\s+0?777\s+/(\s|$)",
+    r"\bchown\s+(-\S+\s+)*-R\s+\S+\s+/(\s|$)",
+];
+
+#[derive(Debug, Clone)]
+pub enum GuardVerdict {
+    Allowed,
+    /// `policy` names the setting the matching pattern came from
+    Blocked { pattern: String, policy: &'static str },
+}
+
+/// Why the agent was refused a command or tool call. Sent back to the model
+/// as the tool result, so it can find a compliant alternative rather than
+/// retrying the same call.
+#[derive(Debug, Clone, Serialize)]
+pub struct Denial {
+    /// The setting or party that refused, e.g. `safety.deny_patterns` or `user`
+    pub policy: String,
+    /// The specific rule that matched, such as a deny pattern
+    pub rule: Option<String>,
+    pub reason: String,
+}
+
+impl Denial {
+    pub fn new(policy: &str, rule: Option<String>, reason: impl Into<String>) -> Self {
+        Self { policy: policy.to_string(), rule, reason: reason.into() }
+    }
+
+    pub fn to_tool_result(&self) -> String {
+        serde_json::json!({
+            "status": "denied",
+            "policy": self.policy,
+            "rule": self.rule,
+            "reason": self.reason,
+            "guidance": "Do not retry the same call. Propose an alternative that satisfies this policy, or explain to the user what you need and why.",
+        })
+        .to_string()
+    }
+}
+
+struct CommandGuard {
+    allow: Vec<Regex>,
+    // Each pattern with the setting it came from
+    deny: Vec<(Regex, &'static str)>,
+}
+
+impl CommandGuard {
+    fn from_config(config: Option<&TypeScriptSafetyConfig>) -> Self {
+        let use_defaults = config.and_then(|c| c.use_default_deny).unwrap_or(true);
+        let default_sources: Vec<String> = if use_defaults {
+            DEFAULT_DENY_PATTERNS.iter().map(|p| p.to_string()).collect()
+        } else {
+            Vec::new()
+        };
+        let deny_sources = config
+            .and_then(|c| c.deny_patterns.clone())
+            .unwrap_or_default();
+        let allow_sources = config
+            .and_then(|c| c.allow_patterns.clone())
+            .unwrap_or_default();
+
+        let deny = compile_patterns(&default_sources).into_iter()
+            .map(|re| (re, "safety.use_default_deny"))
+            .chain(compile_patterns(&deny_sources).into_iter().map(|re| (re, "safety.deny_patterns")))
+            .collect();
+        Self {
+            allow: compile_patterns(&allow_sources),
+            deny,
+        }
+    }
+
+    fn check(&self, command: &str) -> GuardVerdict {
+        if self.allow.iter().any(|re| re.is_match(command)) {
+            return GuardVerdict::Allowed;
+        }
+        match self.deny.iter().find(|(re, _)| re.is_match(command)) {
+            Some((re, policy)) => GuardVerdict::Blocked { pattern: re.as_str().to_string(), policy },
+            None => GuardVerdict::Allowed,
+        }
+    }
+}
+
+fn compile_patterns(sources: &[String]) -> Vec<Regex> {
+    sources.iter()
+        .filter_map(|source| match Regex::new(source) {
+            Ok(re) => Some(re),
+            Err(e) => {
+                tracing::warn!(pattern = %source, error = %e, "ignoring invalid safety pattern");
+                None
+            }
+        })
+        .collect()
+}
+
+lazy_static::lazy_static! {
+    static ref GUARD: RwLock<CommandGuard> = RwLock::new(CommandGuard::from_config(None));
+}
+
+/// Replace the active policy with the one from the loaded configuration
+pub fn install(config: Option<&TypeScriptSafetyConfig>) {
+    if let Ok(mut guard) = GUARD.write() {
+        *guard = CommandGuard::from_config(config);
+    }
+}
+
+pub fn check_command(command: &str) -> GuardVerdict {
+    GUARD.read()
+        .map(|guard| guard.check(command))
+        .unwrap_or(GuardVerdict::Allowed)
+}
+
+/// Ask the user whether to run a blocked command, which they may edit first.
+/// Returns the command to run, or None if it stays blocked, as it does
+/// without a terminal to ask on; answering "always" lets commands matching
+/// the same pattern through for the rest of the session.
+pub fn confirm_blocked(command: &str, pattern: &str) -> Option<String> {
+    println!("!!!! Potentially dangerous command (matched safety pattern: {})", pattern);
+    let decision = approval::ask(&ApprovalRequest {
+        question: "Run it anyway?",
+        subject: &format!("$ {}", command),
+        details: None,
+        editable: true,
+        always_key: Some(format!("guard:{}", pattern)),
+        default_approve: false,
+        risky: true,
+    });
+    match decision {
+        Decision::Deny => None,
+        Decision::Edited(edited) => Some(edited.strip_prefix("$ ").unwrap_or(&edited).trim().to_string()),
+        Decision::Approve | Decision::Always => Some(command.to_string()),
+    }
+}
+
+/// Check a command against the policy, asking for confirmation when it is
+/// blocked. Returns the command to run, as the user may have edited it, or
+/// why it must not run.
+pub fn authorize(command: &str) -> Result<String, Denial> {
+    match check_command(command) {
+        GuardVerdict::Allowed => Ok(command.to_string()),
+        GuardVerdict::Blocked { pattern, policy } => {
+            confirm_blocked(command, &pattern).ok_or_else(|| Denial::new(
+                policy,
+                Some(pattern),
+                "The command matches a safety deny pattern and the user did not approve running it",
+            ))
+        }
+    }
+}
@@ -1,12 +1,20 @@
 use anyhow::Result;
 use deno_core::{
-    JsRuntime, RuntimeOptions, FastString, ModuleSpecifier,
+    JsRuntime, PollEventLoopOptions, RuntimeOptions, FastString, ModuleSpecifier,
 };
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 use serde_json::Value;
 use super::module_loader::TsModuleLoader;
-use super::ops;
+use super::{ops, AgentTool};
+
+/// A TypeScript call was stopped for running too long
+#[derive(Debug, thiserror::Error)]
+#[error("timed out after {0:?}")]
+pub struct TimedOut(pub Duration);
 
 pub struct TypeScriptIsolate {
     runtime: JsRuntime,
@@ -19,15 +27,33 @@ impl TypeScriptIsolate {
             aish_ops,
             ops = [
                 ops::op_get_shell_info,
+                ops::op_get_dir_stack,
                 ops::op_get_env,
                 ops::op_set_env,
+                ops::op_read_file,
+                ops::op_clipboard_read,
+                ops::op_clipboard_write,
                 ops::op_log,
                 ops::op_console_log,
                 ops::op_execute_command,
                 ops::op_register_agent_tool,
                 ops::op_get_agent_tools,
-                ops::op_call_agent_tool,
+                ops::op_listen_event,
+                ops::op_emit_event,
+                ops::op_get_var,
+                ops::op_set_var,
+                ops::op_get_vars,
+                ops::op_format_bytes,
+                ops::op_format_duration,
+                ops::op_format_relative_time,
             ],
+            state = |state| {
+                state.put(ops::RegisteredTools::default());
+                state.put(ops::ListenedEvents::default());
+                state.put(ops::CallDeadline::default());
+                state.put(crate::state::session());
+                state.put(crate::state::shell_state());
+            },
         );
         
         // Create JsRuntime with module loader for TypeScript support
@@ -92,6 +118,59 @@ impl TypeScriptIsolate {
         "#;
         
         runtime.execute_script("console_init", FastString::from(console_init.to_string()))?;
+
+        // registerAgentTool({ name, description, parameters, handler, timeoutMs,
+        // maxOutputBytes, writesFiles }) adds a
+        // tool through op_register_agent_tool and keeps its handler here;
+        // callAgentTool dispatches to it, or to the global of that name
+        let agent_tools_init = r#"
+            (() => {
+                const handlers = {};
+                globalThis.registerAgentTool = ({ name, description, parameters, handler, timeoutMs, maxOutputBytes, writesFiles }) => {
+                    const schema = JSON.stringify(parameters ?? { type: "object", properties: {} });
+                    if (!Deno.core.ops.op_register_agent_tool(name, description ?? "", schema, { timeoutMs, maxOutputBytes, writesFiles })) {
+                        throw new Error(`Invalid parameters schema for agent tool ${name}`);
+                    }
+                    if (handler !== undefined) {
+                        handlers[name] = handler;
+                    }
+                };
+                globalThis.callAgentTool = (name, params) => {
+                    const handler = handlers[name] ?? globalThis[name];
+                    if (typeof handler !== 'function') {
+                        throw new Error(`Agent tool ${name} has no function`);
+                    }
+                    return handler(params ?? {});
+                };
+            })();
+        "#;
+        runtime.execute_script("agent_tools_init", FastString::from(agent_tools_init.to_string()))?;
+
+        // aish.on(event, handler) subscribes to shell events ("*" for all)
+        // and aish.emit(event, payload) queues one; the shell delivers queued
+        // events through dispatchAishEvent, awaiting async handlers
+        let events_init = r#"
+            (() => {
+                const listeners = {};
+                globalThis.aish = globalThis.aish ?? {};
+                globalThis.aish.on = (event, handler) => {
+                    (listeners[event] ??= []).push(handler);
+                    Deno.core.ops.op_listen_event(event);
+                };
+                globalThis.aish.emit = (event, payload) => {
+                    Deno.core.ops.op_emit_event(event, JSON.stringify(payload ?? null));
+                };
+                globalThis.dispatchAishEvent = async (event, payload) => {
+                    for (const handler of listeners[event] ?? []) {
+                        await handler(payload);
+                    }
+                    for (const handler of listeners["*"] ?? []) {
+                        await handler({ event, payload });
+                    }
+                };
+            })();
+        "#;
+        runtime.execute_script("events_init", FastString::from(events_init.to_string()))?;
         
         Ok(Self { runtime })
     }
@@ -112,6 +191,22 @@ impl TypeScriptIsolate {
         Ok(())
     }
 
+    /// Execute an additional module (e.g. a plugin) in the same runtime after the main script
+    pub async fn execute_side_module(&mut self, script_path: &Path) -> Result<()> {
+        let module_specifier = ModuleSpecifier::from_file_path(script_path)
+            .map_err(|_| anyhow::anyhow!("Failed to convert path to module specifier"))?;
+        
+        let module_id = self.runtime.load_side_es_module(&module_specifier).await?;
+        
+        let result = self.runtime.mod_evaluate(module_id);
+        self.runtime.run_event_loop(Default::default()).await?;
+        result.await?;
+        
+        Ok(())
+    }
+
+    /// Call `globalThis[function_name](...args)`. A function that returns a
+    /// Promise is awaited, with the event loop running its pending ops.
     pub async fn call_function(&mut self, function_name: &str, args: &[Value]) -> Result<Value> {
         let args_str = args.iter()
             .map(|arg| arg.to_string())
@@ -120,10 +215,10 @@ impl TypeScriptIsolate {
             
         let script = format!(
             r#"
-            (function() {{
+            (async function() {{
                 if (typeof globalThis.{} === 'function') {{
-                    const result = globalThis.{}({});
-                    return JSON.stringify(result);
+                    const result = await globalThis.{}({});
+                    return result === undefined ? 'null' : JSON.stringify(result);
                 }} else {{
                     throw new Error('Function {} not found or not a function');
                 }}
@@ -132,13 +227,116 @@ impl TypeScriptIsolate {
             function_name, function_name, args_str, function_name
         );
 
-        let result = self.runtime.execute_script("call_function", FastString::from(script))?;
+        self.run_to_json("call_function", script).await
+    }
+
+    /// Like `call_function`, but stopped after `timeout` with [`TimedOut`]:
+    /// a watchdog terminates a script stuck in a loop, the wait for a
+    /// pending Promise is abandoned, and commands the call started are
+    /// killed (see [`ops::CallDeadline`])
+    pub async fn call_function_with_timeout(&mut self, function_name: &str, args: &[Value], timeout: Duration) -> Result<Value> {
+        let handle = self.runtime.v8_isolate().thread_safe_handle();
+        let (done, finished) = mpsc::channel::<()>();
+        let watchdog = std::thread::spawn(move || {
+            let expired = finished.recv_timeout(timeout) == Err(mpsc::RecvTimeoutError::Timeout);
+            if expired {
+                handle.terminate_execution();
+            }
+            expired
+        });
+
+        self.runtime.op_state().borrow_mut().put(ops::CallDeadline(Some(Instant::now() + timeout)));
+        let result = tokio::time::timeout(timeout, self.call_function(function_name, args)).await;
+        self.runtime.op_state().borrow_mut().put(ops::CallDeadline(None));
+        let _ = done.send(());
+        let terminated = watchdog.join().unwrap_or(false);
+        if terminated {
+            self.runtime.v8_isolate().cancel_terminate_execution();
+        }
+        match result {
+            Ok(result) if !terminated => result,
+            _ => Err(TimedOut(timeout).into()),
+        }
+    }
+
+    /// Run `script`, which evaluates to a Promise of a JSON string, until the
+    /// Promise settles, and parse its value
+    async fn run_to_json(&mut self, name: &'static str, script: String) -> Result<Value> {
+        let promise = self.runtime.execute_script(name, FastString::from(script))?;
+        let resolved = Box::pin(self.runtime.resolve(promise));
+        let result = self.runtime.with_event_loop_promise(resolved, PollEventLoopOptions::default()).await?;
         let scope = &mut self.runtime.handle_scope();
         let local_result = deno_core::v8::Local::new(scope, result);
         let result_string = serde_v8::from_v8::<String>(scope, local_result)?;
-        let json_value: Value = serde_json::from_str(&result_string)?;
-        print!("{}", json_value);
-        Ok(json_value)
+        Ok(serde_json::from_str(&result_string)?)
+    }
+
+    /// Call `globalThis[object_name][member_name](...args)`, for functions
+    /// stored in objects; a returned Promise is awaited as in `call_function`
+    pub async fn call_member_function(&mut self, object_name: &str, member_name: &str, args: &[Value]) -> Result<Value> {
+        let args_str = args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let member = Value::String(member_name.to_string()).to_string();
+
+        let script = format!(
+            r#"
+            (async function() {{
+                const target = globalThis.{}?.[{}];
+                if (typeof target === 'function') {{
+                    const result = await target({});
+                    return result === undefined ? 'null' : JSON.stringify(result);
+                }} else {{
+                    throw new Error('Function ' + {} + ' not found in {}');
+                }}
+            }})()
+            "#,
+            object_name, member, args_str, member, object_name
+        );
+
+        self.run_to_json("call_member_function", script).await
+    }
+
+    /// Names of the function-valued properties of a global object
+    pub async fn function_keys(&mut self, object_name: &str) -> Result<Vec<String>> {
+        let script = format!(
+            r#"
+            (function() {{
+                const target = globalThis.{};
+                if (typeof target !== 'object' || target === null) {{
+                    return '[]';
+                }}
+                return JSON.stringify(Object.keys(target).filter(k => typeof target[k] === 'function'));
+            }})()
+            "#,
+            object_name
+        );
+
+        let result = self.runtime.execute_script("function_keys", FastString::from(script))?;
+        let scope = &mut self.runtime.handle_scope();
+        let local_result = deno_core::v8::Local::new(scope, result);
+        let result_string = serde_v8::from_v8::<String>(scope, local_result)?;
+        Ok(serde_json::from_str(&result_string)?)
+    }
+
+    /// Tools registered with `op_register_agent_tool` while running the scripts
+    pub fn registered_tools(&mut self) -> HashMap<String, AgentTool> {
+        self.runtime.op_state().borrow().borrow::<ops::RegisteredTools>().0.clone()
+    }
+
+    /// Events handled with `aish.on` while running the scripts
+    pub fn listened_events(&mut self) -> HashSet<String> {
+        self.runtime.op_state().borrow().borrow::<ops::ListenedEvents>().0.clone()
+    }
+
+    pub async fn has_function(&mut self, function_name: &str) -> Result<bool> {
+        let script = format!("typeof globalThis.{} === 'function'", function_name);
+
+        let result = self.runtime.execute_script("has_function", FastString::from(script))?;
+        let scope = &mut self.runtime.handle_scope();
+        let local_result = deno_core::v8::Local::new(scope, result);
+        Ok(serde_v8::from_v8::<bool>(scope, local_result)?)
     }
 
     pub async fn get_export(&mut self, export_name: &str) -> Result<Value> {
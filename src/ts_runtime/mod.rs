@@ -1,19 +1,30 @@
+pub mod guard;
 pub mod isolate;
 pub mod module_loader;
 pub mod ops;
+pub mod permissions;
 
-pub use isolate::TypeScriptIsolate;
+pub use isolate::{TimedOut, TypeScriptIsolate};
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TypeScriptConfig {
     pub ai: Option<TypeScriptAiConfig>,
     pub shell: Option<TypeScriptShellConfig>,
+    pub safety: Option<TypeScriptSafetyConfig>,
+    /// Named agent profiles selected by the current directory
+    pub profiles: Option<HashMap<String, TypeScriptProfile>>,
+    /// What TypeScript may do through ops; without it everything is allowed
+    pub permissions: Option<TypeScriptPermissionsConfig>,
+    /// Colors for the prompt, agent output, errors and approvals
+    pub theme: Option<TypeScriptThemeConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,6 +34,138 @@ pub struct TypeScriptAiConfig {
     pub base_url: Option<String>,
     pub temperature: Option<f32>,
     pub max_tokens: Option<u32>,
+    /// Nucleus sampling cutoff, sent only when set
+    pub top_p: Option<f32>,
+    /// Sampling seed for providers that support reproducible output, sent only when set
+    pub seed: Option<u64>,
+    /// Named sampling presets for prompt templates and `--preset`, e.g.
+    /// `"deterministic": { temperature: 0, seed: 1 }`
+    pub presets: Option<HashMap<String, SamplingPreset>>,
+    /// Attempts per request when the API returns 429/5xx or is unreachable
+    pub max_attempts: Option<u32>,
+    /// Whether the agent's change_directory tool needs approval: "ask", "allow" or "deny"
+    pub agent_cd_policy: Option<String>,
+    /// How many recent shell commands (with exit codes and output) the agent sees; 0 disables
+    pub context_commands: Option<usize>,
+    /// Per-command output limit for that context, in characters
+    pub context_output_chars: Option<usize>,
+    /// What to do when a command fails: "off" (default), "offer" a diagnosis, or run it "auto"matically
+    pub fix_on_error: Option<String>,
+    /// Named model/provider presets for the `model` builtin, e.g. `"claude-sonnet": { model, base_url, api_key }`
+    pub models: Option<HashMap<String, ModelPreset>>,
+    /// Replaces the built-in agent system prompt; may also be a function, see `call_system_prompt`
+    #[serde(rename = "systemPrompt", alias = "system_prompt")]
+    pub system_prompt: Option<String>,
+    /// Seconds a tool (TypeScript or run_command) may run before it is
+    /// stopped and the model told it timed out
+    pub tool_timeout_secs: Option<u64>,
+    /// Bytes of tool output passed to the model; the rest is cut with a marker
+    pub max_tool_output_bytes: Option<usize>,
+    /// Per-tool overrides by tool name, built-in ones included, e.g.
+    /// `run_command: { timeoutMs: 120000 }`
+    pub tool_limits: Option<HashMap<String, ToolLimits>>,
+    /// Tool calls the agent may start per minute; 0 means no limit
+    pub max_tool_calls_per_minute: Option<u32>,
+    /// Commands the agent started that may still be running, counting ones
+    /// left in the background; 0 means no limit
+    pub max_concurrent_tools: Option<u32>,
+    /// Send a repeated tool call's output as a diff against what the model
+    /// already saw in the conversation (on unless set to false)
+    pub differential_context: Option<bool>,
+    /// Ask a model for inline completions of shell commands when typing pauses
+    pub ghost_suggestions: Option<bool>,
+    /// Model used for those completions; something small and fast is best
+    pub ghost_model: Option<String>,
+    /// How often the agent is re-prompted when `ai.validateResponse` rejects its answer
+    pub max_validation_retries: Option<u32>,
+    /// Command (run with sh) that must also approve the risky actions the
+    /// user approves (commands, file changes, access grants); gets the
+    /// request as JSON on stdin, exit status 0 approves
+    pub approval_command: Option<String>,
+    /// Tokens the model accepts; past `summarize_at` of it, older steps of
+    /// the agent conversation are summarized into one memory message
+    pub context_window: Option<u32>,
+    /// Fraction of `context_window` at which summarizing starts
+    pub summarize_at: Option<f32>,
+    /// Model that writes those summaries; something cheap is best (defaults to `model`)
+    pub summary_model: Option<String>,
+    /// Most recent messages that are always sent verbatim
+    pub keep_recent_messages: Option<usize>,
+    /// Rounds of tool calls per prompt before the user is asked whether to
+    /// keep going; 0 means no limit
+    pub max_iterations: Option<u32>,
+    /// Tokens (prompt and completion) one prompt may use before that question
+    pub max_prompt_tokens: Option<u64>,
+    /// Seconds one prompt may run before that question
+    pub max_prompt_secs: Option<u64>,
+    /// Model for the project index (`index build`, `search_project`), or
+    /// "local" for the built-in embedder that needs no API
+    pub embedding_model: Option<String>,
+    /// Extra headers sent with every AI API request, e.g. `{ "X-Org-Token": "..." }`
+    pub headers: Option<HashMap<String, String>>,
+    /// PEM file of CA certificates to trust for the AI API, e.g. a corporate gateway's
+    pub ca_cert: Option<String>,
+    /// Proxy for AI API requests, e.g. "http://proxy.corp:3128"; without it
+    /// the usual HTTP(S)_PROXY variables apply
+    pub proxy: Option<String>,
+    /// Seconds an identical request is answered from the on-disk response
+    /// cache instead of the API; unset or 0 means no cache
+    pub cache_ttl_secs: Option<u64>,
+}
+
+/// Sampling parameters that override the configured ones where set
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SamplingPreset {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl SamplingPreset {
+    /// These settings, with any set in `over` taking precedence
+    pub fn overlay(&self, over: &SamplingPreset) -> SamplingPreset {
+        SamplingPreset {
+            temperature: over.temperature.or(self.temperature),
+            top_p: over.top_p.or(self.top_p),
+            seed: over.seed.or(self.seed),
+        }
+    }
+}
+
+/// An entry in the `promptTemplates` global: instructions for a recurring
+/// task, optionally with the sampling it should run with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PromptTemplate {
+    Text(String),
+    Full {
+        prompt: String,
+        /// Name of a preset in `ai.presets` (or the built-in `deterministic`)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        preset: Option<String>,
+        /// Sampling settings that override the preset's
+        #[serde(flatten)]
+        sampling: SamplingPreset,
+    },
+}
+
+impl PromptTemplate {
+    pub fn prompt(&self) -> &str {
+        match self {
+            PromptTemplate::Text(prompt) | PromptTemplate::Full { prompt, .. } => prompt,
+        }
+    }
+}
+
+/// A model and, optionally, the provider serving it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelPreset {
+    pub model: String,
+    pub base_url: Option<String>,
+    pub api_key: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,6 +174,160 @@ pub struct TypeScriptShellConfig {
     pub history_size: Option<usize>,
     pub multiline_continuation: Option<String>,
     pub mode_toggle_key: Option<String>,
+    /// Maximum depth of aish sessions started from inside aish
+    pub max_nesting: Option<u32>,
+    /// Confirm typed rm, mv and chmod -R that would touch a lot of files
+    pub dry_run: Option<TypeScriptDryRunConfig>,
+    /// Move files removed with rm (by the user or the agent) to the trash
+    pub safe_rm: Option<bool>,
+    /// Welcome text for interactive sessions, with the prompt's escapes;
+    /// may also be a function, see `call_banner`
+    pub banner: Option<String>,
+    /// Print no banner or configuration notices at startup
+    pub quiet: Option<bool>,
+    /// Announce commands that ran at least this long once they finish
+    pub notify_after_secs: Option<u64>,
+    /// How: "bell" (the default), "desktop" (a terminal notification) or "both"
+    pub notify: Option<String>,
+    /// Desktop notifications for agent turns; off unless the section exists
+    pub notifications: Option<TypeScriptNotificationsConfig>,
+    /// Summary of interactive sessions on exit
+    pub session_summary: Option<TypeScriptSessionSummaryConfig>,
+    /// Expand `!!`, `!$`, `!n` and `!prefix` in typed lines (on unless false)
+    pub history_expansion: Option<bool>,
+    /// Keep sessions' commands and agent messages in SQLite (on unless false)
+    pub session_store: Option<bool>,
+    /// Days stored sessions are kept; 0 keeps them forever
+    pub session_retention_days: Option<u64>,
+    /// Tee commands' stdout, not just stderr, into the agent's context. Off
+    /// unless true: a piped stdout is no terminal, so programs drop colors
+    /// and progress bars and editors they open misbehave. Editors, pagers
+    /// and REPLs always keep the terminal
+    pub capture_stdout: Option<bool>,
+    /// What `command |? question` does: "show" the output and send it to the
+    /// agent (the default), send it "quiet"ly, or "off" to leave `|?` to sh
+    pub prompt_pipe: Option<String>,
+    /// Time limit for every typed command but interactive ones (editors,
+    /// pagers, ssh), in seconds or as "30s", "5m", "1h"; `@timeout` before
+    /// a command sets its own
+    #[serde(rename = "defaultCommandTimeout", alias = "default_command_timeout")]
+    pub default_command_timeout: Option<CommandTimeout>,
+}
+
+/// A time limit given as seconds or as text such as "90s" or "2m"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CommandTimeout {
+    Seconds(f64),
+    Text(String),
+}
+
+/// Desktop notifications while the agent works, for when the user has
+/// switched away; they are on once the section exists
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypeScriptNotificationsConfig {
+    /// Notify when an agent turn that ran at least this long finishes
+    pub after_secs: Option<u64>,
+    /// Notify when the agent waits for an approval (default true)
+    pub approvals: Option<bool>,
+}
+
+/// Where the exit summary goes; it is on once the section exists
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypeScriptSessionSummaryConfig {
+    /// Print it when the shell exits (default true)
+    pub print: Option<bool>,
+    /// Append it to this file; `{date}` becomes today's date, e.g.
+    /// "~/notes/{date}.md" for daily notes
+    pub notes_file: Option<String>,
+    /// Add a short narrative written by the model, e.g. for standup notes
+    pub narrative: Option<bool>,
+}
+
+/// Thresholds for the typed-command dry run; it is on once the section exists
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypeScriptDryRunConfig {
+    pub enabled: Option<bool>,
+    /// Confirm when at least this many files are affected
+    pub min_files: Option<u64>,
+    /// Confirm when at least this many bytes are affected
+    pub min_bytes: Option<u64>,
+}
+
+/// Agent settings applied automatically in matching directories
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypeScriptProfile {
+    /// Directories the profile applies to: paths (including everything below
+    /// them) or globs with `*` and `**`; `~` is the home directory
+    pub paths: Vec<String>,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    #[serde(rename = "systemPrompt", alias = "system_prompt")]
+    pub system_prompt: Option<String>,
+    /// Tools the agent may use; all tools when unset
+    pub tools: Option<Vec<String>>,
+}
+
+/// Guardrails for commands run by the agent and by TypeScript
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypeScriptSafetyConfig {
+    /// Regex patterns that require confirmation before running
+    pub deny_patterns: Option<Vec<String>>,
+    /// Regex patterns that are always allowed, even if a deny pattern matches
+    pub allow_patterns: Option<Vec<String>>,
+    /// Whether the built-in deny patterns apply (default true)
+    pub use_default_deny: Option<bool>,
+}
+
+/// Styles by role, each a space-separated list such as `"bold cyan"`:
+/// color names (`red`, `bright_blue`, `on_black` for backgrounds), 0-255
+/// palette numbers, `#rrggbb`, `bold`, `dim`, `italic`, `underline`,
+/// `reverse`, or `none`. Unset roles keep the built-in colors.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TypeScriptThemeConfig {
+    /// Wraps the whole prompt; escapes in the prompt itself still apply
+    pub prompt: Option<String>,
+    /// The agent's answers
+    pub response: Option<String>,
+    pub error: Option<String>,
+    pub success: Option<String>,
+    /// Approval questions
+    pub warning: Option<String>,
+    /// Notices, hints and other secondary text
+    pub muted: Option<String>,
+    /// The banner for each command and tool the agent runs
+    pub tool: Option<String>,
+    /// Keys offered in approval prompts
+    pub accent: Option<String>,
+    /// Markdown headings
+    pub heading: Option<String>,
+    /// Markdown code spans and blocks
+    pub code: Option<String>,
+    /// Markdown `**bold**`
+    pub emphasis: Option<String>,
+}
+
+/// Capabilities granted to TypeScript, Deno-style. Anything not granted is
+/// asked about when an op first needs it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypeScriptPermissionsConfig {
+    /// Programs `executeCommand` may run, e.g. `["git", "ls"]`
+    pub run: Option<PermissionGrant>,
+    /// Environment variables that may be read or set
+    pub env: Option<PermissionGrant>,
+    /// Files and directories that may be read, e.g. `["~/project"]`
+    pub read: Option<PermissionGrant>,
+    /// Clipboard access: `"read"` and/or `"write"`
+    pub clipboard: Option<PermissionGrant>,
+}
+
+/// `true` grants everything, `false` denies without asking, a list grants
+/// those entries and asks about the rest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PermissionGrant {
+    All(bool),
+    Only(Vec<String>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -38,6 +335,22 @@ pub struct AgentTool {
     pub name: String,
     pub description: String,
     pub parameters: Value, // JSON Schema for parameters
+    #[serde(flatten)]
+    pub limits: ToolLimits,
+    /// The tool writes or deletes files, so each call is previewed and
+    /// confirmed first
+    #[serde(rename = "writesFiles", default)]
+    pub writes_files: bool,
+}
+
+/// How long a tool may run and how much output it may return; unset
+/// fields fall back to the `ai` defaults
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ToolLimits {
+    #[serde(rename = "timeoutMs", skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    #[serde(rename = "maxOutputBytes", skip_serializing_if = "Option::is_none")]
+    pub max_output_bytes: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,6 +358,24 @@ pub struct ToolRegistry {
     pub tools: HashMap<String, AgentTool>,
 }
 
+pub const DEFAULT_MAX_NESTING: u32 = 3;
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+pub const DEFAULT_CONTEXT_COMMANDS: usize = 5;
+pub const DEFAULT_TOOL_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_MAX_VALIDATION_RETRIES: u32 = 2;
+pub const DEFAULT_MAX_TOOL_OUTPUT_BYTES: usize = 64 * 1024;
+pub const DEFAULT_MAX_TOOL_CALLS_PER_MINUTE: u32 = 60;
+pub const DEFAULT_MAX_CONCURRENT_TOOLS: u32 = 4;
+pub const DEFAULT_CONTEXT_OUTPUT_CHARS: usize = 1000;
+pub const DEFAULT_CONTEXT_WINDOW: u32 = 128_000;
+pub const DEFAULT_SUMMARIZE_AT: f32 = 0.75;
+pub const DEFAULT_KEEP_RECENT_MESSAGES: usize = 6;
+pub const DEFAULT_MAX_ITERATIONS: u32 = 25;
+pub const DEFAULT_AGENT_NOTIFY_AFTER_SECS: u64 = 30;
+pub const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+pub const DEFAULT_DRY_RUN_MIN_FILES: u64 = 100;
+pub const DEFAULT_DRY_RUN_MIN_BYTES: u64 = 100 * 1024 * 1024;
+
 impl Default for TypeScriptConfig {
     fn default() -> Self {
         Self {
@@ -54,19 +385,88 @@ impl Default for TypeScriptConfig {
                 base_url: None,
                 temperature: Some(0.7),
                 max_tokens: Some(1000),
+                top_p: None,
+                seed: None,
+                presets: None,
+                max_attempts: Some(DEFAULT_MAX_ATTEMPTS),
+                agent_cd_policy: None,
+                context_commands: Some(DEFAULT_CONTEXT_COMMANDS),
+                context_output_chars: Some(DEFAULT_CONTEXT_OUTPUT_CHARS),
+                fix_on_error: None,
+                models: None,
+                system_prompt: None,
+                tool_timeout_secs: Some(DEFAULT_TOOL_TIMEOUT_SECS),
+                max_tool_output_bytes: Some(DEFAULT_MAX_TOOL_OUTPUT_BYTES),
+                tool_limits: None,
+                max_tool_calls_per_minute: Some(DEFAULT_MAX_TOOL_CALLS_PER_MINUTE),
+                max_concurrent_tools: Some(DEFAULT_MAX_CONCURRENT_TOOLS),
+                differential_context: None,
+                ghost_suggestions: None,
+                ghost_model: None,
+                approval_command: None,
+                max_validation_retries: Some(DEFAULT_MAX_VALIDATION_RETRIES),
+                context_window: None,
+                summarize_at: None,
+                summary_model: None,
+                keep_recent_messages: None,
+                max_iterations: Some(DEFAULT_MAX_ITERATIONS),
+                max_prompt_tokens: None,
+                max_prompt_secs: None,
+                embedding_model: None,
+                headers: None,
+                ca_cert: None,
+                proxy: None,
+                cache_ttl_secs: None,
             }),
             shell: Some(TypeScriptShellConfig {
                 prompt: Some("aish> ".to_string()),
                 history_size: Some(1000),
                 multiline_continuation: Some("... ".to_string()),
                 mode_toggle_key: Some("esc-x".to_string()),
+                max_nesting: Some(DEFAULT_MAX_NESTING),
+                dry_run: None,
+                safe_rm: None,
+                banner: None,
+                quiet: None,
+                notify_after_secs: None,
+                notify: None,
+                notifications: None,
+                session_summary: None,
+                history_expansion: None,
+                session_store: None,
+                session_retention_days: None,
+                capture_stdout: None,
+                prompt_pipe: None,
+                default_command_timeout: None,
             }),
+            safety: None,
+            profiles: None,
+            permissions: None,
+            theme: None,
         }
     }
 }
 
+/// Verdict of `config.ai.validateResponse` on an agent answer
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResponseValidation {
+    Ok,
+    /// Re-prompt the model with this feedback
+    Retry(String),
+}
+
+/// Lifecycle hook functions that `.aish.ts` may define on globalThis
+pub const LIFECYCLE_HOOKS: [&str; 4] = ["onStart", "preExec", "postExec", "onExit"];
+
+/// Middleware functions on globalThis that see each agent request and reply
+pub const AI_MIDDLEWARE: [&str; 2] = ["onAiRequest", "onAiResponse"];
+
 pub struct TypeScriptConfigLoader {
     script_path: PathBuf,
+    // Whether the script was just created from the default template
+    created: bool,
+    // Isolate kept between calls that may keep state in globals
+    kept: RefCell<Option<TypeScriptIsolate>>,
 }
 
 impl TypeScriptConfigLoader {
@@ -74,15 +474,17 @@ impl TypeScriptConfigLoader {
         let config_paths = [
             dirs::home_dir().map(|mut p| { p.push(".aish.ts"); p }),
             dirs::home_dir().map(|mut p| { p.push("aish.ts"); p }),
-            Some(PathBuf::from("aish.ts")),
+            Some(crate::state::current_dir().join("aish.ts")),
         ];
 
         for path_option in &config_paths {
             if let Some(path) = path_option {
                 if path.exists() {
-                    println!("Found TypeScript configuration at: {}", path.display());
+                    tracing::debug!(path = %path.display(), "found configuration");
                     return Ok(Self {
                         script_path: path.clone(),
+                        created: false,
+                        kept: RefCell::new(None),
                     });
                 }
             }
@@ -97,9 +499,30 @@ impl TypeScriptConfigLoader {
         
         Ok(Self {
             script_path: default_path,
+            created: true,
+            kept: RefCell::new(None),
         })
     }
 
+    /// Loader for a specific script, bypassing config discovery
+    pub fn with_path(script_path: PathBuf) -> Self {
+        Self { script_path, created: false, kept: RefCell::new(None) }
+    }
+
+    pub fn script_path(&self) -> &Path {
+        &self.script_path
+    }
+
+    /// The directory the configuration script is in
+    pub fn config_dir(&self) -> &Path {
+        self.script_path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."))
+    }
+
+    /// Whether no configuration existed and the default one was written
+    pub fn created_default(&self) -> bool {
+        self.created
+    }
+
     fn create_default_config(path: &Path) -> Result<()> {
         let default_config = r#"// aish JavaScript Configuration
 // This file is executed by aish to load configuration and custom functions
@@ -117,7 +540,15 @@ const config = {
     prompt: "aish> ",
     history_size: 1000,
     multiline_continuation: "... ",
-  }
+  },
+  // Commands run by the agent or by TypeScript that match a deny pattern
+  // need confirmation; allow patterns override them
+  safety: {
+    deny_patterns: [],
+    allow_patterns: [],
+  },
+  // Colors by role, e.g. { response: "bright_white", error: "bold red", tool: "#5fafff" }
+  theme: {}
 };
 
 // Example custom prompt function
@@ -173,7 +604,10 @@ function readFile(params) {
       success: true,
       content: content,
       path: params.path,
-      lines: params.lines
+      lines: params.lines,
+      // Shared formatters keep sizes, durations and times consistent:
+      // op_format_bytes, op_format_duration (ms), op_format_relative_time (epoch ms)
+      size: Deno.core.ops.op_format_bytes(content.length)
     };
   } catch (error) {
     return {
@@ -202,78 +636,141 @@ function gitStatus(params) {
   }
 }
 
-// Agent tools schema
-const agentTools = {
-  tools: {
-    "list_files": {
-      name: "list_files",
-      description: "List files in a directory with optional pattern matching",
-      parameters: {
-        type: "object",
-        properties: {
-          path: {
-            type: "string",
-            description: "Directory path to list files from (defaults to current directory)"
-          },
-          pattern: {
-            type: "string",
-            description: "Glob pattern to match files (defaults to '*')"
-          }
-        },
-        required: []
+// Custom built-in commands: `greet alice` calls commands.greet(["alice"]).
+// Returned strings are printed; other values are printed as JSON.
+const commands = {
+  greet: (args) => `Hello, ${args.length > 0 ? args.join(" ") : "world"}!`,
+};
+
+// Output post-processors keyed by command name: the command's stdout is
+// captured and replaced by the returned text, e.g.
+//   postprocess: { kubectl: (output, { args, exit_code }) => output.toUpperCase() }
+const postprocess = {};
+
+// Agent tools: a name, a description and a JSON Schema for the model, and
+// the function that runs when the model calls the tool
+registerAgentTool({
+  name: "list_files",
+  description: "List files in a directory with optional pattern matching",
+  parameters: {
+    type: "object",
+    properties: {
+      path: {
+        type: "string",
+        description: "Directory path to list files from (defaults to current directory)"
+      },
+      pattern: {
+        type: "string",
+        description: "Glob pattern to match files (defaults to '*')"
       }
     },
-    "read_file": {
-      name: "read_file",
-      description: "Read the contents of a file",
-      parameters: {
-        type: "object",
-        properties: {
-          path: {
-            type: "string",
-            description: "Path to the file to read"
-          },
-          lines: {
-            type: "number",
-            description: "Number of lines to read from the beginning (optional, reads entire file if not specified)"
-          }
-        },
-        required: ["path"]
+    required: []
+  },
+  handler: listFiles
+});
+
+registerAgentTool({
+  name: "read_file",
+  description: "Read the contents of a file",
+  parameters: {
+    type: "object",
+    properties: {
+      path: {
+        type: "string",
+        description: "Path to the file to read"
+      },
+      lines: {
+        type: "number",
+        description: "Number of lines to read from the beginning (optional, reads entire file if not specified)"
       }
     },
-    "git_status": {
-      name: "git_status",
-      description: "Get git repository status and current branch information",
-      parameters: {
-        type: "object",
-        properties: {},
-        required: []
-      }
-    }
-  }
-};
+    required: ["path"]
+  },
+  handler: readFile
+});
+
+registerAgentTool({
+  name: "git_status",
+  description: "Get git repository status and current branch information",
+  parameters: {
+    type: "object",
+    properties: {},
+    required: []
+  },
+  handler: gitStatus
+});
 
 // Export functions to global scope for Rust access
 globalThis.customPrompt = customPrompt;
 globalThis.getProjectInfo = getProjectInfo;
 globalThis.config = config;
-globalThis.agentTools = agentTools;
-
-// Export tool functions
-globalThis.list_files = listFiles;
-globalThis.read_file = readFile;
-globalThis.git_status = gitStatus;
+globalThis.commands = commands;
+globalThis.postprocess = postprocess;
 "#;
 
         std::fs::write(path, default_config)?;
-        println!("Created default TypeScript configuration at: {}", path.display());
+        tracing::info!(path = %path.display(), "created default configuration");
         Ok(())
     }
 
-    pub async fn load_config(&self) -> Result<TypeScriptConfig> {
+    /// Directory holding plugin scripts that are loaded after the main config
+    pub fn plugins_dir() -> Option<PathBuf> {
+        dirs::home_dir().map(|mut p| { p.push(".aish"); p.push("plugins"); p })
+    }
+
+    fn plugin_paths() -> Vec<PathBuf> {
+        let Some(dir) = Self::plugins_dir() else {
+            return Vec::new();
+        };
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            return Vec::new();
+        };
+
+        let mut paths: Vec<PathBuf> = entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| matches!(p.extension().and_then(|e| e.to_str()), Some("ts" | "js")))
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    /// Create an isolate with the main config and all plugins executed
+    async fn load_isolate(&self) -> Result<TypeScriptIsolate> {
         let mut isolate = TypeScriptIsolate::new(&self.script_path).await?;
         isolate.execute(&self.script_path).await?;
 
+        for plugin in Self::plugin_paths() {
+            if let Err(e) = isolate.execute_side_module(&plugin).await {
+                tracing::error!(plugin = %plugin.display(), error = %e, "failed to load plugin");
+            }
+        }
+
+        Ok(isolate)
+    }
+
+    /// The kept isolate, loaded on first use; give it back with
+    /// `keep_isolate` so what scripts store in globals lasts to the next call
+    async fn kept_isolate(&self) -> Result<TypeScriptIsolate> {
+        let kept = self.kept.borrow_mut().take();
+        match kept {
+            Some(isolate) => Ok(isolate),
+            None => self.load_isolate().await,
+        }
+    }
+
+    fn keep_isolate(&self, isolate: TypeScriptIsolate) {
+        *self.kept.borrow_mut() = Some(isolate);
+    }
+
+    /// Drop the kept isolate, so the next call runs the (reloaded) scripts afresh
+    pub fn reset(&self) {
+        self.kept.borrow_mut().take();
+    }
+
+    pub async fn load_config(&self) -> Result<TypeScriptConfig> {
+        let mut isolate = self.load_isolate().await?;
+
         // Try to get the config from global scope
         match isolate.get_export("config").await {
             Ok(config_value) => {
@@ -288,8 +785,7 @@ globalThis.git_status = gitStatus;
     }
 
     pub async fn call_prompt_function(&self, function_name: &str) -> Result<Option<String>> {
-        let mut isolate = TypeScriptIsolate::new(&self.script_path).await?;
-        isolate.execute(&self.script_path).await?;
+        let mut isolate = self.load_isolate().await?;
 
         match isolate.call_function(function_name, &[]).await {
             Ok(result) => {
@@ -303,31 +799,168 @@ globalThis.git_status = gitStatus;
         }
     }
 
-    pub async fn load_agent_tools(&self) -> Result<ToolRegistry> {
-        let mut isolate = TypeScriptIsolate::new(&self.script_path).await?;
-        isolate.execute(&self.script_path).await?;
+    /// Return which of the given global functions the configuration defines
+    pub async fn defined_functions(&self, names: &[&str]) -> Result<HashSet<String>> {
+        let mut isolate = self.load_isolate().await?;
 
-        // Try to get the tools registry from global scope
-        match isolate.get_export("agentTools").await {
-            Ok(tools_value) => {
-                let tools: ToolRegistry = serde_json::from_value(tools_value)?;
-                Ok(tools)
-            }
-            Err(_) => {
-                // Return empty registry if no tools defined
-                Ok(ToolRegistry {
-                    tools: HashMap::new(),
-                })
+        let mut defined = HashSet::new();
+        for name in names {
+            if isolate.has_function(name).await? {
+                defined.insert(name.to_string());
             }
         }
+        Ok(defined)
     }
 
-    pub async fn call_agent_tool(&self, tool_name: &str, parameters: &Value) -> Result<Value> {
-        let mut isolate = TypeScriptIsolate::new(&self.script_path).await?;
-        isolate.execute(&self.script_path).await?;
+    /// Call a lifecycle hook (`onStart`, `preExec`, `postExec`, `onExit`) in
+    /// the kept isolate, so what one hook stores in a global the next one
+    /// sees (e.g. a timer `preExec` starts and `postExec` reads)
+    pub async fn call_hook(&self, hook_name: &str, args: &[Value]) -> Result<Value> {
+        let mut isolate = self.kept_isolate().await?;
+        let result = isolate.call_function(hook_name, args).await;
+        self.keep_isolate(isolate);
+        result
+    }
+
+    /// Call the `onAiRequest` / `onAiResponse` middleware in the kept isolate,
+    /// so a whole prompt's rounds share one isolate instead of loading the
+    /// scripts each round. A returned Promise is awaited.
+    pub async fn call_ai_middleware(&self, name: &str, arg: &Value) -> Result<Value> {
+        let mut isolate = self.kept_isolate().await?;
+        let result = isolate.call_function(name, std::slice::from_ref(arg)).await;
+        self.keep_isolate(isolate);
+        result
+    }
+
+    /// Event names the configuration subscribes to with `aish.on`
+    pub async fn load_listened_events(&self) -> Result<HashSet<String>> {
+        let mut isolate = self.load_isolate().await?;
+
+        Ok(isolate.listened_events())
+    }
+
+    /// Run the `aish.on` handlers for `event` in the kept isolate, so state
+    /// handlers keep in globals lasts the session; async handlers are awaited
+    pub async fn dispatch_event(&self, event: &str, payload: &Value) -> Result<()> {
+        let mut isolate = self.kept_isolate().await?;
+        let result = isolate.call_function("dispatchAishEvent", &[Value::String(event.to_string()), payload.clone()]).await;
+        self.keep_isolate(isolate);
+        result.map(|_| ())
+    }
+
+    /// Names of the custom built-in commands defined in `globalThis.commands`
+    pub async fn load_custom_commands(&self) -> Result<HashSet<String>> {
+        let mut isolate = self.load_isolate().await?;
+
+        Ok(isolate.function_keys("commands").await?.into_iter().collect())
+    }
+
+    pub async fn call_custom_command(&self, command_name: &str, args: &[String]) -> Result<Value> {
+        let mut isolate = self.load_isolate().await?;
+
+        isolate.call_member_function("commands", command_name, &[serde_json::json!(args)]).await
+    }
+
+    /// Command names with an output post-processor in `globalThis.postprocess`
+    pub async fn load_postprocessors(&self) -> Result<HashSet<String>> {
+        let mut isolate = self.load_isolate().await?;
+
+        Ok(isolate.function_keys("postprocess").await?.into_iter().collect())
+    }
+
+    /// Pass captured command output through `postprocess[command_name](output, context)`
+    pub async fn call_postprocessor(&self, command_name: &str, output: &str, context: &Value) -> Result<Value> {
+        let mut isolate = self.load_isolate().await?;
+
+        isolate.call_member_function("postprocess", command_name, &[Value::String(output.to_string()), context.clone()]).await
+    }
+
+    /// Call `config.ai.systemPrompt(context)` when it is a function. Returns
+    /// None when it isn't one (a string value is read with the rest of the config).
+    pub async fn call_system_prompt(&self, context: &Value) -> Result<Option<String>> {
+        let mut isolate = self.load_isolate().await?;
+
+        if !isolate.function_keys("config?.ai").await?.iter().any(|key| key == "systemPrompt") {
+            return Ok(None);
+        }
+        match isolate.call_member_function("config?.ai", "systemPrompt", &[context.clone()]).await? {
+            Value::String(prompt) => Ok(Some(prompt)),
+            Value::Null => Ok(None),
+            other => Err(anyhow::anyhow!("systemPrompt returned {} instead of a string", other)),
+        }
+    }
+
+    /// Call `config.shell.banner(context)` when it is a function, like
+    /// `call_system_prompt`
+    pub async fn call_banner(&self, context: &Value) -> Result<Option<String>> {
+        let mut isolate = self.load_isolate().await?;
+
+        if !isolate.function_keys("config?.shell").await?.iter().any(|key| key == "banner") {
+            return Ok(None);
+        }
+        match isolate.call_member_function("config?.shell", "banner", &[context.clone()]).await? {
+            Value::String(banner) => Ok(Some(banner)),
+            Value::Null => Ok(None),
+            other => Err(anyhow::anyhow!("banner returned {} instead of a string", other)),
+        }
+    }
+
+    /// Run `config.ai.validateResponse(text, context)` if defined. It may
+    /// return (or resolve to, when async) true or nothing to accept, false or
+    /// a feedback string to retry, or `{ ok, feedback }`.
+    pub async fn call_validate_response(&self, text: &str, context: &Value) -> Result<ResponseValidation> {
+        let mut isolate = self.load_isolate().await?;
+
+        if !isolate.function_keys("config?.ai").await?.iter().any(|key| key == "validateResponse") {
+            return Ok(ResponseValidation::Ok);
+        }
+        let verdict = isolate.call_member_function("config?.ai", "validateResponse", &[Value::String(text.to_string()), context.clone()]).await
+            .map_err(|e| anyhow::anyhow!("validateResponse threw: {}", e))?;
+        const REJECTED: &str = "The response failed validation";
+        Ok(match verdict {
+            Value::Null | Value::Bool(true) => ResponseValidation::Ok,
+            Value::Bool(false) => ResponseValidation::Retry(REJECTED.to_string()),
+            Value::String(feedback) => ResponseValidation::Retry(feedback),
+            Value::Object(verdict) => match verdict.get("ok") {
+                Some(Value::Bool(true)) => ResponseValidation::Ok,
+                _ => ResponseValidation::Retry(
+                    verdict.get("feedback").and_then(Value::as_str).unwrap_or(REJECTED).to_string(),
+                ),
+            },
+            other => return Err(anyhow::anyhow!("validateResponse returned {} instead of a verdict", other)),
+        })
+    }
+
+    /// Tools registered with `registerAgentTool` / `op_register_agent_tool`,
+    /// plus any declared the older way in the `agentTools` global (a
+    /// registered tool wins over a declared one of the same name), then
+    /// those from WASM plugins, which win over both
+    pub async fn load_agent_tools(&self) -> Result<ToolRegistry> {
+        let mut isolate = self.load_isolate().await?;
+
+        let mut registry = match isolate.get_export("agentTools").await {
+            Ok(tools_value) => serde_json::from_value(tools_value)?,
+            Err(_) => ToolRegistry { tools: HashMap::new() },
+        };
+        registry.tools.extend(isolate.registered_tools());
+        registry.tools.extend(crate::wasm_plugins::agent_tools());
+        Ok(registry)
+    }
+
+    /// Named prompt templates from the `promptTemplates` global; none if it is unset
+    pub async fn load_prompt_templates(&self) -> Result<HashMap<String, PromptTemplate>> {
+        let mut isolate = self.load_isolate().await?;
+        match isolate.get_export("promptTemplates").await {
+            Ok(value) if !value.is_null() => Ok(serde_json::from_value(value)?),
+            _ => Ok(HashMap::new()),
+        }
+    }
+
+    /// Call a TypeScript agent tool, stopping it with [`TimedOut`] after `timeout`
+    pub async fn call_agent_tool(&self, tool_name: &str, parameters: &Value, timeout: Duration) -> Result<Value> {
+        let mut isolate = self.load_isolate().await?;
 
-        // Call the tool function with parameters
-        let args = vec![parameters.clone()];
-        isolate.call_function(tool_name, &args).await
+        let args = vec![Value::String(tool_name.to_string()), parameters.clone()];
+        isolate.call_function_with_timeout("callAgentTool", &args, timeout).await
     }
 }
\ No newline at end of file
@@ -3,6 +3,11 @@ use deno_core::{
     ModuleLoadResponse, ModuleLoader, ModuleSource, ModuleSourceCode, ModuleSpecifier, ModuleType,
     RequestedModuleType, ResolutionKind, error::ModuleLoaderError,
 };
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+// npm: specifiers are served through an ESM CDN so no local node_modules is needed
+const NPM_CDN_BASE: &str = "https://esm.sh/";
 
 pub struct TsModuleLoader;
 
@@ -13,6 +18,17 @@ impl ModuleLoader for TsModuleLoader {
         referrer: &str,
         _kind: ResolutionKind,
     ) -> Result<ModuleSpecifier, ModuleLoaderError> {
+        // Rewrite npm:package@version/path to its CDN equivalent
+        if let Some(package) = specifier.strip_prefix("npm:") {
+            let url = format!("{}{}", NPM_CDN_BASE, package.trim_start_matches('/'));
+            return ModuleSpecifier::parse(&url).map_err(|e| {
+                ModuleLoaderError::from(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid npm specifier '{}': {}", specifier, e),
+                ))
+            });
+        }
+
         deno_core::resolve_import(specifier, referrer).map_err(|e| ModuleLoaderError::from(e))
     }
 
@@ -24,13 +40,36 @@ impl ModuleLoader for TsModuleLoader {
         _requested_module_type: RequestedModuleType,
     ) -> ModuleLoadResponse {
         let module_specifier = module_specifier.clone();
-        
+
         let fut = async move {
-            let path = module_specifier
-                .to_file_path()
-                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Only file:// URLs are supported"))?;
+            let (code, media_type) = match module_specifier.scheme() {
+                "file" => {
+                    let path = module_specifier
+                        .to_file_path()
+                        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid file:// URL"))?;
+                    let code = std::fs::read_to_string(&path)
+                        .map_err(|e| ModuleLoaderError::from(e))?;
+                    (code, MediaType::from_path(&path))
+                }
+                "https" => {
+                    let code = fetch_remote_module(&module_specifier).await?;
+                    (code, MediaType::from_specifier(&module_specifier))
+                }
+                // Anyone on the path could swap a module fetched in the clear
+                "http" => {
+                    return Err(ModuleLoaderError::from(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Remote modules must use https: {}", module_specifier),
+                    )));
+                }
+                scheme => {
+                    return Err(ModuleLoaderError::from(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Unsupported module scheme '{}': {}", scheme, module_specifier),
+                    )));
+                }
+            };
 
-            let media_type = MediaType::from_path(&path);
             let (module_type, should_transpile) = match media_type {
                 MediaType::JavaScript | MediaType::Mjs => (ModuleType::JavaScript, false),
                 MediaType::TypeScript
@@ -41,30 +80,8 @@ impl ModuleLoader for TsModuleLoader {
                 _ => (ModuleType::JavaScript, false),
             };
 
-            let code = std::fs::read_to_string(&path)
-                .map_err(|e| ModuleLoaderError::from(e))?;
             let code = if should_transpile {
-                let parsed = deno_ast::parse_module(ParseParams {
-                    specifier: module_specifier.clone(),
-                    text: code.into(),
-                    media_type,
-                    capture_tokens: false,
-                    scope_analysis: false,
-                    maybe_syntax: None,
-                })
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Parse error: {:?}", e)))?;
-
-                let transpiled = parsed.transpile(
-                    &deno_ast::TranspileOptions::default(),
-                    &TranspileModuleOptions::default(),
-                    &deno_ast::EmitOptions {
-                        source_map: SourceMapOption::None,
-                        ..Default::default()
-                    },
-                )
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Transpile error: {:?}", e)))?;
-
-                transpiled.into_source().text
+                transpile(&module_specifier, code, media_type)?
             } else {
                 code
             };
@@ -81,4 +98,108 @@ impl ModuleLoader for TsModuleLoader {
 
         ModuleLoadResponse::Async(Box::pin(fut))
     }
-}
\ No newline at end of file
+}
+
+fn transpile(
+    module_specifier: &ModuleSpecifier,
+    code: String,
+    media_type: MediaType,
+) -> Result<String, ModuleLoaderError> {
+    let parsed = deno_ast::parse_module(ParseParams {
+        specifier: module_specifier.clone(),
+        text: code.into(),
+        media_type,
+        capture_tokens: false,
+        scope_analysis: false,
+        maybe_syntax: None,
+    })
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Parse error: {}", e)))?;
+
+    let transpiled = parsed.transpile(
+        &deno_ast::TranspileOptions::default(),
+        &TranspileModuleOptions::default(),
+        &deno_ast::EmitOptions {
+            source_map: SourceMapOption::None,
+            ..Default::default()
+        },
+    )
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Transpile error: {:?}", e)))?;
+
+    Ok(transpiled.into_source().text)
+}
+
+/// Directory where remote modules are cached (~/.cache/aish/remote)
+fn remote_cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("aish")
+        .join("remote")
+}
+
+fn remote_cache_path(module_specifier: &ModuleSpecifier) -> PathBuf {
+    remote_cache_dir().join(hex(&Sha256::digest(module_specifier.as_str())))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The digest pinned with a `#sha256=<hex>` fragment, as in
+/// `import "https://example.com/mod.ts#sha256=…"`
+fn pinned_digest(module_specifier: &ModuleSpecifier) -> Option<String> {
+    module_specifier.fragment()
+        .and_then(|fragment| fragment.strip_prefix("sha256="))
+        .map(str::to_ascii_lowercase)
+}
+
+/// Reject `code` when its specifier pins a different digest
+fn check_pin(module_specifier: &ModuleSpecifier, code: &str) -> Result<(), ModuleLoaderError> {
+    let Some(expected) = pinned_digest(module_specifier) else {
+        return Ok(());
+    };
+    let actual = hex(&Sha256::digest(code.as_bytes()));
+    if actual != expected {
+        return Err(ModuleLoaderError::from(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} has sha256 {}, not the pinned {}", module_specifier, actual, expected),
+        )));
+    }
+    Ok(())
+}
+
+/// Fetch a remote module, serving it from the on-disk cache when available.
+/// Set AISH_RELOAD_MODULES=1 to bypass the cache and re-download.
+async fn fetch_remote_module(module_specifier: &ModuleSpecifier) -> Result<String, ModuleLoaderError> {
+    let cache_path = remote_cache_path(module_specifier);
+    let reload = std::env::var("AISH_RELOAD_MODULES").is_ok();
+
+    if !reload {
+        if let Ok(code) = std::fs::read_to_string(&cache_path) {
+            check_pin(module_specifier, &code)?;
+            return Ok(code);
+        }
+    }
+
+    let response = reqwest::get(module_specifier.as_str())
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("Failed to fetch {}: {}", module_specifier, e),
+        ))?;
+    let code = response
+        .text()
+        .await
+        .map_err(|e| std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Failed to read {}: {}", module_specifier, e),
+        ))?;
+    check_pin(module_specifier, &code)?;
+
+    // Caching is best-effort; a failed write only means we fetch again next time
+    if std::fs::create_dir_all(remote_cache_dir()).is_ok() {
+        let _ = std::fs::write(&cache_path, &code);
+    }
+
+    Ok(code)
+}
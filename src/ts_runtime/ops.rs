@@ -1,26 +1,40 @@
-use deno_core::op2;
+use deno_core::{op2, OpState};
 use deno_error::{JsErrorClass, AdditionalProperties};
 use std::env;
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::rc::Rc;
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
 use serde_json::Value;
 
+use super::{AgentTool, ToolLimits};
+use crate::state::{SharedSession, SharedShellState};
+
 // Custom error type for operations
 #[derive(Debug, thiserror::Error)]
 pub enum AishError {
     #[error("Command execution failed: {0}")]
     CommandFailed(String),
-    #[error("Tool not found: {0}")]
-    ToolNotFound(String),
+    #[error("Command blocked by safety policy (matched {0})")]
+    CommandBlocked(String),
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("{0}")]
+    Io(String),
 }
 
 impl JsErrorClass for AishError {
     fn get_class(&self) -> Cow<'static, str> {
         match self {
             AishError::CommandFailed(_) => Cow::Borrowed("Error"),
-            AishError::ToolNotFound(_) => Cow::Borrowed("Error"),
+            AishError::CommandBlocked(_) => Cow::Borrowed("Error"),
+            AishError::PermissionDenied(_) => Cow::Borrowed("Error"),
+            AishError::Io(_) => Cow::Borrowed("Error"),
         }
     }
 
@@ -41,50 +55,140 @@ impl JsErrorClass for AishError {
 pub struct ShellInfo {
     pub current_dir: String,
     pub mode: String,
+    pub last_exit_code: i32,
     pub user: String,
     pub hostname: String,
     pub home_dir: Option<String>,
+    pub git_branch: Option<String>,
+    pub git_dirty: bool,
+    pub git_ahead: u32,
+    pub git_behind: u32,
+    pub kube_context: Option<String>,
+}
+
+/// The shell's directory, from the state the isolate was given
+fn shell_dir(state: &OpState) -> PathBuf {
+    state.borrow::<SharedShellState>().lock()
+        .map(|shell| shell.current_dir.clone())
+        .unwrap_or_else(|_| crate::state::current_dir())
 }
 
-/// Get current shell information
+/// Get current shell information, from the directory and session state the
+/// isolate was given
 #[op2]
 #[serde]
-pub fn op_get_shell_info() -> ShellInfo {
-    let current_dir = env::current_dir()
-        .map(|p| p.display().to_string())
-        .unwrap_or_else(|_| "/".to_string());
+pub fn op_get_shell_info(state: &OpState) -> ShellInfo {
+    let cwd = shell_dir(state);
+    let status = crate::status_cache::snapshot(&cwd);
+    let current_dir = cwd.display().to_string();
     
-    let mode = env::var("AISH_MODE").unwrap_or_else(|_| "agent".to_string());
+    let session = state.borrow::<SharedSession>().read()
+        .map(|session| (session.mode.as_str(), session.last_exit_code));
+    let (mode, last_exit_code) = session.unwrap_or(("agent", 0));
     let user = env::var("USER").unwrap_or_else(|_| "user".to_string());
-    let hostname = env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    let hostname = crate::platform::hostname();
     let home_dir = dirs::home_dir().map(|p| p.display().to_string());
     
     ShellInfo {
         current_dir,
-        mode,
+        mode: mode.to_string(),
+        last_exit_code,
         user,
         hostname,
         home_dir,
+        git_branch: status.git_branch,
+        git_dirty: status.git_dirty,
+        git_ahead: status.git_ahead,
+        git_behind: status.git_behind,
+        kube_context: status.kube_context,
+    }
+}
+
+// Directory stack maintained by pushd/popd, mirrored here for TypeScript
+lazy_static::lazy_static! {
+    static ref DIR_STACK: Mutex<Vec<String>> = Mutex::new(Vec::new());
+}
+
+pub fn set_dir_stack(stack: Vec<String>) {
+    if let Ok(mut dir_stack) = DIR_STACK.lock() {
+        *dir_stack = stack;
     }
 }
 
-/// Get environment variable
+/// Get the pushd/popd directory stack, most recently pushed first
+#[op2]
+#[serde]
+pub fn op_get_dir_stack() -> Vec<String> {
+    DIR_STACK.lock().map(|stack| stack.clone()).unwrap_or_default()
+}
+
+/// Format a byte count like `1.5 MB`
 #[op2]
 #[string]
-pub fn op_get_env(#[string] key: String) -> Option<String> {
-    env::var(key).ok()
+pub fn op_format_bytes(bytes: f64) -> String {
+    crate::humanize::bytes(bytes.max(0.0) as u64)
 }
 
-/// Set environment variable (for configuration)
-#[op2(fast)]
-pub fn op_set_env(#[string] key: String, #[string] value: String) {
-    env::set_var(key, value);
+/// Format a duration in milliseconds like `2.4s` or `3m 12s`
+#[op2]
+#[string]
+pub fn op_format_duration(millis: f64) -> String {
+    crate::humanize::duration(std::time::Duration::from_millis(millis.max(0.0) as u64))
+}
+
+/// Format a time in milliseconds since the epoch (as from `Date.now()`) like `5m ago`
+#[op2]
+#[string]
+pub fn op_format_relative_time(epoch_millis: f64) -> String {
+    crate::humanize::relative_time((epoch_millis.max(0.0) / 1000.0) as u64)
+}
+
+/// Get an environment variable as commands see it (see `state::env_var`),
+/// if `permissions.env` allows
+#[op2]
+#[string]
+pub fn op_get_env(#[string] key: String) -> Result<Option<String>, AishError> {
+    super::permissions::check_env(&key).map_err(AishError::PermissionDenied)?;
+    Ok(crate::state::env_var(&key))
+}
+
+/// Set an environment variable for commands the shell runs from now on, if
+/// `permissions.env` allows
+#[op2]
+pub fn op_set_env(#[string] key: String, #[string] value: String) -> Result<(), AishError> {
+    super::permissions::check_env(&key).map_err(AishError::PermissionDenied)?;
+    crate::state::set_env(&key, &value);
+    Ok(())
+}
+
+/// Read a text file, relative to the shell's directory, if `permissions.read` allows
+#[op2]
+#[string]
+pub fn op_read_file(state: &OpState, #[string] path: String) -> Result<String, AishError> {
+    let path = crate::platform::expand_home(&path).unwrap_or_else(|| shell_dir(state).join(&path));
+    super::permissions::check_read(&path).map_err(AishError::PermissionDenied)?;
+    std::fs::read_to_string(&path).map_err(|e| AishError::Io(format!("{}: {}", path.display(), e)))
+}
+
+/// The clipboard's text (through the terminal with OSC 52 over SSH)
+#[op2]
+#[string]
+pub fn op_clipboard_read() -> Result<String, AishError> {
+    super::permissions::check_clipboard("read").map_err(AishError::PermissionDenied)?;
+    crate::clipboard::read().map_err(|e| AishError::Io(format!("clipboard: {}", e)))
+}
+
+/// Put text on the clipboard (through the terminal with OSC 52 over SSH)
+#[op2]
+pub fn op_clipboard_write(#[string] text: String) -> Result<(), AishError> {
+    super::permissions::check_clipboard("write").map_err(AishError::PermissionDenied)?;
+    crate::clipboard::write(&text).map_err(|e| AishError::Io(format!("clipboard: {}", e)))
 }
 
 /// Log message from TypeScript
 #[op2(fast)]
 pub fn op_log(#[string] message: String) {
-    println!("[TS] {}", message);
+    tracing::info!(target: "aish::ts", "{}", message);
 }
 
 /// Console.log implementation
@@ -93,17 +197,51 @@ pub fn op_console_log(#[string] message: String) {
     println!("{}", message);
 }
 
-/// Execute shell command from TypeScript
+/// Execute shell command from TypeScript, in the shell's directory
 #[op2(async)]
 #[string]
-pub async fn op_execute_command(#[string] command: String) -> Result<String, AishError> {
-    use std::process::Command;
-    
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg(&command)
-        .output()
+pub async fn op_execute_command(state: Rc<RefCell<OpState>>, #[string] command: String) -> Result<String, AishError> {
+    let (cwd, deadline) = {
+        let state = state.borrow();
+        (shell_dir(&state), state.try_borrow::<CallDeadline>().and_then(|deadline| deadline.0))
+    };
+    super::permissions::check_run(&command).map_err(AishError::PermissionDenied)?;
+    let command = super::guard::authorize(&command)
+        .map_err(|denial| AishError::CommandBlocked(denial.rule.unwrap_or(denial.policy)))?;
+    tracing::info!(target: "aish::ts", %command, "command from TypeScript");
+
+    let mut cmd = crate::platform::shell_command(&command);
+    cmd.current_dir(cwd)
+        .envs(crate::state::child_env())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Its own group, so a timeout stops everything the command started
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    let child = tokio::process::Command::from(cmd)
+        .spawn()
         .map_err(|e| AishError::CommandFailed(format!("Failed to execute command: {}", e)))?;
+    let pid = child.id();
+    if let Some(pid) = pid {
+        crate::tool_rate::track(pid);
+    }
+    let output = match deadline {
+        None => child.wait_with_output().await,
+        Some(deadline) => {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match tokio::time::timeout(left, child.wait_with_output()).await {
+                Ok(output) => output,
+                Err(_) => {
+                    if let Some(pid) = pid {
+                        crate::timeout::kill(pid);
+                    }
+                    return Err(AishError::CommandFailed(format!("Command timed out: {}", command)));
+                }
+            }
+        }
+    }
+    .map_err(|e| AishError::CommandFailed(format!("Failed to execute command: {}", e)))?;
     
     let stdout = String::from_utf8_lossy(&output.stdout);
     let stderr = String::from_utf8_lossy(&output.stderr);
@@ -116,68 +254,157 @@ pub async fn op_execute_command(#[string] command: String) -> Result<String, Ais
     }
 }
 
-// Global tool registry for storing registered tools
+// Session-scoped variables set with the `setvar` builtin
 lazy_static::lazy_static! {
-    static ref TOOL_REGISTRY: Arc<Mutex<HashMap<String, (String, Value)>>> = 
+    static ref SESSION_VARS: Arc<Mutex<HashMap<String, String>>> =
         Arc::new(Mutex::new(HashMap::new()));
 }
 
-/// Register a tool for AI agent use with JSON schema
-#[op2(fast)]
-pub fn op_register_agent_tool(#[string] name: String, #[string] description: String, #[string] parameters: String) -> bool {
-    if let Ok(mut registry) = TOOL_REGISTRY.lock() {
-        // Parse the JSON parameters string
-        if let Ok(params_json) = serde_json::from_str::<Value>(&parameters) {
-            registry.insert(name.clone(), (description, params_json));
-            println!("[TS] Registered agent tool: {}", name);
-            true
-        } else {
-            false
-        }
-    } else {
-        false
+/// Prefix under which session variables are visible as environment variables
+pub const SESSION_VAR_ENV_PREFIX: &str = "AISH_VAR_";
+
+pub fn get_session_var(name: &str) -> Option<String> {
+    SESSION_VARS.lock().ok().and_then(|vars| vars.get(name).cloned())
+}
+
+pub fn set_session_var(name: &str, value: &str) {
+    if let Ok(mut vars) = SESSION_VARS.lock() {
+        vars.insert(name.to_string(), value.to_string());
     }
 }
 
-/// Get available agent tools with their schemas
-#[op2]
-#[string]
-pub fn op_get_agent_tools() -> String {
-    if let Ok(registry) = TOOL_REGISTRY.lock() {
-        let tools: Vec<Value> = registry.iter().map(|(name, (description, parameters))| {
-            serde_json::json!({
-                "type": "function",
-                "function": {
-                    "name": name,
-                    "description": description,
-                    "parameters": parameters
-                }
-            })
-        }).collect();
-        
-        serde_json::to_string(&serde_json::json!(tools)).unwrap_or_else(|_| "[]".to_string())
-    } else {
-        "[]".to_string()
+pub fn remove_session_var(name: &str) -> Option<String> {
+    SESSION_VARS.lock().ok().and_then(|mut vars| vars.remove(name))
+}
+
+/// Snapshot of all session variables, sorted by name
+pub fn session_vars() -> Vec<(String, String)> {
+    let mut vars: Vec<(String, String)> = SESSION_VARS
+        .lock()
+        .map(|vars| vars.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+        .unwrap_or_default();
+    vars.sort();
+    vars
+}
+
+/// Replace all session variables, e.g. when a subshell group ends
+pub fn replace_session_vars(vars: Vec<(String, String)>) {
+    if let Ok(mut current) = SESSION_VARS.lock() {
+        *current = vars.into_iter().collect();
     }
 }
 
-/// Call an agent tool with parameters
-#[op2(async)]
+/// Session variables as AISH_VAR_* environment pairs for child processes
+pub fn session_var_env() -> Vec<(String, String)> {
+    session_vars()
+        .into_iter()
+        .map(|(name, value)| (format!("{}{}", SESSION_VAR_ENV_PREFIX, name), value))
+        .collect()
+}
+
+/// Get a session variable
+#[op2]
 #[string]
-pub async fn op_call_agent_tool(#[string] tool_name: String, #[string] parameters: String) -> Result<String, AishError> {
-    // Check if tool exists in registry
-    {
-        let registry = TOOL_REGISTRY.lock().map_err(|_| AishError::ToolNotFound("Registry lock failed".to_string()))?;
-        if !registry.contains_key(&tool_name) {
-            return Err(AishError::ToolNotFound(tool_name));
-        }
-    }
+pub fn op_get_var(#[string] name: String) -> Option<String> {
+    get_session_var(&name)
+}
 
-    // For now, return a placeholder indicating the tool call would be dispatched
-    // In a full implementation, this would call the actual TypeScript function
-    Ok(serde_json::to_string(&serde_json::json!({
-        "tool": tool_name,
-        "parameters": parameters,
-        "note": "Tool call would be dispatched to TypeScript runtime"
-    })).unwrap_or_else(|_| "{}".to_string()))
+/// Set a session variable
+#[op2(fast)]
+pub fn op_set_var(#[string] name: String, #[string] value: String) {
+    set_session_var(&name, &value);
+}
+
+/// Get all session variables as an object
+#[op2]
+#[serde]
+pub fn op_get_vars() -> HashMap<String, String> {
+    session_vars().into_iter().collect()
+}
+
+/// Tools registered with `op_register_agent_tool` in one isolate, kept in
+/// its op state and merged into the [`ToolRegistry`](super::ToolRegistry)
+#[derive(Debug, Clone, Default)]
+pub struct RegisteredTools(pub HashMap<String, AgentTool>);
+
+/// When the TypeScript call running now must end, if it has a time limit;
+/// commands it runs with `op_execute_command` are killed at that point
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CallDeadline(pub Option<Instant>);
+
+/// Options given when registering an agent tool
+#[derive(Debug, Default, Deserialize)]
+pub struct ToolOptions {
+    #[serde(flatten)]
+    pub limits: ToolLimits,
+    #[serde(rename = "writesFiles")]
+    pub writes_files: Option<bool>,
+}
+
+/// Register a tool for AI agent use with JSON schema and optional
+/// `{ timeoutMs, maxOutputBytes, writesFiles }` options. `writesFiles: true`
+/// may also be set in the schema itself. The function that
+/// implements it is the global of the same name, or the handler given to
+/// `registerAgentTool`, which calls this op.
+#[op2]
+pub fn op_register_agent_tool(
+    state: &mut OpState,
+    #[string] name: String,
+    #[string] description: String,
+    #[string] parameters: String,
+    #[serde] options: Option<ToolOptions>,
+) -> bool {
+    let Ok(mut parameters) = serde_json::from_str::<Value>(&parameters) else {
+        return false;
+    };
+    let Some(schema) = parameters.as_object_mut() else {
+        return false;
+    };
+    // Not a JSON Schema keyword, so it isn't passed on to the model
+    let schema_writes_files = schema.remove("writesFiles").and_then(|flag| flag.as_bool()).unwrap_or(false);
+    let options = options.unwrap_or_default();
+    tracing::debug!(target: "aish::ts", tool = %name, "registered agent tool");
+    state.borrow_mut::<RegisteredTools>().0.insert(name.clone(), AgentTool {
+        name,
+        description,
+        parameters,
+        limits: options.limits,
+        writes_files: options.writes_files.unwrap_or(false) || schema_writes_files,
+    });
+    true
+}
+
+/// Event names the configuration handles with `aish.on`, collected while
+/// its scripts run
+#[derive(Default)]
+pub struct ListenedEvents(pub HashSet<String>);
+
+/// Note that the script handles `event`; called by `aish.on`
+#[op2(fast)]
+pub fn op_listen_event(state: &mut OpState, #[string] event: String) {
+    state.borrow_mut::<ListenedEvents>().0.insert(event);
+}
+
+/// Queue an event for the shell to deliver to `aish.on` handlers; called by `aish.emit`
+#[op2(fast)]
+pub fn op_emit_event(#[string] event: String, #[string] payload: String) {
+    let payload = serde_json::from_str(&payload).unwrap_or(Value::Null);
+    crate::events::emit(&event, payload);
+}
+
+/// Get available agent tools with their schemas
+#[op2]
+#[string]
+pub fn op_get_agent_tools(state: &mut OpState) -> String {
+    let tools: Vec<Value> = state.borrow::<RegisteredTools>().0.values().map(|tool| {
+        serde_json::json!({
+            "type": "function",
+            "function": {
+                "name": tool.name,
+                "description": tool.description,
+                "parameters": tool.parameters
+            }
+        })
+    }).collect();
+    serde_json::to_string(&tools).unwrap_or_else(|_| "[]".to_string())
 }
\ No newline at end of file
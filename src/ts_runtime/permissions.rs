@@ -0,0 +1,169 @@
+//! Deno-style permissions for TypeScript (`permissions` in `.aish.ts`):
+//! which programs ops may run, which environment variables they may read or
+//! set, which paths they may read, and whether they may use the clipboard.
+//! Capabilities that aren't granted are asked about when an op first needs
+//! them; "always" and "no" answers hold for the rest of the session.
+//! Without a `permissions` block, ops may do anything, as before.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+use super::{PermissionGrant, TypeScriptPermissionsConfig};
+use crate::{approval, platform};
+
+lazy_static::lazy_static! {
+    static ref PERMISSIONS: RwLock<Option<TypeScriptPermissionsConfig>> = RwLock::new(None);
+    // `kind:item` pairs the user refused, not asked about again
+    static ref REFUSED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+// Words that start or continue compound commands; the command proper follows them
+const KEYWORDS: &[&str] = &["if", "then", "else", "elif", "fi", "do", "done", "while", "until", "!", "{", "}", "time"];
+// Words that start parts with no command in them (`for x in ...`, `case x in`)
+const NOT_COMMANDS: &[&str] = &["for", "select", "case", "esac", "in"];
+
+/// Replace the active permissions with those from the configuration loaded
+/// from `config_dir`; relative `permissions.read` entries are taken from there
+pub fn install(config: Option<&TypeScriptPermissionsConfig>, config_dir: &Path) {
+    let config = config.cloned().map(|mut config| {
+        if let Some(PermissionGrant::Only(entries)) = &mut config.read {
+            for entry in entries.iter_mut() {
+                *entry = resolve(entry, config_dir).display().to_string();
+            }
+        }
+        config
+    });
+    if let Ok(mut permissions) = PERMISSIONS.write() {
+        *permissions = config;
+    }
+}
+
+/// Whether `command` may run: every program in it (the first word of each
+/// pipeline, list or substitution part) must be granted by `permissions.run`
+pub fn check_run(command: &str) -> Result<(), String> {
+    for program in programs(command) {
+        check("run", |permissions| permissions.run.clone(), &program, |entry| grants_program(entry, &program))?;
+    }
+    Ok(())
+}
+
+/// Whether environment variable `name` may be read or set (`permissions.env`)
+pub fn check_env(name: &str) -> Result<(), String> {
+    check("env", |permissions| permissions.env.clone(), name, |entry| entry == name)
+}
+
+/// Whether `path` may be read: it must be inside a `permissions.read` entry
+pub fn check_read(path: &Path) -> Result<(), String> {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    check("read", |permissions| permissions.read.clone(), &path.display().to_string(), |entry| {
+        path.starts_with(entry)
+    })
+}
+
+/// Whether the clipboard may be used for `access` ("read" or "write"),
+/// per `permissions.clipboard`
+pub fn check_clipboard(access: &str) -> Result<(), String> {
+    check("clipboard", |permissions| permissions.clipboard.clone(), access, |entry| entry == access)
+}
+
+fn check(
+    kind: &str,
+    grant: impl Fn(&TypeScriptPermissionsConfig) -> Option<PermissionGrant>,
+    item: &str,
+    granted: impl Fn(&str) -> bool,
+) -> Result<(), String> {
+    let grant = match PERMISSIONS.read() {
+        Ok(permissions) => match permissions.as_ref() {
+            Some(permissions) => grant(permissions),
+            None => return Ok(()),
+        },
+        Err(_) => return Ok(()),
+    };
+    let denied = || Err(format!("Requires {} access to {}; grant it with permissions.{} in .aish.ts", kind, item, kind));
+    match grant {
+        Some(PermissionGrant::All(true)) => return Ok(()),
+        Some(PermissionGrant::All(false)) => return denied(),
+        Some(PermissionGrant::Only(entries)) if entries.iter().any(|entry| granted(entry)) => return Ok(()),
+        _ => {}
+    }
+
+    let key = format!("{}:{}", kind, item);
+    if REFUSED.lock().is_ok_and(|refused| refused.contains(&key)) {
+        return denied();
+    }
+    let approved = approval::ask(&approval::ApprovalRequest {
+        question: &format!("Allow TypeScript {} access?", kind),
+        subject: &format!("{} {}", kind, item),
+        details: None,
+        editable: false,
+        always_key: Some(format!("ts_permission:{}", key)),
+        default_approve: false,
+        risky: true,
+    })
+    .approved();
+    if approved {
+        return Ok(());
+    }
+    tracing::info!(target: "aish::ts", %kind, %item, "permission refused");
+    if let Ok(mut refused) = REFUSED.lock() {
+        refused.insert(key);
+    }
+    denied()
+}
+
+/// The programs a command line runs, as written: the first word (after any
+/// `VAR=value` assignments and keywords such as `then` or `do`) of each part
+/// between `|`, `;`, `&`, newlines, parentheses and backticks
+fn programs(command: &str) -> Vec<String> {
+    // `2>&1` is a redirection, not a list
+    let command = command.replace(">&", "> ").replace("<&", "< ");
+    let mut programs: Vec<String> = command
+        .split(|c: char| matches!(c, '|' | ';' | '&' | '\n' | '(' | ')' | '`'))
+        .filter_map(|part| {
+            let mut words = part.split_whitespace().skip_while(|word| is_assignment(word) || KEYWORDS.contains(word));
+            words.next().filter(|word| !NOT_COMMANDS.contains(word))
+        })
+        .filter(|word| *word != "$")
+        .map(|word| word.trim_matches(|c| c == '"' || c == '\'').to_string())
+        .collect();
+    programs.sort();
+    programs.dedup();
+    programs
+}
+
+/// Whether `permissions.run` entry `entry` covers `program` as the command
+/// names it. A bare name is granted only by the same bare name; the shell
+/// looks it up in PATH. A path is granted only when it is the very file an
+/// entry names, or the one PATH finds for a bare entry.
+fn grants_program(entry: &str, program: &str) -> bool {
+    if !program.contains(std::path::is_separator) {
+        return entry == program;
+    }
+    let Ok(program) = resolve(program, &crate::state::current_dir()).canonicalize() else {
+        return false;
+    };
+    let entry = if entry.contains(std::path::is_separator) {
+        Some(resolve(entry, &crate::state::current_dir()))
+    } else {
+        find_in_path(entry)
+    };
+    entry.and_then(|entry| entry.canonicalize().ok()).is_some_and(|entry| entry == program)
+}
+
+/// The file PATH finds for program `name`
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    let path = crate::state::env_var("PATH")?;
+    std::env::split_paths(&path).map(|dir| dir.join(name)).find(|candidate| candidate.is_file())
+}
+
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=')
+        .is_some_and(|(name, _)| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+/// `entry` as an absolute path, relative ones taken from `dir`
+fn resolve(entry: &str, dir: &Path) -> PathBuf {
+    let path = platform::expand_home(entry).unwrap_or_else(|| dir.join(entry));
+    path.canonicalize().unwrap_or(path)
+}
@@ -0,0 +1,183 @@
+//! Colors by role. Everything aish colors goes through here, so the config's
+//! `theme` section restyles it all at once, and `NO_COLOR` (set to anything
+//! but empty) or `--no-color` turns color off everywhere, prompt included.
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use crate::state;
+use crate::ts_runtime::TypeScriptThemeConfig;
+
+const RESET: &str = "\x1b[0m";
+
+/// What a piece of text is, which decides how it is styled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    Prompt,
+    Response,
+    Error,
+    Success,
+    Warning,
+    Muted,
+    Tool,
+    Accent,
+    Heading,
+    Code,
+    Emphasis,
+}
+
+impl Role {
+    /// SGR parameters used when the theme doesn't set the role
+    fn default_sgr(self) -> &'static str {
+        match self {
+            Role::Prompt | Role::Response => "",
+            Role::Error => "31",
+            Role::Success => "32",
+            Role::Warning => "1;33",
+            Role::Muted => "2",
+            Role::Tool | Role::Code => "36",
+            Role::Accent => "1;36",
+            Role::Heading | Role::Emphasis => "1",
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    // SGR parameters for the roles the theme sets
+    static ref THEME: RwLock<HashMap<Role, String>> = RwLock::new(HashMap::new());
+}
+
+static NO_COLOR_FLAG: AtomicBool = AtomicBool::new(false);
+
+/// Use the styles in `theme`, replacing any set before. A style that
+/// doesn't parse is reported and left at its default.
+pub fn install(theme: Option<&TypeScriptThemeConfig>) {
+    let mut styles = HashMap::new();
+    if let Some(theme) = theme {
+        let roles = [
+            ("prompt", Role::Prompt, &theme.prompt),
+            ("response", Role::Response, &theme.response),
+            ("error", Role::Error, &theme.error),
+            ("success", Role::Success, &theme.success),
+            ("warning", Role::Warning, &theme.warning),
+            ("muted", Role::Muted, &theme.muted),
+            ("tool", Role::Tool, &theme.tool),
+            ("accent", Role::Accent, &theme.accent),
+            ("heading", Role::Heading, &theme.heading),
+            ("code", Role::Code, &theme.code),
+            ("emphasis", Role::Emphasis, &theme.emphasis),
+        ];
+        for (name, role, spec) in roles {
+            let Some(spec) = spec else {
+                continue;
+            };
+            match parse(spec) {
+                Ok(sgr) => {
+                    styles.insert(role, sgr);
+                }
+                Err(e) => eprintln!("theme.{}: {}; using the default", name, e),
+            }
+        }
+    }
+    if let Ok(mut theme) = THEME.write() {
+        *theme = styles;
+    }
+}
+
+/// Turn color off for the rest of the process (`--no-color`)
+pub fn disable() {
+    NO_COLOR_FLAG.store(true, Ordering::Relaxed);
+}
+
+/// Whether output may be colored
+pub fn enabled() -> bool {
+    !NO_COLOR_FLAG.load(Ordering::Relaxed) && state::env_var("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+/// The escape sequence that starts `role`'s style; empty when color is off
+/// or the role is unstyled
+pub fn style(role: Role) -> String {
+    if !enabled() {
+        return String::new();
+    }
+    let themed = THEME.read().ok().and_then(|theme| theme.get(&role).cloned());
+    let sgr = themed.as_deref().unwrap_or(role.default_sgr());
+    if sgr.is_empty() {
+        String::new()
+    } else {
+        format!("\x1b[{}m", sgr)
+    }
+}
+
+/// The escape sequence that ends a style; empty when color is off
+pub fn reset() -> &'static str {
+    if enabled() {
+        RESET
+    } else {
+        ""
+    }
+}
+
+/// `text` in `role`'s style
+pub fn paint(role: Role, text: &str) -> String {
+    let style = style(role);
+    if style.is_empty() {
+        text.to_string()
+    } else {
+        format!("{}{}{}", style, text, RESET)
+    }
+}
+
+/// SGR parameters for a style such as `"bold bright_green on_black"`
+fn parse(spec: &str) -> Result<String> {
+    let mut params = Vec::new();
+    for word in spec.split_whitespace() {
+        let word = word.to_lowercase();
+        let param = match word.as_str() {
+            "none" | "default" | "plain" => continue,
+            "bold" => "1".to_string(),
+            "dim" => "2".to_string(),
+            "italic" => "3".to_string(),
+            "underline" => "4".to_string(),
+            "reverse" => "7".to_string(),
+            _ => color(&word).ok_or_else(|| anyhow!("unknown style `{}`", word))?,
+        };
+        params.push(param);
+    }
+    Ok(params.join(";"))
+}
+
+/// A foreground color, or a background one with an `on_` prefix
+fn color(word: &str) -> Option<String> {
+    let (background, name) = match word.strip_prefix("on_") {
+        Some(name) => (true, name),
+        None => (false, word),
+    };
+    if let Some(hex) = name.strip_prefix('#') {
+        // Checked first: slicing a multibyte character at a non-boundary panics
+        if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).ok();
+        let (r, g, b) = (channel(0)?, channel(2)?, channel(4)?);
+        return Some(format!("{};2;{};{};{}", if background { 48 } else { 38 }, r, g, b));
+    }
+    if let Ok(index) = name.parse::<u8>() {
+        return Some(format!("{};5;{}", if background { 48 } else { 38 }, index));
+    }
+    const NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+    let (bright, base) = match name.strip_prefix("bright_") {
+        Some(base) => (true, base),
+        None => (false, name),
+    };
+    let offset = NAMES.iter().position(|known| *known == base)?;
+    let first = match (background, bright) {
+        (false, false) => 30,
+        (false, true) => 90,
+        (true, false) => 40,
+        (true, true) => 100,
+    };
+    Some((first + offset).to_string())
+}
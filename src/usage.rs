@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Token counts reported in the `usage` field of a chat completion
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
+
+/// USD per million tokens as (input, output). Matched by longest model-name
+/// prefix, so dated variants like gpt-4o-2024-08-06 use the gpt-4o price.
+const PRICING: &[(&str, f64, f64)] = &[
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1", 2.00, 8.00),
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-4", 30.00, 60.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("o1-mini", 1.10, 4.40),
+    ("o1", 15.00, 60.00),
+    ("o3-mini", 1.10, 4.40),
+    ("o3", 2.00, 8.00),
+    ("o4-mini", 1.10, 4.40),
+];
+
+fn price_for(model: &str) -> Option<(f64, f64)> {
+    PRICING.iter()
+        .filter(|(prefix, _, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _, _)| prefix.len())
+        .map(|(_, input, output)| (*input, *output))
+}
+
+#[derive(Debug, Default)]
+struct ModelTotals {
+    requests: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+impl ModelTotals {
+    fn cost(&self, model: &str) -> Option<f64> {
+        price_for(model).map(|(input, output)| {
+            (self.prompt_tokens as f64 * input + self.completion_tokens as f64 * output) / 1_000_000.0
+        })
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref SESSION_USAGE: Mutex<HashMap<String, ModelTotals>> = Mutex::new(HashMap::new());
+}
+
+/// Add the usage reported for one response to the session totals
+pub fn record(model: &str, usage: &TokenUsage) {
+    if let Ok(mut totals) = SESSION_USAGE.lock() {
+        let entry = totals.entry(model.to_string()).or_default();
+        entry.requests += 1;
+        entry.prompt_tokens += usage.prompt_tokens;
+        entry.completion_tokens += usage.completion_tokens;
+    }
+}
+
+/// Total tokens used this session, formatted compactly (e.g. "12.3k") for the prompt
+pub fn session_tokens_short() -> String {
+    let total: u64 = SESSION_USAGE.lock()
+        .map(|totals| totals.values().map(|t| t.prompt_tokens + t.completion_tokens).sum())
+        .unwrap_or(0);
+    match total {
+        0..=999 => total.to_string(),
+        1_000..=999_999 => format!("{:.1}k", total as f64 / 1_000.0),
+        _ => format!("{:.1}M", total as f64 / 1_000_000.0),
+    }
+}
+
+/// Requests, tokens and estimated cost (of models with known pricing) this session
+pub fn session_totals() -> (u64, u64, f64) {
+    let Ok(totals) = SESSION_USAGE.lock() else {
+        return (0, 0, 0.0);
+    };
+    totals.iter().fold((0, 0, 0.0), |(requests, tokens, cost), (model, t)| {
+        (requests + t.requests, tokens + t.prompt_tokens + t.completion_tokens, cost + t.cost(model).unwrap_or(0.0))
+    })
+}
+
+/// Print per-model token totals and estimated cost for the `usage` builtin
+pub fn print_usage() {
+    let Ok(totals) = SESSION_USAGE.lock() else {
+        return;
+    };
+    if totals.is_empty() {
+        println!("No AI requests made yet this session");
+        return;
+    }
+
+    let mut models: Vec<_> = totals.iter().collect();
+    models.sort_by(|a, b| a.0.cmp(b.0));
+
+    println!("{:<24} {:>8} {:>12} {:>12} {:>10}", "model", "requests", "prompt", "completion", "cost");
+    let mut total_cost = 0.0;
+    let mut all_priced = true;
+    for (model, t) in models {
+        let cost = match t.cost(model) {
+            Some(cost) => {
+                total_cost += cost;
+                format!("${:.4}", cost)
+            }
+            None => {
+                all_priced = false;
+                "n/a".to_string()
+            }
+        };
+        println!("{:<24} {:>8} {:>12} {:>12} {:>10}", model, t.requests, t.prompt_tokens, t.completion_tokens, cost);
+    }
+    println!(
+        "Estimated total: ${:.4}{}",
+        total_cost,
+        if all_priced { "" } else { " (excluding models without known pricing)" }
+    );
+}
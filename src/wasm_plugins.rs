@@ -0,0 +1,262 @@
+//! WebAssembly component plugins (`~/.aish/plugins/*.wasm`) implementing the
+//! `aish:plugin` world in `wit/plugin.wit`: agent tools and shell commands
+//! written in any language that targets the component model. Each call gets
+//! a fresh instance with WASI but no preopened directories, environment or
+//! network, a memory cap and the tool's time limit.
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+use wasmtime::component::{Component, Linker, ResourceTable};
+use wasmtime::{Engine, Store, StoreLimits, StoreLimitsBuilder, Trap};
+use wasmtime_wasi::{WasiCtx, WasiCtxBuilder, WasiView};
+
+use crate::ts_runtime::{self, AgentTool, TimedOut, TypeScriptConfigLoader};
+
+wasmtime::component::bindgen!({
+    path: "wit/plugin.wit",
+    world: "plugin",
+});
+
+// Linear memory each plugin instance may grow to
+const MAX_MEMORY_BYTES: usize = 64 * 1024 * 1024;
+// Time limit for shell commands; agent tools use their tool limits
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct PluginState {
+    plugin: String,
+    wasi: WasiCtx,
+    table: ResourceTable,
+    limits: StoreLimits,
+}
+
+impl WasiView for PluginState {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+
+    fn ctx(&mut self) -> &mut WasiCtx {
+        &mut self.wasi
+    }
+}
+
+impl PluginImports for PluginState {
+    fn log(&mut self, message: String) {
+        tracing::info!(target: "aish::wasm", plugin = %self.plugin, "{}", message);
+    }
+}
+
+/// A loaded plugin and what it provides
+struct WasmPlugin {
+    name: String,
+    component: Component,
+    tools: Vec<Tool>,
+    commands: Vec<Command>,
+}
+
+struct Plugins {
+    engine: Engine,
+    linker: Linker<PluginState>,
+    plugins: Vec<WasmPlugin>,
+}
+
+lazy_static::lazy_static! {
+    static ref PLUGINS: Mutex<Option<Plugins>> = Mutex::new(None);
+}
+
+/// Compile and register every `.wasm` plugin; failures are reported and skipped
+pub fn load() {
+    let paths = plugin_paths();
+    if paths.is_empty() {
+        return;
+    }
+    let mut loaded = match Plugins::new() {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            tracing::error!(error = %e, "WASM runtime unavailable");
+            return;
+        }
+    };
+    for path in paths {
+        match loaded.load(&path) {
+            Ok(plugin) => {
+                tracing::info!(plugin = %plugin.name, tools = plugin.tools.len(), commands = plugin.commands.len(), "loaded WASM plugin");
+                loaded.plugins.push(plugin);
+            }
+            Err(e) => {
+                tracing::error!(plugin = %path.display(), error = %e, "failed to load WASM plugin");
+            }
+        }
+    }
+    if let Ok(mut plugins) = PLUGINS.lock() {
+        *plugins = Some(loaded);
+    }
+}
+
+/// Plugin tools, in the form TypeScript tools are registered in
+pub fn agent_tools() -> HashMap<String, AgentTool> {
+    let Ok(plugins) = PLUGINS.lock() else {
+        return HashMap::new();
+    };
+    plugins.iter()
+        .flat_map(|loaded| &loaded.plugins)
+        .flat_map(|plugin| &plugin.tools)
+        .filter_map(|tool| {
+            let parameters = serde_json::from_str::<Value>(&tool.parameters).ok().filter(Value::is_object)?;
+            Some((tool.name.clone(), AgentTool {
+                name: tool.name.clone(),
+                description: tool.description.clone(),
+                parameters,
+                limits: ts_runtime::ToolLimits::default(),
+                // Plugins have no filesystem, so `writes-files` has nothing to preview
+                writes_files: false,
+            }))
+        })
+        .collect()
+}
+
+/// Whether a plugin provides agent tool `name`
+pub fn provides_tool(name: &str) -> bool {
+    find(|plugin| plugin.tools.iter().any(|tool| tool.name == name)).is_some()
+}
+
+/// Plugin commands as (name, description), sorted by name
+pub fn commands() -> Vec<(String, String)> {
+    let Ok(plugins) = PLUGINS.lock() else {
+        return Vec::new();
+    };
+    let mut commands: Vec<(String, String)> = plugins.iter()
+        .flat_map(|loaded| &loaded.plugins)
+        .flat_map(|plugin| &plugin.commands)
+        .map(|command| (command.name.clone(), command.description.clone()))
+        .collect();
+    commands.sort();
+    commands
+}
+
+/// Whether a plugin provides shell command `name`
+pub fn provides_command(name: &str) -> bool {
+    find(|plugin| plugin.commands.iter().any(|command| command.name == name)).is_some()
+}
+
+/// Call plugin tool `name`, stopping it with [`TimedOut`] after `timeout`.
+/// A result that parses as JSON is returned as such, anything else as a string.
+pub fn call_tool(name: &str, args: &Value, timeout: Duration) -> Result<Value> {
+    let arguments = args.to_string();
+    let output = call(|plugin| plugin.tools.iter().any(|tool| tool.name == name), timeout, |bindings, store| {
+        bindings.call_call_tool(store, name, &arguments)
+    })?;
+    Ok(serde_json::from_str(&output).unwrap_or(Value::String(output)))
+}
+
+/// Run plugin command `name` with `args`, returning its output
+pub fn run_command(name: &str, args: &[String]) -> Result<String> {
+    call(|plugin| plugin.commands.iter().any(|command| command.name == name), COMMAND_TIMEOUT, |bindings, store| {
+        bindings.call_run_command(store, name, args)
+    })
+}
+
+impl Plugins {
+    fn new() -> Result<Self> {
+        let mut config = wasmtime::Config::new();
+        config.wasm_component_model(true);
+        config.epoch_interruption(true);
+        // Reuse compiled components across startups (wasmtime's default cache directory)
+        if let Err(e) = config.cache_config_load_default() {
+            tracing::warn!(error = %e, "wasmtime compilation cache unavailable");
+        }
+        let engine = Engine::new(&config)?;
+        let mut linker = Linker::new(&engine);
+        wasmtime_wasi::add_to_linker_sync(&mut linker)?;
+        Plugin::add_to_linker(&mut linker, |state: &mut PluginState| state)?;
+        Ok(Self { engine, linker, plugins: Vec::new() })
+    }
+
+    fn load(&self, path: &Path) -> Result<WasmPlugin> {
+        let name = path.file_stem().map_or_else(|| path.display().to_string(), |stem| stem.to_string_lossy().into_owned());
+        let component = Component::from_file(&self.engine, path)?;
+        let (mut store, bindings) = self.instantiate(&name, &component, COMMAND_TIMEOUT)?;
+        let (tools, commands) = with_watchdog(&self.engine, COMMAND_TIMEOUT, || {
+            Ok((bindings.call_tools(&mut store)?, bindings.call_commands(&mut store)?))
+        })?;
+        Ok(WasmPlugin { name, component, tools, commands })
+    }
+
+    fn instantiate(&self, name: &str, component: &Component, timeout: Duration) -> Result<(Store<PluginState>, Plugin)> {
+        let state = PluginState {
+            plugin: name.to_string(),
+            wasi: WasiCtxBuilder::new().build(),
+            table: ResourceTable::new(),
+            limits: StoreLimitsBuilder::new().memory_size(MAX_MEMORY_BYTES).build(),
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_epoch_deadline(1);
+        let bindings = with_watchdog(&self.engine, timeout, || Plugin::instantiate(&mut store, component, &self.linker))?;
+        Ok((store, bindings))
+    }
+}
+
+/// Run `invoke` on a fresh instance of the plugin matching `provides`
+fn call(
+    provides: impl Fn(&WasmPlugin) -> bool,
+    timeout: Duration,
+    invoke: impl FnOnce(&Plugin, &mut Store<PluginState>) -> wasmtime::Result<std::result::Result<String, String>>,
+) -> Result<String> {
+    let plugins = PLUGINS.lock().map_err(|_| anyhow!("plugin registry unavailable"))?;
+    let loaded = plugins.as_ref().ok_or_else(|| anyhow!("no WASM plugins loaded"))?;
+    let plugin = loaded.plugins.iter().find(|plugin| provides(plugin)).ok_or_else(|| anyhow!("no plugin provides this"))?;
+    let (mut store, bindings) = loaded.instantiate(&plugin.name, &plugin.component, timeout)?;
+    with_watchdog(&loaded.engine, timeout, || invoke(&bindings, &mut store))?
+        .map_err(|message| anyhow!("{}: {}", plugin.name, message))
+}
+
+/// Run `call`, interrupting the plugin's instance (as [`TimedOut`]) if it
+/// runs longer than `timeout`
+fn with_watchdog<T>(engine: &Engine, timeout: Duration, call: impl FnOnce() -> wasmtime::Result<T>) -> Result<T> {
+    let (done, finished) = mpsc::channel::<()>();
+    let watchdog = {
+        let engine = engine.clone();
+        std::thread::spawn(move || {
+            let expired = finished.recv_timeout(timeout) == Err(mpsc::RecvTimeoutError::Timeout);
+            if expired {
+                engine.increment_epoch();
+            }
+            expired
+        })
+    };
+    let result = call();
+    let _ = done.send(());
+    let expired = watchdog.join().unwrap_or(false);
+    result.map_err(|e| {
+        if expired && e.downcast_ref::<Trap>() == Some(&Trap::Interrupt) {
+            TimedOut(timeout).into()
+        } else {
+            e
+        }
+    })
+}
+
+fn find(provides: impl Fn(&WasmPlugin) -> bool) -> Option<String> {
+    let plugins = PLUGINS.lock().ok()?;
+    plugins.as_ref()?.plugins.iter().find(|plugin| provides(plugin)).map(|plugin| plugin.name.clone())
+}
+
+fn plugin_paths() -> Vec<PathBuf> {
+    let Some(dir) = TypeScriptConfigLoader::plugins_dir() else {
+        return Vec::new();
+    };
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "wasm"))
+        .collect();
+    paths.sort();
+    paths
+}